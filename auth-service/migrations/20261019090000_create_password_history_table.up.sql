@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS password_history(
+   id BIGSERIAL PRIMARY KEY,
+   email TEXT NOT NULL REFERENCES users(email) ON DELETE CASCADE,
+   password_hash TEXT NOT NULL,
+   created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+CREATE INDEX IF NOT EXISTS password_history_email_idx ON password_history(email, id DESC);
@@ -0,0 +1,3 @@
+ALTER TABLE users
+    ADD COLUMN password_changed_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    ADD COLUMN must_change_password BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,13 @@
+ALTER TABLE users
+    ADD COLUMN status TEXT NOT NULL DEFAULT 'active'
+        CHECK (status IN ('active', 'disabled', 'suspended', 'pending'));
+
+CREATE TABLE IF NOT EXISTS account_status_changes(
+   id BIGSERIAL PRIMARY KEY,
+   email TEXT NOT NULL REFERENCES users(email) ON DELETE CASCADE,
+   status TEXT NOT NULL,
+   reason TEXT NOT NULL,
+   changed_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS account_status_changes_email_idx ON account_status_changes(email, id DESC);
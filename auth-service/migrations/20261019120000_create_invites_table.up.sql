@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS invites(
+   code TEXT NOT NULL PRIMARY KEY,
+   email TEXT,
+   max_uses INTEGER NOT NULL CHECK (max_uses > 0),
+   uses INTEGER NOT NULL DEFAULT 0 CHECK (uses >= 0),
+   expires_at TIMESTAMPTZ NOT NULL,
+   created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS totp_secrets(
+   email TEXT NOT NULL PRIMARY KEY REFERENCES users(email) ON DELETE CASCADE,
+   secret TEXT NOT NULL,
+   confirmed BOOLEAN NOT NULL DEFAULT FALSE,
+   -- Time step of the last accepted code, used to refuse replays
+   last_used_step BIGINT,
+   created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS recovery_codes(
+   email TEXT NOT NULL REFERENCES users(email) ON DELETE CASCADE,
+   -- SHA-256 of the normalized code
+   code_hash TEXT NOT NULL,
+   used_at TIMESTAMPTZ,
+   created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+   PRIMARY KEY (email, code_hash)
+);
@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS webauthn_credentials(
+   -- Base64url credential ID chosen by the authenticator
+   credential_id TEXT NOT NULL PRIMARY KEY,
+   email TEXT NOT NULL REFERENCES users(email) ON DELETE CASCADE,
+   -- Uncompressed SEC1 P-256 point
+   public_key BYTEA NOT NULL,
+   sign_count BIGINT NOT NULL DEFAULT 0,
+   created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+   last_used_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS webauthn_credentials_email_idx ON webauthn_credentials(email);
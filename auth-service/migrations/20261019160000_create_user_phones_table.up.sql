@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS user_phones(
+   email TEXT NOT NULL PRIMARY KEY REFERENCES users(email) ON DELETE CASCADE,
+   -- E.164 format
+   phone_number TEXT NOT NULL,
+   verified BOOLEAN NOT NULL DEFAULT FALSE,
+   -- Send 2FA codes by SMS instead of email
+   sms_delivery BOOLEAN NOT NULL DEFAULT FALSE,
+   updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
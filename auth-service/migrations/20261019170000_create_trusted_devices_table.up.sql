@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS trusted_devices(
+   id TEXT NOT NULL PRIMARY KEY,
+   email TEXT NOT NULL REFERENCES users(email) ON DELETE CASCADE,
+   -- Taken from the User-Agent of the browser that completed 2FA
+   label TEXT NOT NULL,
+   created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+   last_used_at TIMESTAMPTZ,
+   expires_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS trusted_devices_email_idx ON trusted_devices(email);
@@ -0,0 +1,3 @@
+ALTER TABLE users
+    ADD COLUMN preferred_2fa_method TEXT
+        CHECK (preferred_2fa_method IN ('totp', 'webauthn', 'sms', 'email'));
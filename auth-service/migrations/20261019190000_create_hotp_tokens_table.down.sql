@@ -0,0 +1,8 @@
+UPDATE users SET preferred_2fa_method = NULL WHERE preferred_2fa_method = 'hotp';
+
+ALTER TABLE users
+    DROP CONSTRAINT IF EXISTS users_preferred_2fa_method_check,
+    ADD CONSTRAINT users_preferred_2fa_method_check
+        CHECK (preferred_2fa_method IN ('totp', 'webauthn', 'sms', 'email'));
+
+DROP TABLE IF EXISTS hotp_tokens;
@@ -0,0 +1,17 @@
+CREATE TABLE IF NOT EXISTS hotp_tokens(
+   serial TEXT NOT NULL PRIMARY KEY,
+   -- Hex-encoded seed from the vendor's seed file
+   secret TEXT NOT NULL,
+   digits SMALLINT NOT NULL CHECK (digits BETWEEN 6 AND 8),
+   -- Next moving factor expected from the token
+   counter BIGINT NOT NULL DEFAULT 0 CHECK (counter >= 0),
+   -- Empty until a user enrolls the token. Tokens of deleted users can be handed out again.
+   email TEXT UNIQUE REFERENCES users(email) ON DELETE SET NULL,
+   assigned_at TIMESTAMPTZ,
+   created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+ALTER TABLE users
+    DROP CONSTRAINT IF EXISTS users_preferred_2fa_method_check,
+    ADD CONSTRAINT users_preferred_2fa_method_check
+        CHECK (preferred_2fa_method IN ('totp', 'hotp', 'webauthn', 'sms', 'email'));
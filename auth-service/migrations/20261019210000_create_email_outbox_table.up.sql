@@ -0,0 +1,22 @@
+-- Emails waiting to be delivered, and a record of those that were sent or given up on
+CREATE TABLE IF NOT EXISTS email_outbox(
+   id TEXT NOT NULL PRIMARY KEY,
+   -- Derived from the event the email is about, so queueing the same email twice sends it once
+   idempotency_key TEXT NOT NULL UNIQUE,
+   recipient TEXT NOT NULL,
+   subject TEXT NOT NULL,
+   -- Emptied once the email is sent, as they may hold codes and login links
+   html_body TEXT NOT NULL,
+   text_body TEXT NOT NULL,
+   status TEXT NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'sent', 'dead')),
+   attempts INTEGER NOT NULL DEFAULT 0,
+   next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+   last_error TEXT,
+   created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+   sent_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS email_outbox_due_idx ON email_outbox(next_attempt_at)
+   WHERE status = 'pending';
+CREATE INDEX IF NOT EXISTS email_outbox_dead_idx ON email_outbox(created_at)
+   WHERE status = 'dead';
@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS email_outbox_dead_expiry_idx;
+ALTER TABLE email_outbox DROP COLUMN IF EXISTS expires_at;
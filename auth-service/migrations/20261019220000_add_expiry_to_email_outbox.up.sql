@@ -0,0 +1,5 @@
+-- When the codes or links in an email stop working. NULL for emails that don't carry any.
+ALTER TABLE email_outbox ADD COLUMN IF NOT EXISTS expires_at TIMESTAMPTZ;
+
+CREATE INDEX IF NOT EXISTS email_outbox_dead_expiry_idx ON email_outbox(expires_at)
+   WHERE status = 'dead';
@@ -0,0 +1,7 @@
+ALTER TABLE user_phones ADD COLUMN IF NOT EXISTS verified BOOLEAN NOT NULL DEFAULT FALSE;
+UPDATE user_phones SET verified = TRUE WHERE phone_number IS NOT NULL;
+UPDATE user_phones SET phone_number = pending_phone_number, verified = FALSE
+   WHERE phone_number IS NULL;
+DELETE FROM user_phones WHERE phone_number IS NULL;
+ALTER TABLE user_phones ALTER COLUMN phone_number SET NOT NULL;
+ALTER TABLE user_phones DROP COLUMN IF EXISTS pending_phone_number;
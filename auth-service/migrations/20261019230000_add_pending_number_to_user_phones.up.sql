@@ -0,0 +1,8 @@
+-- A number the user has asked to switch to, until they send back the code texted to it. The
+-- verified number in phone_number keeps receiving 2FA codes in the meantime.
+ALTER TABLE user_phones ADD COLUMN IF NOT EXISTS pending_phone_number TEXT;
+ALTER TABLE user_phones ALTER COLUMN phone_number DROP NOT NULL;
+
+UPDATE user_phones SET pending_phone_number = phone_number, phone_number = NULL
+   WHERE NOT verified;
+ALTER TABLE user_phones DROP COLUMN IF EXISTS verified;
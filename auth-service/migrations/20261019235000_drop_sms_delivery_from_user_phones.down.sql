@@ -0,0 +1,6 @@
+ALTER TABLE user_phones ADD COLUMN IF NOT EXISTS sms_delivery BOOLEAN NOT NULL DEFAULT FALSE;
+
+UPDATE user_phones SET sms_delivery = TRUE
+   FROM users
+   WHERE users.email = user_phones.email AND users.preferred_2fa_method = 'sms'
+      AND user_phones.phone_number IS NOT NULL;
@@ -0,0 +1,8 @@
+-- Texting 2FA codes is now chosen like any other method, as the preferred one. Users who had
+-- switched delivery to SMS without picking a method keep getting texts.
+UPDATE users SET preferred_2fa_method = 'sms'
+   FROM user_phones
+   WHERE user_phones.email = users.email AND user_phones.sms_delivery
+      AND users.preferred_2fa_method IS NULL;
+
+ALTER TABLE user_phones DROP COLUMN IF EXISTS sms_delivery;
@@ -0,0 +1,9 @@
+-- Nothing ever moved an account out of 'pending', so those set to it are disabled instead, which
+-- keeps them from logging in until an admin reactivates them.
+INSERT INTO account_status_changes (email, status, reason)
+   SELECT email, 'disabled', 'Pending status retired' FROM users WHERE status = 'pending';
+UPDATE users SET status = 'disabled' WHERE status = 'pending';
+
+ALTER TABLE users
+    DROP CONSTRAINT IF EXISTS users_status_check,
+    ADD CONSTRAINT users_status_check CHECK (status IN ('active', 'disabled', 'suspended'));
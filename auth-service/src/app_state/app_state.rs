@@ -1,39 +1,112 @@
+use super::settings::Settings;
+use crate::domain::email_domain_policy::EmailDomainPolicy;
+use crate::domain::email_template::EmailTemplates;
 use crate::services::hashmap_two_fa_code_store::HashMapTwoFACodeStore;
 use crate::{
     data_stores::redis_two_fa_code_store::RedisTwoFACodeStore,
     domain::{
-        data_store::{BannedTokenStore, UserStore},
-        email_client, EmailClient,
+        data_store::{
+            BannedTokenStore, EmailOutboxStore, HotpStore, InviteStore, LoginAttemptStore,
+            PhoneStore, RecoveryCodeStore, TotpStore, TrustedDeviceStore, UserStore,
+            WebAuthnCeremonyStore, WebAuthnCredentialStore,
+        },
+        EmailClient, SmsClient,
     },
 };
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use tokio::sync::{Notify, RwLock};
 
 pub type UserStoreType = Arc<RwLock<dyn UserStore + Send + Sync>>;
 pub type TokenStore = Arc<RwLock<dyn BannedTokenStore + Send + Sync>>;
 pub type EmailClientType = Arc<dyn EmailClient + Send + Sync>;
+pub type EmailTemplatesType = Arc<EmailTemplates>;
+pub type EmailOutboxStoreType = Arc<RwLock<dyn EmailOutboxStore + Send + Sync>>;
+pub type SmsClientType = Arc<dyn SmsClient + Send + Sync>;
 pub type CodeStore = Arc<RwLock<RedisTwoFACodeStore>>;
+pub type LoginAttemptStoreType = Arc<RwLock<dyn LoginAttemptStore + Send + Sync>>;
+pub type InviteStoreType = Arc<RwLock<dyn InviteStore + Send + Sync>>;
+pub type EmailDomainPolicyType = Arc<RwLock<EmailDomainPolicy>>;
+pub type TotpStoreType = Arc<RwLock<dyn TotpStore + Send + Sync>>;
+pub type HotpStoreType = Arc<RwLock<dyn HotpStore + Send + Sync>>;
+pub type RecoveryCodeStoreType = Arc<RwLock<dyn RecoveryCodeStore + Send + Sync>>;
+pub type WebAuthnCredentialStoreType = Arc<RwLock<dyn WebAuthnCredentialStore + Send + Sync>>;
+pub type WebAuthnCeremonyStoreType = Arc<RwLock<dyn WebAuthnCeremonyStore + Send + Sync>>;
+pub type PhoneStoreType = Arc<RwLock<dyn PhoneStore + Send + Sync>>;
+pub type TrustedDeviceStoreType = Arc<RwLock<dyn TrustedDeviceStore + Send + Sync>>;
 
 #[derive(Clone)]
 pub struct AppState {
     pub userstore: UserStoreType,
     pub tokenstore: TokenStore,
     pub two_fa_code_store: CodeStore,
-    pub email_client: EmailClientType,
+    // Codes for passwordless login, kept apart from 2FA codes
+    pub login_code_store: CodeStore,
+    pub email_templates: EmailTemplatesType,
+    // Emails are queued here and sent by the outbox worker, which the wakeup rouses
+    pub email_outbox: EmailOutboxStoreType,
+    pub email_outbox_wakeup: Arc<Notify>,
+    pub login_attempt_store: LoginAttemptStoreType,
+    pub invite_store: InviteStoreType,
+    // Replaced in place when the domain lists are reloaded
+    pub email_domain_policy: EmailDomainPolicyType,
+    pub totp_store: TotpStoreType,
+    pub hotp_store: HotpStoreType,
+    pub recovery_code_store: RecoveryCodeStoreType,
+    pub webauthn_credential_store: WebAuthnCredentialStoreType,
+    pub webauthn_ceremony_store: WebAuthnCeremonyStoreType,
+    pub sms_client: SmsClientType,
+    pub phone_store: PhoneStoreType,
+    // Codes sent to confirm a new phone number
+    pub phone_code_store: CodeStore,
+    pub trusted_device_store: TrustedDeviceStoreType,
+    pub settings: Arc<Settings>,
 }
 
 impl AppState {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         userstore: UserStoreType,
         tokenstore: TokenStore,
         two_fa_code_store: CodeStore,
-        email_client: EmailClientType,
+        login_code_store: CodeStore,
+        email_templates: EmailTemplatesType,
+        email_outbox: EmailOutboxStoreType,
+        email_outbox_wakeup: Arc<Notify>,
+        login_attempt_store: LoginAttemptStoreType,
+        invite_store: InviteStoreType,
+        email_domain_policy: EmailDomainPolicyType,
+        totp_store: TotpStoreType,
+        hotp_store: HotpStoreType,
+        recovery_code_store: RecoveryCodeStoreType,
+        webauthn_credential_store: WebAuthnCredentialStoreType,
+        webauthn_ceremony_store: WebAuthnCeremonyStoreType,
+        sms_client: SmsClientType,
+        phone_store: PhoneStoreType,
+        phone_code_store: CodeStore,
+        trusted_device_store: TrustedDeviceStoreType,
+        settings: Settings,
     ) -> Self {
         Self {
             userstore,
             tokenstore,
             two_fa_code_store,
-            email_client,
+            login_code_store,
+            email_templates,
+            email_outbox,
+            email_outbox_wakeup,
+            login_attempt_store,
+            invite_store,
+            email_domain_policy,
+            totp_store,
+            hotp_store,
+            recovery_code_store,
+            webauthn_credential_store,
+            webauthn_ceremony_store,
+            sms_client,
+            phone_store,
+            phone_code_store,
+            trusted_device_store,
+            settings: Arc::new(settings),
         }
     }
 }
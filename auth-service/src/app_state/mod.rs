@@ -1 +1,2 @@
 pub mod app_state;
+pub mod settings;
@@ -0,0 +1,503 @@
+use crate::domain::locale::Locale;
+use crate::domain::webauthn::RelyingParty;
+use crate::utils::constants::{
+    ADMIN_API_TOKEN, BLOCK_DISPOSABLE_EMAILS, DEFAULT_BLOCK_DISPOSABLE_EMAILS,
+    DEFAULT_EMAIL_OUTBOX_MAX_ATTEMPTS, DEFAULT_EMAIL_OUTBOX_POLL_INTERVAL_SECONDS,
+    DEFAULT_EMAIL_OUTBOX_RETRY_BASE_SECONDS, DEFAULT_EMAIL_OUTBOX_RETRY_MAX_SECONDS,
+    DEFAULT_HOTP_LOOK_AHEAD, DEFAULT_LOCKOUT_BASE_SECONDS, DEFAULT_LOCKOUT_MAX_FAILURES,
+    DEFAULT_LOCKOUT_MAX_SECONDS, DEFAULT_PUBLIC_URL, DEFAULT_STEP_UP_MAX_AGE_SECONDS,
+    DEFAULT_TOTP_ISSUER, DEFAULT_TOTP_SKEW_STEPS, DEFAULT_TWO_FA_CODE_LENGTH,
+    DEFAULT_TWO_FA_CODE_TTL_SECONDS, DEFAULT_TWO_FA_MAX_ATTEMPTS,
+    DEFAULT_TWO_FA_MAX_PENDING_LOGINS, DEFAULT_TWO_FA_MAX_RESENDS,
+    DEFAULT_TWO_FA_RESEND_COOLDOWN_SECONDS, DEFAULT_WEBAUTHN_RP_ID, DEFAULT_WEBAUTHN_RP_NAME,
+    EMAIL_DEFAULT_LOCALE, EMAIL_DOMAIN_ALLOWLIST_PATH, EMAIL_DOMAIN_BLOCKLIST_PATH,
+    EMAIL_OUTBOX_MAX_ATTEMPTS, EMAIL_OUTBOX_POLL_INTERVAL, EMAIL_OUTBOX_RETRY_BASE_DELAY,
+    EMAIL_OUTBOX_RETRY_MAX_DELAY, EMAIL_TEMPLATE_DIRS, HOTP_LOOK_AHEAD, LOCKOUT_BASE_DURATION,
+    LOCKOUT_MAX_DURATION, LOCKOUT_MAX_FAILURES, MAX_TWO_FA_CODE_LENGTH, MIN_TWO_FA_CODE_LENGTH,
+    PASSWORD_MAX_AGE, PUBLIC_URL, SIGNUP_MODE, STEP_UP_MAX_AGE, TOTP_ISSUER, TOTP_SKEW_STEPS,
+    TWO_FA_CODE_FORMAT, TWO_FA_CODE_TTL, TWO_FA_MAX_ATTEMPTS, TWO_FA_MAX_PENDING_LOGINS,
+    TWO_FA_MAX_RESENDS, TWO_FA_RESEND_COOLDOWN, WEBAUTHN_ORIGIN, WEBAUTHN_RP_ID, WEBAUTHN_RP_NAME,
+};
+use chrono::Duration;
+use color_eyre::eyre::{eyre, Report};
+use secrecy::Secret;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+// Runtime policy shared by the route handlers
+#[derive(Clone, Default)]
+pub struct Settings {
+    // Bearer token required by the /admin routes; they are disabled when unset
+    pub admin_api_token: Option<Secret<String>>,
+    // Passwords older than this have to be changed before the user can log in again
+    pub password_max_age: Option<Duration>,
+    pub lockout: LockoutPolicy,
+    // Base URL used for links in emails, e.g. magic login links
+    pub public_url: String,
+    pub signup_mode: SignupMode,
+    pub email_domains: EmailDomainSettings,
+    pub email_templates: EmailTemplateSettings,
+    pub email_outbox: EmailOutboxSettings,
+    pub totp: TotpSettings,
+    pub hotp: HotpSettings,
+    pub webauthn: WebAuthnSettings,
+    pub two_fa: TwoFactorSettings,
+    pub step_up: StepUpSettings,
+}
+
+impl Settings {
+    pub fn from_env() -> Self {
+        Self {
+            admin_api_token: ADMIN_API_TOKEN.clone(),
+            password_max_age: *PASSWORD_MAX_AGE,
+            lockout: LockoutPolicy {
+                max_failures: *LOCKOUT_MAX_FAILURES,
+                base_duration: *LOCKOUT_BASE_DURATION,
+                max_duration: *LOCKOUT_MAX_DURATION,
+            },
+            public_url: PUBLIC_URL.clone(),
+            signup_mode: *SIGNUP_MODE,
+            email_domains: EmailDomainSettings {
+                allowlist_path: EMAIL_DOMAIN_ALLOWLIST_PATH.clone(),
+                blocklist_path: EMAIL_DOMAIN_BLOCKLIST_PATH.clone(),
+                block_disposable: *BLOCK_DISPOSABLE_EMAILS,
+            },
+            email_templates: EmailTemplateSettings {
+                dirs: EMAIL_TEMPLATE_DIRS.clone(),
+                default_locale: EMAIL_DEFAULT_LOCALE.clone(),
+            },
+            email_outbox: EmailOutboxSettings {
+                max_attempts: *EMAIL_OUTBOX_MAX_ATTEMPTS,
+                base_delay: *EMAIL_OUTBOX_RETRY_BASE_DELAY,
+                max_delay: *EMAIL_OUTBOX_RETRY_MAX_DELAY,
+                poll_interval: *EMAIL_OUTBOX_POLL_INTERVAL,
+            },
+            totp: TotpSettings {
+                issuer: TOTP_ISSUER.clone(),
+                skew_steps: *TOTP_SKEW_STEPS,
+            },
+            hotp: HotpSettings {
+                look_ahead: *HOTP_LOOK_AHEAD,
+            },
+            webauthn: WebAuthnSettings {
+                rp_id: WEBAUTHN_RP_ID.clone(),
+                rp_name: WEBAUTHN_RP_NAME.clone(),
+                origin: WEBAUTHN_ORIGIN.clone(),
+            },
+            two_fa: TwoFactorSettings {
+                max_attempts: *TWO_FA_MAX_ATTEMPTS,
+                resend_cooldown: *TWO_FA_RESEND_COOLDOWN,
+                max_resends: *TWO_FA_MAX_RESENDS,
+                max_pending_logins: *TWO_FA_MAX_PENDING_LOGINS,
+                code_ttl: *TWO_FA_CODE_TTL,
+                code_format: *TWO_FA_CODE_FORMAT,
+            },
+            step_up: StepUpSettings {
+                max_age: *STEP_UP_MAX_AGE,
+            },
+        }
+    }
+}
+
+// Whether anyone may sign up or only holders of an invite code
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SignupMode {
+    #[default]
+    Open,
+    InviteOnly,
+}
+
+impl FromStr for SignupMode {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "open" => Ok(Self::Open),
+            "invite_only" => Ok(Self::InviteOnly),
+            _ => Err(eyre!("Unknown signup mode: {}", s)),
+        }
+    }
+}
+
+// Which service delivers emails. Postmark needs POSTMARK_AUTH_TOKEN; SMTP is for deployments
+// that can only reach a mail server of their own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EmailProvider {
+    #[default]
+    Postmark,
+    Smtp,
+}
+
+impl FromStr for EmailProvider {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "postmark" => Ok(Self::Postmark),
+            "smtp" => Ok(Self::Smtp),
+            _ => Err(eyre!("Unknown email provider: {}", s)),
+        }
+    }
+}
+
+// Where the signup domain lists are read from. The files are read again when an admin
+// reloads them, so they can be edited without restarting the service.
+#[derive(Debug, Clone)]
+pub struct EmailDomainSettings {
+    pub allowlist_path: Option<PathBuf>,
+    pub blocklist_path: Option<PathBuf>,
+    pub block_disposable: bool,
+}
+
+impl Default for EmailDomainSettings {
+    fn default() -> Self {
+        Self {
+            allowlist_path: None,
+            blocklist_path: None,
+            block_disposable: DEFAULT_BLOCK_DISPOSABLE_EMAILS,
+        }
+    }
+}
+
+// Directories with templates that override or translate the built-in English emails, and the
+// locale for users who haven't chosen one
+#[derive(Debug, Clone, Default)]
+pub struct EmailTemplateSettings {
+    pub dirs: Vec<PathBuf>,
+    pub default_locale: Locale,
+}
+
+// Failed deliveries are retried after `base_delay`, doubling with every further failure up to
+// `max_delay`. After `max_attempts` the email becomes a dead letter.
+#[derive(Debug, Clone, Copy)]
+pub struct EmailOutboxSettings {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    // How often the worker looks for retries that have come due
+    pub poll_interval: Duration,
+}
+
+impl EmailOutboxSettings {
+    // How long to wait before the next try, given the number of failed attempts so far
+    pub fn retry_delay(&self, attempts: u32) -> Option<Duration> {
+        if attempts >= self.max_attempts {
+            return None;
+        }
+
+        let doublings = attempts.saturating_sub(1).min(30);
+        let delay = self
+            .base_delay
+            .checked_mul(1i32 << doublings)
+            .unwrap_or(self.max_delay);
+
+        Some(delay.min(self.max_delay))
+    }
+}
+
+impl Default for EmailOutboxSettings {
+    fn default() -> Self {
+        Self {
+            max_attempts: DEFAULT_EMAIL_OUTBOX_MAX_ATTEMPTS,
+            base_delay: Duration::seconds(DEFAULT_EMAIL_OUTBOX_RETRY_BASE_SECONDS),
+            max_delay: Duration::seconds(DEFAULT_EMAIL_OUTBOX_RETRY_MAX_SECONDS),
+            poll_interval: Duration::seconds(DEFAULT_EMAIL_OUTBOX_POLL_INTERVAL_SECONDS),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TotpSettings {
+    pub issuer: String,
+    // How many time steps of clock drift between server and authenticator app to tolerate
+    pub skew_steps: u8,
+}
+
+impl Default for TotpSettings {
+    fn default() -> Self {
+        Self {
+            issuer: DEFAULT_TOTP_ISSUER.to_owned(),
+            skew_steps: DEFAULT_TOTP_SKEW_STEPS,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HotpSettings {
+    // How far past the expected counter a hardware token code may be. Accepting one moves the
+    // counter along, which brings tokens pressed while away from the login page back in step.
+    pub look_ahead: u64,
+}
+
+impl Default for HotpSettings {
+    fn default() -> Self {
+        Self {
+            look_ahead: DEFAULT_HOTP_LOOK_AHEAD,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WebAuthnSettings {
+    // Relying party ID, the domain passkeys are scoped to
+    pub rp_id: String,
+    // Name shown by the browser when creating a passkey
+    pub rp_name: String,
+    // Origin of the pages that run the ceremonies, e.g. https://auth.example.com
+    pub origin: String,
+}
+
+impl WebAuthnSettings {
+    pub fn relying_party(&self) -> RelyingParty<'_> {
+        RelyingParty {
+            id: &self.rp_id,
+            origin: &self.origin,
+        }
+    }
+}
+
+impl Default for WebAuthnSettings {
+    fn default() -> Self {
+        Self {
+            rp_id: DEFAULT_WEBAUTHN_RP_ID.to_owned(),
+            rp_name: DEFAULT_WEBAUTHN_RP_NAME.to_owned(),
+            origin: DEFAULT_PUBLIC_URL.to_owned(),
+        }
+    }
+}
+
+// Limits on the one-time codes sent by email or SMS
+#[derive(Debug, Clone)]
+pub struct TwoFactorSettings {
+    // Wrong guesses after which a code stops working and the user has to start over
+    pub max_attempts: u32,
+    // Minimum time between sends of a code for the same login attempt
+    pub resend_cooldown: Duration,
+    pub max_resends: u32,
+    // Logins awaiting a code at once; starting another drops the oldest
+    pub max_pending_logins: u32,
+    // How long a code can be used after it was sent
+    pub code_ttl: Duration,
+    pub code_format: TwoFaCodeFormat,
+}
+
+impl Default for TwoFactorSettings {
+    fn default() -> Self {
+        Self {
+            max_attempts: DEFAULT_TWO_FA_MAX_ATTEMPTS,
+            resend_cooldown: Duration::seconds(DEFAULT_TWO_FA_RESEND_COOLDOWN_SECONDS),
+            max_resends: DEFAULT_TWO_FA_MAX_RESENDS,
+            max_pending_logins: DEFAULT_TWO_FA_MAX_PENDING_LOGINS,
+            code_ttl: Duration::seconds(DEFAULT_TWO_FA_CODE_TTL_SECONDS),
+            code_format: TwoFaCodeFormat::default(),
+        }
+    }
+}
+
+// Shape of the one-time codes sent by email or SMS
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TwoFaCodeFormat {
+    pub length: usize,
+    pub alphabet: TwoFaCodeAlphabet,
+}
+
+impl TwoFaCodeFormat {
+    pub fn new(length: usize, alphabet: TwoFaCodeAlphabet) -> Result<Self, Report> {
+        if !(MIN_TWO_FA_CODE_LENGTH..=MAX_TWO_FA_CODE_LENGTH).contains(&length) {
+            return Err(eyre!(
+                "2FA codes must be between {} and {} characters long",
+                MIN_TWO_FA_CODE_LENGTH,
+                MAX_TWO_FA_CODE_LENGTH
+            ));
+        }
+        Ok(Self { length, alphabet })
+    }
+}
+
+impl Default for TwoFaCodeFormat {
+    fn default() -> Self {
+        Self {
+            length: DEFAULT_TWO_FA_CODE_LENGTH,
+            alphabet: TwoFaCodeAlphabet::Numeric,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TwoFaCodeAlphabet {
+    #[default]
+    Numeric,
+    // Digits and letters without I, L, O and U. Codes of the same length are much harder to
+    // guess, and letters that look like digits are read as those digits.
+    CrockfordBase32,
+}
+
+impl TwoFaCodeAlphabet {
+    pub fn characters(&self) -> &'static [u8] {
+        match self {
+            TwoFaCodeAlphabet::Numeric => b"0123456789",
+            TwoFaCodeAlphabet::CrockfordBase32 => b"0123456789ABCDEFGHJKMNPQRSTVWXYZ",
+        }
+    }
+}
+
+impl FromStr for TwoFaCodeAlphabet {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "numeric" => Ok(Self::Numeric),
+            "crockford_base32" => Ok(Self::CrockfordBase32),
+            _ => Err(eyre!("Unknown 2FA code alphabet: {}", s)),
+        }
+    }
+}
+
+// Sensitive operations, such as changing the password or the 2FA setup, need a login at
+// least as strong as the account allows that is no older than `max_age`
+#[derive(Debug, Clone, Copy)]
+pub struct StepUpSettings {
+    pub max_age: Duration,
+}
+
+impl Default for StepUpSettings {
+    fn default() -> Self {
+        Self {
+            max_age: Duration::seconds(DEFAULT_STEP_UP_MAX_AGE_SECONDS),
+        }
+    }
+}
+
+// Accounts are locked once `max_failures` consecutive logins fail. The first lockout lasts
+// `base_duration` and every further failure doubles it, up to `max_duration`.
+#[derive(Debug, Clone, Copy)]
+pub struct LockoutPolicy {
+    pub max_failures: u32,
+    pub base_duration: Duration,
+    pub max_duration: Duration,
+}
+
+impl LockoutPolicy {
+    // How long to lock an account for after the given number of consecutive failures
+    pub fn lockout_duration(&self, failures: u32) -> Option<Duration> {
+        if self.max_failures == 0 || failures < self.max_failures {
+            return None;
+        }
+
+        let doublings = (failures - self.max_failures).min(30);
+        let duration = self
+            .base_duration
+            .checked_mul(1i32 << doublings)
+            .unwrap_or(self.max_duration);
+
+        Some(duration.min(self.max_duration))
+    }
+}
+
+impl Default for LockoutPolicy {
+    fn default() -> Self {
+        Self {
+            max_failures: DEFAULT_LOCKOUT_MAX_FAILURES,
+            base_duration: Duration::seconds(DEFAULT_LOCKOUT_BASE_SECONDS),
+            max_duration: Duration::seconds(DEFAULT_LOCKOUT_MAX_SECONDS),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> LockoutPolicy {
+        LockoutPolicy {
+            max_failures: 3,
+            base_duration: Duration::seconds(60),
+            max_duration: Duration::seconds(600),
+        }
+    }
+
+    #[test]
+    fn no_lockout_below_max_failures() {
+        assert_eq!(policy().lockout_duration(1), None);
+        assert_eq!(policy().lockout_duration(2), None);
+    }
+
+    #[test]
+    fn lockout_doubles_with_every_further_failure() {
+        assert_eq!(policy().lockout_duration(3), Some(Duration::seconds(60)));
+        assert_eq!(policy().lockout_duration(4), Some(Duration::seconds(120)));
+        assert_eq!(policy().lockout_duration(5), Some(Duration::seconds(240)));
+    }
+
+    #[test]
+    fn lockout_is_capped() {
+        assert_eq!(policy().lockout_duration(7), Some(Duration::seconds(600)));
+        assert_eq!(
+            policy().lockout_duration(u32::MAX),
+            Some(Duration::seconds(600))
+        );
+    }
+
+    #[test]
+    fn zero_max_failures_disables_lockout() {
+        let policy = LockoutPolicy {
+            max_failures: 0,
+            ..policy()
+        };
+        assert_eq!(policy.lockout_duration(100), None);
+    }
+
+    fn outbox() -> EmailOutboxSettings {
+        EmailOutboxSettings {
+            max_attempts: 5,
+            base_delay: Duration::seconds(30),
+            max_delay: Duration::seconds(100),
+            poll_interval: Duration::seconds(5),
+        }
+    }
+
+    #[test]
+    fn email_retries_back_off_exponentially_up_to_the_cap() {
+        assert_eq!(outbox().retry_delay(1), Some(Duration::seconds(30)));
+        assert_eq!(outbox().retry_delay(2), Some(Duration::seconds(60)));
+        assert_eq!(outbox().retry_delay(3), Some(Duration::seconds(100)));
+        assert_eq!(outbox().retry_delay(4), Some(Duration::seconds(100)));
+    }
+
+    #[test]
+    fn emails_are_given_up_on_after_max_attempts() {
+        assert_eq!(outbox().retry_delay(5), None);
+        assert_eq!(outbox().retry_delay(u32::MAX), None);
+    }
+
+    #[test]
+    fn signup_mode_parses_from_env_values() {
+        assert_eq!("open".parse::<SignupMode>().unwrap(), SignupMode::Open);
+        assert_eq!(
+            "invite_only".parse::<SignupMode>().unwrap(),
+            SignupMode::InviteOnly
+        );
+        assert!("closed".parse::<SignupMode>().is_err());
+    }
+
+    #[test]
+    fn email_provider_parses_from_env_values() {
+        assert_eq!(
+            "postmark".parse::<EmailProvider>().unwrap(),
+            EmailProvider::Postmark
+        );
+        assert_eq!(
+            "smtp".parse::<EmailProvider>().unwrap(),
+            EmailProvider::Smtp
+        );
+        assert!("sendgrid".parse::<EmailProvider>().is_err());
+    }
+
+    #[test]
+    fn two_fa_code_length_is_bounded() {
+        assert!(TwoFaCodeFormat::new(3, TwoFaCodeAlphabet::Numeric).is_err());
+        assert!(TwoFaCodeFormat::new(4, TwoFaCodeAlphabet::Numeric).is_ok());
+        assert!(TwoFaCodeFormat::new(12, TwoFaCodeAlphabet::CrockfordBase32).is_ok());
+        assert!(TwoFaCodeFormat::new(13, TwoFaCodeAlphabet::CrockfordBase32).is_err());
+    }
+}
@@ -0,0 +1,46 @@
+// Usage: cargo run --release --bin calibrate_argon2 -- [target_ms] [max_memory_kib] [parallelism]
+//
+// Prints the ARGON2_* environment variables that make a single password hash take roughly
+// `target_ms` milliseconds on this machine.
+use auth_service::utils::constants::env::{
+    ARGON2_M_COST_ENV_VAR, ARGON2_P_COST_ENV_VAR, ARGON2_T_COST_ENV_VAR,
+};
+use auth_service::utils::password_hashing::calibrate_argon2_params;
+use color_eyre::eyre::{Context, Result};
+use std::time::Duration;
+
+const DEFAULT_TARGET_MS: u64 = 500;
+const DEFAULT_MAX_M_COST: u32 = 256 * 1024;
+const DEFAULT_P_COST: u32 = 1;
+
+fn main() -> Result<()> {
+    color_eyre::install()?;
+
+    let mut args = std::env::args().skip(1);
+    let target_ms = match args.next() {
+        Some(arg) => arg.parse().wrap_err("Invalid target_ms")?,
+        None => DEFAULT_TARGET_MS,
+    };
+    let max_m_cost = match args.next() {
+        Some(arg) => arg.parse().wrap_err("Invalid max_memory_kib")?,
+        None => DEFAULT_MAX_M_COST,
+    };
+    let p_cost = match args.next() {
+        Some(arg) => arg.parse().wrap_err("Invalid parallelism")?,
+        None => DEFAULT_P_COST,
+    };
+
+    let (params, elapsed) =
+        calibrate_argon2_params(Duration::from_millis(target_ms), max_m_cost, p_cost)?;
+
+    eprintln!(
+        "Hashing took {} ms (target {} ms)",
+        elapsed.as_millis(),
+        target_ms
+    );
+    println!("{}={}", ARGON2_M_COST_ENV_VAR, params.m_cost());
+    println!("{}={}", ARGON2_T_COST_ENV_VAR, params.t_cost());
+    println!("{}={}", ARGON2_P_COST_ENV_VAR, params.p_cost());
+
+    Ok(())
+}
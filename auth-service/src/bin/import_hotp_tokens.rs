@@ -0,0 +1,80 @@
+// Usage: cargo run --release --bin import_hotp_tokens -- tokens.csv
+//
+// Imports hardware token seeds from the vendor's seed file. The CSV needs a header row with
+// the columns `serial` and `secret`, the seed in hex. The optional `digits` and `counter`
+// columns default to six digits and a counter of zero. Users enroll a token by entering its
+// serial and a code from it.
+use auth_service::data_stores::postgres_hotp_store::PostgresHotpStore;
+use auth_service::domain::{
+    data_store::{HotpStore, HotpStoreError},
+    hotp::{HotpSecret, HotpToken, DEFAULT_HOTP_DIGITS},
+};
+use auth_service::get_postgres_pool;
+use auth_service::utils::constants::DATABASE_URL;
+use color_eyre::eyre::{eyre, Context, Result};
+use secrecy::Secret;
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+struct HotpTokenRecord {
+    serial: String,
+    secret: String,
+    digits: Option<usize>,
+    counter: Option<u64>,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    color_eyre::install()?;
+
+    let path = std::env::args()
+        .nth(1)
+        .ok_or_else(|| eyre!("Usage: import_hotp_tokens <tokens.csv>"))?;
+
+    let pg_pool = get_postgres_pool(&DATABASE_URL)
+        .await
+        .wrap_err("Failed to create Postgres connection pool")?;
+    let mut hotp_store = PostgresHotpStore::new(pg_pool);
+
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_path(&path)
+        .wrap_err("Failed to open CSV file")?;
+    let (mut imported, mut skipped) = (0, 0);
+
+    for (line, record) in reader.deserialize::<HotpTokenRecord>().enumerate() {
+        // Line numbers are 1-based and the header takes the first line.
+        let line = line + 2;
+        let record = record.wrap_err_with(|| format!("Invalid record on line {}", line))?;
+
+        let token = HotpSecret::parse(Secret::new(record.secret)).and_then(|secret| {
+            HotpToken::new(
+                &record.serial,
+                secret,
+                record.digits.unwrap_or(DEFAULT_HOTP_DIGITS),
+                record.counter.unwrap_or(0),
+            )
+        });
+        let token = match token {
+            Ok(token) => token,
+            Err(e) => {
+                eprintln!("Skipping line {}: {}", line, e);
+                skipped += 1;
+                continue;
+            }
+        };
+
+        match hotp_store.add_token(token).await {
+            Ok(()) => imported += 1,
+            Err(HotpStoreError::TokenAlreadyExists) => {
+                eprintln!("Skipping line {}: token already exists", line);
+                skipped += 1;
+            }
+            Err(e) => return Err(e).wrap_err_with(|| format!("Failed to import line {}", line)),
+        }
+    }
+
+    println!("Imported {} tokens, skipped {}", imported, skipped);
+
+    Ok(())
+}
@@ -0,0 +1,77 @@
+// Usage: cargo run --release --bin import_legacy_users -- users.csv
+//
+// Imports users exported from the previous system. The CSV needs a header row with the
+// columns `email`, `password_hash` and `requires_2fa`. Supported hash formats are Argon2,
+// bcrypt, PBKDF2-SHA256 and scrypt; they are upgraded to Argon2id on the next login.
+use auth_service::argon2_password_hasher::Argon2PasswordHasher;
+use auth_service::data_stores::postgres_user_store::PostgresUserStore;
+use auth_service::domain::{data_store::UserStoreError, Email};
+use auth_service::get_postgres_pool;
+use auth_service::legacy_password_hasher::LegacyPasswordHasher;
+use auth_service::utils::constants::{ARGON2_PARAMS, DATABASE_URL, PASSWORD_HISTORY_SIZE};
+use color_eyre::eyre::{eyre, Context, Result};
+use secrecy::Secret;
+use serde::Deserialize;
+use std::sync::Arc;
+
+#[derive(Deserialize)]
+struct LegacyUserRecord {
+    email: String,
+    password_hash: String,
+    requires_2fa: bool,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    color_eyre::install()?;
+
+    let path = std::env::args()
+        .nth(1)
+        .ok_or_else(|| eyre!("Usage: import_legacy_users <users.csv>"))?;
+
+    let pg_pool = get_postgres_pool(&DATABASE_URL)
+        .await
+        .wrap_err("Failed to create Postgres connection pool")?;
+    let password_hasher = Arc::new(LegacyPasswordHasher::new(Argon2PasswordHasher::new(
+        ARGON2_PARAMS.clone(),
+    )));
+    let user_store = PostgresUserStore::new(pg_pool, password_hasher, *PASSWORD_HISTORY_SIZE);
+
+    let mut reader = csv::Reader::from_path(&path).wrap_err("Failed to open CSV file")?;
+    let (mut imported, mut skipped) = (0, 0);
+
+    for (line, record) in reader.deserialize::<LegacyUserRecord>().enumerate() {
+        // Line numbers are 1-based and the header takes the first line.
+        let line = line + 2;
+        let record = record.wrap_err_with(|| format!("Invalid record on line {}", line))?;
+
+        let email = match Email::parse(Secret::new(record.email)) {
+            Ok(email) => email,
+            Err(e) => {
+                eprintln!("Skipping line {}: {}", line, e);
+                skipped += 1;
+                continue;
+            }
+        };
+
+        match user_store
+            .import_user(
+                &email,
+                Secret::new(record.password_hash),
+                record.requires_2fa,
+            )
+            .await
+        {
+            Ok(()) => imported += 1,
+            Err(UserStoreError::UserAlreadyExists) => {
+                eprintln!("Skipping line {}: user already exists", line);
+                skipped += 1;
+            }
+            Err(e) => return Err(e).wrap_err_with(|| format!("Failed to import line {}", line)),
+        }
+    }
+
+    println!("Imported {} users, skipped {}", imported, skipped);
+
+    Ok(())
+}
@@ -0,0 +1,132 @@
+use serde::{Deserialize, Serialize};
+
+// How the user proved who they are at login, recorded in the auth token's `amr` claim. The
+// names follow RFC 8176 where it has one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthMethod {
+    Pwd,
+    // A one-time code from an authenticator app
+    Otp,
+    // A one-time code texted to the user's phone
+    Sms,
+    // A code from a hardware token
+    Hwk,
+    // One of the codes handed out when 2FA was set up
+    RecoveryCode,
+    // A passkey, which also verified the user, e.g. by fingerprint or PIN
+    Webauthn,
+    // The user's inbox: a login code or link sent instead of a password, or an emailed 2FA code
+    Email,
+    // A device remembered at an earlier 2FA login
+    Device,
+    // A second factor on top of a first one that was not recorded with the login attempt
+    Mfa,
+}
+
+// Authenticator assurance level of a login, recorded in the `acr` claim. Ordered, so a
+// requirement is met by its own level or any higher one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthLevel {
+    // A single factor
+    Aal1,
+    // Two independent factors, or a passkey with user verification
+    Aal2,
+}
+
+impl AuthLevel {
+    // Counts independent factors rather than methods, so two checks of the same inbox are one
+    // factor. A recovery code stands in for a lost second factor and only adds one to a
+    // password. A remembered device stands in for 2FA at login but is never counted, so it
+    // can't satisfy a step-up on its own.
+    pub fn from_methods(methods: &[AuthMethod]) -> Self {
+        let has = |method: AuthMethod| methods.contains(&method);
+        let multi_factor = has(AuthMethod::Mfa) || has(AuthMethod::Webauthn);
+        // Something the user holds other than their inbox
+        let possession = methods
+            .iter()
+            .any(|method| matches!(method, AuthMethod::Otp | AuthMethod::Sms | AuthMethod::Hwk));
+        let second_to_password =
+            possession || has(AuthMethod::Email) || has(AuthMethod::RecoveryCode);
+
+        if multi_factor
+            || (has(AuthMethod::Pwd) && second_to_password)
+            || (has(AuthMethod::Email) && possession)
+        {
+            AuthLevel::Aal2
+        } else {
+            AuthLevel::Aal1
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuthLevel::Aal1 => "aal1",
+            AuthLevel::Aal2 => "aal2",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_factor_is_aal1() {
+        assert_eq!(AuthLevel::from_methods(&[AuthMethod::Pwd]), AuthLevel::Aal1);
+        assert_eq!(
+            AuthLevel::from_methods(&[AuthMethod::Email]),
+            AuthLevel::Aal1
+        );
+    }
+
+    #[test]
+    fn two_factors_or_a_passkey_are_aal2() {
+        assert_eq!(
+            AuthLevel::from_methods(&[AuthMethod::Pwd, AuthMethod::Otp]),
+            AuthLevel::Aal2
+        );
+        assert_eq!(
+            AuthLevel::from_methods(&[AuthMethod::Otp, AuthMethod::Mfa]),
+            AuthLevel::Aal2
+        );
+        assert_eq!(
+            AuthLevel::from_methods(&[AuthMethod::Pwd, AuthMethod::Email]),
+            AuthLevel::Aal2
+        );
+        assert_eq!(
+            AuthLevel::from_methods(&[AuthMethod::Email, AuthMethod::Sms]),
+            AuthLevel::Aal2
+        );
+        assert_eq!(
+            AuthLevel::from_methods(&[AuthMethod::Webauthn]),
+            AuthLevel::Aal2
+        );
+    }
+
+    #[test]
+    fn the_inbox_counts_as_one_factor() {
+        assert_eq!(
+            AuthLevel::from_methods(&[AuthMethod::Email, AuthMethod::Email]),
+            AuthLevel::Aal1
+        );
+        assert_eq!(
+            AuthLevel::from_methods(&[AuthMethod::Email, AuthMethod::RecoveryCode]),
+            AuthLevel::Aal1
+        );
+    }
+
+    #[test]
+    fn remembered_device_does_not_count_as_a_factor() {
+        assert_eq!(
+            AuthLevel::from_methods(&[AuthMethod::Pwd, AuthMethod::Device]),
+            AuthLevel::Aal1
+        );
+    }
+
+    #[test]
+    fn higher_levels_satisfy_lower_requirements() {
+        assert!(AuthLevel::Aal2 > AuthLevel::Aal1);
+    }
+}
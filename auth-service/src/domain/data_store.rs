@@ -1,11 +1,28 @@
 // domain/data_store.rs
 use super::{Email, Password};
-use crate::domain::user::User;
+use crate::app_state::settings::{TwoFaCodeAlphabet, TwoFaCodeFormat};
+use crate::domain::authentication::AuthMethod;
+use crate::domain::email_outbox::{OutboxEmail, OutboxEmailId};
+use crate::domain::hotp::HotpToken;
+use crate::domain::invite::{Invite, InviteCode};
+use crate::domain::locale::Locale;
+use crate::domain::phone_number::{PhoneNumber, UserPhone};
+use crate::domain::recovery_code::RecoveryCode;
+use crate::domain::totp::{TotpEnrollment, TotpSecret};
+use crate::domain::trusted_device::{TrustedDevice, TrustedDeviceId};
+use crate::domain::two_factor::TwoFactorMethod;
+use crate::domain::user::{AccountStatus, StatusChange, User};
+use crate::domain::webauthn::{
+    CredentialId, WebAuthnCeremony, WebAuthnChallenge, WebAuthnCredential,
+};
+use chrono::{DateTime, Duration, Utc};
 use color_eyre::eyre::{eyre, Context, Report, Result};
 use rand::Rng;
 use secrecy::{ExposeSecret, Secret};
 use serde::{Deserialize, Serialize};
 use std::any::Any;
+use std::hash::{Hash, Hasher};
+use subtle::ConstantTimeEq;
 use thiserror::Error;
 use uuid::Uuid;
 
@@ -15,6 +32,43 @@ pub trait UserStore {
     async fn get_user(&self, email: &Email) -> Result<User, UserStoreError>;
     async fn validate_user(&self, email: &Email, password: &Password)
         -> Result<(), UserStoreError>;
+    // Replaces the user's password, refusing any of the most recent passwords kept in the
+    // store's password history (the current password included). Resets the password age and
+    // clears the must-change-password flag.
+    async fn update_password(
+        &mut self,
+        email: &Email,
+        new_password: Password,
+    ) -> Result<(), UserStoreError>;
+    async fn set_must_change_password(
+        &mut self,
+        email: &Email,
+        must_change_password: bool,
+    ) -> Result<(), UserStoreError>;
+    async fn set_require_2fa(
+        &mut self,
+        email: &Email,
+        require_2fa: bool,
+    ) -> Result<(), UserStoreError>;
+    async fn set_preferred_2fa_method(
+        &mut self,
+        email: &Email,
+        method: Option<TwoFactorMethod>,
+    ) -> Result<(), UserStoreError>;
+    async fn set_locale(
+        &mut self,
+        email: &Email,
+        locale: Option<Locale>,
+    ) -> Result<(), UserStoreError>;
+    // Changes the account status and records the change alongside the reason given
+    async fn set_status(
+        &mut self,
+        email: &Email,
+        status: AccountStatus,
+        reason: String,
+    ) -> Result<(), UserStoreError>;
+    // Returns the recorded status changes, most recent first
+    async fn get_status_history(&self, email: &Email) -> Result<Vec<StatusChange>, UserStoreError>;
 }
 
 #[derive(Debug, Error)]
@@ -25,6 +79,8 @@ pub enum UserStoreError {
     UserNotFound,
     #[error("Invalid user credentials")]
     InvalidCredentials,
+    #[error("Password was used recently")]
+    PasswordReused,
     #[error("Unexpected error")]
     UnexpectedError(#[source] Report),
 }
@@ -36,6 +92,7 @@ impl PartialEq for UserStoreError {
             (Self::UserAlreadyExists, Self::UserAlreadyExists)
                 | (Self::UserNotFound, Self::UserNotFound)
                 | (Self::InvalidCredentials, Self::InvalidCredentials)
+                | (Self::PasswordReused, Self::PasswordReused)
                 | (Self::UnexpectedError(_), Self::UnexpectedError(_))
         )
     }
@@ -57,19 +114,484 @@ pub enum BannedTokenStoreError {
     UnexpectedError(#[source] Report),
 }
 
+// Tracks consecutive failed logins per account, whether or not the account exists, so
+// locking out an address does not reveal that it is registered.
+#[async_trait::async_trait]
+pub trait LoginAttemptStore {
+    // Records a failed login and returns the number of consecutive failures so far
+    async fn record_failure(&mut self, email: &Email) -> Result<u32, LoginAttemptStoreError>;
+    async fn lock_account(
+        &mut self,
+        email: &Email,
+        until: DateTime<Utc>,
+    ) -> Result<(), LoginAttemptStoreError>;
+    // Returns when the current lockout ends, if the account is locked
+    async fn locked_until(
+        &self,
+        email: &Email,
+    ) -> Result<Option<DateTime<Utc>>, LoginAttemptStoreError>;
+    // Clears the failure count and any lockout after a successful login
+    async fn reset(&mut self, email: &Email) -> Result<(), LoginAttemptStoreError>;
+}
+
+#[derive(Debug, Error)]
+pub enum LoginAttemptStoreError {
+    #[error("Unexpected error")]
+    UnexpectedError(#[source] Report),
+}
+
+#[async_trait::async_trait]
+pub trait InviteStore {
+    async fn add_invite(&mut self, invite: Invite) -> Result<(), InviteStoreError>;
+    // Uses up one of the invite's uses for signing up with the given email. Checking and
+    // counting the use happen together, so concurrent signups cannot overspend an invite.
+    async fn consume_invite(
+        &mut self,
+        code: &InviteCode,
+        email: &Email,
+    ) -> Result<(), InviteStoreError>;
+    // Gives back a use taken by a signup that did not go through
+    async fn release_invite(&mut self, code: &InviteCode) -> Result<(), InviteStoreError>;
+}
+
+#[derive(Debug, Error)]
+pub enum InviteStoreError {
+    #[error("Invite not found")]
+    InviteNotFound,
+    #[error("Invite expired")]
+    InviteExpired,
+    #[error("Invite has no uses left")]
+    InviteExhausted,
+    #[error("Invite was issued for another email")]
+    EmailMismatch,
+    #[error("Unexpected error")]
+    UnexpectedError(#[source] Report),
+}
+
+impl PartialEq for InviteStoreError {
+    fn eq(&self, other: &Self) -> bool {
+        matches!(
+            (self, other),
+            (Self::InviteNotFound, Self::InviteNotFound)
+                | (Self::InviteExpired, Self::InviteExpired)
+                | (Self::InviteExhausted, Self::InviteExhausted)
+                | (Self::EmailMismatch, Self::EmailMismatch)
+                | (Self::UnexpectedError(_), Self::UnexpectedError(_))
+        )
+    }
+}
+
+// Authenticator app secrets. A secret only takes part in 2FA once the user has confirmed it
+// with a first code.
+#[async_trait::async_trait]
+pub trait TotpStore {
+    // Starts enrollment with a new unconfirmed secret, replacing any earlier unconfirmed one
+    async fn set_pending_secret(
+        &mut self,
+        email: &Email,
+        secret: TotpSecret,
+    ) -> Result<(), TotpStoreError>;
+    async fn get_enrollment(&self, email: &Email) -> Result<TotpEnrollment, TotpStoreError>;
+    async fn confirm(&mut self, email: &Email) -> Result<(), TotpStoreError>;
+    // Records the time step of an accepted code. Steps at or before the last recorded one are
+    // refused, so each code works only once.
+    async fn use_step(&mut self, email: &Email, step: u64) -> Result<(), TotpStoreError>;
+}
+
+#[derive(Debug, Error)]
+pub enum TotpStoreError {
+    #[error("No authenticator app enrolled")]
+    NotEnrolled,
+    #[error("Authenticator app already enrolled")]
+    AlreadyEnrolled,
+    #[error("Code was already used")]
+    CodeReused,
+    #[error("Unexpected error")]
+    UnexpectedError(#[source] Report),
+}
+
+impl PartialEq for TotpStoreError {
+    fn eq(&self, other: &Self) -> bool {
+        matches!(
+            (self, other),
+            (Self::NotEnrolled, Self::NotEnrolled)
+                | (Self::AlreadyEnrolled, Self::AlreadyEnrolled)
+                | (Self::CodeReused, Self::CodeReused)
+                | (Self::UnexpectedError(_), Self::UnexpectedError(_))
+        )
+    }
+}
+
+// Hardware tokens for counter-based codes. Tokens are imported from the vendor's seed file
+// without an owner and belong to a user once they have entered a code from one.
+#[async_trait::async_trait]
+pub trait HotpStore {
+    async fn add_token(&mut self, token: HotpToken) -> Result<(), HotpStoreError>;
+    // Fails with TokenNotFound for unknown serials and tokens someone already enrolled
+    async fn get_unassigned_token(&self, serial: &str) -> Result<HotpToken, HotpStoreError>;
+    // Hands the token to the user, with `counter` as the next one expected from it
+    async fn assign_token(
+        &mut self,
+        serial: &str,
+        email: &Email,
+        counter: u64,
+    ) -> Result<(), HotpStoreError>;
+    async fn get_token(&self, email: &Email) -> Result<HotpToken, HotpStoreError>;
+    // Moves the user's token on to `counter`. Counters at or before the current one are
+    // refused, so each code works only once.
+    async fn advance_counter(&mut self, email: &Email, counter: u64) -> Result<(), HotpStoreError>;
+}
+
+#[derive(Debug, Error)]
+pub enum HotpStoreError {
+    #[error("Token already exists")]
+    TokenAlreadyExists,
+    #[error("Token not found")]
+    TokenNotFound,
+    #[error("No hardware token enrolled")]
+    NotEnrolled,
+    #[error("Hardware token already enrolled")]
+    AlreadyEnrolled,
+    #[error("Code was already used")]
+    CodeReused,
+    #[error("Unexpected error")]
+    UnexpectedError(#[source] Report),
+}
+
+impl PartialEq for HotpStoreError {
+    fn eq(&self, other: &Self) -> bool {
+        matches!(
+            (self, other),
+            (Self::TokenAlreadyExists, Self::TokenAlreadyExists)
+                | (Self::TokenNotFound, Self::TokenNotFound)
+                | (Self::NotEnrolled, Self::NotEnrolled)
+                | (Self::AlreadyEnrolled, Self::AlreadyEnrolled)
+                | (Self::CodeReused, Self::CodeReused)
+                | (Self::UnexpectedError(_), Self::UnexpectedError(_))
+        )
+    }
+}
+
+// Phone numbers users have registered for receiving 2FA codes by SMS
+#[async_trait::async_trait]
+pub trait PhoneStore {
+    // Keeps `number` aside until it is verified, replacing any other number waiting to be. The
+    // verified number, if there is one, stays in use until then.
+    async fn set_pending_phone(
+        &mut self,
+        email: &Email,
+        number: PhoneNumber,
+    ) -> Result<(), PhoneStoreError>;
+    // The user's verified number. Fails with PhoneNotFound until a number has been verified.
+    async fn get_phone(&self, email: &Email) -> Result<UserPhone, PhoneStoreError>;
+    // Makes the pending number the verified one. Fails with PhoneNotFound without one.
+    async fn mark_verified(&mut self, email: &Email) -> Result<(), PhoneStoreError>;
+}
+
+#[derive(Debug, Error)]
+pub enum PhoneStoreError {
+    #[error("No phone number registered")]
+    PhoneNotFound,
+    #[error("Unexpected error")]
+    UnexpectedError(#[source] Report),
+}
+
+impl PartialEq for PhoneStoreError {
+    fn eq(&self, other: &Self) -> bool {
+        matches!(
+            (self, other),
+            (Self::PhoneNotFound, Self::PhoneNotFound)
+                | (Self::UnexpectedError(_), Self::UnexpectedError(_))
+        )
+    }
+}
+
+#[async_trait::async_trait]
+pub trait TrustedDeviceStore {
+    async fn add_device(
+        &mut self,
+        email: &Email,
+        device: TrustedDevice,
+    ) -> Result<(), TrustedDeviceStoreError>;
+    // Records a login from the device. Fails with DeviceNotFound if the device was revoked, has
+    // expired or belongs to someone else.
+    async fn touch_device(
+        &mut self,
+        email: &Email,
+        id: &TrustedDeviceId,
+    ) -> Result<(), TrustedDeviceStoreError>;
+    // The user's unexpired devices, most recently added first
+    async fn get_devices(
+        &self,
+        email: &Email,
+    ) -> Result<Vec<TrustedDevice>, TrustedDeviceStoreError>;
+    async fn revoke_device(
+        &mut self,
+        email: &Email,
+        id: &TrustedDeviceId,
+    ) -> Result<(), TrustedDeviceStoreError>;
+}
+
+#[derive(Debug, Error)]
+pub enum TrustedDeviceStoreError {
+    #[error("Trusted device not found")]
+    DeviceNotFound,
+    #[error("Unexpected error")]
+    UnexpectedError(#[source] Report),
+}
+
+impl PartialEq for TrustedDeviceStoreError {
+    fn eq(&self, other: &Self) -> bool {
+        matches!(
+            (self, other),
+            (Self::DeviceNotFound, Self::DeviceNotFound)
+                | (Self::UnexpectedError(_), Self::UnexpectedError(_))
+        )
+    }
+}
+
+#[async_trait::async_trait]
+pub trait RecoveryCodeStore {
+    // Replaces all of the user's recovery codes, used or not, with a new set
+    async fn replace_codes(
+        &mut self,
+        email: &Email,
+        codes: &[RecoveryCode],
+    ) -> Result<(), RecoveryCodeStoreError>;
+    // Uses up a code and returns how many unused codes the user has left
+    async fn use_code(
+        &mut self,
+        email: &Email,
+        code: &RecoveryCode,
+    ) -> Result<usize, RecoveryCodeStoreError>;
+    async fn count_unused(&self, email: &Email) -> Result<usize, RecoveryCodeStoreError>;
+}
+
+#[derive(Debug, Error)]
+pub enum RecoveryCodeStoreError {
+    #[error("Invalid or already used recovery code")]
+    InvalidCode,
+    #[error("Unexpected error")]
+    UnexpectedError(#[source] Report),
+}
+
+impl PartialEq for RecoveryCodeStoreError {
+    fn eq(&self, other: &Self) -> bool {
+        matches!(
+            (self, other),
+            (Self::InvalidCode, Self::InvalidCode)
+                | (Self::UnexpectedError(_), Self::UnexpectedError(_))
+        )
+    }
+}
+
+#[async_trait::async_trait]
+pub trait WebAuthnCredentialStore {
+    async fn add_credential(
+        &mut self,
+        email: &Email,
+        credential: WebAuthnCredential,
+    ) -> Result<(), WebAuthnCredentialStoreError>;
+    async fn get_credentials(
+        &self,
+        email: &Email,
+    ) -> Result<Vec<WebAuthnCredential>, WebAuthnCredentialStoreError>;
+    // Looks a credential up on its own, for logins where the user did not give an email
+    async fn get_credential(
+        &self,
+        id: &CredentialId,
+    ) -> Result<(Email, WebAuthnCredential), WebAuthnCredentialStoreError>;
+    // Only succeeds if the counter is still `previous`, so that of two logins racing with
+    // responses from a cloned authenticator only one gets through
+    async fn update_sign_count(
+        &mut self,
+        id: &CredentialId,
+        previous: u32,
+        new: u32,
+    ) -> Result<(), WebAuthnCredentialStoreError>;
+}
+
+#[derive(Debug, Error)]
+pub enum WebAuthnCredentialStoreError {
+    #[error("Credential not found")]
+    CredentialNotFound,
+    #[error("Credential already registered")]
+    CredentialAlreadyExists,
+    #[error("Signature counter changed concurrently")]
+    SignCountChanged,
+    #[error("Unexpected error")]
+    UnexpectedError(#[source] Report),
+}
+
+impl PartialEq for WebAuthnCredentialStoreError {
+    fn eq(&self, other: &Self) -> bool {
+        matches!(
+            (self, other),
+            (Self::CredentialNotFound, Self::CredentialNotFound)
+                | (Self::CredentialAlreadyExists, Self::CredentialAlreadyExists)
+                | (Self::SignCountChanged, Self::SignCountChanged)
+                | (Self::UnexpectedError(_), Self::UnexpectedError(_))
+        )
+    }
+}
+
+// Pending registration and authentication ceremonies, keyed by their challenge
+#[async_trait::async_trait]
+pub trait WebAuthnCeremonyStore {
+    async fn add_ceremony(
+        &mut self,
+        ceremony: WebAuthnCeremony,
+        ttl: Duration,
+    ) -> Result<(), WebAuthnCeremonyStoreError>;
+    // Removes the ceremony, so each challenge is only answered once
+    async fn take_ceremony(
+        &mut self,
+        challenge: &WebAuthnChallenge,
+    ) -> Result<WebAuthnCeremony, WebAuthnCeremonyStoreError>;
+}
+
+#[derive(Debug, Error)]
+pub enum WebAuthnCeremonyStoreError {
+    #[error("Ceremony not found or expired")]
+    CeremonyNotFound,
+    #[error("Unexpected error")]
+    UnexpectedError(#[source] Report),
+}
+
+impl PartialEq for WebAuthnCeremonyStoreError {
+    fn eq(&self, other: &Self) -> bool {
+        matches!(
+            (self, other),
+            (Self::CeremonyNotFound, Self::CeremonyNotFound)
+                | (Self::UnexpectedError(_), Self::UnexpectedError(_))
+        )
+    }
+}
+
+// Emails waiting to be delivered by the outbox worker
+#[async_trait::async_trait]
+pub trait EmailOutboxStore {
+    // Queuing an email under a key that was already used does nothing
+    async fn enqueue(&mut self, email: OutboxEmail) -> Result<(), EmailOutboxStoreError>;
+    // Up to `limit` pending emails that are due, oldest first. They are held back from further
+    // claims for `lease`, after which they come up again unless marked sent or failed. Pending
+    // emails that have expired are never claimed, but moved to the dead letters with their
+    // bodies emptied.
+    async fn claim_due(
+        &mut self,
+        limit: usize,
+        lease: Duration,
+    ) -> Result<Vec<OutboxEmail>, EmailOutboxStoreError>;
+    async fn mark_sent(&mut self, id: &OutboxEmailId) -> Result<(), EmailOutboxStoreError>;
+    // Records a failed attempt. The email is tried again at `retry_at`, or moved to the dead
+    // letters when there is none.
+    async fn mark_failed(
+        &mut self,
+        id: &OutboxEmailId,
+        error: &str,
+        retry_at: Option<DateTime<Utc>>,
+    ) -> Result<(), EmailOutboxStoreError>;
+    // Newest first
+    async fn get_dead_letters(&self) -> Result<Vec<OutboxEmail>, EmailOutboxStoreError>;
+    // Puts a dead letter back in the queue with its attempts reset. Fails with EmailExpired once
+    // the codes or links in it have stopped working.
+    async fn replay(&mut self, id: &OutboxEmailId) -> Result<(), EmailOutboxStoreError>;
+    // Empties the bodies of dead letters that have expired, so codes and links are not kept
+    // around in the clear. Returns how many were emptied.
+    async fn clear_expired_dead_letters(&mut self) -> Result<usize, EmailOutboxStoreError>;
+}
+
+#[derive(Debug, Error)]
+pub enum EmailOutboxStoreError {
+    #[error("Email not found")]
+    EmailNotFound,
+    #[error("Email has expired")]
+    EmailExpired,
+    #[error("Unexpected error")]
+    UnexpectedError(#[source] Report),
+}
+
+impl PartialEq for EmailOutboxStoreError {
+    fn eq(&self, other: &Self) -> bool {
+        matches!(
+            (self, other),
+            (Self::EmailNotFound, Self::EmailNotFound)
+                | (Self::EmailExpired, Self::EmailExpired)
+                | (Self::UnexpectedError(_), Self::UnexpectedError(_))
+        )
+    }
+}
+
 #[async_trait::async_trait]
 pub trait TwoFaCodeStore {
+    // Each login attempt gets its own code, so a user can have several logins in flight at once.
+    // Once the user has `max_pending` attempts outstanding the oldest ones are dropped to make
+    // room.
     async fn add_code(
         &mut self,
         email: &Email,
         login_attempt_id: LoginAttemptId,
         code: TwoFACode,
+        max_pending: u32,
+    ) -> Result<(), TwoFaCodeStoreError>;
+    async fn remove_code(
+        &mut self,
+        email: &Email,
+        login_attempt_id: &LoginAttemptId,
     ) -> Result<(), TwoFaCodeStoreError>;
-    async fn remove_code(&mut self, email: &Email) -> Result<(), TwoFaCodeStoreError>;
+    // Fails with LoginAttempIdNotFound unless the attempt exists and belongs to `email`
     async fn get_code(
         &self,
         email: &Email,
-    ) -> Result<(LoginAttemptId, TwoFACode), TwoFaCodeStoreError>;
+        login_attempt_id: &LoginAttemptId,
+    ) -> Result<TwoFACode, TwoFaCodeStoreError>;
+    // The user's outstanding login attempts, oldest first
+    async fn get_login_attempts(
+        &self,
+        email: &Email,
+    ) -> Result<Vec<LoginAttemptId>, TwoFaCodeStoreError>;
+    // Counts a wrong guess against the attempt's code and returns how many guesses are left. The
+    // guess that uses up `max_attempts` removes the code and fails with TooManyAttempts, so the
+    // user has to ask for a new one.
+    async fn record_failed_attempt(
+        &mut self,
+        email: &Email,
+        login_attempt_id: &LoginAttemptId,
+        max_attempts: u32,
+    ) -> Result<u32, TwoFaCodeStoreError>;
+    // Swaps in a new code for a resend. The login attempt and its wrong guesses carry over, and
+    // the code's expiry starts again. Fails with ResendTooSoon within `cooldown` of the last
+    // send and with TooManyResends once `max_resends` resends have been made.
+    async fn rotate_code(
+        &mut self,
+        email: &Email,
+        login_attempt_id: &LoginAttemptId,
+        code: TwoFACode,
+        cooldown: Duration,
+        max_resends: u32,
+    ) -> Result<(), TwoFaCodeStoreError>;
+    // Records how the login attempt was started, e.g. with a password, so the second factor can
+    // be added to it in the auth token
+    async fn set_first_factor(
+        &mut self,
+        email: &Email,
+        login_attempt_id: &LoginAttemptId,
+        method: AuthMethod,
+    ) -> Result<(), TwoFaCodeStoreError>;
+    // None when the attempt was added without one
+    async fn get_first_factor(
+        &self,
+        email: &Email,
+        login_attempt_id: &LoginAttemptId,
+    ) -> Result<Option<AuthMethod>, TwoFaCodeStoreError>;
+    // Records a send to `email` for a new login attempt. Fails with ResendTooSoon within
+    // `cooldown` of the last one, so requests for the same address can't flood the inbox or
+    // crowd the user's own attempts out of the store.
+    async fn claim_send(
+        &mut self,
+        email: &Email,
+        cooldown: Duration,
+    ) -> Result<(), TwoFaCodeStoreError>;
 }
 
 #[derive(Debug, Error)]
@@ -78,10 +600,14 @@ pub enum TwoFaCodeStoreError {
     LoginAttempIdNotFound,
     #[error("Unexpectd Error")]
     UnexpectedError(#[source] Report),
-    #[error("User Already had a code")]
-    UserHasCode,
     #[error("User code not found")]
     CodeNotFound,
+    #[error("Too many incorrect codes")]
+    TooManyAttempts,
+    #[error("Code was sent too recently")]
+    ResendTooSoon,
+    #[error("Code was resent too many times")]
+    TooManyResends,
 }
 
 impl PartialEq for TwoFaCodeStoreError {
@@ -90,6 +616,10 @@ impl PartialEq for TwoFaCodeStoreError {
             (self, other),
             (Self::LoginAttempIdNotFound, Self::LoginAttempIdNotFound)
                 | (Self::UnexpectedError(_), Self::UnexpectedError(_))
+                | (Self::CodeNotFound, Self::CodeNotFound)
+                | (Self::TooManyAttempts, Self::TooManyAttempts)
+                | (Self::ResendTooSoon, Self::ResendTooSoon)
+                | (Self::TooManyResends, Self::TooManyResends)
         )
     }
 }
@@ -118,6 +648,14 @@ impl PartialEq for LoginAttemptId {
     }
 }
 
+impl Eq for LoginAttemptId {}
+
+impl Hash for LoginAttemptId {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.expose_secret().hash(state);
+    }
+}
+
 impl AsRef<Secret<String>> for LoginAttemptId {
     fn as_ref(&self) -> &Secret<String> {
         &self.0
@@ -127,9 +665,15 @@ impl AsRef<Secret<String>> for LoginAttemptId {
 #[derive(Debug, Clone, Deserialize)]
 pub struct TwoFACode(Secret<String>);
 
+// Constant-time, so response timing does not reveal how many leading digits of a guess match
 impl PartialEq for TwoFACode {
     fn eq(&self, other: &Self) -> bool {
-        self.0.expose_secret() == other.0.expose_secret()
+        bool::from(
+            self.0
+                .expose_secret()
+                .as_bytes()
+                .ct_eq(other.0.expose_secret().as_bytes()),
+        )
     }
 }
 
@@ -140,24 +684,103 @@ impl AsRef<Secret<String>> for TwoFACode {
 }
 
 impl TwoFACode {
-    pub fn parse(code: Secret<String>) -> Result<Self> {
-        let code_as_u32 = code
-            .expose_secret()
-            .parse::<u32>()
-            .wrap_err("Invalid 2FA code")?;
-
-        if (100_000..=999_999).contains(&code_as_u32) {
-            Ok(Self(code))
-        } else {
-            Err(eyre!("Invalid 2FA code"))
+    // Accepts codes in `format`. Base32 codes are accepted in any case and with the letters
+    // Crockford reads as digits.
+    pub fn parse(code: Secret<String>, format: &TwoFaCodeFormat) -> Result<Self> {
+        let normalized: String = match format.alphabet {
+            TwoFaCodeAlphabet::Numeric => code.expose_secret().to_owned(),
+            TwoFaCodeAlphabet::CrockfordBase32 => code
+                .expose_secret()
+                .chars()
+                .map(|c| match c.to_ascii_uppercase() {
+                    'O' => '0',
+                    'I' | 'L' => '1',
+                    c => c,
+                })
+                .collect(),
+        };
+
+        let characters = format.alphabet.characters();
+        if normalized.len() != format.length || !normalized.bytes().all(|b| characters.contains(&b))
+        {
+            return Err(eyre!("Invalid 2FA code"));
         }
+        Ok(Self(Secret::new(normalized)))
     }
-}
 
-impl Default for TwoFACode {
-    fn default() -> Self {
+    pub fn generate(format: &TwoFaCodeFormat) -> Self {
+        let characters = format.alphabet.characters();
         let mut rng = rand::rng();
-        let code = rng.random_range(100_000..=999_999); // Generate 6-digit number
-        Self(Secret::new(code.to_string()))
+        let code = (0..format.length)
+            .map(|_| characters[rng.random_range(0..characters.len())] as char)
+            .collect();
+        Self(Secret::new(code))
+    }
+
+    // A code read back from a store, taken as is as it was checked when it was generated. Codes
+    // entered by users are parsed with the current format, so after a change of format a code
+    // sent before it only still matches if it is valid in the new format too. Otherwise the user
+    // has to ask for a new one.
+    pub fn from_stored(code: Secret<String>) -> Self {
+        Self(code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(length: usize, alphabet: TwoFaCodeAlphabet) -> TwoFaCodeFormat {
+        TwoFaCodeFormat::new(length, alphabet).unwrap()
+    }
+
+    #[test]
+    fn generated_codes_follow_the_format() {
+        for format in [
+            format(6, TwoFaCodeAlphabet::Numeric),
+            format(8, TwoFaCodeAlphabet::CrockfordBase32),
+        ] {
+            let code = TwoFACode::generate(&format);
+            assert_eq!(code.as_ref().expose_secret().len(), format.length);
+            assert!(TwoFACode::parse(code.as_ref().clone(), &format).is_ok());
+        }
+    }
+
+    #[test]
+    fn codes_of_another_length_or_alphabet_are_rejected() {
+        let numeric = format(6, TwoFaCodeAlphabet::Numeric);
+        for code in ["12345", "1234567", "12345a", " 12345", ""] {
+            assert!(TwoFACode::parse(Secret::new(code.to_owned()), &numeric).is_err());
+        }
+
+        let base32 = format(6, TwoFaCodeAlphabet::CrockfordBase32);
+        assert!(TwoFACode::parse(Secret::new("ABCDEU".to_owned()), &base32).is_err());
+    }
+
+    #[test]
+    fn base32_codes_are_read_the_crockford_way() {
+        let base32 = format(6, TwoFaCodeAlphabet::CrockfordBase32);
+        let code = TwoFACode::parse(Secret::new("a1b2c3".to_owned()), &base32).unwrap();
+        assert_eq!(code.as_ref().expose_secret(), "A1B2C3");
+
+        let typed = TwoFACode::parse(Secret::new("oiL9zz".to_owned()), &base32).unwrap();
+        let sent = TwoFACode::parse(Secret::new("0119ZZ".to_owned()), &base32).unwrap();
+        assert_eq!(typed, sent);
+    }
+
+    #[test]
+    fn codes_sent_before_a_format_change_only_match_if_still_valid() {
+        let numeric = format(6, TwoFaCodeAlphabet::Numeric);
+        let sent = TwoFACode::generate(&numeric);
+        let stored = TwoFACode::from_stored(sent.as_ref().clone());
+
+        // Digits are valid base32, so only changing the alphabet keeps the code working
+        let base32 = format(6, TwoFaCodeAlphabet::CrockfordBase32);
+        let entered = TwoFACode::parse(sent.as_ref().clone(), &base32).unwrap();
+        assert_eq!(entered, stored);
+
+        // A code of the old length can't even be entered any more
+        let longer = format(8, TwoFaCodeAlphabet::Numeric);
+        assert!(TwoFACode::parse(sent.as_ref().clone(), &longer).is_err());
     }
 }
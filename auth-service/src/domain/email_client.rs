@@ -1,7 +1,15 @@
 use super::Email;
 use color_eyre::eyre::Result;
 
+// A rendered email, ready to be handed to the email provider
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EmailMessage {
+    pub subject: String,
+    pub html_body: String,
+    pub text_body: String,
+}
+
 #[async_trait::async_trait]
 pub trait EmailClient {
-    async fn send_email(&self, recipient: &Email, subject: &str, content: &str) -> Result<()>;
+    async fn send_email(&self, recipient: &Email, message: &EmailMessage) -> Result<()>;
 }
@@ -0,0 +1,177 @@
+use crate::app_state::settings::EmailDomainSettings;
+use crate::domain::Email;
+use color_eyre::eyre::{Context, Result};
+use secrecy::ExposeSecret;
+use std::collections::HashSet;
+use std::path::Path;
+use thiserror::Error;
+
+const DISPOSABLE_EMAIL_DOMAINS: &str = include_str!("disposable_email_domains.txt");
+
+// Decides which email domains may be used to sign up. A domain listed in a set also covers
+// its subdomains.
+#[derive(Debug, Clone, Default)]
+pub struct EmailDomainPolicy {
+    // When not empty, only these domains may sign up
+    allowed: HashSet<String>,
+    blocked: HashSet<String>,
+    disposable: HashSet<String>,
+}
+
+#[derive(Debug, Error, Clone, Copy, PartialEq)]
+pub enum EmailDomainRejection {
+    #[error("Email domain is not allowed")]
+    NotAllowed,
+    #[error("Email domain is blocked")]
+    Blocked,
+    #[error("Disposable email addresses are not allowed")]
+    Disposable,
+}
+
+impl EmailDomainPolicy {
+    pub fn new(
+        allowed: impl IntoIterator<Item = String>,
+        blocked: impl IntoIterator<Item = String>,
+        block_disposable: bool,
+    ) -> Self {
+        Self {
+            allowed: allowed.into_iter().map(|d| d.to_lowercase()).collect(),
+            blocked: blocked.into_iter().map(|d| d.to_lowercase()).collect(),
+            disposable: if block_disposable {
+                parse_domain_list(DISPOSABLE_EMAIL_DOMAINS).collect()
+            } else {
+                HashSet::new()
+            },
+        }
+    }
+
+    // Reads the configured list files. Called at startup and again whenever an admin asks
+    // for the lists to be reloaded.
+    pub fn load(settings: &EmailDomainSettings) -> Result<Self> {
+        let allowed = match &settings.allowlist_path {
+            Some(path) => read_domain_list(path)?,
+            None => Vec::new(),
+        };
+        let blocked = match &settings.blocklist_path {
+            Some(path) => read_domain_list(path)?,
+            None => Vec::new(),
+        };
+
+        Ok(Self::new(allowed, blocked, settings.block_disposable))
+    }
+
+    pub fn check(&self, email: &Email) -> Result<(), EmailDomainRejection> {
+        let address = email.as_ref().expose_secret().to_lowercase();
+        let domain = address.rsplit('@').next().unwrap_or_default();
+
+        if matches_any(&self.blocked, domain) {
+            return Err(EmailDomainRejection::Blocked);
+        }
+        if matches_any(&self.disposable, domain) {
+            return Err(EmailDomainRejection::Disposable);
+        }
+        if !self.allowed.is_empty() && !matches_any(&self.allowed, domain) {
+            return Err(EmailDomainRejection::NotAllowed);
+        }
+
+        Ok(())
+    }
+
+    pub fn allowed_count(&self) -> usize {
+        self.allowed.len()
+    }
+
+    pub fn blocked_count(&self) -> usize {
+        self.blocked.len()
+    }
+}
+
+// Checks the domain itself and every parent domain, so `mail.example.com` matches an entry
+// for `example.com`
+fn matches_any(domains: &HashSet<String>, domain: &str) -> bool {
+    let mut candidate = domain;
+    loop {
+        if domains.contains(candidate) {
+            return true;
+        }
+        match candidate.split_once('.') {
+            Some((_, parent)) if parent.contains('.') => candidate = parent,
+            _ => return false,
+        }
+    }
+}
+
+fn read_domain_list(path: &Path) -> Result<Vec<String>> {
+    let contents = std::fs::read_to_string(path)
+        .wrap_err_with(|| format!("Failed to read email domain list {}", path.display()))?;
+    Ok(parse_domain_list(&contents).collect())
+}
+
+// One domain per line; blank lines and `#` comments are skipped
+fn parse_domain_list(contents: &str) -> impl Iterator<Item = String> + '_ {
+    contents
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| line.trim_start_matches('@').to_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use secrecy::Secret;
+
+    fn email(address: &str) -> Email {
+        Email::parse(Secret::new(address.to_owned())).unwrap()
+    }
+
+    #[test]
+    fn allowlist_restricts_signups_to_listed_domains() {
+        let policy = EmailDomainPolicy::new(vec!["ourcompany.com".to_owned()], vec![], false);
+
+        assert!(policy.check(&email("jane@ourcompany.com")).is_ok());
+        assert!(policy.check(&email("jane@Eng.OurCompany.com")).is_ok());
+        assert_eq!(
+            policy.check(&email("jane@gmail.com")),
+            Err(EmailDomainRejection::NotAllowed)
+        );
+        assert_eq!(
+            policy.check(&email("jane@notourcompany.com")),
+            Err(EmailDomainRejection::NotAllowed)
+        );
+    }
+
+    #[test]
+    fn blocklist_rejects_domain_and_subdomains() {
+        let policy = EmailDomainPolicy::new(vec![], vec!["spam.example".to_owned()], false);
+
+        assert_eq!(
+            policy.check(&email("x@spam.example")),
+            Err(EmailDomainRejection::Blocked)
+        );
+        assert_eq!(
+            policy.check(&email("x@a.spam.example")),
+            Err(EmailDomainRejection::Blocked)
+        );
+        assert!(policy.check(&email("x@example.com")).is_ok());
+    }
+
+    #[test]
+    fn bundled_disposable_domains_are_rejected_when_enabled() {
+        let policy = EmailDomainPolicy::new(vec![], vec![], true);
+        assert_eq!(
+            policy.check(&email("x@mailinator.com")),
+            Err(EmailDomainRejection::Disposable)
+        );
+
+        let policy = EmailDomainPolicy::new(vec![], vec![], false);
+        assert!(policy.check(&email("x@mailinator.com")).is_ok());
+    }
+
+    #[test]
+    fn domain_lists_skip_comments_and_blank_lines() {
+        let domains: Vec<String> =
+            parse_domain_list("# comment\n\nExample.com  # trailing\n@other.org\n").collect();
+        assert_eq!(domains, vec!["example.com", "other.org"]);
+    }
+}
@@ -0,0 +1,192 @@
+use super::{Email, EmailMessage};
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{eyre, Context, Report, Result};
+use secrecy::ExposeSecret;
+use sha2::{Digest, Sha256};
+use std::str::FromStr;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct OutboxEmailId(Uuid);
+
+impl OutboxEmailId {
+    pub fn parse(id: &str) -> Result<Self> {
+        Uuid::parse_str(id)
+            .map(Self)
+            .wrap_err("Invalid outbox email id")
+    }
+}
+
+impl Default for OutboxEmailId {
+    fn default() -> Self {
+        Self(Uuid::new_v4())
+    }
+}
+
+impl std::fmt::Display for OutboxEmailId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+// Names the event an email is about. An email queued again for the same event, e.g. when a
+// request is retried, is dropped instead of reaching the user twice.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct IdempotencyKey(String);
+
+impl IdempotencyKey {
+    // Hashes the parts, so codes and tokens that identify the event aren't stored in the clear
+    pub fn derive(recipient: &Email, parts: &[&str]) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(recipient.as_ref().expose_secret().as_bytes());
+        for part in parts {
+            // Length prefixes keep ["ab", "c"] and ["a", "bc"] apart
+            hasher.update((part.len() as u64).to_be_bytes());
+            hasher.update(part.as_bytes());
+        }
+        Self(hex::encode(hasher.finalize()))
+    }
+}
+
+impl From<String> for IdempotencyKey {
+    fn from(key: String) -> Self {
+        Self(key)
+    }
+}
+
+impl AsRef<str> for IdempotencyKey {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutboxStatus {
+    Pending,
+    Sent,
+    // Every attempt failed. The email stays put until an admin replays it.
+    Dead,
+}
+
+impl OutboxStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OutboxStatus::Pending => "pending",
+            OutboxStatus::Sent => "sent",
+            OutboxStatus::Dead => "dead",
+        }
+    }
+}
+
+impl FromStr for OutboxStatus {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pending" => Ok(Self::Pending),
+            "sent" => Ok(Self::Sent),
+            "dead" => Ok(Self::Dead),
+            _ => Err(eyre!("Unknown outbox status: {}", s)),
+        }
+    }
+}
+
+// A rendered email waiting in the outbox for the delivery worker
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutboxEmail {
+    pub id: OutboxEmailId,
+    pub idempotency_key: IdempotencyKey,
+    pub recipient: Email,
+    pub message: EmailMessage,
+    pub status: OutboxStatus,
+    // Failed deliveries so far
+    pub attempts: u32,
+    pub next_attempt_at: DateTime<Utc>,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    // When the codes or links in the email stop working. An email still pending then is
+    // dead-lettered instead of sent, a dead letter can't be replayed after it, and in both cases
+    // the body is dropped.
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+// Recorded as the last error of emails whose codes or links ran out before they were delivered
+pub const EXPIRED_BEFORE_SENDING: &str = "Expired before it could be sent";
+
+impl OutboxEmail {
+    pub fn new(
+        recipient: Email,
+        message: EmailMessage,
+        idempotency_key: IdempotencyKey,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Self {
+        let now = Utc::now();
+        Self {
+            id: OutboxEmailId::default(),
+            idempotency_key,
+            recipient,
+            message,
+            status: OutboxStatus::Pending,
+            attempts: 0,
+            next_attempt_at: now,
+            last_error: None,
+            created_at: now,
+            expires_at,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use secrecy::Secret;
+
+    fn email(address: &str) -> Email {
+        Email::parse(Secret::new(address.to_owned())).unwrap()
+    }
+
+    #[test]
+    fn keys_differ_by_recipient_and_parts() {
+        let alice = email("alice@example.com");
+        let key = IdempotencyKey::derive(&alice, &["two_factor_code", "attempt", "123456"]);
+
+        assert_eq!(
+            key,
+            IdempotencyKey::derive(&alice, &["two_factor_code", "attempt", "123456"])
+        );
+        assert_ne!(
+            key,
+            IdempotencyKey::derive(
+                &email("bob@example.com"),
+                &["two_factor_code", "attempt", "123456"]
+            )
+        );
+        assert_ne!(
+            key,
+            IdempotencyKey::derive(&alice, &["two_factor_code", "attempt", "654321"])
+        );
+        assert_ne!(
+            IdempotencyKey::derive(&alice, &["ab", "c"]),
+            IdempotencyKey::derive(&alice, &["a", "bc"])
+        );
+    }
+
+    #[test]
+    fn keys_do_not_contain_the_parts() {
+        let key = IdempotencyKey::derive(&email("alice@example.com"), &["123456"]);
+        assert!(!key.as_ref().contains("123456"));
+        assert_eq!(key.as_ref().len(), 64);
+    }
+
+    #[test]
+    fn status_round_trips_through_its_string_form() {
+        for status in [
+            OutboxStatus::Pending,
+            OutboxStatus::Sent,
+            OutboxStatus::Dead,
+        ] {
+            assert_eq!(status.as_str().parse::<OutboxStatus>().unwrap(), status);
+        }
+        assert!("queued".parse::<OutboxStatus>().is_err());
+    }
+}
@@ -0,0 +1,615 @@
+use crate::app_state::settings::EmailTemplateSettings;
+use crate::domain::email_client::EmailMessage;
+use crate::domain::locale::Locale;
+use chrono::{DateTime, Duration, Utc};
+use color_eyre::eyre::{eyre, Context, Result};
+use std::collections::{hash_map::Entry, HashMap};
+use std::path::Path;
+
+// The emails the service sends, each with the values its templates can use
+#[derive(Debug, Clone)]
+pub enum TransactionalEmail {
+    TwoFactorCode {
+        code: String,
+        expires_in_minutes: i64,
+    },
+    LoginCode {
+        code: String,
+        expires_in_minutes: i64,
+    },
+    MagicLink {
+        link: String,
+        expires_in_minutes: i64,
+    },
+    PasswordReset {
+        token: String,
+        expires_in_minutes: i64,
+    },
+    AccountLocked {
+        failures: u32,
+        locked_until: DateTime<Utc>,
+    },
+    LowRecoveryCodes {
+        remaining: usize,
+    },
+    // Only ever texted, to a number the user has just registered, so it has no email templates
+    PhoneVerificationCode {
+        code: String,
+        expires_in_minutes: i64,
+    },
+}
+
+// Template names with the variables each may use
+const MESSAGES: [(&str, &[&str]); 6] = [
+    ("two_factor_code", &["code", "expires_in_minutes"]),
+    ("login_code", &["code", "expires_in_minutes"]),
+    ("magic_link", &["link", "expires_in_minutes"]),
+    ("password_reset", &["token", "expires_in_minutes"]),
+    ("account_locked", &["failures", "locked_until"]),
+    ("low_recovery_codes", &["remaining"]),
+];
+
+macro_rules! built_in {
+    ($name:literal) => {
+        (
+            $name,
+            include_str!(concat!("email_templates/en/", $name, ".subject.txt")),
+            include_str!(concat!("email_templates/en/", $name, ".txt")),
+            include_str!(concat!("email_templates/en/", $name, ".html")),
+        )
+    };
+}
+
+// English templates compiled into the binary, used for anything the template directories
+// don't provide
+const BUILT_IN: [(&str, &str, &str, &str); 6] = [
+    built_in!("two_factor_code"),
+    built_in!("login_code"),
+    built_in!("magic_link"),
+    built_in!("password_reset"),
+    built_in!("account_locked"),
+    built_in!("low_recovery_codes"),
+];
+
+// Messages that can be texted, from a `<name>.sms.txt` template next to the email ones, with
+// the variables used by those that are never emailed
+const SMS_MESSAGES: [(&str, &[&str]); 2] = [
+    ("two_factor_code", &[]),
+    ("phone_verification_code", &["code", "expires_in_minutes"]),
+];
+
+const BUILT_IN_SMS: [(&str, &str); 2] = [
+    (
+        "two_factor_code",
+        include_str!("email_templates/en/two_factor_code.sms.txt"),
+    ),
+    (
+        "phone_verification_code",
+        include_str!("email_templates/en/phone_verification_code.sms.txt"),
+    ),
+];
+
+impl TransactionalEmail {
+    fn template_name(&self) -> &'static str {
+        match self {
+            Self::TwoFactorCode { .. } => "two_factor_code",
+            Self::LoginCode { .. } => "login_code",
+            Self::MagicLink { .. } => "magic_link",
+            Self::PasswordReset { .. } => "password_reset",
+            Self::AccountLocked { .. } => "account_locked",
+            Self::LowRecoveryCodes { .. } => "low_recovery_codes",
+            Self::PhoneVerificationCode { .. } => "phone_verification_code",
+        }
+    }
+
+    // How long the codes or links in the email keep working. Emails without any don't expire.
+    pub fn expires_in(&self) -> Option<Duration> {
+        match self {
+            Self::TwoFactorCode {
+                expires_in_minutes, ..
+            }
+            | Self::LoginCode {
+                expires_in_minutes, ..
+            }
+            | Self::MagicLink {
+                expires_in_minutes, ..
+            }
+            | Self::PasswordReset {
+                expires_in_minutes, ..
+            }
+            | Self::PhoneVerificationCode {
+                expires_in_minutes, ..
+            } => Some(Duration::minutes(*expires_in_minutes)),
+            Self::AccountLocked { .. } | Self::LowRecoveryCodes { .. } => None,
+        }
+    }
+
+    fn variables(&self) -> Vec<(&'static str, String)> {
+        match self {
+            Self::TwoFactorCode {
+                code,
+                expires_in_minutes,
+            }
+            | Self::LoginCode {
+                code,
+                expires_in_minutes,
+            }
+            | Self::PhoneVerificationCode {
+                code,
+                expires_in_minutes,
+            } => vec![
+                ("code", code.clone()),
+                ("expires_in_minutes", expires_in_minutes.to_string()),
+            ],
+            Self::MagicLink {
+                link,
+                expires_in_minutes,
+            } => vec![
+                ("link", link.clone()),
+                ("expires_in_minutes", expires_in_minutes.to_string()),
+            ],
+            Self::PasswordReset {
+                token,
+                expires_in_minutes,
+            } => vec![
+                ("token", token.clone()),
+                ("expires_in_minutes", expires_in_minutes.to_string()),
+            ],
+            Self::AccountLocked {
+                failures,
+                locked_until,
+            } => vec![
+                ("failures", failures.to_string()),
+                (
+                    "locked_until",
+                    locked_until.format("%Y-%m-%d %H:%M:%S UTC").to_string(),
+                ),
+            ],
+            Self::LowRecoveryCodes { remaining } => vec![("remaining", remaining.to_string())],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Text(String),
+    Variable(String),
+}
+
+// Text with `{{ name }}` placeholders
+#[derive(Debug, Clone, PartialEq)]
+struct Template(Vec<Part>);
+
+impl Template {
+    fn parse(source: &str, variables: &[&str]) -> Result<Self> {
+        let mut parts = Vec::new();
+        let mut rest = source;
+        while let Some(start) = rest.find("{{") {
+            parts.push(Part::Text(rest[..start].to_owned()));
+            let end = rest[start..]
+                .find("}}")
+                .ok_or_else(|| eyre!("Unclosed placeholder"))?;
+            let name = rest[start + 2..start + end].trim();
+            if !variables.contains(&name) {
+                return Err(eyre!("Unknown placeholder {{{{ {} }}}}", name));
+            }
+            parts.push(Part::Variable(name.to_owned()));
+            rest = &rest[start + end + 2..];
+        }
+        parts.push(Part::Text(rest.to_owned()));
+        Ok(Self(parts))
+    }
+
+    fn render(&self, variables: &[(&str, String)], escape: fn(&str) -> String) -> String {
+        self.0
+            .iter()
+            .map(|part| match part {
+                Part::Text(text) => text.clone(),
+                Part::Variable(name) => variables
+                    .iter()
+                    .find(|(variable, _)| variable == name)
+                    .map(|(_, value)| escape(value))
+                    .unwrap_or_default(),
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+struct MessageTemplates {
+    subject: Template,
+    text: Template,
+    html: Template,
+}
+
+impl MessageTemplates {
+    fn parse(name: &str, subject: &str, text: &str, html: &str) -> Result<Self> {
+        let variables = message_variables(name);
+        let parse = |source: &str, file: &str| {
+            Template::parse(source, variables).wrap_err_with(|| format!("Invalid {}", file))
+        };
+        Ok(Self {
+            subject: parse(subject.trim(), &format!("{}.subject.txt", name))?,
+            text: parse(text, &format!("{}.txt", name))?,
+            html: parse(html, &format!("{}.html", name))?,
+        })
+    }
+}
+
+fn message_variables(name: &str) -> &'static [&'static str] {
+    MESSAGES
+        .iter()
+        .chain(SMS_MESSAGES.iter())
+        .find(|(message, variables)| *message == name && !variables.is_empty())
+        .map(|(_, variables)| *variables)
+        .unwrap_or_default()
+}
+
+fn parse_sms(name: &str, text: &str) -> Result<Template> {
+    Template::parse(text.trim(), message_variables(name))
+        .wrap_err_with(|| format!("Invalid {}.sms.txt", name))
+}
+
+// Subject, HTML and plain-text templates for every email, per locale. Each template directory
+// holds one subdirectory per locale, e.g. `de/two_factor_code.html`, and every message there
+// needs all three of `<name>.subject.txt`, `<name>.txt` and `<name>.html`. Messages that can be
+// texted may also have a `<name>.sms.txt`, which is all a message that is only texted has.
+// Earlier directories take precedence over later ones, and all of them over the built-in
+// English templates.
+#[derive(Debug, Clone)]
+pub struct EmailTemplates {
+    templates: HashMap<(Locale, &'static str), MessageTemplates>,
+    sms_templates: HashMap<(Locale, &'static str), Template>,
+    default_locale: Locale,
+}
+
+impl EmailTemplates {
+    // Reads the configured template directories. Mistakes in the templates are reported here,
+    // at startup, rather than when an email fails to render.
+    pub fn load(settings: &EmailTemplateSettings) -> Result<Self> {
+        let mut templates = HashMap::new();
+        let mut sms_templates = HashMap::new();
+        for dir in &settings.dirs {
+            read_template_dir(dir, &mut templates, &mut sms_templates)?;
+        }
+        for (name, subject, text, html) in BUILT_IN {
+            if let Entry::Vacant(entry) = templates.entry((Locale::default(), name)) {
+                entry.insert(MessageTemplates::parse(name, subject, text, html)?);
+            }
+        }
+        for (name, text) in BUILT_IN_SMS {
+            if let Entry::Vacant(entry) = sms_templates.entry((Locale::default(), name)) {
+                entry.insert(parse_sms(name, text)?);
+            }
+        }
+
+        for (name, _) in MESSAGES {
+            if !templates.contains_key(&(settings.default_locale.clone(), name)) {
+                return Err(eyre!(
+                    "No {} email template for the default locale {}",
+                    name,
+                    settings.default_locale
+                ));
+            }
+        }
+        for (name, _) in SMS_MESSAGES {
+            if !sms_templates.contains_key(&(settings.default_locale.clone(), name)) {
+                return Err(eyre!(
+                    "No {} SMS template for the default locale {}",
+                    name,
+                    settings.default_locale
+                ));
+            }
+        }
+
+        Ok(Self {
+            templates,
+            sms_templates,
+            default_locale: settings.default_locale.clone(),
+        })
+    }
+
+    // Renders the email in the first of the user's locale, its language, and the default
+    // locale that has templates for it
+    pub fn render(&self, email: &TransactionalEmail, locale: Option<&Locale>) -> EmailMessage {
+        let name = email.template_name();
+        let templates = self
+            .locales(locale)
+            .find_map(|locale| self.templates.get(&(locale, name)))
+            .expect("templates for the default locale are checked when loading");
+
+        let variables = email.variables();
+        EmailMessage {
+            subject: templates.subject.render(&variables, str::to_owned),
+            text_body: templates.text.render(&variables, str::to_owned),
+            html_body: templates.html.render(&variables, escape_html),
+        }
+    }
+
+    // Renders the text message version of the email, picking the locale the same way as
+    // `render`. None for messages that are never texted.
+    pub fn render_sms(
+        &self,
+        email: &TransactionalEmail,
+        locale: Option<&Locale>,
+    ) -> Option<String> {
+        let name = email.template_name();
+        let template = self
+            .locales(locale)
+            .find_map(|locale| self.sms_templates.get(&(locale, name)))?;
+        Some(template.render(&email.variables(), str::to_owned))
+    }
+
+    fn locales(&self, locale: Option<&Locale>) -> impl Iterator<Item = Locale> {
+        locale
+            .map(Locale::fallbacks)
+            .unwrap_or_default()
+            .into_iter()
+            .chain(std::iter::once(self.default_locale.clone()))
+    }
+}
+
+impl Default for EmailTemplates {
+    fn default() -> Self {
+        Self::load(&EmailTemplateSettings::default()).expect("built-in email templates are valid")
+    }
+}
+
+fn read_template_dir(
+    dir: &Path,
+    templates: &mut HashMap<(Locale, &'static str), MessageTemplates>,
+    sms_templates: &mut HashMap<(Locale, &'static str), Template>,
+) -> Result<()> {
+    let entries = std::fs::read_dir(dir)
+        .wrap_err_with(|| format!("Failed to read email template directory {}", dir.display()))?;
+
+    for entry in entries {
+        let path = entry?.path();
+        if !path.is_dir() {
+            continue;
+        }
+        let locale = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| Locale::parse(name).ok())
+            .ok_or_else(|| eyre!("{} is not named after a locale", path.display()))?;
+
+        for (name, _) in MESSAGES {
+            let files = [
+                path.join(format!("{}.subject.txt", name)),
+                path.join(format!("{}.txt", name)),
+                path.join(format!("{}.html", name)),
+            ];
+            let present = files.iter().filter(|file| file.is_file()).count();
+            if present == 0 || templates.contains_key(&(locale.clone(), name)) {
+                continue;
+            }
+            if present < files.len() {
+                return Err(eyre!(
+                    "{} needs a subject, text and HTML template for {}",
+                    path.display(),
+                    name
+                ));
+            }
+
+            let [subject, text, html] = files.map(|file| {
+                std::fs::read_to_string(&file)
+                    .wrap_err_with(|| format!("Failed to read {}", file.display()))
+            });
+            let message = MessageTemplates::parse(name, &subject?, &text?, &html?)
+                .wrap_err_with(|| format!("Invalid email template in {}", path.display()))?;
+            templates.insert((locale.clone(), name), message);
+        }
+
+        for (name, _) in SMS_MESSAGES {
+            let file = path.join(format!("{}.sms.txt", name));
+            if !file.is_file() || sms_templates.contains_key(&(locale.clone(), name)) {
+                continue;
+            }
+            let text = std::fs::read_to_string(&file)
+                .wrap_err_with(|| format!("Failed to read {}", file.display()))?;
+            let template = parse_sms(name, &text)
+                .wrap_err_with(|| format!("Invalid SMS template in {}", path.display()))?;
+            sms_templates.insert((locale.clone(), name), template);
+        }
+    }
+
+    Ok(())
+}
+
+fn escape_html(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn code_email() -> TransactionalEmail {
+        TransactionalEmail::TwoFactorCode {
+            code: "123456".to_owned(),
+            expires_in_minutes: 10,
+        }
+    }
+
+    // A template directory under the system temp dir, removed when dropped
+    struct TemplateDir(PathBuf);
+
+    impl TemplateDir {
+        fn new() -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("email-templates-{}", uuid::Uuid::new_v4()));
+            fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+
+        fn add(&self, locale: &str, name: &str, subject: &str, text: &str, html: &str) {
+            let dir = self.0.join(locale);
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join(format!("{}.subject.txt", name)), subject).unwrap();
+            fs::write(dir.join(format!("{}.txt", name)), text).unwrap();
+            fs::write(dir.join(format!("{}.html", name)), html).unwrap();
+        }
+
+        fn settings(&self) -> EmailTemplateSettings {
+            EmailTemplateSettings {
+                dirs: vec![self.0.clone()],
+                ..EmailTemplateSettings::default()
+            }
+        }
+    }
+
+    impl Drop for TemplateDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn built_in_templates_render_every_message() {
+        let templates = EmailTemplates::default();
+        let message = templates.render(&code_email(), None);
+        assert!(message.text_body.contains("123456"));
+        assert!(message.html_body.contains("123456"));
+        assert_ne!(message.text_body, message.html_body);
+        assert!(!message.subject.contains('\n'));
+    }
+
+    #[test]
+    fn emails_use_the_closest_locale_available() {
+        let dir = TemplateDir::new();
+        dir.add(
+            "de",
+            "two_factor_code",
+            "Ihr Code",
+            "Ihr Code: {{ code }}",
+            "<p>Ihr Code: {{code}}</p>",
+        );
+        let templates = EmailTemplates::load(&dir.settings()).unwrap();
+
+        let german = templates.render(&code_email(), Some(&Locale::parse("de-AT").unwrap()));
+        assert_eq!(german.subject, "Ihr Code");
+        assert_eq!(german.text_body, "Ihr Code: 123456");
+
+        let french = templates.render(&code_email(), Some(&Locale::parse("fr").unwrap()));
+        assert_eq!(
+            french.subject,
+            EmailTemplates::default()
+                .render(&code_email(), None)
+                .subject
+        );
+    }
+
+    #[test]
+    fn texts_use_the_closest_locale_available() {
+        let dir = TemplateDir::new();
+        fs::create_dir_all(dir.0.join("de")).unwrap();
+        fs::write(
+            dir.0.join("de/two_factor_code.sms.txt"),
+            "Ihr Code: {{ code }}\n",
+        )
+        .unwrap();
+        let templates = EmailTemplates::load(&dir.settings()).unwrap();
+
+        assert_eq!(
+            templates.render_sms(&code_email(), Some(&Locale::parse("de-AT").unwrap())),
+            Some("Ihr Code: 123456".to_owned())
+        );
+        assert_eq!(
+            templates.render_sms(&code_email(), Some(&Locale::parse("fr").unwrap())),
+            Some("Your 2FA code is: 123456".to_owned())
+        );
+        assert_eq!(
+            templates.render_sms(&TransactionalEmail::LowRecoveryCodes { remaining: 2 }, None),
+            None
+        );
+    }
+
+    #[test]
+    fn text_only_messages_need_no_email_templates() {
+        let dir = TemplateDir::new();
+        fs::create_dir_all(dir.0.join("de")).unwrap();
+        fs::write(
+            dir.0.join("de/phone_verification_code.sms.txt"),
+            "Ihr Bestätigungscode: {{ code }}, gültig {{ expires_in_minutes }} Minuten\n",
+        )
+        .unwrap();
+        let templates = EmailTemplates::load(&dir.settings()).unwrap();
+
+        let message = TransactionalEmail::PhoneVerificationCode {
+            code: "123456".to_owned(),
+            expires_in_minutes: 10,
+        };
+        assert_eq!(
+            templates.render_sms(&message, Some(&Locale::parse("de").unwrap())),
+            Some("Ihr Bestätigungscode: 123456, gültig 10 Minuten".to_owned())
+        );
+        assert_eq!(
+            templates.render_sms(&message, None),
+            Some("Your phone verification code is: 123456".to_owned())
+        );
+    }
+
+    #[test]
+    fn values_are_escaped_in_html_only() {
+        let dir = TemplateDir::new();
+        dir.add(
+            "en",
+            "password_reset",
+            "Reset",
+            "{{ token }}",
+            "<b>{{ token }}</b>",
+        );
+        let templates = EmailTemplates::load(&dir.settings()).unwrap();
+
+        let message = templates.render(
+            &TransactionalEmail::PasswordReset {
+                token: "a<b>&c".to_owned(),
+                expires_in_minutes: 10,
+            },
+            None,
+        );
+        assert_eq!(message.text_body, "a<b>&c");
+        assert_eq!(message.html_body, "<b>a&lt;b&gt;&amp;c</b>");
+    }
+
+    #[test]
+    fn broken_template_directories_are_rejected() {
+        let dir = TemplateDir::new();
+        dir.add("de", "login_code", "Code", "{{ token }}", "{{ code }}");
+        assert!(EmailTemplates::load(&dir.settings()).is_err());
+
+        let dir = TemplateDir::new();
+        dir.add("de", "login_code", "Code", "{{ code", "{{ code }}");
+        assert!(EmailTemplates::load(&dir.settings()).is_err());
+
+        let dir = TemplateDir::new();
+        fs::create_dir_all(dir.0.join("de")).unwrap();
+        fs::write(dir.0.join("de/login_code.txt"), "{{ code }}").unwrap();
+        assert!(EmailTemplates::load(&dir.settings()).is_err());
+
+        let dir = TemplateDir::new();
+        fs::create_dir_all(dir.0.join("de")).unwrap();
+        fs::write(dir.0.join("de/two_factor_code.sms.txt"), "{{ link }}").unwrap();
+        assert!(EmailTemplates::load(&dir.settings()).is_err());
+
+        let settings = EmailTemplateSettings {
+            default_locale: Locale::parse("de").unwrap(),
+            ..EmailTemplateSettings::default()
+        };
+        assert!(EmailTemplates::load(&settings).is_err());
+    }
+}
@@ -1,3 +1,6 @@
+use crate::domain::authentication::AuthLevel;
+use crate::domain::email_domain_policy::EmailDomainRejection;
+use chrono::Duration;
 use color_eyre::eyre::Report;
 use thiserror::Error;
 
@@ -15,4 +18,48 @@ pub enum AuthAPIError {
     InvalidToken,
     #[error("Mising Token")]
     MissingToken,
+    #[error("Password was used recently")]
+    PasswordReused,
+    #[error("User not found")]
+    UserNotFound,
+    #[error("Dead letter not found")]
+    DeadLetterNotFound,
+    #[error("Dead letter expired")]
+    DeadLetterExpired,
+    #[error("Account locked")]
+    AccountLocked,
+    #[error("Account is not active")]
+    AccountInactive,
+    #[error("Invalid invite code")]
+    InvalidInviteCode,
+    #[error("Email domain rejected: {0}")]
+    EmailDomainRejected(EmailDomainRejection),
+    #[error("Authenticator app already enrolled")]
+    TotpAlreadyEnrolled,
+    #[error("No authenticator app enrollment in progress")]
+    TotpNotEnrolled,
+    #[error("Hardware token already enrolled")]
+    HotpAlreadyEnrolled,
+    #[error("Two-factor authentication is not enabled")]
+    TwoFactorNotEnabled,
+    #[error("Phone number has not been verified")]
+    PhoneNotVerified,
+    #[error("2FA method not available")]
+    TwoFactorMethodUnavailable,
+    // The user's only second factor is the inbox a passwordless login was started from
+    #[error("Password login required")]
+    PasswordLoginRequired,
+    #[error("Too many incorrect codes")]
+    TooManyCodeAttempts,
+    #[error("Code was sent too recently")]
+    ResendTooSoon,
+    #[error("Code was resent too many times")]
+    TooManyResends,
+    #[error("Invalid locale")]
+    InvalidLocale,
+    #[error("Trusted device not found")]
+    TrustedDeviceNotFound,
+    // The login behind the auth token is too old or too weak for the operation
+    #[error("Reauthentication required")]
+    ReauthenticationRequired { level: AuthLevel, max_age: Duration },
 }
@@ -0,0 +1,161 @@
+use color_eyre::eyre::{eyre, Result};
+use secrecy::{ExposeSecret, Secret};
+use subtle::ConstantTimeEq;
+use totp_rs::{Algorithm, TOTP};
+
+// RFC 4226 allows six to eight digits; key fobs almost always show six
+pub const DEFAULT_HOTP_DIGITS: usize = 6;
+const MIN_HOTP_DIGITS: usize = 6;
+const MAX_HOTP_DIGITS: usize = 8;
+const MAX_SERIAL_LENGTH: usize = 64;
+
+// Hex-encoded seed of a hardware token, as token vendors ship them
+#[derive(Debug, Clone)]
+pub struct HotpSecret(Secret<String>);
+
+impl HotpSecret {
+    pub fn parse(secret: Secret<String>) -> Result<Self> {
+        let encoded = secret.expose_secret().trim().to_ascii_lowercase();
+        let bytes = hex::decode(&encoded).map_err(|_| eyre!("HOTP seed is not valid hex"))?;
+        // RFC 4226 requires at least 128 bits
+        if bytes.len() < 16 {
+            return Err(eyre!("HOTP seed is too short"));
+        }
+        Ok(Self(Secret::new(encoded)))
+    }
+
+    fn bytes(&self) -> Result<Vec<u8>> {
+        hex::decode(self.0.expose_secret()).map_err(|_| eyre!("HOTP seed is not valid hex"))
+    }
+}
+
+impl AsRef<Secret<String>> for HotpSecret {
+    fn as_ref(&self) -> &Secret<String> {
+        &self.0
+    }
+}
+
+// A provisioned key fob. `counter` is the next moving factor the server expects, so it moves
+// past every accepted code.
+#[derive(Debug, Clone)]
+pub struct HotpToken {
+    pub serial: String,
+    pub secret: HotpSecret,
+    pub digits: usize,
+    pub counter: u64,
+}
+
+impl HotpToken {
+    pub fn new(serial: &str, secret: HotpSecret, digits: usize, counter: u64) -> Result<Self> {
+        let serial = serial.trim();
+        if serial.is_empty() || serial.len() > MAX_SERIAL_LENGTH {
+            return Err(eyre!(
+                "Token serial must be between 1 and {} characters",
+                MAX_SERIAL_LENGTH
+            ));
+        }
+        if !(MIN_HOTP_DIGITS..=MAX_HOTP_DIGITS).contains(&digits) {
+            return Err(eyre!(
+                "HOTP codes must have between {} and {} digits",
+                MIN_HOTP_DIGITS,
+                MAX_HOTP_DIGITS
+            ));
+        }
+        Ok(Self {
+            serial: serial.to_owned(),
+            secret,
+            digits,
+            counter,
+        })
+    }
+
+    pub fn code_at(&self, counter: u64) -> Result<String> {
+        // HOTP is TOTP with a one second step and the counter standing in for the time
+        let hotp = TOTP::new(
+            Algorithm::SHA1,
+            self.digits,
+            0,
+            1,
+            self.secret.bytes()?,
+            None,
+            self.serial.clone(),
+        )
+        .map_err(|e| eyre!("Invalid HOTP parameters: {:?}", e))?;
+        Ok(hotp.generate(counter))
+    }
+
+    // Returns the counter the code was generated for if it is the expected one or up to
+    // `look_ahead` after it. Presses of the button that never reached the server are skipped
+    // this way; the caller moves the counter past the match.
+    pub fn verify(&self, code: &str, look_ahead: u64) -> Result<Option<u64>> {
+        let mut matched = None;
+        for counter in self.counter..=self.counter.saturating_add(look_ahead) {
+            let expected = self.code_at(counter)?;
+            // Every candidate is compared so the timing does not reveal which counter matched
+            if bool::from(expected.as_bytes().ct_eq(code.as_bytes())) && matched.is_none() {
+                matched = Some(counter);
+            }
+        }
+        Ok(matched)
+    }
+}
+
+// Whether the code has the shape of a HOTP code, before checking it against a token
+pub fn is_well_formed_code(code: &str) -> bool {
+    (MIN_HOTP_DIGITS..=MAX_HOTP_DIGITS).contains(&code.len())
+        && code.bytes().all(|b| b.is_ascii_digit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Secret from the RFC 4226 test vectors ("12345678901234567890")
+    const RFC_SECRET: &str = "3132333435363738393031323334353637383930";
+    // RFC 4226 appendix D, counters 0 to 9
+    const RFC_CODES: [&str; 10] = [
+        "755224", "287082", "359152", "969429", "338314", "254676", "287922", "162583", "399871",
+        "520489",
+    ];
+
+    fn token(counter: u64) -> HotpToken {
+        let secret = HotpSecret::parse(Secret::new(RFC_SECRET.to_owned())).unwrap();
+        HotpToken::new("FOB-0001", secret, DEFAULT_HOTP_DIGITS, counter).unwrap()
+    }
+
+    #[test]
+    fn matches_rfc_4226_test_vectors() {
+        for (counter, code) in RFC_CODES.iter().enumerate() {
+            assert_eq!(&token(0).code_at(counter as u64).unwrap(), code);
+        }
+    }
+
+    #[test]
+    fn accepts_codes_within_the_look_ahead_window_only() {
+        let token = token(2);
+        assert_eq!(token.verify(RFC_CODES[2], 0).unwrap(), Some(2));
+        assert_eq!(token.verify(RFC_CODES[5], 3).unwrap(), Some(5));
+        assert_eq!(token.verify(RFC_CODES[6], 3).unwrap(), None);
+        // Codes for counters already passed are never accepted again
+        assert_eq!(token.verify(RFC_CODES[1], 3).unwrap(), None);
+    }
+
+    #[test]
+    fn rejects_bad_seeds_and_token_parameters() {
+        assert!(HotpSecret::parse(Secret::new("31323334".to_owned())).is_err());
+        assert!(HotpSecret::parse(Secret::new("not hex at all!".to_owned())).is_err());
+
+        let secret = HotpSecret::parse(Secret::new(RFC_SECRET.to_uppercase())).unwrap();
+        assert_eq!(secret.as_ref().expose_secret(), RFC_SECRET);
+        assert!(HotpToken::new(" ", secret.clone(), 6, 0).is_err());
+        assert!(HotpToken::new("FOB-0001", secret, 9, 0).is_err());
+    }
+
+    #[test]
+    fn well_formed_codes_have_six_to_eight_digits() {
+        assert!(is_well_formed_code("755224"));
+        assert!(is_well_formed_code("84755224"));
+        assert!(!is_well_formed_code("75522"));
+        assert!(!is_well_formed_code("75522a"));
+    }
+}
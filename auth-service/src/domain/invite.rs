@@ -0,0 +1,94 @@
+use crate::domain::Email;
+use chrono::{DateTime, Duration, Utc};
+use color_eyre::eyre::{eyre, Result};
+use rand::{distr::Alphanumeric, Rng};
+use secrecy::{ExposeSecret, Secret};
+use std::hash::Hash;
+
+const INVITE_CODE_LENGTH: usize = 20;
+
+// An invitation to sign up, usable `max_uses` times before it expires. Invites bound to an
+// email address can only be used to sign up with that address.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Invite {
+    pub code: InviteCode,
+    pub email: Option<Email>,
+    pub max_uses: u32,
+    pub uses: u32,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl Invite {
+    pub fn new(email: Option<Email>, max_uses: u32, ttl: Duration) -> Self {
+        Self {
+            code: InviteCode::default(),
+            email,
+            max_uses,
+            uses: 0,
+            expires_at: Utc::now() + ttl,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct InviteCode(Secret<String>);
+
+impl InviteCode {
+    pub fn parse(code: Secret<String>) -> Result<Self> {
+        let trimmed = code.expose_secret().trim();
+        if trimmed.is_empty() || !trimmed.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(eyre!("Invalid invite code"));
+        }
+        Ok(Self(Secret::new(trimmed.to_owned())))
+    }
+}
+
+impl Default for InviteCode {
+    fn default() -> Self {
+        let code: String = rand::rng()
+            .sample_iter(&Alphanumeric)
+            .take(INVITE_CODE_LENGTH)
+            .map(char::from)
+            .collect();
+        Self(Secret::new(code))
+    }
+}
+
+impl PartialEq for InviteCode {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.expose_secret() == other.0.expose_secret()
+    }
+}
+
+impl Eq for InviteCode {}
+
+impl Hash for InviteCode {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.0.expose_secret().hash(state);
+    }
+}
+
+impl AsRef<Secret<String>> for InviteCode {
+    fn as_ref(&self) -> &Secret<String> {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_codes_parse() {
+        let code = InviteCode::default();
+        assert_eq!(code.as_ref().expose_secret().len(), INVITE_CODE_LENGTH);
+        assert_eq!(InviteCode::parse(code.as_ref().clone()).unwrap(), code);
+    }
+
+    #[test]
+    fn blank_or_non_alphanumeric_codes_are_rejected() {
+        assert!(InviteCode::parse(Secret::new("".to_owned())).is_err());
+        assert!(InviteCode::parse(Secret::new("   ".to_owned())).is_err());
+        assert!(InviteCode::parse(Secret::new("abc-123".to_owned())).is_err());
+    }
+}
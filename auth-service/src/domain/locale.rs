@@ -0,0 +1,99 @@
+use color_eyre::eyre::{eyre, Result};
+use std::fmt;
+use std::str::FromStr;
+
+// A language, optionally narrowed to a region, e.g. "en" or "pt-BR". Emails are written in
+// the user's locale when there are templates for it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Locale(String);
+
+impl Locale {
+    pub fn parse(locale: &str) -> Result<Self> {
+        let invalid = || eyre!("{} is not a valid locale", locale);
+        let mut parts = locale.trim().split(['-', '_']);
+
+        let language = parts.next().unwrap_or_default();
+        if !(2..=3).contains(&language.len()) || !language.bytes().all(|b| b.is_ascii_alphabetic())
+        {
+            return Err(invalid());
+        }
+        let mut normalized = language.to_ascii_lowercase();
+
+        if let Some(region) = parts.next() {
+            let is_region = (region.len() == 2 && region.bytes().all(|b| b.is_ascii_alphabetic()))
+                || (region.len() == 3 && region.bytes().all(|b| b.is_ascii_digit()));
+            if !is_region {
+                return Err(invalid());
+            }
+            normalized.push('-');
+            normalized.push_str(&region.to_ascii_uppercase());
+        }
+        if parts.next().is_some() {
+            return Err(invalid());
+        }
+
+        Ok(Self(normalized))
+    }
+
+    // The locale itself, then its language without the region
+    pub fn fallbacks(&self) -> Vec<Locale> {
+        let mut locales = vec![self.clone()];
+        if let Some((language, _)) = self.0.split_once('-') {
+            locales.push(Locale(language.to_owned()));
+        }
+        locales
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Default for Locale {
+    fn default() -> Self {
+        Self("en".to_owned())
+    }
+}
+
+impl FromStr for Locale {
+    type Err = color_eyre::eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::parse(s)
+    }
+}
+
+impl fmt::Display for Locale {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locales_are_normalized() {
+        assert_eq!(Locale::parse("en").unwrap().as_str(), "en");
+        assert_eq!(Locale::parse("pt_br").unwrap().as_str(), "pt-BR");
+        assert_eq!(Locale::parse("ES-419").unwrap().as_str(), "es-419");
+    }
+
+    #[test]
+    fn malformed_locales_are_rejected() {
+        for locale in ["", "e", "english", "en-", "en-GBR", "en-GB-x", "../en"] {
+            assert!(Locale::parse(locale).is_err(), "{} was accepted", locale);
+        }
+    }
+
+    #[test]
+    fn regional_locales_fall_back_to_their_language() {
+        let fallbacks = Locale::parse("pt-BR").unwrap().fallbacks();
+        assert_eq!(
+            fallbacks.iter().map(Locale::as_str).collect::<Vec<_>>(),
+            ["pt-BR", "pt"]
+        );
+        assert_eq!(Locale::parse("fr").unwrap().fallbacks().len(), 1);
+    }
+}
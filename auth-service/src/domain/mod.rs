@@ -1,11 +1,28 @@
+pub mod authentication;
 pub mod data_store;
 pub mod email_client;
+pub mod email_domain_policy;
+pub mod email_outbox;
+pub mod email_template;
 pub mod error;
+pub mod hotp;
+pub mod invite;
+pub mod locale;
+pub mod password_hasher;
+pub mod phone_number;
+pub mod recovery_code;
+pub mod sms_client;
+pub mod totp;
+pub mod trusted_device;
+pub mod two_factor;
 pub mod user;
+pub mod webauthn;
 use color_eyre::eyre::{eyre, Result};
 pub use email_client::*;
+pub use password_hasher::*;
 use secrecy::{ExposeSecret, Secret};
 use serde::Deserialize;
+pub use sms_client::*;
 use std::hash::Hash;
 
 #[derive(Debug, Deserialize, Clone)]
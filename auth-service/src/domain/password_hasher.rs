@@ -0,0 +1,14 @@
+use color_eyre::eyre::Result;
+use secrecy::Secret;
+
+#[async_trait::async_trait]
+pub trait PasswordHasher {
+    async fn hash(&self, password: Secret<String>) -> Result<Secret<String>>;
+    async fn verify(
+        &self,
+        password_hash: Secret<String>,
+        password_candidate: Secret<String>,
+    ) -> Result<()>;
+    // Whether a hash that just verified successfully should be replaced with a fresh one.
+    fn needs_rehash(&self, password_hash: &Secret<String>) -> bool;
+}
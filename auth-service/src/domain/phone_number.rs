@@ -0,0 +1,81 @@
+use color_eyre::eyre::{eyre, Result};
+use secrecy::{ExposeSecret, Secret};
+
+// Phone number in E.164 format, e.g. +447700900123
+#[derive(Debug, Clone)]
+pub struct PhoneNumber(Secret<String>);
+
+impl PhoneNumber {
+    // Accepts the usual separators people type and stores the number without them
+    pub fn parse(number: Secret<String>) -> Result<Self> {
+        let normalized: String = number
+            .expose_secret()
+            .chars()
+            .filter(|c| !c.is_whitespace() && !matches!(c, '-' | '(' | ')' | '.'))
+            .collect();
+
+        let Some(digits) = normalized.strip_prefix('+') else {
+            return Err(eyre!(
+                "Phone number must start with a + and the country code"
+            ));
+        };
+        // E.164 allows at most 15 digits, and no country code starts with 0
+        if !(8..=15).contains(&digits.len())
+            || !digits.bytes().all(|b| b.is_ascii_digit())
+            || digits.starts_with('0')
+        {
+            return Err(eyre!("Invalid phone number"));
+        }
+
+        Ok(Self(Secret::new(normalized)))
+    }
+
+    // Last digits only, for telling the user where a code went
+    pub fn masked(&self) -> String {
+        let number = self.0.expose_secret();
+        format!("•••{}", &number[number.len() - 2..])
+    }
+}
+
+impl PartialEq for PhoneNumber {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.expose_secret() == other.0.expose_secret()
+    }
+}
+
+impl AsRef<Secret<String>> for PhoneNumber {
+    fn as_ref(&self) -> &Secret<String> {
+        &self.0
+    }
+}
+
+// A phone number the user has proven they receive texts at, by entering a code sent to it
+#[derive(Debug, Clone, PartialEq)]
+pub struct UserPhone {
+    pub number: PhoneNumber,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(number: &str) -> Result<PhoneNumber> {
+        PhoneNumber::parse(Secret::new(number.to_owned()))
+    }
+
+    #[test]
+    fn separators_are_removed() {
+        let number = parse("+44 (7700) 900-123").unwrap();
+        assert_eq!(number.as_ref().expose_secret(), "+447700900123");
+        assert_eq!(number.masked(), "•••23");
+    }
+
+    #[test]
+    fn numbers_without_country_code_are_rejected() {
+        assert!(parse("07700900123").is_err());
+        assert!(parse("+07700900123").is_err());
+        assert!(parse("+4477009001231234").is_err());
+        assert!(parse("+44770090012a").is_err());
+        assert!(parse("+").is_err());
+    }
+}
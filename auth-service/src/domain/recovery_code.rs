@@ -0,0 +1,77 @@
+use color_eyre::eyre::{eyre, Result};
+use rand::Rng;
+use secrecy::{ExposeSecret, Secret};
+use sha2::{Digest, Sha256};
+
+// Crockford base32 without the characters that are easy to mix up when typed from paper
+const ALPHABET: &[u8] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+const CODE_LENGTH: usize = 10;
+// Number of codes issued at a time
+pub const RECOVERY_CODE_COUNT: usize = 10;
+// The user is emailed a warning once this many unused codes or fewer are left
+pub const LOW_RECOVERY_CODES_THRESHOLD: usize = 3;
+
+// Single-use code that completes 2FA when the usual second factor is not at hand. Codes are
+// random enough that a fast hash is sufficient to store them.
+#[derive(Debug, Clone)]
+pub struct RecoveryCode(Secret<String>);
+
+impl RecoveryCode {
+    // Accepts codes as displayed, with the dash and in any case
+    pub fn parse(code: Secret<String>) -> Result<Self> {
+        let normalized: String = code
+            .expose_secret()
+            .chars()
+            .filter(|c| *c != '-' && !c.is_whitespace())
+            .map(|c| c.to_ascii_uppercase())
+            .collect();
+
+        if normalized.len() != CODE_LENGTH || !normalized.bytes().all(|b| ALPHABET.contains(&b)) {
+            return Err(eyre!("Invalid recovery code"));
+        }
+        Ok(Self(Secret::new(normalized)))
+    }
+
+    pub fn hash(&self) -> String {
+        hex::encode(Sha256::digest(self.0.expose_secret().as_bytes()))
+    }
+
+    // Code as shown to the user, split in two halves for readability
+    pub fn display(&self) -> String {
+        let code = self.0.expose_secret();
+        format!("{}-{}", &code[..CODE_LENGTH / 2], &code[CODE_LENGTH / 2..])
+    }
+}
+
+impl Default for RecoveryCode {
+    fn default() -> Self {
+        let mut rng = rand::rng();
+        let code = (0..CODE_LENGTH)
+            .map(|_| ALPHABET[rng.random_range(0..ALPHABET.len())] as char)
+            .collect();
+        Self(Secret::new(code))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn displayed_codes_parse_back_to_the_same_hash() {
+        let code = RecoveryCode::default();
+        let displayed = code.display();
+        assert_eq!(displayed.len(), CODE_LENGTH + 1);
+
+        let parsed = RecoveryCode::parse(Secret::new(displayed.to_lowercase())).unwrap();
+        assert_eq!(parsed.hash(), code.hash());
+    }
+
+    #[test]
+    fn malformed_codes_are_rejected() {
+        assert!(RecoveryCode::parse(Secret::new("".to_owned())).is_err());
+        assert!(RecoveryCode::parse(Secret::new("ABCDE-FGHJ".to_owned())).is_err());
+        assert!(RecoveryCode::parse(Secret::new("ABCDE-FGHJU".to_owned())).is_err());
+        assert!(RecoveryCode::parse(Secret::new("123456".to_owned())).is_err());
+    }
+}
@@ -0,0 +1,7 @@
+use super::phone_number::PhoneNumber;
+use color_eyre::eyre::Result;
+
+#[async_trait::async_trait]
+pub trait SmsClient {
+    async fn send_sms(&self, recipient: &PhoneNumber, content: &str) -> Result<()>;
+}
@@ -0,0 +1,104 @@
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{Context, Result};
+use uuid::Uuid;
+
+// Longest label kept from the User-Agent header
+const MAX_LABEL_LENGTH: usize = 200;
+
+// Identifies a remembered device. It is carried in the device cookie and is what the user
+// refers to when revoking the device.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TrustedDeviceId(Uuid);
+
+impl TrustedDeviceId {
+    pub fn parse(id: &str) -> Result<Self> {
+        Uuid::parse_str(id)
+            .map(Self)
+            .wrap_err("Invalid trusted device id")
+    }
+}
+
+impl Default for TrustedDeviceId {
+    fn default() -> Self {
+        Self(Uuid::new_v4())
+    }
+}
+
+impl From<Uuid> for TrustedDeviceId {
+    fn from(id: Uuid) -> Self {
+        Self(id)
+    }
+}
+
+impl AsRef<Uuid> for TrustedDeviceId {
+    fn as_ref(&self) -> &Uuid {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for TrustedDeviceId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+// A device the user chose to remember after completing 2FA on it. Logins from it skip the
+// second factor until it expires or is revoked.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrustedDevice {
+    pub id: TrustedDeviceId,
+    // Describes the device in the user's device list, taken from its User-Agent
+    pub label: String,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl TrustedDevice {
+    pub fn new(label: Option<&str>, expires_at: DateTime<Utc>) -> Self {
+        let label = label
+            .map(str::trim)
+            .filter(|label| !label.is_empty())
+            .unwrap_or("Unknown device")
+            .chars()
+            .take(MAX_LABEL_LENGTH)
+            .collect();
+
+        Self {
+            id: TrustedDeviceId::default(),
+            label,
+            created_at: Utc::now(),
+            last_used_at: None,
+            expires_at,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn label_falls_back_when_user_agent_is_missing() {
+        let expires_at = Utc::now();
+        assert_eq!(TrustedDevice::new(None, expires_at).label, "Unknown device");
+        assert_eq!(
+            TrustedDevice::new(Some("  "), expires_at).label,
+            "Unknown device"
+        );
+    }
+
+    #[test]
+    fn long_labels_are_truncated() {
+        let user_agent = "a".repeat(MAX_LABEL_LENGTH + 50);
+        let device = TrustedDevice::new(Some(&user_agent), Utc::now());
+        assert_eq!(device.label.len(), MAX_LABEL_LENGTH);
+    }
+
+    #[test]
+    fn id_round_trips_through_its_string_form() {
+        let id = TrustedDeviceId::default();
+        assert_eq!(TrustedDeviceId::parse(&id.to_string()).unwrap(), id);
+        assert!(TrustedDeviceId::parse("not-a-uuid").is_err());
+    }
+}
@@ -0,0 +1,161 @@
+use color_eyre::eyre::{eyre, Result};
+use serde::{Deserialize, Serialize};
+
+// A way of completing the second step of a login
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TwoFactorMethod {
+    // A code from an authenticator app
+    Totp,
+    // A code from a hardware token that counts button presses
+    Hotp,
+    // A passkey, through /webauthn/login/start and /webauthn/login/finish
+    Webauthn,
+    // A code texted to the user's verified phone
+    Sms,
+    // A code emailed to the user
+    Email,
+    // One of the codes handed out when 2FA was set up, for when nothing else is at hand
+    RecoveryCode,
+}
+
+impl TwoFactorMethod {
+    // The order methods are listed in after the preferred one
+    pub const ALL: [TwoFactorMethod; 6] = [
+        TwoFactorMethod::Totp,
+        TwoFactorMethod::Hotp,
+        TwoFactorMethod::Webauthn,
+        TwoFactorMethod::Sms,
+        TwoFactorMethod::Email,
+        TwoFactorMethod::RecoveryCode,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TwoFactorMethod::Totp => "totp",
+            TwoFactorMethod::Hotp => "hotp",
+            TwoFactorMethod::Webauthn => "webauthn",
+            TwoFactorMethod::Sms => "sms",
+            TwoFactorMethod::Email => "email",
+            TwoFactorMethod::RecoveryCode => "recovery_code",
+        }
+    }
+
+    pub fn parse(method: &str) -> Result<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|candidate| candidate.as_str() == method)
+            .ok_or_else(|| eyre!("Unknown 2FA method: {}", method))
+    }
+
+    // Whether the service sends the user a code for this method when a login starts
+    pub fn is_sent_code(&self) -> bool {
+        matches!(self, TwoFactorMethod::Sms | TwoFactorMethod::Email)
+    }
+
+    // Recovery codes are a fallback, not something to be asked for at every login
+    pub fn can_be_preferred(&self) -> bool {
+        *self != TwoFactorMethod::RecoveryCode
+    }
+}
+
+// The methods a user has set up, with the one a login should ask for first at the front
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TwoFactorMethods(Vec<TwoFactorMethod>);
+
+impl TwoFactorMethods {
+    // `enrolled` may come in any order. A preference for a method the user has not set up is
+    // ignored in favour of `fallback`, and failing that the first method in the usual order.
+    pub fn new(
+        enrolled: &[TwoFactorMethod],
+        preferred: Option<TwoFactorMethod>,
+        fallback: TwoFactorMethod,
+    ) -> Self {
+        let mut methods: Vec<TwoFactorMethod> = TwoFactorMethod::ALL
+            .into_iter()
+            .filter(|method| enrolled.contains(method))
+            .collect();
+
+        let first = [preferred, Some(fallback)]
+            .into_iter()
+            .flatten()
+            .find(|method| method.can_be_preferred() && methods.contains(method));
+        if let Some(first) = first {
+            methods.retain(|method| *method != first);
+            methods.insert(0, first);
+        }
+        Self(methods)
+    }
+
+    pub fn preferred(&self) -> Option<TwoFactorMethod> {
+        self.0
+            .first()
+            .copied()
+            .filter(TwoFactorMethod::can_be_preferred)
+    }
+
+    // Leaves `method` out, keeping the order of the rest
+    pub fn without(mut self, method: TwoFactorMethod) -> Self {
+        self.0.retain(|candidate| *candidate != method);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn contains(&self, method: TwoFactorMethod) -> bool {
+        self.0.contains(&method)
+    }
+
+    pub fn as_slice(&self) -> &[TwoFactorMethod] {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use TwoFactorMethod::*;
+
+    #[test]
+    fn preferred_method_comes_first_and_the_rest_keep_their_order() {
+        let methods = TwoFactorMethods::new(&[RecoveryCode, Email, Totp], Some(Email), Totp);
+        assert_eq!(methods.as_slice(), &[Email, Totp, RecoveryCode]);
+        assert_eq!(methods.preferred(), Some(Email));
+    }
+
+    #[test]
+    fn preference_for_a_method_not_set_up_falls_back() {
+        let methods = TwoFactorMethods::new(&[Email, Sms], Some(Totp), Sms);
+        assert_eq!(methods.as_slice(), &[Sms, Email]);
+
+        let methods = TwoFactorMethods::new(&[Email, Webauthn], Some(Totp), Sms);
+        assert_eq!(methods.as_slice(), &[Webauthn, Email]);
+        assert_eq!(methods.preferred(), Some(Webauthn));
+    }
+
+    #[test]
+    fn leaving_a_method_out_moves_the_next_one_up() {
+        let methods = TwoFactorMethods::new(&[Email, Sms, RecoveryCode], Some(Email), Email);
+        let methods = methods.without(Email);
+        assert_eq!(methods.as_slice(), &[Sms, RecoveryCode]);
+        assert_eq!(methods.preferred(), Some(Sms));
+        assert!(methods.without(Sms).without(RecoveryCode).is_empty());
+    }
+
+    #[test]
+    fn recovery_codes_are_never_preferred() {
+        let methods = TwoFactorMethods::new(&[RecoveryCode], Some(RecoveryCode), RecoveryCode);
+        assert_eq!(methods.as_slice(), &[RecoveryCode]);
+        assert_eq!(methods.preferred(), None);
+    }
+
+    #[test]
+    fn methods_round_trip_through_their_names() {
+        for method in TwoFactorMethod::ALL {
+            assert_eq!(TwoFactorMethod::parse(method.as_str()).unwrap(), method);
+        }
+        assert!(TwoFactorMethod::parse("carrier_pigeon").is_err());
+    }
+}
@@ -1,6 +1,10 @@
+use crate::domain::locale::Locale;
+use crate::domain::two_factor::TwoFactorMethod;
 use crate::domain::{Email, Password};
-use color_eyre::eyre::Report;
+use chrono::{DateTime, Duration, Utc};
+use color_eyre::eyre::eyre;
 use rand::Rng;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 #[derive(Debug, Clone, PartialEq)]
@@ -8,6 +12,14 @@ pub struct User {
     pub email: Email,
     pub password: Password,
     pub require_2fa: bool,
+    // The second factor a login should ask for first, if the user has picked one
+    pub preferred_2fa_method: Option<TwoFactorMethod>,
+    // Language the user's emails are written in; the configured default when unset
+    pub locale: Option<Locale>,
+    pub password_changed_at: DateTime<Utc>,
+    // Set by an administrator to force a password change on the next login
+    pub must_change_password: bool,
+    pub status: AccountStatus,
 }
 
 impl User {
@@ -16,6 +28,134 @@ impl User {
             email,
             password,
             require_2fa,
+            preferred_2fa_method: None,
+            locale: None,
+            password_changed_at: Utc::now(),
+            must_change_password: false,
+            status: AccountStatus::Active,
         }
     }
+
+    // Why the user has to pick a new password before being let in, if they have to
+    pub fn password_change_reason(
+        &self,
+        max_age: Option<Duration>,
+    ) -> Option<PasswordChangeReason> {
+        if self.must_change_password {
+            return Some(PasswordChangeReason::Required);
+        }
+
+        match max_age {
+            Some(max_age) if Utc::now() - self.password_changed_at >= max_age => {
+                Some(PasswordChangeReason::Expired)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PasswordChangeReason {
+    Required,
+    Expired,
+}
+
+// Only active accounts may log in or use their tokens
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountStatus {
+    Active,
+    Disabled,
+    Suspended,
+}
+
+impl AccountStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AccountStatus::Active => "active",
+            AccountStatus::Disabled => "disabled",
+            AccountStatus::Suspended => "suspended",
+        }
+    }
+
+    pub fn parse(status: &str) -> color_eyre::eyre::Result<Self> {
+        match status {
+            "active" => Ok(AccountStatus::Active),
+            "disabled" => Ok(AccountStatus::Disabled),
+            "suspended" => Ok(AccountStatus::Suspended),
+            _ => Err(eyre!("{} is not a valid account status.", status)),
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        *self == AccountStatus::Active
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StatusChange {
+    pub status: AccountStatus,
+    pub reason: String,
+    #[serde(rename = "changedAt")]
+    pub changed_at: DateTime<Utc>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use secrecy::Secret;
+
+    fn user() -> User {
+        User::new(
+            Email::parse(Secret::new("test@mail.com".to_owned())).unwrap(),
+            Password::parse(Secret::new("password123".to_owned())).unwrap(),
+            false,
+        )
+    }
+
+    #[test]
+    fn fresh_password_does_not_need_changing() {
+        assert_eq!(user().password_change_reason(None), None);
+        assert_eq!(
+            user().password_change_reason(Some(Duration::days(90))),
+            None
+        );
+    }
+
+    #[test]
+    fn password_older_than_max_age_has_expired() {
+        let mut user = user();
+        user.password_changed_at = Utc::now() - Duration::days(91);
+
+        assert_eq!(user.password_change_reason(None), None);
+        assert_eq!(
+            user.password_change_reason(Some(Duration::days(90))),
+            Some(PasswordChangeReason::Expired)
+        );
+    }
+
+    #[test]
+    fn flagged_user_must_change_password() {
+        let mut user = user();
+        user.must_change_password = true;
+
+        assert_eq!(
+            user.password_change_reason(None),
+            Some(PasswordChangeReason::Required)
+        );
+    }
+
+    #[test]
+    fn account_status_round_trips_through_str() {
+        for status in [
+            AccountStatus::Active,
+            AccountStatus::Disabled,
+            AccountStatus::Suspended,
+        ] {
+            assert_eq!(AccountStatus::parse(status.as_str()).unwrap(), status);
+        }
+        assert!(AccountStatus::parse("deleted").is_err());
+        assert!(AccountStatus::parse("pending").is_err());
+    }
 }
@@ -0,0 +1,574 @@
+use crate::domain::data_store::LoginAttemptId;
+use crate::domain::Email;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ciborium::value::Value;
+use color_eyre::eyre::{eyre, Result};
+use p256::ecdsa::{signature::Verifier, DerSignature, VerifyingKey};
+use p256::EncodedPoint;
+use rand::RngCore;
+use secrecy::ExposeSecret;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+// COSE key parameters (RFC 9053). Only ES256 keys on P-256 are supported.
+const COSE_KEY_TYPE: i128 = 1;
+const COSE_ALGORITHM: i128 = 3;
+const COSE_EC2_CURVE: i128 = -1;
+const COSE_EC2_X: i128 = -2;
+const COSE_EC2_Y: i128 = -3;
+const COSE_KEY_TYPE_EC2: i128 = 2;
+pub const COSE_ALGORITHM_ES256: i64 = -7;
+const COSE_CURVE_P256: i128 = 1;
+
+// Authenticator data flags
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_CREDENTIAL_DATA: u8 = 0x40;
+
+const CHALLENGE_BYTES: usize = 32;
+// Longest credential ID the WebAuthn spec allows
+const MAX_CREDENTIAL_ID_BYTES: usize = 1023;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum WebAuthnError {
+    #[error("Malformed {0}")]
+    Malformed(&'static str),
+    #[error("Client data is for a different ceremony")]
+    WrongCeremonyType,
+    #[error("Challenge does not match")]
+    ChallengeMismatch,
+    #[error("Origin does not match")]
+    OriginMismatch,
+    #[error("Relying party ID does not match")]
+    RpIdMismatch,
+    #[error("User presence was not confirmed")]
+    UserNotPresent,
+    #[error("User was not verified")]
+    UserNotVerified,
+    #[error("Unsupported attestation format")]
+    UnsupportedAttestation,
+    #[error("Unsupported public key")]
+    UnsupportedKey,
+    #[error("Invalid signature")]
+    InvalidSignature,
+    // The authenticator's counter went backwards, which suggests the credential was cloned
+    #[error("Signature counter did not increase")]
+    SignCountRegressed,
+}
+
+// Random challenge the authenticator signs over, base64url-encoded as it appears in client data
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct WebAuthnChallenge(String);
+
+impl WebAuthnChallenge {
+    pub fn parse(challenge: String) -> Result<Self> {
+        match URL_SAFE_NO_PAD.decode(&challenge) {
+            Ok(bytes) if bytes.len() == CHALLENGE_BYTES => Ok(Self(challenge)),
+            _ => Err(eyre!("Invalid WebAuthn challenge")),
+        }
+    }
+}
+
+impl Default for WebAuthnChallenge {
+    fn default() -> Self {
+        let mut bytes = [0u8; CHALLENGE_BYTES];
+        rand::rng().fill_bytes(&mut bytes);
+        Self(URL_SAFE_NO_PAD.encode(bytes))
+    }
+}
+
+impl AsRef<str> for WebAuthnChallenge {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+// Base64url-encoded credential ID chosen by the authenticator
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CredentialId(String);
+
+impl CredentialId {
+    pub fn parse(id: String) -> Result<Self> {
+        match URL_SAFE_NO_PAD.decode(&id) {
+            Ok(bytes) if !bytes.is_empty() && bytes.len() <= MAX_CREDENTIAL_ID_BYTES => {
+                Ok(Self(id))
+            }
+            _ => Err(eyre!("Invalid credential ID")),
+        }
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Self {
+        Self(URL_SAFE_NO_PAD.encode(bytes))
+    }
+}
+
+impl AsRef<str> for CredentialId {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+// A registered authenticator. The public key is an uncompressed SEC1 point.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WebAuthnCredential {
+    pub id: CredentialId,
+    pub public_key: Vec<u8>,
+    pub sign_count: u32,
+}
+
+// What a pending ceremony was started for, checked when its response comes back
+#[derive(Debug, Clone, PartialEq)]
+pub enum CeremonyPurpose {
+    Registration {
+        email: Email,
+    },
+    // With a login attempt the credential stands in for a 2FA code after a password login.
+    // Without one it is the only factor, and the email is only known if the user gave it.
+    Authentication {
+        email: Option<Email>,
+        login_attempt_id: Option<LoginAttemptId>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WebAuthnCeremony {
+    pub challenge: WebAuthnChallenge,
+    pub purpose: CeremonyPurpose,
+}
+
+// Where ceremonies are checked against: the relying party ID is the domain credentials are
+// scoped to and the origin is where the browser ran the ceremony
+#[derive(Debug, Clone, Copy)]
+pub struct RelyingParty<'a> {
+    pub id: &'a str,
+    pub origin: &'a str,
+}
+
+#[derive(Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    ceremony_type: String,
+    challenge: String,
+    origin: String,
+}
+
+// The challenge a response was made for, used to find the pending ceremony
+pub fn client_data_challenge(client_data_json: &[u8]) -> Result<WebAuthnChallenge, WebAuthnError> {
+    let client_data: ClientData = serde_json::from_slice(client_data_json)
+        .map_err(|_| WebAuthnError::Malformed("client data"))?;
+    WebAuthnChallenge::parse(client_data.challenge)
+        .map_err(|_| WebAuthnError::Malformed("client data"))
+}
+
+fn check_client_data(
+    client_data_json: &[u8],
+    ceremony_type: &str,
+    challenge: &WebAuthnChallenge,
+    rp: RelyingParty,
+) -> Result<(), WebAuthnError> {
+    let client_data: ClientData = serde_json::from_slice(client_data_json)
+        .map_err(|_| WebAuthnError::Malformed("client data"))?;
+
+    if client_data.ceremony_type != ceremony_type {
+        return Err(WebAuthnError::WrongCeremonyType);
+    }
+    if client_data.challenge != challenge.0 {
+        return Err(WebAuthnError::ChallengeMismatch);
+    }
+    if client_data.origin != rp.origin {
+        return Err(WebAuthnError::OriginMismatch);
+    }
+    Ok(())
+}
+
+struct AuthenticatorData<'a> {
+    flags: u8,
+    sign_count: u32,
+    // Attested credential data and extensions, if any
+    rest: &'a [u8],
+}
+
+fn parse_authenticator_data<'a>(
+    data: &'a [u8],
+    rp: RelyingParty,
+    require_user_verification: bool,
+) -> Result<AuthenticatorData<'a>, WebAuthnError> {
+    if data.len() < 37 {
+        return Err(WebAuthnError::Malformed("authenticator data"));
+    }
+    let (rp_id_hash, rest) = data.split_at(32);
+    if rp_id_hash != Sha256::digest(rp.id.as_bytes()).as_slice() {
+        return Err(WebAuthnError::RpIdMismatch);
+    }
+
+    let flags = rest[0];
+    if flags & FLAG_USER_PRESENT == 0 {
+        return Err(WebAuthnError::UserNotPresent);
+    }
+    if require_user_verification && flags & FLAG_USER_VERIFIED == 0 {
+        return Err(WebAuthnError::UserNotVerified);
+    }
+
+    let sign_count = u32::from_be_bytes([rest[1], rest[2], rest[3], rest[4]]);
+    Ok(AuthenticatorData {
+        flags,
+        sign_count,
+        rest: &rest[5..],
+    })
+}
+
+fn map_get<'a>(map: &'a [(Value, Value)], key: &str) -> Option<&'a Value> {
+    map.iter()
+        .find(|(k, _)| k.as_text() == Some(key))
+        .map(|(_, v)| v)
+}
+
+fn cose_int(map: &[(Value, Value)], key: i128) -> Option<i128> {
+    map.iter()
+        .find(|(k, _)| k.as_integer().is_some_and(|k| i128::from(k) == key))
+        .and_then(|(_, v)| v.as_integer())
+        .map(i128::from)
+}
+
+fn cose_bytes(map: &[(Value, Value)], key: i128) -> Option<&[u8]> {
+    map.iter()
+        .find(|(k, _)| k.as_integer().is_some_and(|k| i128::from(k) == key))
+        .and_then(|(_, v)| v.as_bytes())
+        .map(Vec::as_slice)
+}
+
+// Reads an ES256 COSE key into an uncompressed SEC1 point
+fn parse_cose_key(data: &[u8]) -> Result<Vec<u8>, WebAuthnError> {
+    let key: Value =
+        ciborium::from_reader(data).map_err(|_| WebAuthnError::Malformed("public key"))?;
+    let key = key.as_map().ok_or(WebAuthnError::Malformed("public key"))?;
+
+    if cose_int(key, COSE_KEY_TYPE) != Some(COSE_KEY_TYPE_EC2)
+        || cose_int(key, COSE_ALGORITHM) != Some(COSE_ALGORITHM_ES256.into())
+        || cose_int(key, COSE_EC2_CURVE) != Some(COSE_CURVE_P256)
+    {
+        return Err(WebAuthnError::UnsupportedKey);
+    }
+    let (Some(x), Some(y)) = (cose_bytes(key, COSE_EC2_X), cose_bytes(key, COSE_EC2_Y)) else {
+        return Err(WebAuthnError::Malformed("public key"));
+    };
+    if x.len() != 32 || y.len() != 32 {
+        return Err(WebAuthnError::Malformed("public key"));
+    }
+
+    let point = EncodedPoint::from_affine_coordinates(x.into(), y.into(), false);
+    // Rejects points that are not on the curve
+    VerifyingKey::from_encoded_point(&point).map_err(|_| WebAuthnError::UnsupportedKey)?;
+    Ok(point.as_bytes().to_vec())
+}
+
+// Checks the response to a registration ceremony and returns the new credential. Only the
+// "none" attestation format is accepted, so nothing is claimed about the authenticator model.
+pub fn verify_registration(
+    rp: RelyingParty,
+    challenge: &WebAuthnChallenge,
+    client_data_json: &[u8],
+    attestation_object: &[u8],
+) -> Result<WebAuthnCredential, WebAuthnError> {
+    check_client_data(client_data_json, "webauthn.create", challenge, rp)?;
+
+    let attestation: Value = ciborium::from_reader(attestation_object)
+        .map_err(|_| WebAuthnError::Malformed("attestation object"))?;
+    let attestation = attestation
+        .as_map()
+        .ok_or(WebAuthnError::Malformed("attestation object"))?;
+
+    let format = map_get(attestation, "fmt").and_then(Value::as_text);
+    let statement = map_get(attestation, "attStmt").and_then(Value::as_map);
+    match (format, statement) {
+        (Some("none"), Some(statement)) if statement.is_empty() => {}
+        _ => return Err(WebAuthnError::UnsupportedAttestation),
+    }
+
+    let auth_data = map_get(attestation, "authData")
+        .and_then(Value::as_bytes)
+        .ok_or(WebAuthnError::Malformed("attestation object"))?;
+    let auth_data = parse_authenticator_data(auth_data, rp, false)?;
+    if auth_data.flags & FLAG_ATTESTED_CREDENTIAL_DATA == 0 || auth_data.rest.len() < 18 {
+        return Err(WebAuthnError::Malformed("authenticator data"));
+    }
+
+    // Skips the 16 byte AAGUID, which is all zeroes with "none" attestation anyway
+    let id_len = u16::from_be_bytes([auth_data.rest[16], auth_data.rest[17]]) as usize;
+    let rest = &auth_data.rest[18..];
+    if id_len == 0 || id_len > MAX_CREDENTIAL_ID_BYTES || rest.len() < id_len {
+        return Err(WebAuthnError::Malformed("authenticator data"));
+    }
+    let (id, public_key) = rest.split_at(id_len);
+
+    Ok(WebAuthnCredential {
+        id: CredentialId::from_bytes(id),
+        public_key: parse_cose_key(public_key)?,
+        sign_count: auth_data.sign_count,
+    })
+}
+
+// Checks the response to an authentication ceremony against a stored credential and returns
+// the authenticator's new signature counter
+pub fn verify_assertion(
+    rp: RelyingParty,
+    challenge: &WebAuthnChallenge,
+    credential: &WebAuthnCredential,
+    client_data_json: &[u8],
+    authenticator_data: &[u8],
+    signature: &[u8],
+    require_user_verification: bool,
+) -> Result<u32, WebAuthnError> {
+    check_client_data(client_data_json, "webauthn.get", challenge, rp)?;
+    let auth_data = parse_authenticator_data(authenticator_data, rp, require_user_verification)?;
+
+    let key = VerifyingKey::from_sec1_bytes(&credential.public_key)
+        .map_err(|_| WebAuthnError::UnsupportedKey)?;
+    let signature =
+        DerSignature::try_from(signature).map_err(|_| WebAuthnError::Malformed("signature"))?;
+
+    let mut signed = authenticator_data.to_vec();
+    signed.extend_from_slice(&Sha256::digest(client_data_json));
+    key.verify(&signed, &signature)
+        .map_err(|_| WebAuthnError::InvalidSignature)?;
+
+    // Authenticators that do not keep a counter always report zero
+    let counters_in_use = auth_data.sign_count != 0 || credential.sign_count != 0;
+    if counters_in_use && auth_data.sign_count <= credential.sign_count {
+        return Err(WebAuthnError::SignCountRegressed);
+    }
+
+    Ok(auth_data.sign_count)
+}
+
+// Opaque WebAuthn user handle, so the email address is not stored on the authenticator
+pub fn user_handle(email: &Email) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(email.as_ref().expose_secret().as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use p256::ecdsa::{signature::Signer, SigningKey};
+    use rand_core::OsRng;
+
+    const RP: RelyingParty = RelyingParty {
+        id: "localhost",
+        origin: "http://localhost:8000",
+    };
+
+    // Software authenticator with a single P-256 credential
+    struct Authenticator {
+        key: SigningKey,
+        id: Vec<u8>,
+        sign_count: u32,
+    }
+
+    impl Authenticator {
+        fn new() -> Self {
+            Self {
+                key: SigningKey::random(&mut OsRng),
+                id: vec![7; 16],
+                sign_count: 0,
+            }
+        }
+
+        fn client_data(ceremony_type: &str, challenge: &WebAuthnChallenge) -> Vec<u8> {
+            serde_json::json!({
+                "type": ceremony_type,
+                "challenge": challenge.as_ref(),
+                "origin": RP.origin,
+            })
+            .to_string()
+            .into_bytes()
+        }
+
+        fn auth_data(&self, flags: u8) -> Vec<u8> {
+            let mut data = Sha256::digest(RP.id.as_bytes()).to_vec();
+            data.push(flags);
+            data.extend_from_slice(&self.sign_count.to_be_bytes());
+            data
+        }
+
+        fn register(&self, challenge: &WebAuthnChallenge) -> (Vec<u8>, Vec<u8>) {
+            let point = self.key.verifying_key().to_encoded_point(false);
+            let cose_key = Value::Map(vec![
+                (Value::from(1), Value::from(2)),
+                (Value::from(3), Value::from(-7)),
+                (Value::from(-1), Value::from(1)),
+                (Value::from(-2), Value::Bytes(point.x().unwrap().to_vec())),
+                (Value::from(-3), Value::Bytes(point.y().unwrap().to_vec())),
+            ]);
+
+            let mut auth_data = self.auth_data(FLAG_USER_PRESENT | FLAG_ATTESTED_CREDENTIAL_DATA);
+            auth_data.extend_from_slice(&[0; 16]);
+            auth_data.extend_from_slice(&(self.id.len() as u16).to_be_bytes());
+            auth_data.extend_from_slice(&self.id);
+            ciborium::into_writer(&cose_key, &mut auth_data).unwrap();
+
+            let attestation = Value::Map(vec![
+                (Value::from("fmt"), Value::from("none")),
+                (Value::from("attStmt"), Value::Map(vec![])),
+                (Value::from("authData"), Value::Bytes(auth_data)),
+            ]);
+            let mut attestation_object = Vec::new();
+            ciborium::into_writer(&attestation, &mut attestation_object).unwrap();
+
+            (
+                Self::client_data("webauthn.create", challenge),
+                attestation_object,
+            )
+        }
+
+        fn assert(&mut self, challenge: &WebAuthnChallenge, flags: u8) -> [Vec<u8>; 3] {
+            self.sign_count += 1;
+            let client_data = Self::client_data("webauthn.get", challenge);
+            let auth_data = self.auth_data(flags);
+
+            let mut signed = auth_data.clone();
+            signed.extend_from_slice(&Sha256::digest(&client_data));
+            let signature: p256::ecdsa::Signature = self.key.sign(&signed);
+
+            [
+                client_data,
+                auth_data,
+                signature.to_der().as_bytes().to_vec(),
+            ]
+        }
+    }
+
+    fn registered() -> (Authenticator, WebAuthnCredential) {
+        let authenticator = Authenticator::new();
+        let challenge = WebAuthnChallenge::default();
+        let (client_data, attestation) = authenticator.register(&challenge);
+        let credential = verify_registration(RP, &challenge, &client_data, &attestation).unwrap();
+        (authenticator, credential)
+    }
+
+    #[test]
+    fn registers_es256_credential() {
+        let (authenticator, credential) = registered();
+        assert_eq!(credential.id, CredentialId::from_bytes(&authenticator.id));
+        assert_eq!(
+            credential.public_key,
+            authenticator
+                .key
+                .verifying_key()
+                .to_encoded_point(false)
+                .as_bytes()
+        );
+        assert_eq!(credential.sign_count, 0);
+    }
+
+    #[test]
+    fn registration_is_bound_to_challenge_and_origin() {
+        let authenticator = Authenticator::new();
+        let challenge = WebAuthnChallenge::default();
+        let (client_data, attestation) = authenticator.register(&challenge);
+
+        assert_eq!(
+            verify_registration(
+                RP,
+                &WebAuthnChallenge::default(),
+                &client_data,
+                &attestation
+            ),
+            Err(WebAuthnError::ChallengeMismatch)
+        );
+        let other_origin = RelyingParty {
+            origin: "https://evil.example",
+            ..RP
+        };
+        assert_eq!(
+            verify_registration(other_origin, &challenge, &client_data, &attestation),
+            Err(WebAuthnError::OriginMismatch)
+        );
+        let other_rp = RelyingParty {
+            id: "example.com",
+            ..RP
+        };
+        assert_eq!(
+            verify_registration(other_rp, &challenge, &client_data, &attestation),
+            Err(WebAuthnError::RpIdMismatch)
+        );
+    }
+
+    #[test]
+    fn verifies_assertions_and_their_counter() {
+        let (mut authenticator, mut credential) = registered();
+        let challenge = WebAuthnChallenge::default();
+
+        let [client_data, auth_data, signature] =
+            authenticator.assert(&challenge, FLAG_USER_PRESENT);
+        let count = verify_assertion(
+            RP,
+            &challenge,
+            &credential,
+            &client_data,
+            &auth_data,
+            &signature,
+            false,
+        )
+        .unwrap();
+        assert_eq!(count, 1);
+        credential.sign_count = count;
+
+        // Replaying the same response fails on the counter
+        assert_eq!(
+            verify_assertion(
+                RP,
+                &challenge,
+                &credential,
+                &client_data,
+                &auth_data,
+                &signature,
+                false
+            ),
+            Err(WebAuthnError::SignCountRegressed)
+        );
+    }
+
+    #[test]
+    fn rejects_tampered_or_unverified_assertions() {
+        let (mut authenticator, credential) = registered();
+        let challenge = WebAuthnChallenge::default();
+        let [client_data, mut auth_data, signature] =
+            authenticator.assert(&challenge, FLAG_USER_PRESENT);
+
+        assert_eq!(
+            verify_assertion(
+                RP,
+                &challenge,
+                &credential,
+                &client_data,
+                &auth_data,
+                &signature,
+                true
+            ),
+            Err(WebAuthnError::UserNotVerified)
+        );
+
+        auth_data[32] |= FLAG_USER_VERIFIED;
+        assert_eq!(
+            verify_assertion(
+                RP,
+                &challenge,
+                &credential,
+                &client_data,
+                &auth_data,
+                &signature,
+                true
+            ),
+            Err(WebAuthnError::InvalidSignature)
+        );
+    }
+
+    #[test]
+    fn parses_challenge_from_client_data() {
+        let challenge = WebAuthnChallenge::default();
+        let client_data = Authenticator::client_data("webauthn.get", &challenge);
+        assert_eq!(client_data_challenge(&client_data), Ok(challenge));
+        assert!(client_data_challenge(b"{}").is_err());
+    }
+}
@@ -1,4 +1,4 @@
-use axum::http::{Method, StatusCode};
+use axum::http::{header, Method, StatusCode};
 use axum::response::{IntoResponse, Json, Response};
 use axum::routing::{get, post};
 use axum::serve::Serve;
@@ -8,12 +8,38 @@ use std::error::Error;
 pub mod routes;
 pub mod utils;
 use routes::{
-    login::login, logout::logout, signup::signup, verify_2fa::verify_2fa,
+    admin::{
+        create_invite, get_account_status_history, list_dead_letters, reload_email_domains,
+        replay_dead_letter, set_account_status, set_must_change_password,
+    },
+    change_password::change_password,
+    email_code_login::{request_login_code, verify_login_code},
+    forgot_password::forgot_password,
+    hotp::enroll_hotp,
+    locale::set_locale,
+    login::login,
+    logout::logout,
+    magic_link::{magic_link_callback, request_magic_link},
+    recovery_codes::regenerate_recovery_codes,
+    resend_2fa::resend_2fa,
+    reset_password::reset_password,
+    signup::signup,
+    sms::{register_phone, verify_phone},
+    totp::{confirm_totp, enroll_totp},
+    trusted_devices::{list_trusted_devices, revoke_trusted_device},
+    two_factor_methods::{list_two_factor_methods, set_preferred_two_factor_method},
+    verify_2fa::verify_2fa,
     verify_token::verify_token,
+    webauthn::{
+        finish_webauthn_login, finish_webauthn_registration, start_webauthn_login,
+        start_webauthn_registration,
+    },
 };
 pub mod app_state;
 pub mod domain;
 pub mod services;
+use crate::domain::authentication::AuthLevel;
+use crate::domain::email_domain_policy::EmailDomainRejection;
 use crate::domain::error::AuthAPIError;
 pub use app_state::app_state::AppState;
 use redis::Client;
@@ -30,6 +56,16 @@ pub struct ErrorResponse {
     pub error: String,
 }
 
+// Tells the client what kind of login the operation needs, so it can send the user back
+// through it and retry
+#[derive(Serialize, Deserialize)]
+pub struct ReauthenticationRequiredResponse {
+    pub error: String,
+    pub acr: AuthLevel,
+    #[serde(rename = "maxAge")]
+    pub max_age: i64,
+}
+
 impl IntoResponse for AuthAPIError {
     fn into_response(self) -> Response {
         log_error_chain(&self);
@@ -44,6 +80,79 @@ impl IntoResponse for AuthAPIError {
             }
             AuthAPIError::InvalidToken => (StatusCode::UNAUTHORIZED, "Invalid token"),
             AuthAPIError::MissingToken => (StatusCode::BAD_REQUEST, "Missing token"),
+            AuthAPIError::PasswordReused => (
+                StatusCode::BAD_REQUEST,
+                "Password was used recently, please choose a different one",
+            ),
+            AuthAPIError::UserNotFound => (StatusCode::NOT_FOUND, "User not found"),
+            AuthAPIError::DeadLetterNotFound => (StatusCode::NOT_FOUND, "Dead letter not found"),
+            AuthAPIError::DeadLetterExpired => (
+                StatusCode::GONE,
+                "Dead letter has expired and can no longer be replayed",
+            ),
+            AuthAPIError::AccountLocked => (
+                StatusCode::LOCKED,
+                "Account temporarily locked after too many failed login attempts",
+            ),
+            AuthAPIError::AccountInactive => (StatusCode::FORBIDDEN, "Account is not active"),
+            AuthAPIError::InvalidInviteCode => (
+                StatusCode::FORBIDDEN,
+                "Invite code is missing, invalid, expired or used up",
+            ),
+            AuthAPIError::EmailDomainRejected(rejection) => (
+                StatusCode::BAD_REQUEST,
+                match rejection {
+                    EmailDomainRejection::NotAllowed => "Email domain is not allowed",
+                    EmailDomainRejection::Blocked => "Email domain is blocked",
+                    EmailDomainRejection::Disposable => {
+                        "Disposable email addresses are not allowed"
+                    }
+                },
+            ),
+            AuthAPIError::TotpAlreadyEnrolled => {
+                (StatusCode::CONFLICT, "Authenticator app already enrolled")
+            }
+            AuthAPIError::TotpNotEnrolled => (
+                StatusCode::BAD_REQUEST,
+                "No authenticator app enrollment in progress",
+            ),
+            AuthAPIError::HotpAlreadyEnrolled => {
+                (StatusCode::CONFLICT, "Hardware token already enrolled")
+            }
+            AuthAPIError::TwoFactorNotEnabled => (
+                StatusCode::BAD_REQUEST,
+                "Two-factor authentication is not enabled",
+            ),
+            AuthAPIError::PhoneNotVerified => (
+                StatusCode::BAD_REQUEST,
+                "Phone number has not been verified",
+            ),
+            AuthAPIError::TwoFactorMethodUnavailable => {
+                (StatusCode::BAD_REQUEST, "2FA method not available")
+            }
+            AuthAPIError::PasswordLoginRequired => (
+                StatusCode::FORBIDDEN,
+                "Log in with your password to complete two-factor authentication",
+            ),
+            AuthAPIError::TooManyCodeAttempts => (
+                StatusCode::TOO_MANY_REQUESTS,
+                "Too many incorrect codes, please start over",
+            ),
+            AuthAPIError::ResendTooSoon => (
+                StatusCode::TOO_MANY_REQUESTS,
+                "Please wait before requesting another code",
+            ),
+            AuthAPIError::TooManyResends => (
+                StatusCode::TOO_MANY_REQUESTS,
+                "Too many codes requested, please start over",
+            ),
+            AuthAPIError::InvalidLocale => (StatusCode::BAD_REQUEST, "Invalid locale"),
+            AuthAPIError::TrustedDeviceNotFound => {
+                (StatusCode::NOT_FOUND, "Trusted device not found")
+            }
+            AuthAPIError::ReauthenticationRequired { level, max_age } => {
+                return reauthentication_required(level, max_age.num_seconds())
+            }
         };
         let body = Json(ErrorResponse {
             error: error_message.to_string(),
@@ -75,9 +184,57 @@ impl Application {
             .nest_service("/", ServeDir::new("assets"))
             .route("/signup", post(signup))
             .route("/login", post(login))
+            .route("/login/email-code", post(request_login_code))
+            .route("/login/email-code/verify", post(verify_login_code))
+            .route("/login/magic-link", post(request_magic_link))
+            .route("/login/magic-link/callback", get(magic_link_callback))
             .route("/logout", post(logout))
             .route("/verify-token", post(verify_token))
             .route("/verify-2fa", post(verify_2fa))
+            .route("/resend-2fa", post(resend_2fa))
+            .route("/2fa/totp/enroll", post(enroll_totp))
+            .route("/2fa/totp/confirm", post(confirm_totp))
+            .route("/2fa/hotp/enroll", post(enroll_hotp))
+            .route("/2fa/recovery-codes", post(regenerate_recovery_codes))
+            .route("/2fa/sms/phone", post(register_phone))
+            .route("/2fa/sms/phone/verify", post(verify_phone))
+            .route("/2fa/methods", get(list_two_factor_methods))
+            .route(
+                "/2fa/methods/preferred",
+                post(set_preferred_two_factor_method),
+            )
+            .route("/trusted-devices", get(list_trusted_devices))
+            .route("/trusted-devices/revoke", post(revoke_trusted_device))
+            .route(
+                "/webauthn/register/start",
+                post(start_webauthn_registration),
+            )
+            .route(
+                "/webauthn/register/finish",
+                post(finish_webauthn_registration),
+            )
+            .route("/webauthn/login/start", post(start_webauthn_login))
+            .route("/webauthn/login/finish", post(finish_webauthn_login))
+            .route("/change-password", post(change_password))
+            .route("/forgot-password", post(forgot_password))
+            .route("/reset-password", post(reset_password))
+            .route("/locale", post(set_locale))
+            .route(
+                "/admin/must-change-password",
+                post(set_must_change_password),
+            )
+            .route("/admin/user-status", post(set_account_status))
+            .route("/admin/invites", post(create_invite))
+            .route("/admin/email-domains/reload", post(reload_email_domains))
+            .route("/admin/email-outbox/dead-letters", get(list_dead_letters))
+            .route(
+                "/admin/email-outbox/dead-letters/replay",
+                post(replay_dead_letter),
+            )
+            .route(
+                "/admin/user-status-history",
+                post(get_account_status_history),
+            )
             .with_state(app_state)
             .layer(cors)
             .layer(
@@ -116,6 +273,26 @@ pub fn get_redis_client(redis_hostname: String) -> RedisResult<Client> {
     redis::Client::open(redis_url)
 }
 
+// Follows RFC 9470, so OAuth-style clients recognise the challenge from the header alone
+fn reauthentication_required(level: AuthLevel, max_age: i64) -> Response {
+    let challenge = format!(
+        r#"Bearer error="insufficient_user_authentication", acr_values="{}", max_age={}"#,
+        level.as_str(),
+        max_age
+    );
+    let body = Json(ReauthenticationRequiredResponse {
+        error: "Reauthentication required".to_string(),
+        acr: level,
+        max_age,
+    });
+    (
+        StatusCode::UNAUTHORIZED,
+        [(header::WWW_AUTHENTICATE, challenge)],
+        body,
+    )
+        .into_response()
+}
+
 fn log_error_chain(e: &(dyn Error + 'static)) {
     let separator =
         "\n-----------------------------------------------------------------------------------\n";
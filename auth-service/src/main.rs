@@ -1,18 +1,52 @@
+use auth_service::app_state::{
+    app_state::{EmailClientType, SmsClientType},
+    settings::{EmailProvider, Settings},
+};
+use auth_service::data_stores::postgres_email_outbox_store::PostgresEmailOutboxStore;
+use auth_service::data_stores::postgres_hotp_store::PostgresHotpStore;
+use auth_service::data_stores::postgres_invite_store::PostgresInviteStore;
+use auth_service::data_stores::postgres_phone_store::PostgresPhoneStore;
+use auth_service::data_stores::postgres_recovery_code_store::PostgresRecoveryCodeStore;
+use auth_service::data_stores::postgres_totp_store::PostgresTotpStore;
+use auth_service::data_stores::postgres_trusted_device_store::PostgresTrustedDeviceStore;
 use auth_service::data_stores::postgres_user_store::PostgresUserStore;
-use auth_service::data_stores::redis_two_fa_code_store::RedisTwoFACodeStore;
+use auth_service::data_stores::postgres_webauthn_credential_store::PostgresWebAuthnCredentialStore;
+use auth_service::data_stores::redis_login_attempt_store::RedisLoginAttemptStore;
+use auth_service::data_stores::redis_two_fa_code_store::{
+    RedisTwoFACodeStore, LOGIN_CODE_PREFIX, PHONE_CODE_PREFIX,
+};
+use auth_service::data_stores::redis_webauthn_ceremony_store::RedisWebAuthnCeremonyStore;
+use auth_service::domain::email_domain_policy::EmailDomainPolicy;
+use auth_service::domain::email_template::EmailTemplates;
+use auth_service::domain::phone_number::PhoneNumber;
 use auth_service::get_postgres_pool;
-use auth_service::utils::constants::{prod, DATABASE_URL, POSTMARK_AUTH_TOKEN, REDIS_HOST_NAME};
+use auth_service::utils::constants::{
+    prod, ARGON2_PARAMS, DATABASE_URL, EMAIL_PROVIDER, PASSWORD_HISTORY_SIZE, POSTMARK_AUTH_TOKEN,
+    REDIS_HOST_NAME, SMTP_HOST, SMTP_PASSWORD, SMTP_POOL_SIZE, SMTP_PORT, SMTP_TLS, SMTP_USERNAME,
+    TWILIO_ACCOUNT_SID, TWILIO_AUTH_TOKEN, TWILIO_FROM_NUMBER,
+};
 use auth_service::utils::tracing::init_tracing;
 use auth_service::{
-    app_state::app_state::AppState, domain::Email, get_redis_client,
+    app_state::app_state::AppState,
+    argon2_password_hasher::Argon2PasswordHasher,
+    domain::Email,
+    get_redis_client,
     hashset_banned_token_store::HashsetBannedTokenStore,
-    services::postmark_email_client::PostmarkEmailClient, Application,
+    legacy_password_hasher::LegacyPasswordHasher,
+    services::{
+        email_outbox_worker::EmailOutboxWorker,
+        mock_sms_client::MockSmsClient,
+        postmark_email_client::PostmarkEmailClient,
+        smtp_email_client::{SmtpConfig, SmtpEmailClient},
+        twilio_sms_client::TwilioSmsClient,
+    },
+    Application,
 };
 use reqwest::Client;
 use secrecy::Secret;
 use sqlx::PgPool;
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use tokio::sync::{Notify, RwLock};
 
 #[tokio::main]
 async fn main() {
@@ -20,19 +54,67 @@ async fn main() {
     init_tracing().expect("Failed to install color_eyre");
     let pg_pool = configure_postgresql().await;
     let redis_conn = configure_redis();
-    let userstore = PostgresUserStore::new(pg_pool);
+    let password_hasher = Arc::new(LegacyPasswordHasher::new(Argon2PasswordHasher::new(
+        ARGON2_PARAMS.clone(),
+    )));
+    let invite_store = PostgresInviteStore::new(pg_pool.clone());
+    let totp_store = PostgresTotpStore::new(pg_pool.clone());
+    let hotp_store = PostgresHotpStore::new(pg_pool.clone());
+    let recovery_code_store = PostgresRecoveryCodeStore::new(pg_pool.clone());
+    let webauthn_credential_store = PostgresWebAuthnCredentialStore::new(pg_pool.clone());
+    let phone_store = PostgresPhoneStore::new(pg_pool.clone());
+    let trusted_device_store = PostgresTrustedDeviceStore::new(pg_pool.clone());
+    let email_outbox = Arc::new(RwLock::new(PostgresEmailOutboxStore::new(pg_pool.clone())));
+    let email_outbox_wakeup = Arc::new(Notify::new());
+    let userstore = PostgresUserStore::new(pg_pool, password_hasher, *PASSWORD_HISTORY_SIZE);
     let tokenstore = HashsetBannedTokenStore::new();
-    let two_fa_code_store = RedisTwoFACodeStore::new(redis_conn);
-    let email_client = Arc::new(configure_postmark_email_client());
+    let settings = Settings::from_env();
+    let code_ttl = settings.two_fa.code_ttl;
+    let two_fa_code_store = RedisTwoFACodeStore::new(redis_conn.clone(), code_ttl);
+    let login_code_store =
+        RedisTwoFACodeStore::with_key_prefix(redis_conn.clone(), LOGIN_CODE_PREFIX, code_ttl);
+    let phone_code_store =
+        RedisTwoFACodeStore::with_key_prefix(redis_conn.clone(), PHONE_CODE_PREFIX, code_ttl);
+    let webauthn_ceremony_store = RedisWebAuthnCeremonyStore::new(redis_conn.clone());
+    let login_attempt_store = RedisLoginAttemptStore::new(redis_conn);
+    let email_client = configure_email_client();
+    let sms_client = configure_sms_client();
+    let email_domain_policy = EmailDomainPolicy::load(&settings.email_domains)
+        .expect("Failed to load the email domain lists");
+    let email_templates =
+        EmailTemplates::load(&settings.email_templates).expect("Failed to load email templates");
+    let email_outbox_worker = EmailOutboxWorker::new(
+        email_outbox.clone(),
+        email_client,
+        settings.email_outbox,
+        email_outbox_wakeup.clone(),
+    );
     let app_state = AppState::new(
         Arc::new(RwLock::new(userstore)),
         Arc::new(RwLock::new(tokenstore)),
         Arc::new(RwLock::new(two_fa_code_store)),
-        email_client,
+        Arc::new(RwLock::new(login_code_store)),
+        Arc::new(email_templates),
+        email_outbox.clone(),
+        email_outbox_wakeup.clone(),
+        Arc::new(RwLock::new(login_attempt_store)),
+        Arc::new(RwLock::new(invite_store)),
+        Arc::new(RwLock::new(email_domain_policy)),
+        Arc::new(RwLock::new(totp_store)),
+        Arc::new(RwLock::new(hotp_store)),
+        Arc::new(RwLock::new(recovery_code_store)),
+        Arc::new(RwLock::new(webauthn_credential_store)),
+        Arc::new(RwLock::new(webauthn_ceremony_store)),
+        sms_client,
+        Arc::new(RwLock::new(phone_store)),
+        Arc::new(RwLock::new(phone_code_store)),
+        Arc::new(RwLock::new(trusted_device_store)),
+        settings,
     );
     let app = Application::build(app_state, prod::APP_ADDRESS)
         .await
         .expect("Could not build the app");
+    tokio::spawn(email_outbox_worker.run());
 
     app.run().await.expect("Could not run app");
 }
@@ -59,6 +141,13 @@ fn configure_redis() -> Arc<RwLock<redis::Connection>> {
     Arc::new(RwLock::new(conn))
 }
 
+fn configure_email_client() -> EmailClientType {
+    match *EMAIL_PROVIDER {
+        EmailProvider::Postmark => Arc::new(configure_postmark_email_client()),
+        EmailProvider::Smtp => Arc::new(configure_smtp_email_client()),
+    }
+}
+
 fn configure_postmark_email_client() -> PostmarkEmailClient {
     let http_client = Client::builder()
         .timeout(prod::email_client::TIMEOUT)
@@ -72,3 +161,52 @@ fn configure_postmark_email_client() -> PostmarkEmailClient {
         http_client,
     )
 }
+
+fn configure_smtp_email_client() -> SmtpEmailClient {
+    let host = SMTP_HOST
+        .to_owned()
+        .expect("SMTP_HOST must be set when EMAIL_PROVIDER is smtp.");
+    let credentials = match (SMTP_USERNAME.to_owned(), SMTP_PASSWORD.to_owned()) {
+        (Some(username), Some(password)) => Some((username, password)),
+        (None, None) => None,
+        _ => panic!("SMTP_USERNAME and SMTP_PASSWORD must be set together."),
+    };
+
+    SmtpEmailClient::new(
+        SmtpConfig {
+            host,
+            port: SMTP_PORT.unwrap_or_else(|| SMTP_TLS.default_port()),
+            tls: *SMTP_TLS,
+            credentials,
+            pool_size: *SMTP_POOL_SIZE,
+            timeout: prod::email_client::TIMEOUT,
+        },
+        Email::parse(Secret::new(prod::email_client::SENDER.to_owned())).unwrap(),
+    )
+    .expect("Failed to configure the SMTP email client")
+}
+
+// Without Twilio credentials SMS codes are only logged, which is enough for local development
+fn configure_sms_client() -> SmsClientType {
+    let (Some(account_sid), Some(auth_token), Some(from_number)) = (
+        TWILIO_ACCOUNT_SID.to_owned(),
+        TWILIO_AUTH_TOKEN.to_owned(),
+        TWILIO_FROM_NUMBER.to_owned(),
+    ) else {
+        tracing::warn!("Twilio is not configured, SMS messages will not be delivered");
+        return Arc::new(MockSmsClient);
+    };
+
+    let http_client = Client::builder()
+        .timeout(prod::sms_client::TIMEOUT)
+        .build()
+        .expect("Failed to build HTTP client");
+
+    Arc::new(TwilioSmsClient::new(
+        prod::sms_client::BASE_URL.to_owned(),
+        account_sid,
+        auth_token,
+        PhoneNumber::parse(Secret::new(from_number)).expect("TWILIO_FROM_NUMBER must be valid"),
+        http_client,
+    ))
+}
@@ -0,0 +1,296 @@
+use crate::app_state::app_state::AppState;
+use crate::domain::data_store::{EmailOutboxStoreError, UserStoreError};
+use crate::domain::email_domain_policy::EmailDomainPolicy;
+use crate::domain::email_outbox::OutboxEmailId;
+use crate::domain::error::AuthAPIError;
+use crate::domain::invite::Invite;
+use crate::domain::user::{AccountStatus, StatusChange};
+use crate::domain::Email;
+use crate::utils::admin_auth::AdminAuth;
+use crate::utils::constants::DEFAULT_INVITE_TTL_HOURS;
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use chrono::{DateTime, Duration, Utc};
+use secrecy::{ExposeSecret, Secret};
+use serde::{Deserialize, Serialize};
+
+#[tracing::instrument(name = "Set Must Change Password", skip_all)]
+pub async fn set_must_change_password(
+    _: AdminAuth,
+    State(state): State<AppState>,
+    Json(request): Json<SetMustChangePasswordRequest>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let email =
+        Email::parse(Secret::new(request.email)).map_err(|_| AuthAPIError::InvalidCredentials)?;
+
+    match state
+        .userstore
+        .write()
+        .await
+        .set_must_change_password(&email, request.must_change_password)
+        .await
+    {
+        Ok(_) => Ok((
+            StatusCode::OK,
+            Json(AdminResponse {
+                message: "User updated".to_string(),
+            }),
+        )),
+        Err(UserStoreError::UserNotFound) => Err(AuthAPIError::UserNotFound),
+        Err(e) => Err(AuthAPIError::UnexpectedError(e.into())),
+    }
+}
+
+#[tracing::instrument(name = "Set Account Status", skip_all)]
+pub async fn set_account_status(
+    _: AdminAuth,
+    State(state): State<AppState>,
+    Json(request): Json<SetAccountStatusRequest>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let email =
+        Email::parse(Secret::new(request.email)).map_err(|_| AuthAPIError::InvalidCredentials)?;
+    if request.reason.trim().is_empty() {
+        return Err(AuthAPIError::InvalidCredentials);
+    }
+
+    match state
+        .userstore
+        .write()
+        .await
+        .set_status(&email, request.status, request.reason)
+        .await
+    {
+        Ok(_) => Ok((
+            StatusCode::OK,
+            Json(AdminResponse {
+                message: "User updated".to_string(),
+            }),
+        )),
+        Err(UserStoreError::UserNotFound) => Err(AuthAPIError::UserNotFound),
+        Err(e) => Err(AuthAPIError::UnexpectedError(e.into())),
+    }
+}
+
+#[tracing::instrument(name = "Get Account Status History", skip_all)]
+pub async fn get_account_status_history(
+    _: AdminAuth,
+    State(state): State<AppState>,
+    Json(request): Json<AccountStatusHistoryRequest>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let email =
+        Email::parse(Secret::new(request.email)).map_err(|_| AuthAPIError::InvalidCredentials)?;
+
+    let user_store = state.userstore.read().await;
+    let user = match user_store.get_user(&email).await {
+        Ok(user) => user,
+        Err(UserStoreError::UserNotFound) => return Err(AuthAPIError::UserNotFound),
+        Err(e) => return Err(AuthAPIError::UnexpectedError(e.into())),
+    };
+    let history = user_store
+        .get_status_history(&email)
+        .await
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+
+    Ok((
+        StatusCode::OK,
+        Json(AccountStatusHistoryResponse {
+            status: user.status,
+            history,
+        }),
+    ))
+}
+
+#[tracing::instrument(name = "Create Invite", skip_all)]
+pub async fn create_invite(
+    _: AdminAuth,
+    State(state): State<AppState>,
+    Json(request): Json<CreateInviteRequest>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let email = request
+        .email
+        .map(|email| Email::parse(Secret::new(email)))
+        .transpose()
+        .map_err(|_| AuthAPIError::InvalidCredentials)?;
+    let max_uses = request.max_uses.unwrap_or(1);
+    let expires_in_hours = request.expires_in_hours.unwrap_or(DEFAULT_INVITE_TTL_HOURS);
+    if max_uses == 0 || max_uses > i32::MAX as u32 || !(1..=24 * 365).contains(&expires_in_hours) {
+        return Err(AuthAPIError::InvalidCredentials);
+    }
+
+    let invite = Invite::new(email, max_uses, Duration::hours(expires_in_hours));
+    let response = CreateInviteResponse {
+        message: "Invite created".to_string(),
+        invite_code: invite.code.as_ref().expose_secret().to_owned(),
+        max_uses: invite.max_uses,
+        expires_at: invite.expires_at,
+    };
+
+    state
+        .invite_store
+        .write()
+        .await
+        .add_invite(invite)
+        .await
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+
+    Ok((StatusCode::CREATED, Json(response)))
+}
+
+// Re-reads the signup domain allowlist and blocklist files. If a file cannot be read the
+// lists currently in use are kept.
+#[tracing::instrument(name = "Reload Email Domains", skip_all)]
+pub async fn reload_email_domains(
+    _: AdminAuth,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let policy = EmailDomainPolicy::load(&state.settings.email_domains)
+        .map_err(AuthAPIError::UnexpectedError)?;
+    let response = ReloadEmailDomainsResponse {
+        message: "Email domain lists reloaded".to_string(),
+        allowed_domains: policy.allowed_count(),
+        blocked_domains: policy.blocked_count(),
+    };
+
+    *state.email_domain_policy.write().await = policy;
+
+    Ok((StatusCode::OK, Json(response)))
+}
+
+// Emails the outbox gave up on after running out of retries. Bodies are left out as they may
+// hold codes and login links.
+#[tracing::instrument(name = "List Dead Letters", skip_all)]
+pub async fn list_dead_letters(
+    _: AdminAuth,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let dead_letters = state
+        .email_outbox
+        .read()
+        .await
+        .get_dead_letters()
+        .await
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?
+        .into_iter()
+        .map(|email| DeadLetter {
+            id: email.id.to_string(),
+            recipient: email.recipient.as_ref().expose_secret().to_owned(),
+            subject: email.message.subject,
+            attempts: email.attempts,
+            last_error: email.last_error,
+            created_at: email.created_at,
+            expires_at: email.expires_at,
+        })
+        .collect();
+
+    Ok((StatusCode::OK, Json(DeadLettersResponse { dead_letters })))
+}
+
+// Puts a dead letter back in the outbox to be delivered as if it had just been queued
+#[tracing::instrument(name = "Replay Dead Letter", skip_all)]
+pub async fn replay_dead_letter(
+    _: AdminAuth,
+    State(state): State<AppState>,
+    Json(request): Json<ReplayDeadLetterRequest>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let id = OutboxEmailId::parse(&request.id).map_err(|_| AuthAPIError::InvalidCredentials)?;
+
+    match state.email_outbox.write().await.replay(&id).await {
+        Ok(()) => {}
+        Err(EmailOutboxStoreError::EmailNotFound) => return Err(AuthAPIError::DeadLetterNotFound),
+        Err(EmailOutboxStoreError::EmailExpired) => return Err(AuthAPIError::DeadLetterExpired),
+        Err(e) => return Err(AuthAPIError::UnexpectedError(e.into())),
+    }
+    state.email_outbox_wakeup.notify_one();
+
+    Ok((
+        StatusCode::OK,
+        Json(AdminResponse {
+            message: "Email queued for delivery".to_string(),
+        }),
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct SetMustChangePasswordRequest {
+    pub email: String,
+    #[serde(rename = "mustChangePassword")]
+    pub must_change_password: bool,
+}
+
+#[derive(Deserialize, Default, Serialize, Debug)]
+pub struct AdminResponse {
+    pub message: String,
+}
+
+#[derive(Deserialize)]
+pub struct SetAccountStatusRequest {
+    pub email: String,
+    pub status: AccountStatus,
+    pub reason: String,
+}
+
+#[derive(Deserialize)]
+pub struct AccountStatusHistoryRequest {
+    pub email: String,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct AccountStatusHistoryResponse {
+    pub status: AccountStatus,
+    pub history: Vec<StatusChange>,
+}
+
+#[derive(Deserialize)]
+pub struct CreateInviteRequest {
+    // Binds the invite to a single email address
+    pub email: Option<String>,
+    #[serde(rename = "maxUses")]
+    pub max_uses: Option<u32>,
+    #[serde(rename = "expiresInHours")]
+    pub expires_in_hours: Option<i64>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct CreateInviteResponse {
+    pub message: String,
+    #[serde(rename = "inviteCode")]
+    pub invite_code: String,
+    #[serde(rename = "maxUses")]
+    pub max_uses: u32,
+    #[serde(rename = "expiresAt")]
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ReloadEmailDomainsResponse {
+    pub message: String,
+    #[serde(rename = "allowedDomains")]
+    pub allowed_domains: usize,
+    #[serde(rename = "blockedDomains")]
+    pub blocked_domains: usize,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct DeadLetter {
+    pub id: String,
+    pub recipient: String,
+    pub subject: String,
+    pub attempts: u32,
+    #[serde(rename = "lastError")]
+    pub last_error: Option<String>,
+    #[serde(rename = "createdAt")]
+    pub created_at: DateTime<Utc>,
+    // After this the email can't be replayed, as the codes or links in it no longer work
+    #[serde(rename = "expiresAt")]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct DeadLettersResponse {
+    #[serde(rename = "deadLetters")]
+    pub dead_letters: Vec<DeadLetter>,
+}
+
+#[derive(Deserialize)]
+pub struct ReplayDeadLetterRequest {
+    pub id: String,
+}
@@ -0,0 +1,95 @@
+use crate::app_state::app_state::AppState;
+use crate::domain::data_store::UserStoreError;
+use crate::domain::error::AuthAPIError;
+use crate::domain::{Email, Password};
+use crate::utils::{
+    auth::{validate_purpose_token, TokenPurpose},
+    user_auth::StepUpUser,
+};
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use secrecy::Secret;
+use serde::{Deserialize, Serialize};
+
+#[tracing::instrument(name = "Change Password", skip_all)]
+pub async fn change_password(
+    State(state): State<AppState>,
+    user: Result<StepUpUser, AuthAPIError>,
+    Json(request): Json<ChangePasswordRequest>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    // Users forced to change their password at login only hold a password change token, which
+    // they got from a login that just happened. Anyone else needs a recent strong login.
+    let email = match &request.password_change_token {
+        Some(token) => {
+            let claims = validate_purpose_token(
+                Secret::new(token.to_owned()),
+                TokenPurpose::PasswordChange,
+                state.tokenstore.clone(),
+            )
+            .await
+            .map_err(|_| AuthAPIError::InvalidToken)?;
+            Email::parse(Secret::new(claims.sub)).map_err(|_| AuthAPIError::InvalidToken)?
+        }
+        None => user?.email,
+    };
+
+    let current_password = Password::parse(Secret::new(request.current_password))
+        .map_err(|_| AuthAPIError::InvalidCredentials)?;
+    let new_password = Password::parse(Secret::new(request.new_password))
+        .map_err(|_| AuthAPIError::InvalidCredentials)?;
+
+    let mut user_store = state.userstore.write().await;
+
+    match user_store.get_user(&email).await {
+        Ok(user) if !user.status.is_active() => return Err(AuthAPIError::AccountInactive),
+        Ok(_) => {}
+        Err(_) => return Err(AuthAPIError::InvalidToken),
+    }
+
+    if user_store
+        .validate_user(&email, &current_password)
+        .await
+        .is_err()
+    {
+        return Err(AuthAPIError::IncorrectCredentials);
+    }
+
+    match user_store.update_password(&email, new_password).await {
+        Ok(_) => {}
+        Err(UserStoreError::PasswordReused) => return Err(AuthAPIError::PasswordReused),
+        Err(e) => return Err(AuthAPIError::UnexpectedError(e.into())),
+    }
+    drop(user_store);
+
+    // Password change tokens are single-use as well
+    if let Some(token) = request.password_change_token {
+        state
+            .tokenstore
+            .write()
+            .await
+            .store_token(Secret::new(token))
+            .await
+            .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+    }
+
+    Ok((
+        StatusCode::OK,
+        Json(ChangePasswordResponse {
+            message: "Password changed successfully".to_string(),
+        }),
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct ChangePasswordRequest {
+    #[serde(rename = "currentPassword")]
+    pub current_password: String,
+    #[serde(rename = "newPassword")]
+    pub new_password: String,
+    #[serde(rename = "passwordChangeToken", default)]
+    pub password_change_token: Option<String>,
+}
+
+#[derive(Deserialize, Default, Serialize, Debug)]
+pub struct ChangePasswordResponse {
+    pub message: String,
+}
@@ -0,0 +1,185 @@
+use crate::app_state::app_state::AppState;
+use crate::domain::authentication::AuthMethod;
+use crate::domain::data_store::{LoginAttemptId, TwoFACode, TwoFaCodeStore, TwoFaCodeStoreError};
+use crate::domain::email_outbox::IdempotencyKey;
+use crate::domain::email_template::TransactionalEmail;
+use crate::domain::error::AuthAPIError;
+use crate::domain::Email;
+use crate::routes::login::{handle_2fa, handle_no_2fa, handle_password_change_required};
+use crate::routes::verify_2fa::reject_code;
+use crate::utils::email::queue_email;
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use axum_extra::extract::CookieJar;
+use secrecy::{ExposeSecret, Secret};
+use serde::{Deserialize, Serialize};
+
+#[tracing::instrument(name = "Request Login Code", skip_all)]
+pub async fn request_login_code(
+    State(state): State<AppState>,
+    Json(request): Json<LoginCodeRequest>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let email =
+        Email::parse(Secret::new(request.email)).map_err(|_| AuthAPIError::InvalidCredentials)?;
+
+    // Unknown and inactive accounts get a login attempt id too, which simply never matches a
+    // code, so the response does not tell whether the email is registered
+    let login_attempt_id = LoginAttemptId::default();
+    let response = (
+        StatusCode::OK,
+        Json(LoginCodeResponse {
+            message: "If an account exists for this email, a login code has been sent".to_string(),
+            login_attempt_id: login_attempt_id.as_ref().expose_secret().to_owned(),
+        }),
+    );
+
+    let user = match state.userstore.read().await.get_user(&email).await {
+        Ok(user) if user.status.is_active() => user,
+        _ => return Ok(response),
+    };
+    if !claim_login_email(&state, &email).await? {
+        return Ok(response);
+    }
+
+    let code = TwoFACode::generate(&state.settings.two_fa.code_format);
+    state
+        .login_code_store
+        .write()
+        .await
+        .add_code(
+            &email,
+            login_attempt_id.clone(),
+            code.clone(),
+            state.settings.two_fa.max_pending_logins,
+        )
+        .await
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+
+    let code = code.as_ref().expose_secret();
+    let idempotency_key = IdempotencyKey::derive(
+        &email,
+        &[
+            "login_code",
+            login_attempt_id.as_ref().expose_secret(),
+            code,
+        ],
+    );
+    queue_email(
+        &state,
+        &email,
+        user.locale.as_ref(),
+        &TransactionalEmail::LoginCode {
+            code: code.to_owned(),
+            expires_in_minutes: state.settings.two_fa.code_ttl.num_minutes(),
+        },
+        idempotency_key,
+    )
+    .await?;
+
+    Ok(response)
+}
+
+// Login codes and magic links share a cooldown per address, as both email the user a way in.
+// Returns false while it runs, in which case nothing should be sent.
+pub(crate) async fn claim_login_email(
+    state: &AppState,
+    email: &Email,
+) -> Result<bool, AuthAPIError> {
+    match state
+        .login_code_store
+        .write()
+        .await
+        .claim_send(email, state.settings.two_fa.resend_cooldown)
+        .await
+    {
+        Ok(()) => Ok(true),
+        Err(TwoFaCodeStoreError::ResendTooSoon) => Ok(false),
+        Err(e) => Err(AuthAPIError::UnexpectedError(e.into())),
+    }
+}
+
+#[tracing::instrument(name = "Verify Login Code", skip_all)]
+pub async fn verify_login_code(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Json(request): Json<VerifyLoginCodeRequest>,
+) -> (CookieJar, Result<impl IntoResponse, AuthAPIError>) {
+    let email = match Email::parse(Secret::new(request.email)) {
+        Ok(email) => email,
+        Err(_) => return (jar, Err(AuthAPIError::InvalidCredentials)),
+    };
+    let login_attempt_id = match LoginAttemptId::parse(Secret::new(request.login_attempt_id)) {
+        Ok(id) => id,
+        Err(_) => return (jar, Err(AuthAPIError::InvalidCredentials)),
+    };
+    let code = match TwoFACode::parse(
+        Secret::new(request.code),
+        &state.settings.two_fa.code_format,
+    ) {
+        Ok(code) => code,
+        Err(_) => return (jar, Err(AuthAPIError::InvalidCredentials)),
+    };
+
+    let mut login_code_store = state.login_code_store.write().await;
+
+    let expected_code = match login_code_store.get_code(&email, &login_attempt_id).await {
+        Ok(code) => code,
+        Err(_) => return (jar, Err(AuthAPIError::IncorrectCredentials)),
+    };
+
+    if expected_code != code {
+        let error = reject_code(
+            &mut *login_code_store,
+            &email,
+            &login_attempt_id,
+            state.settings.two_fa.max_attempts,
+            AuthAPIError::InvalidCredentials,
+        )
+        .await;
+        return (jar, Err(error));
+    }
+
+    let _ = login_code_store
+        .remove_code(&email, &login_attempt_id)
+        .await;
+    drop(login_code_store);
+
+    let user = match state.userstore.read().await.get_user(&email).await {
+        Ok(user) => user,
+        Err(e) => return (jar, Err(AuthAPIError::UnexpectedError(e.into()))),
+    };
+    if !user.status.is_active() {
+        return (jar, Err(AuthAPIError::AccountInactive));
+    }
+
+    // The code stands in for the password, so 2FA users continue with the same login
+    // attempt into the regular 2FA challenge
+    if user.require_2fa {
+        return handle_2fa(&state, &user, login_attempt_id, AuthMethod::Email, jar).await;
+    }
+
+    if let Some(reason) = user.password_change_reason(state.settings.password_max_age) {
+        return handle_password_change_required(&email, reason, jar);
+    }
+
+    handle_no_2fa(&email, &[AuthMethod::Email], jar).await
+}
+
+#[derive(Deserialize)]
+pub struct LoginCodeRequest {
+    pub email: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct LoginCodeResponse {
+    pub message: String,
+    #[serde(rename = "loginAttemptId")]
+    pub login_attempt_id: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct VerifyLoginCodeRequest {
+    email: String,
+    #[serde(rename = "loginAttemptId")]
+    login_attempt_id: String,
+    code: String,
+}
@@ -0,0 +1,63 @@
+use crate::app_state::app_state::AppState;
+use crate::domain::data_store::UserStoreError;
+use crate::domain::email_outbox::IdempotencyKey;
+use crate::domain::email_template::TransactionalEmail;
+use crate::domain::error::AuthAPIError;
+use crate::domain::Email;
+use crate::utils::auth::{generate_purpose_token, TokenPurpose, TOKEN_TTL_SECONDS};
+use crate::utils::email::queue_email;
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use secrecy::Secret;
+use serde::{Deserialize, Serialize};
+
+// Always answers the same way, whether or not the account exists, so the route cannot be
+// used to discover registered emails.
+#[tracing::instrument(name = "Forgot Password", skip_all)]
+pub async fn forgot_password(
+    State(state): State<AppState>,
+    Json(request): Json<ForgotPasswordRequest>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let email =
+        Email::parse(Secret::new(request.email)).map_err(|_| AuthAPIError::InvalidCredentials)?;
+
+    let user_store = state.userstore.read().await;
+    match user_store.get_user(&email).await {
+        Ok(user) if user.status.is_active() => {
+            let token = generate_purpose_token(&email, TokenPurpose::PasswordReset)
+                .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+
+            let idempotency_key = IdempotencyKey::derive(&email, &["password_reset", &token]);
+            queue_email(
+                &state,
+                &email,
+                user.locale.as_ref(),
+                &TransactionalEmail::PasswordReset {
+                    token,
+                    expires_in_minutes: TOKEN_TTL_SECONDS / 60,
+                },
+                idempotency_key,
+            )
+            .await?;
+        }
+        // Inactive accounts are answered the same way, without a token to get back in with
+        Ok(_) | Err(UserStoreError::UserNotFound) => {}
+        Err(e) => return Err(AuthAPIError::UnexpectedError(e.into())),
+    }
+
+    Ok((
+        StatusCode::OK,
+        Json(ForgotPasswordResponse {
+            message: "If the account exists, a password reset email has been sent".to_string(),
+        }),
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct ForgotPasswordRequest {
+    pub email: String,
+}
+
+#[derive(Deserialize, Default, Serialize, Debug)]
+pub struct ForgotPasswordResponse {
+    pub message: String,
+}
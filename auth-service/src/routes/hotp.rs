@@ -0,0 +1,120 @@
+use crate::app_state::app_state::AppState;
+use crate::domain::data_store::HotpStoreError;
+use crate::domain::error::AuthAPIError;
+use crate::domain::Email;
+use crate::routes::totp::enable_2fa;
+use crate::utils::user_auth::StepUpUser;
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use serde::{Deserialize, Serialize};
+
+// Gives the user a hardware token an admin imported. A code from the token proves they are
+// holding it, and turns on 2FA for the account.
+#[tracing::instrument(name = "Enroll HOTP", skip_all)]
+pub async fn enroll_hotp(
+    user: StepUpUser,
+    State(state): State<AppState>,
+    Json(request): Json<EnrollHotpRequest>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    if has_hotp(&state, &user.email).await? {
+        return Err(AuthAPIError::HotpAlreadyEnrolled);
+    }
+
+    // Unknown serials and tokens enrolled by someone else look the same as a wrong code
+    let token = match state
+        .hotp_store
+        .read()
+        .await
+        .get_unassigned_token(request.serial.trim())
+        .await
+    {
+        Ok(token) => token,
+        Err(HotpStoreError::TokenNotFound) => return Err(AuthAPIError::IncorrectCredentials),
+        Err(e) => return Err(AuthAPIError::UnexpectedError(e.into())),
+    };
+    let counter = token
+        .verify(&request.code, state.settings.hotp.look_ahead)
+        .map_err(AuthAPIError::UnexpectedError)?
+        .ok_or(AuthAPIError::IncorrectCredentials)?;
+
+    match state
+        .hotp_store
+        .write()
+        .await
+        .assign_token(&token.serial, &user.email, counter.saturating_add(1))
+        .await
+    {
+        Ok(_) => {}
+        Err(HotpStoreError::AlreadyEnrolled) => return Err(AuthAPIError::HotpAlreadyEnrolled),
+        Err(HotpStoreError::TokenNotFound) => return Err(AuthAPIError::IncorrectCredentials),
+        Err(e) => return Err(AuthAPIError::UnexpectedError(e.into())),
+    }
+
+    let recovery_codes = enable_2fa(&state, &user.email).await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(EnrollHotpResponse {
+            message: "Hardware token enabled".to_string(),
+            recovery_codes,
+        }),
+    ))
+}
+
+// Whether the user has enrolled a hardware token
+pub(crate) async fn has_hotp(state: &AppState, email: &Email) -> Result<bool, AuthAPIError> {
+    match state.hotp_store.read().await.get_token(email).await {
+        Ok(_) => Ok(true),
+        Err(HotpStoreError::NotEnrolled) => Ok(false),
+        Err(e) => Err(AuthAPIError::UnexpectedError(e.into())),
+    }
+}
+
+// Checks a code against the user's hardware token. An accepted code moves the token's counter
+// past it, so neither it nor any code before it works again.
+pub(crate) async fn verify_hotp_code(
+    state: &AppState,
+    email: &Email,
+    code: &str,
+) -> Result<bool, AuthAPIError> {
+    let token = match state.hotp_store.read().await.get_token(email).await {
+        Ok(token) => token,
+        Err(HotpStoreError::NotEnrolled) => return Ok(false),
+        Err(e) => return Err(AuthAPIError::UnexpectedError(e.into())),
+    };
+    let counter = token
+        .verify(code, state.settings.hotp.look_ahead)
+        .map_err(AuthAPIError::UnexpectedError)?;
+    let Some(counter) = counter else {
+        return Ok(false);
+    };
+
+    match state
+        .hotp_store
+        .write()
+        .await
+        .advance_counter(email, counter.saturating_add(1))
+        .await
+    {
+        Ok(_) => Ok(true),
+        Err(HotpStoreError::CodeReused) => Ok(false),
+        Err(e) => Err(AuthAPIError::UnexpectedError(e.into())),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct EnrollHotpRequest {
+    // Serial number printed on the token
+    pub serial: String,
+    pub code: String,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct EnrollHotpResponse {
+    pub message: String,
+    #[serde(
+        rename = "recoveryCodes",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub recovery_codes: Option<Vec<String>>,
+}
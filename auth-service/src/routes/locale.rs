@@ -0,0 +1,47 @@
+use crate::app_state::app_state::AppState;
+use crate::domain::error::AuthAPIError;
+use crate::domain::locale::Locale;
+use crate::utils::user_auth::AuthenticatedUser;
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use serde::{Deserialize, Serialize};
+
+// Sets the language the user's emails are written in. Without one they get the default.
+#[tracing::instrument(name = "Set locale", skip_all)]
+pub async fn set_locale(
+    user: AuthenticatedUser,
+    State(state): State<AppState>,
+    Json(request): Json<LocaleRequest>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let locale = parse_locale(request.locale)?;
+
+    state
+        .userstore
+        .write()
+        .await
+        .set_locale(&user.email, locale.clone())
+        .await
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+
+    Ok((
+        StatusCode::OK,
+        Json(LocaleResponse {
+            locale: locale.as_ref().map(ToString::to_string),
+        }),
+    ))
+}
+
+pub(crate) fn parse_locale(locale: Option<String>) -> Result<Option<Locale>, AuthAPIError> {
+    locale
+        .map(|locale| Locale::parse(&locale).map_err(|_| AuthAPIError::InvalidLocale))
+        .transpose()
+}
+
+#[derive(Deserialize)]
+pub struct LocaleRequest {
+    pub locale: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct LocaleResponse {
+    pub locale: Option<String>,
+}
@@ -1,12 +1,23 @@
 use crate::app_state::app_state::AppState;
+use crate::domain::authentication::AuthMethod;
 use crate::domain::data_store::*;
+use crate::domain::email_outbox::IdempotencyKey;
+use crate::domain::email_template::TransactionalEmail;
 use crate::domain::error::AuthAPIError;
+use crate::domain::two_factor::TwoFactorMethod;
+use crate::domain::user::PasswordChangeReason;
 use crate::domain::user::User;
 use crate::domain::{Email, Password};
-use crate::utils::auth::generate_auth_cookie;
+use crate::routes::sms::verified_phone;
+use crate::routes::totp::verify_totp_code;
+use crate::routes::trusted_devices::is_trusted_device;
+use crate::routes::two_factor_methods::attempt_methods;
+use crate::utils::auth::{generate_auth_cookie, generate_purpose_token, TokenPurpose};
+use crate::utils::email::queue_email;
 use axum::response::Response;
 use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
 use axum_extra::extract::CookieJar;
+use chrono::Utc;
 use secrecy::{ExposeSecret, Secret};
 use serde::{Deserialize, Serialize};
 
@@ -26,10 +37,17 @@ pub async fn login(
         Err(_) => return (jar, Err(AuthAPIError::InvalidCredentials)),
     };
 
+    if let Err(e) = check_not_locked(&state, &email).await {
+        return (jar, Err(e));
+    }
+
     let user_store = &state.userstore.read().await;
 
     if let Err(_) = user_store.validate_user(&email, &password).await {
-        return (jar, Err(AuthAPIError::IncorrectCredentials));
+        return (
+            jar,
+            Err(handle_failed_login(&state, &**user_store, &email).await),
+        );
     }
 
     let user = match user_store.get_user(&email).await {
@@ -37,64 +55,282 @@ pub async fn login(
         Err(e) => return (jar, Err(AuthAPIError::UnexpectedError(e.into()))),
     };
 
-    let auth_cookie = match generate_auth_cookie(&email) {
-        Ok(cookie) => cookie,
-        Err(e) => return (jar, Err(AuthAPIError::UnexpectedError(e.into()))),
+    if !user.status.is_active() {
+        return (jar, Err(AuthAPIError::AccountInactive));
+    }
+
+    // A code from an authenticator app sent along with the password completes 2FA right away.
+    // Wrong codes count towards a lockout like wrong passwords do.
+    let totp_verified = match (&request.totp_code, user.require_2fa) {
+        (Some(code), true) => match verify_totp_code(&state, &email, code).await {
+            Ok(true) => true,
+            Ok(false) => {
+                return (
+                    jar,
+                    Err(handle_failed_login(&state, &**user_store, &email).await),
+                )
+            }
+            Err(e) => return (jar, Err(e)),
+        },
+        _ => false,
+    };
+
+    // A device the user asked to remember at an earlier 2FA login counts as the second factor,
+    // unless the user is stepping up to a login that needs the second factor itself
+    let device_trusted = if user.require_2fa && !totp_verified && !request.step_up {
+        match is_trusted_device(&state, &jar, &email).await {
+            Ok(trusted) => trusted,
+            Err(e) => return (jar, Err(e)),
+        }
+    } else {
+        false
     };
 
-    let updated_jar = jar.add(auth_cookie);
+    // 2FA users are checked for a pending password change once they pass verify_2fa
+    if user.require_2fa && !totp_verified && !device_trusted {
+        return handle_2fa(
+            &state,
+            &user,
+            LoginAttemptId::default(),
+            AuthMethod::Pwd,
+            jar,
+        )
+        .await;
+    }
 
-    match user.require_2fa {
-        true => handle_2fa(&state, &user.email, updated_jar).await,
-        false => handle_no_2fa(&email, updated_jar).await,
+    if let Err(e) = reset_failed_logins(&state, &email).await {
+        return (jar, Err(e));
     }
+
+    if let Some(reason) = user.password_change_reason(state.settings.password_max_age) {
+        return handle_password_change_required(&email, reason, jar);
+    }
+
+    let methods: &[AuthMethod] = if totp_verified {
+        &[AuthMethod::Pwd, AuthMethod::Otp]
+    } else if device_trusted {
+        &[AuthMethod::Pwd, AuthMethod::Device]
+    } else {
+        &[AuthMethod::Pwd]
+    };
+    handle_no_2fa(&email, methods, jar).await
 }
 
-#[tracing::instrument(name = "Handle 2FA", skip_all)]
-async fn handle_2fa(
+pub(crate) async fn check_not_locked(state: &AppState, email: &Email) -> Result<(), AuthAPIError> {
+    match state
+        .login_attempt_store
+        .read()
+        .await
+        .locked_until(email)
+        .await
+    {
+        Ok(Some(_)) => Err(AuthAPIError::AccountLocked),
+        Ok(None) => Ok(()),
+        Err(e) => Err(AuthAPIError::UnexpectedError(e.into())),
+    }
+}
+
+// Failures only stop counting once the user has passed every factor the login asks for, so
+// guesses at the second factor add up with wrong passwords
+pub(crate) async fn reset_failed_logins(
+    state: &AppState,
+    email: &Email,
+) -> Result<(), AuthAPIError> {
+    state
+        .login_attempt_store
+        .write()
+        .await
+        .reset(email)
+        .await
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))
+}
+
+// Counts the failure towards a lockout and locks the account once the policy says so
+#[tracing::instrument(name = "Handle Failed Login", skip_all)]
+pub(crate) async fn handle_failed_login(
     state: &AppState,
+    user_store: &(dyn UserStore + Send + Sync),
     email: &Email,
+) -> AuthAPIError {
+    let mut login_attempt_store = state.login_attempt_store.write().await;
+
+    let failures = match login_attempt_store.record_failure(email).await {
+        Ok(failures) => failures,
+        Err(e) => return AuthAPIError::UnexpectedError(e.into()),
+    };
+
+    let Some(duration) = state.settings.lockout.lockout_duration(failures) else {
+        return AuthAPIError::IncorrectCredentials;
+    };
+
+    let until = Utc::now() + duration;
+    if let Err(e) = login_attempt_store.lock_account(email, until).await {
+        return AuthAPIError::UnexpectedError(e.into());
+    }
+    drop(login_attempt_store);
+
+    // Attempts against unknown addresses are locked out too, but there is nobody to tell
+    if let Ok(user) = user_store.get_user(email).await {
+        let idempotency_key =
+            IdempotencyKey::derive(email, &["account_locked", &until.to_rfc3339()]);
+        if let Err(e) = queue_email(
+            state,
+            email,
+            user.locale.as_ref(),
+            &TransactionalEmail::AccountLocked {
+                failures,
+                locked_until: until,
+            },
+            idempotency_key,
+        )
+        .await
+        {
+            tracing::error!("Failed to queue lockout email: {:?}", e);
+        }
+    }
+
+    AuthAPIError::AccountLocked
+}
+
+#[tracing::instrument(name = "Handle 2FA", skip_all)]
+pub(crate) async fn handle_2fa(
+    state: &AppState,
+    user: &User,
+    login_attempt_id: LoginAttemptId,
+    first_factor: AuthMethod,
     jar: CookieJar,
 ) -> (CookieJar, Result<Response, AuthAPIError>) {
-    let login_attempt_id = LoginAttemptId::default();
-    let two_fa_code = TwoFACode::default();
+    let email = &user.email;
+    let two_fa_code = TwoFACode::generate(&state.settings.two_fa.code_format);
 
+    let methods = match attempt_methods(state, user, Some(first_factor)).await {
+        Ok(methods) => methods,
+        Err(e) => return (jar, Err(e)),
+    };
+    // Only reachable from a passwordless login, as email is always a method otherwise
+    if methods.is_empty() {
+        return (jar, Err(AuthAPIError::PasswordLoginRequired));
+    }
     let response = TwoFactorAuthResponse {
         message: "2FA required".to_string(),
         login_attempt_id: login_attempt_id.as_ref().expose_secret().to_owned(),
+        methods: methods.as_slice().to_vec(),
     };
 
     let mut code_store = state.two_fa_code_store.write().await;
     if let Err(e) = code_store
-        .add_code(email, login_attempt_id.clone(), two_fa_code.clone()) // Add .clone()
+        .add_code(
+            email,
+            login_attempt_id.clone(),
+            two_fa_code.clone(),
+            state.settings.two_fa.max_pending_logins,
+        )
         .await
     {
         return (jar, Err(AuthAPIError::UnexpectedError((e.into()))));
     }
-
-    if let Err(e) = state
-        .email_client
-        .send_email(
-            email,
-            "Your 2FA Code",
-            &format!("Your 2FA code is: {}", two_fa_code.as_ref().expose_secret()),
-        )
+    if let Err(e) = code_store
+        .set_first_factor(email, &login_attempt_id, first_factor)
         .await
     {
         return (jar, Err(AuthAPIError::UnexpectedError(e.into())));
     }
+    drop(code_store);
+
+    // A code is only sent when the user is asked for one first. The stored code still ties
+    // verify_2fa to this login attempt, and /resend-2fa can send it later.
+    if let Some(method) = methods.preferred().filter(TwoFactorMethod::is_sent_code) {
+        if let Err(e) = send_2fa_code(state, user, &login_attempt_id, &two_fa_code, method).await {
+            return (jar, Err(e));
+        }
+    }
 
     (jar, Ok((StatusCode::OK, Json(response)).into_response()))
 }
 
+// Texts the code to the user's verified phone or emails it, depending on `method`
+pub(crate) async fn send_2fa_code(
+    state: &AppState,
+    user: &User,
+    login_attempt_id: &LoginAttemptId,
+    code: &TwoFACode,
+    method: TwoFactorMethod,
+) -> Result<(), AuthAPIError> {
+    let message = TransactionalEmail::TwoFactorCode {
+        code: code.as_ref().expose_secret().to_owned(),
+        expires_in_minutes: state.settings.two_fa.code_ttl.num_minutes(),
+    };
+    if method == TwoFactorMethod::Sms {
+        let phone = verified_phone(state, &user.email)
+            .await?
+            .ok_or(AuthAPIError::PhoneNotVerified)?;
+        let text = state
+            .email_templates
+            .render_sms(&message, user.locale.as_ref())
+            .expect("2FA codes have SMS templates");
+        return state
+            .sms_client
+            .send_sms(&phone.number, &text)
+            .await
+            .map_err(AuthAPIError::UnexpectedError);
+    }
+
+    // Resent codes are new codes, so they get keys of their own
+    let idempotency_key = IdempotencyKey::derive(
+        &user.email,
+        &[
+            "two_factor_code",
+            login_attempt_id.as_ref().expose_secret(),
+            code.as_ref().expose_secret(),
+        ],
+    );
+    queue_email(
+        state,
+        &user.email,
+        user.locale.as_ref(),
+        &message,
+        idempotency_key,
+    )
+    .await
+}
+
 #[tracing::instrument(name = "Handle No 2FA", skip_all)]
-async fn handle_no_2fa(
+pub(crate) async fn handle_no_2fa(
     email: &Email,
+    methods: &[AuthMethod],
     jar: CookieJar,
 ) -> (CookieJar, Result<Response, AuthAPIError>) {
+    let auth_cookie = match generate_auth_cookie(email, methods) {
+        Ok(cookie) => cookie,
+        Err(e) => return (jar, Err(AuthAPIError::UnexpectedError(e.into()))),
+    };
+
     let response = RegularAuth {
         message: "You have successfully logged in!".to_string(),
     };
+    (
+        jar.add(auth_cookie),
+        Ok((StatusCode::OK, Json(response)).into_response()),
+    )
+}
+
+// Instead of an auth cookie the user gets a token that is only accepted by /change-password
+#[tracing::instrument(name = "Handle Password Change Required", skip_all)]
+pub(crate) fn handle_password_change_required(
+    email: &Email,
+    reason: PasswordChangeReason,
+    jar: CookieJar,
+) -> (CookieJar, Result<Response, AuthAPIError>) {
+    let token = match generate_purpose_token(email, TokenPurpose::PasswordChange) {
+        Ok(token) => token,
+        Err(e) => return (jar, Err(AuthAPIError::UnexpectedError(e.into()))),
+    };
+
+    let response = PasswordChangeRequiredResponse {
+        message: "Password change required".to_string(),
+        reason,
+        password_change_token: token,
+    };
     (jar, Ok((StatusCode::OK, Json(response)).into_response()))
 }
 
@@ -104,6 +340,13 @@ pub struct LoginRequest {
     pub password: String,
     #[serde(rename = "requires2FA")]
     pub requires_2fa: bool,
+    // Code from the user's authenticator app, to skip the separate verify_2fa step
+    #[serde(rename = "totpCode", default)]
+    pub totp_code: Option<String>,
+    // Asks for 2FA even on a remembered device, to get a login strong enough for sensitive
+    // operations
+    #[serde(rename = "stepUp", default)]
+    pub step_up: bool,
 }
 
 #[derive(Deserialize, Default, Serialize, Debug)]
@@ -116,6 +359,16 @@ pub struct TwoFactorAuthResponse {
     pub message: String,
     #[serde(rename = "loginAttemptId")]
     pub login_attempt_id: String,
+    // The second factors that can complete this login, the one to ask for first at the front
+    pub methods: Vec<TwoFactorMethod>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PasswordChangeRequiredResponse {
+    pub message: String,
+    pub reason: PasswordChangeReason,
+    #[serde(rename = "passwordChangeToken")]
+    pub password_change_token: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -123,4 +376,5 @@ pub struct TwoFactorAuthResponse {
 pub enum LoginResponse {
     RegularAuth(RegularAuth),
     TwoFactorAuth(TwoFactorAuthResponse),
+    PasswordChangeRequired(PasswordChangeRequiredResponse),
 }
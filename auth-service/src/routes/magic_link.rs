@@ -0,0 +1,141 @@
+use crate::app_state::app_state::AppState;
+use crate::domain::authentication::AuthMethod;
+use crate::domain::data_store::LoginAttemptId;
+use crate::domain::email_outbox::IdempotencyKey;
+use crate::domain::email_template::TransactionalEmail;
+use crate::domain::error::AuthAPIError;
+use crate::domain::Email;
+use crate::routes::email_code_login::claim_login_email;
+use crate::routes::login::{handle_2fa, handle_no_2fa, handle_password_change_required};
+use crate::utils::auth::{
+    generate_magic_link_token, validate_purpose_token, TokenPurpose, TOKEN_TTL_SECONDS,
+};
+use crate::utils::email::queue_email;
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use axum_extra::extract::CookieJar;
+use secrecy::Secret;
+use serde::{Deserialize, Serialize};
+
+#[tracing::instrument(name = "Request Magic Link", skip_all)]
+pub async fn request_magic_link(
+    State(state): State<AppState>,
+    Json(request): Json<MagicLinkRequest>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let email =
+        Email::parse(Secret::new(request.email)).map_err(|_| AuthAPIError::InvalidCredentials)?;
+
+    // Respond the same way whether or not the account exists to avoid leaking which emails
+    // are registered
+    let response = (
+        StatusCode::OK,
+        Json(MagicLinkResponse {
+            message: "If an account exists for this email, a login link has been sent".to_string(),
+        }),
+    );
+
+    let user = match state.userstore.read().await.get_user(&email).await {
+        Ok(user) => user,
+        Err(_) => return Ok(response),
+    };
+    if !user.status.is_active() || !claim_login_email(&state, &email).await? {
+        return Ok(response);
+    }
+
+    let login_attempt_id = LoginAttemptId::default();
+    let token = generate_magic_link_token(&email, &login_attempt_id)
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+    let link = format!(
+        "{}/login/magic-link/callback?token={}",
+        state.settings.public_url.trim_end_matches('/'),
+        token
+    );
+
+    let idempotency_key = IdempotencyKey::derive(&email, &["magic_link", &token]);
+    queue_email(
+        &state,
+        &email,
+        user.locale.as_ref(),
+        &TransactionalEmail::MagicLink {
+            link,
+            expires_in_minutes: TOKEN_TTL_SECONDS / 60,
+        },
+        idempotency_key,
+    )
+    .await?;
+
+    Ok(response)
+}
+
+#[tracing::instrument(name = "Magic Link Callback", skip_all)]
+pub async fn magic_link_callback(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Query(query): Query<MagicLinkCallbackQuery>,
+) -> (CookieJar, Result<impl IntoResponse, AuthAPIError>) {
+    let token = Secret::new(query.token);
+
+    let claims = match validate_purpose_token(
+        token.clone(),
+        TokenPurpose::MagicLink,
+        state.tokenstore.clone(),
+    )
+    .await
+    {
+        Ok(claims) => claims,
+        Err(_) => return (jar, Err(AuthAPIError::InvalidToken)),
+    };
+
+    let email = match Email::parse(Secret::new(claims.sub)) {
+        Ok(email) => email,
+        Err(_) => return (jar, Err(AuthAPIError::InvalidToken)),
+    };
+    let login_attempt_id = match claims.jti.map(|id| LoginAttemptId::parse(Secret::new(id))) {
+        Some(Ok(id)) => id,
+        _ => return (jar, Err(AuthAPIError::InvalidToken)),
+    };
+
+    // Magic links are single-use
+    if let Err(e) = state.tokenstore.write().await.store_token(token).await {
+        return (jar, Err(AuthAPIError::UnexpectedError(e.into())));
+    }
+
+    let user = match state.userstore.read().await.get_user(&email).await {
+        Ok(user) => user,
+        Err(_) => return (jar, Err(AuthAPIError::InvalidToken)),
+    };
+    if !user.status.is_active() {
+        return (jar, Err(AuthAPIError::AccountInactive));
+    }
+
+    // The link stands in for the password, so 2FA users still have to enter a code. The
+    // challenge continues the login attempt the link was issued for.
+    if user.require_2fa {
+        return handle_2fa(&state, &user, login_attempt_id, AuthMethod::Email, jar).await;
+    }
+
+    if let Some(reason) = user.password_change_reason(state.settings.password_max_age) {
+        return handle_password_change_required(&email, reason, jar);
+    }
+
+    handle_no_2fa(&email, &[AuthMethod::Email], jar).await
+}
+
+#[derive(Deserialize)]
+pub struct MagicLinkRequest {
+    pub email: String,
+}
+
+#[derive(Deserialize)]
+pub struct MagicLinkCallbackQuery {
+    pub token: String,
+}
+
+#[derive(Deserialize, Default, Serialize, Debug)]
+pub struct MagicLinkResponse {
+    pub message: String,
+}
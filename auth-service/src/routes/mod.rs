@@ -1,8 +1,23 @@
+pub mod admin;
+pub mod change_password;
+pub mod email_code_login;
+pub mod forgot_password;
+pub mod hotp;
+pub mod locale;
 pub mod login;
 pub mod logout;
+pub mod magic_link;
+pub mod recovery_codes;
+pub mod resend_2fa;
+pub mod reset_password;
 pub mod signup;
+pub mod sms;
+pub mod totp;
+pub mod trusted_devices;
+pub mod two_factor_methods;
 pub mod verify_2fa;
 pub mod verify_token;
+pub mod webauthn;
 
 //pub use login::*;
 pub use logout::*;
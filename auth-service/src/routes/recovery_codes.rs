@@ -0,0 +1,112 @@
+use crate::app_state::app_state::AppState;
+use crate::domain::data_store::RecoveryCodeStoreError;
+use crate::domain::email_outbox::IdempotencyKey;
+use crate::domain::email_template::TransactionalEmail;
+use crate::domain::error::AuthAPIError;
+use crate::domain::recovery_code::{
+    RecoveryCode, LOW_RECOVERY_CODES_THRESHOLD, RECOVERY_CODE_COUNT,
+};
+use crate::domain::Email;
+use crate::utils::email::queue_email;
+use crate::utils::user_auth::StepUpUser;
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use serde::{Deserialize, Serialize};
+
+// Replaces the user's recovery codes with a fresh set, e.g. after the old ones were lost
+#[tracing::instrument(name = "Regenerate recovery codes", skip_all)]
+pub async fn regenerate_recovery_codes(
+    user: StepUpUser,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let require_2fa = state
+        .userstore
+        .read()
+        .await
+        .get_user(&user.email)
+        .await
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?
+        .require_2fa;
+    if !require_2fa {
+        return Err(AuthAPIError::TwoFactorNotEnabled);
+    }
+
+    let recovery_codes = issue_recovery_codes(&state, &user.email).await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(RecoveryCodesResponse {
+            message: "Store these codes somewhere safe. They will not be shown again.".to_string(),
+            recovery_codes,
+        }),
+    ))
+}
+
+// Generates a new set of codes, replacing any previous ones, and returns them for display.
+// Only their hashes are kept.
+pub(crate) async fn issue_recovery_codes(
+    state: &AppState,
+    email: &Email,
+) -> Result<Vec<String>, AuthAPIError> {
+    let codes: Vec<RecoveryCode> = (0..RECOVERY_CODE_COUNT)
+        .map(|_| RecoveryCode::default())
+        .collect();
+
+    state
+        .recovery_code_store
+        .write()
+        .await
+        .replace_codes(email, &codes)
+        .await
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+
+    Ok(codes.iter().map(RecoveryCode::display).collect())
+}
+
+// Uses up a recovery code, warning the user by email when few are left
+pub(crate) async fn redeem_recovery_code(
+    state: &AppState,
+    email: &Email,
+    code: &RecoveryCode,
+) -> Result<bool, AuthAPIError> {
+    let remaining = match state
+        .recovery_code_store
+        .write()
+        .await
+        .use_code(email, code)
+        .await
+    {
+        Ok(remaining) => remaining,
+        Err(RecoveryCodeStoreError::InvalidCode) => return Ok(false),
+        Err(e) => return Err(AuthAPIError::UnexpectedError(e.into())),
+    };
+
+    if remaining <= LOW_RECOVERY_CODES_THRESHOLD {
+        let locale = match state.userstore.read().await.get_user(email).await {
+            Ok(user) => user.locale,
+            Err(_) => None,
+        };
+        let idempotency_key =
+            IdempotencyKey::derive(email, &["low_recovery_codes", &code.display()]);
+        // The login itself succeeded, so a failure to warn is only logged
+        if let Err(e) = queue_email(
+            state,
+            email,
+            locale.as_ref(),
+            &TransactionalEmail::LowRecoveryCodes { remaining },
+            idempotency_key,
+        )
+        .await
+        {
+            tracing::error!("Failed to queue low recovery codes email: {:?}", e);
+        }
+    }
+
+    Ok(true)
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct RecoveryCodesResponse {
+    pub message: String,
+    #[serde(rename = "recoveryCodes")]
+    pub recovery_codes: Vec<String>,
+}
@@ -0,0 +1,106 @@
+use crate::app_state::app_state::AppState;
+use crate::domain::data_store::{LoginAttemptId, TwoFACode, TwoFaCodeStore, TwoFaCodeStoreError};
+use crate::domain::error::AuthAPIError;
+use crate::domain::two_factor::TwoFactorMethod;
+use crate::domain::Email;
+use crate::routes::login::{send_2fa_code, TwoFactorAuthResponse};
+use crate::routes::two_factor_methods::attempt_methods;
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use secrecy::{ExposeSecret, Secret};
+use serde::Deserialize;
+
+// Sends a new code for a login attempt whose code was delayed or lost. The previous code stops
+// working, and wrong guesses made against it still count.
+#[tracing::instrument(name = "Resend 2FA", skip_all)]
+pub async fn resend_2fa(
+    State(state): State<AppState>,
+    Json(request): Json<Resend2FARequest>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let email =
+        Email::parse(Secret::new(request.email)).map_err(|_| AuthAPIError::InvalidCredentials)?;
+    let login_attempt_id = LoginAttemptId::parse(Secret::new(request.login_attempt_id))
+        .map_err(|_| AuthAPIError::InvalidCredentials)?;
+
+    // Without a method, the code goes wherever the user would have been sent one first. Users
+    // with an authenticator app have no sent-code method, so whoever holds their password can't
+    // fall back to email, and neither can a login started from the user's inbox.
+    let user = state
+        .userstore
+        .read()
+        .await
+        .get_user(&email)
+        .await
+        .map_err(|_| AuthAPIError::IncorrectCredentials)?;
+    if !user.status.is_active() {
+        return Err(AuthAPIError::AccountInactive);
+    }
+    let first_factor = match state
+        .two_fa_code_store
+        .read()
+        .await
+        .get_first_factor(&email, &login_attempt_id)
+        .await
+    {
+        Ok(method) => method,
+        Err(TwoFaCodeStoreError::LoginAttempIdNotFound) => {
+            return Err(AuthAPIError::IncorrectCredentials)
+        }
+        Err(e) => return Err(AuthAPIError::UnexpectedError(e.into())),
+    };
+    let methods = attempt_methods(&state, &user, first_factor).await?;
+    let method = request.method.or_else(|| {
+        methods
+            .as_slice()
+            .iter()
+            .copied()
+            .find(TwoFactorMethod::is_sent_code)
+    });
+    let method = match method {
+        Some(method) if method.is_sent_code() && methods.contains(method) => method,
+        _ => return Err(AuthAPIError::TwoFactorMethodUnavailable),
+    };
+
+    let code = TwoFACode::generate(&state.settings.two_fa.code_format);
+    match state
+        .two_fa_code_store
+        .write()
+        .await
+        .rotate_code(
+            &email,
+            &login_attempt_id,
+            code.clone(),
+            state.settings.two_fa.resend_cooldown,
+            state.settings.two_fa.max_resends,
+        )
+        .await
+    {
+        Ok(_) => {}
+        Err(TwoFaCodeStoreError::LoginAttempIdNotFound) => {
+            return Err(AuthAPIError::IncorrectCredentials)
+        }
+        Err(TwoFaCodeStoreError::ResendTooSoon) => return Err(AuthAPIError::ResendTooSoon),
+        Err(TwoFaCodeStoreError::TooManyResends) => return Err(AuthAPIError::TooManyResends),
+        Err(e) => return Err(AuthAPIError::UnexpectedError(e.into())),
+    }
+
+    send_2fa_code(&state, &user, &login_attempt_id, &code, method).await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(TwoFactorAuthResponse {
+            message: "2FA code resent".to_string(),
+            login_attempt_id: login_attempt_id.as_ref().expose_secret().to_owned(),
+            methods: methods.as_slice().to_vec(),
+        }),
+    ))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Resend2FARequest {
+    email: String,
+    #[serde(rename = "loginAttemptId")]
+    login_attempt_id: String,
+    // Where to send the code, "email" or "sms"
+    #[serde(default)]
+    method: Option<TwoFactorMethod>,
+}
@@ -0,0 +1,74 @@
+use crate::app_state::app_state::AppState;
+use crate::domain::data_store::UserStoreError;
+use crate::domain::error::AuthAPIError;
+use crate::domain::{Email, Password};
+use crate::utils::auth::{validate_purpose_token, TokenPurpose};
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use secrecy::Secret;
+use serde::{Deserialize, Serialize};
+
+#[tracing::instrument(name = "Reset Password", skip_all)]
+pub async fn reset_password(
+    State(state): State<AppState>,
+    Json(request): Json<ResetPasswordRequest>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let token = Secret::new(request.token);
+
+    let claims = validate_purpose_token(
+        token.clone(),
+        TokenPurpose::PasswordReset,
+        state.tokenstore.clone(),
+    )
+    .await
+    .map_err(|_| AuthAPIError::InvalidToken)?;
+
+    let email = Email::parse(Secret::new(claims.sub)).map_err(|_| AuthAPIError::InvalidToken)?;
+    let new_password = Password::parse(Secret::new(request.new_password))
+        .map_err(|_| AuthAPIError::InvalidCredentials)?;
+
+    let mut user_store = state.userstore.write().await;
+
+    // The account may have been suspended or disabled since the token was sent
+    match user_store.get_user(&email).await {
+        Ok(user) if !user.status.is_active() => return Err(AuthAPIError::AccountInactive),
+        Ok(_) => {}
+        Err(UserStoreError::UserNotFound) => return Err(AuthAPIError::InvalidToken),
+        Err(e) => return Err(AuthAPIError::UnexpectedError(e.into())),
+    }
+
+    match user_store.update_password(&email, new_password).await {
+        Ok(_) => {}
+        Err(UserStoreError::PasswordReused) => return Err(AuthAPIError::PasswordReused),
+        Err(UserStoreError::UserNotFound) => return Err(AuthAPIError::InvalidToken),
+        Err(e) => return Err(AuthAPIError::UnexpectedError(e.into())),
+    }
+    drop(user_store);
+
+    // Reset tokens are single-use
+    state
+        .tokenstore
+        .write()
+        .await
+        .store_token(token)
+        .await
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+
+    Ok((
+        StatusCode::OK,
+        Json(ResetPasswordResponse {
+            message: "Password reset successfully".to_string(),
+        }),
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct ResetPasswordRequest {
+    pub token: String,
+    #[serde(rename = "newPassword")]
+    pub new_password: String,
+}
+
+#[derive(Deserialize, Default, Serialize, Debug)]
+pub struct ResetPasswordResponse {
+    pub message: String,
+}
@@ -1,8 +1,12 @@
 use crate::app_state::app_state::AppState;
+use crate::app_state::settings::SignupMode;
 use crate::domain::data_store::*;
 use crate::domain::error::AuthAPIError;
+use crate::domain::invite::InviteCode;
 use crate::domain::user::User;
 use crate::domain::{Email, Password};
+use crate::routes::locale::parse_locale;
+use crate::routes::recovery_codes::issue_recovery_codes;
 use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
 use secrecy::Secret;
 use serde::{Deserialize, Serialize};
@@ -17,14 +21,56 @@ pub async fn signup(
 
     let password = Password::parse(Secret::new(request.password))
         .map_err(|_| AuthAPIError::InvalidCredentials)?;
+    let locale = parse_locale(request.locale)?;
 
-    let user = User::new(email, password, request.requires_2fa);
-    let mut user_store = state.userstore.write().await;
+    state
+        .email_domain_policy
+        .read()
+        .await
+        .check(&email)
+        .map_err(AuthAPIError::EmailDomainRejected)?;
 
-    match user_store.add_user(user).await {
+    // In invite-only mode a use of the invite is taken before the user is created, and given
+    // back if creating the user fails
+    let invite_code = match state.settings.signup_mode {
+        SignupMode::Open => None,
+        SignupMode::InviteOnly => {
+            let code = request
+                .invite_code
+                .map(Secret::new)
+                .and_then(|code| InviteCode::parse(code).ok())
+                .ok_or(AuthAPIError::InvalidInviteCode)?;
+            consume_invite(&state, &code, &email).await?;
+            Some(code)
+        }
+    };
+
+    let user = User {
+        locale,
+        ..User::new(email.clone(), password, request.requires_2fa)
+    };
+    let result = state.userstore.write().await.add_user(user).await;
+
+    if let (Err(_), Some(code)) = (&result, &invite_code) {
+        if let Err(e) = state.invite_store.write().await.release_invite(code).await {
+            tracing::warn!(
+                "Failed to release invite after unsuccessful signup: {:?}",
+                e
+            );
+        }
+    }
+
+    match result {
         Ok(_) => {
+            // Recovery codes are only shown this once
+            let recovery_codes = if request.requires_2fa {
+                Some(issue_recovery_codes(&state, &email).await?)
+            } else {
+                None
+            };
             let response = Json(SignupResponse {
                 message: "User created successfully".to_string(),
+                recovery_codes,
             });
             Ok((StatusCode::CREATED, response))
         }
@@ -33,15 +79,49 @@ pub async fn signup(
     }
 }
 
+async fn consume_invite(
+    state: &AppState,
+    code: &InviteCode,
+    email: &Email,
+) -> Result<(), AuthAPIError> {
+    match state
+        .invite_store
+        .write()
+        .await
+        .consume_invite(code, email)
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(InviteStoreError::UnexpectedError(e)) => Err(AuthAPIError::UnexpectedError(e)),
+        Err(_) => Err(AuthAPIError::InvalidInviteCode),
+    }
+}
+
 #[derive(Deserialize, Serialize)]
 pub struct SignupRequest {
     pub email: String,
     pub password: String,
     #[serde(rename = "requires2FA")]
     pub requires_2fa: bool,
+    // Only required when signups are invite-only
+    #[serde(
+        rename = "inviteCode",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub invite_code: Option<String>,
+    // Language for the user's emails, e.g. "de" or "pt-BR"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locale: Option<String>,
 }
 
 #[derive(Deserialize, Default, Serialize)]
 pub struct SignupResponse {
     pub message: String,
+    #[serde(
+        rename = "recoveryCodes",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub recovery_codes: Option<Vec<String>>,
 }
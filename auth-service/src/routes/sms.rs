@@ -0,0 +1,166 @@
+use crate::app_state::app_state::AppState;
+use crate::domain::data_store::{
+    LoginAttemptId, PhoneStoreError, TwoFACode, TwoFaCodeStore, TwoFaCodeStoreError,
+};
+use crate::domain::email_template::TransactionalEmail;
+use crate::domain::error::AuthAPIError;
+use crate::domain::phone_number::{PhoneNumber, UserPhone};
+use crate::domain::Email;
+use crate::routes::verify_2fa::reject_code;
+use crate::utils::user_auth::{AuthenticatedUser, StepUpUser};
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use secrecy::{ExposeSecret, Secret};
+use serde::{Deserialize, Serialize};
+
+// Registers a new phone number and texts it a code. The number can only receive 2FA codes once
+// the user has sent that code back, and until then any verified number stays in use.
+#[tracing::instrument(name = "Register Phone", skip_all)]
+pub async fn register_phone(
+    user: StepUpUser,
+    State(state): State<AppState>,
+    Json(request): Json<RegisterPhoneRequest>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let number = PhoneNumber::parse(Secret::new(request.phone_number))
+        .map_err(|_| AuthAPIError::InvalidCredentials)?;
+
+    // Each call texts whichever number it is given, so they share the 2FA resend cooldown
+    match state
+        .phone_code_store
+        .write()
+        .await
+        .claim_send(&user.email, state.settings.two_fa.resend_cooldown)
+        .await
+    {
+        Ok(()) => {}
+        Err(TwoFaCodeStoreError::ResendTooSoon) => return Err(AuthAPIError::ResendTooSoon),
+        Err(e) => return Err(AuthAPIError::UnexpectedError(e.into())),
+    }
+
+    state
+        .phone_store
+        .write()
+        .await
+        .set_pending_phone(&user.email, number.clone())
+        .await
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+
+    // Only one code is kept per user, so only the latest number can be verified
+    let code = TwoFACode::generate(&state.settings.two_fa.code_format);
+    state
+        .phone_code_store
+        .write()
+        .await
+        .add_code(&user.email, LoginAttemptId::default(), code.clone(), 1)
+        .await
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+
+    let locale = state
+        .userstore
+        .read()
+        .await
+        .get_user(&user.email)
+        .await
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?
+        .locale;
+    let message = TransactionalEmail::PhoneVerificationCode {
+        code: code.as_ref().expose_secret().to_owned(),
+        expires_in_minutes: state.settings.two_fa.code_ttl.num_minutes(),
+    };
+    let text = state
+        .email_templates
+        .render_sms(&message, locale.as_ref())
+        .expect("phone verification codes have SMS templates");
+    state
+        .sms_client
+        .send_sms(&number, &text)
+        .await
+        .map_err(AuthAPIError::UnexpectedError)?;
+
+    Ok((
+        StatusCode::OK,
+        Json(PhoneResponse {
+            message: format!("Verification code sent to {}", number.masked()),
+        }),
+    ))
+}
+
+#[tracing::instrument(name = "Verify Phone", skip_all)]
+pub async fn verify_phone(
+    user: AuthenticatedUser,
+    State(state): State<AppState>,
+    Json(request): Json<VerifyPhoneRequest>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let code = TwoFACode::parse(
+        Secret::new(request.code),
+        &state.settings.two_fa.code_format,
+    )
+    .map_err(|_| AuthAPIError::InvalidCredentials)?;
+
+    let mut phone_code_store = state.phone_code_store.write().await;
+    let attempt_id = match phone_code_store.get_login_attempts(&user.email).await {
+        Ok(attempts) => attempts
+            .into_iter()
+            .last()
+            .ok_or(AuthAPIError::IncorrectCredentials)?,
+        Err(e) => return Err(AuthAPIError::UnexpectedError(e.into())),
+    };
+    match phone_code_store.get_code(&user.email, &attempt_id).await {
+        Ok(expected_code) if expected_code == code => {}
+        Ok(_) => {
+            return Err(reject_code(
+                &mut *phone_code_store,
+                &user.email,
+                &attempt_id,
+                state.settings.two_fa.max_attempts,
+                AuthAPIError::IncorrectCredentials,
+            )
+            .await)
+        }
+        Err(_) => return Err(AuthAPIError::IncorrectCredentials),
+    }
+    let _ = phone_code_store.remove_code(&user.email, &attempt_id).await;
+    drop(phone_code_store);
+
+    state
+        .phone_store
+        .write()
+        .await
+        .mark_verified(&user.email)
+        .await
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+
+    Ok((
+        StatusCode::OK,
+        Json(PhoneResponse {
+            message: "Phone number verified".to_string(),
+        }),
+    ))
+}
+
+// The user's phone number, once they have proven they receive texts at it
+pub(crate) async fn verified_phone(
+    state: &AppState,
+    email: &Email,
+) -> Result<Option<UserPhone>, AuthAPIError> {
+    match state.phone_store.read().await.get_phone(email).await {
+        Ok(phone) => Ok(Some(phone)),
+        Err(PhoneStoreError::PhoneNotFound) => Ok(None),
+        Err(e) => Err(AuthAPIError::UnexpectedError(e.into())),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RegisterPhoneRequest {
+    #[serde(rename = "phoneNumber")]
+    pub phone_number: String,
+}
+
+#[derive(Deserialize)]
+pub struct VerifyPhoneRequest {
+    pub code: String,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct PhoneResponse {
+    pub message: String,
+}
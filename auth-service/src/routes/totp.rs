@@ -0,0 +1,185 @@
+use crate::app_state::app_state::AppState;
+use crate::domain::data_store::TotpStoreError;
+use crate::domain::error::AuthAPIError;
+use crate::domain::totp::{qr_code_png_base64, qr_code_svg, TotpSecret};
+use crate::domain::Email;
+use crate::routes::recovery_codes::issue_recovery_codes;
+use crate::utils::user_auth::{AuthenticatedUser, StepUpUser};
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use chrono::Utc;
+use secrecy::ExposeSecret;
+use serde::{Deserialize, Serialize};
+
+// Generates a new authenticator app secret. It only takes effect once confirmed with a code.
+#[tracing::instrument(name = "Enroll TOTP", skip_all)]
+pub async fn enroll_totp(
+    user: StepUpUser,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let secret = TotpSecret::generate();
+    let otpauth_uri = secret
+        .otpauth_uri(&state.settings.totp.issuer, &user.email)
+        .map_err(AuthAPIError::UnexpectedError)?;
+
+    let response = TotpEnrollmentResponse {
+        message: "Scan the QR code with your authenticator app and confirm with a code".to_string(),
+        secret: secret.as_ref().expose_secret().to_owned(),
+        qr_code_svg: qr_code_svg(&otpauth_uri).map_err(AuthAPIError::UnexpectedError)?,
+        qr_code_png: qr_code_png_base64(&otpauth_uri).map_err(AuthAPIError::UnexpectedError)?,
+        otpauth_uri,
+    };
+
+    match state
+        .totp_store
+        .write()
+        .await
+        .set_pending_secret(&user.email, secret)
+        .await
+    {
+        Ok(_) => Ok((StatusCode::OK, Json(response))),
+        Err(TotpStoreError::AlreadyEnrolled) => Err(AuthAPIError::TotpAlreadyEnrolled),
+        Err(e) => Err(AuthAPIError::UnexpectedError(e.into())),
+    }
+}
+
+// Confirms enrollment with a first code and turns on 2FA for the account
+#[tracing::instrument(name = "Confirm TOTP", skip_all)]
+pub async fn confirm_totp(
+    user: AuthenticatedUser,
+    State(state): State<AppState>,
+    Json(request): Json<ConfirmTotpRequest>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let enrollment = match state
+        .totp_store
+        .read()
+        .await
+        .get_enrollment(&user.email)
+        .await
+    {
+        Ok(enrollment) if !enrollment.confirmed => enrollment,
+        Ok(_) => return Err(AuthAPIError::TotpAlreadyEnrolled),
+        Err(TotpStoreError::NotEnrolled) => return Err(AuthAPIError::TotpNotEnrolled),
+        Err(e) => return Err(AuthAPIError::UnexpectedError(e.into())),
+    };
+
+    if !check_totp_code(&state, &user.email, &enrollment.secret, &request.code).await? {
+        return Err(AuthAPIError::IncorrectCredentials);
+    }
+
+    state
+        .totp_store
+        .write()
+        .await
+        .confirm(&user.email)
+        .await
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+
+    let recovery_codes = enable_2fa(&state, &user.email).await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(ConfirmTotpResponse {
+            message: "Authenticator app enabled".to_string(),
+            recovery_codes,
+        }),
+    ))
+}
+
+// Turns on 2FA once a first second factor is set up. Users who already had 2FA keep the
+// recovery codes they were given then; others get theirs now.
+pub(crate) async fn enable_2fa(
+    state: &AppState,
+    email: &Email,
+) -> Result<Option<Vec<String>>, AuthAPIError> {
+    let mut userstore = state.userstore.write().await;
+    let had_2fa = userstore
+        .get_user(email)
+        .await
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?
+        .require_2fa;
+    userstore
+        .set_require_2fa(email, true)
+        .await
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+    drop(userstore);
+
+    if had_2fa {
+        return Ok(None);
+    }
+    Ok(Some(issue_recovery_codes(state, email).await?))
+}
+
+// Whether the user has a confirmed authenticator app
+pub(crate) async fn has_totp(state: &AppState, email: &Email) -> Result<bool, AuthAPIError> {
+    match state.totp_store.read().await.get_enrollment(email).await {
+        Ok(enrollment) => Ok(enrollment.confirmed),
+        Err(TotpStoreError::NotEnrolled) => Ok(false),
+        Err(e) => Err(AuthAPIError::UnexpectedError(e.into())),
+    }
+}
+
+// Checks a code against the user's confirmed authenticator app. An accepted code is used up.
+pub(crate) async fn verify_totp_code(
+    state: &AppState,
+    email: &Email,
+    code: &str,
+) -> Result<bool, AuthAPIError> {
+    let enrollment = match state.totp_store.read().await.get_enrollment(email).await {
+        Ok(enrollment) if enrollment.confirmed => enrollment,
+        Ok(_) | Err(TotpStoreError::NotEnrolled) => return Ok(false),
+        Err(e) => return Err(AuthAPIError::UnexpectedError(e.into())),
+    };
+
+    check_totp_code(state, email, &enrollment.secret, code).await
+}
+
+async fn check_totp_code(
+    state: &AppState,
+    email: &Email,
+    secret: &TotpSecret,
+    code: &str,
+) -> Result<bool, AuthAPIError> {
+    let now = Utc::now().timestamp().max(0) as u64;
+    let step = secret
+        .verify(email, code, now, state.settings.totp.skew_steps)
+        .map_err(AuthAPIError::UnexpectedError)?;
+    let Some(step) = step else {
+        return Ok(false);
+    };
+
+    match state.totp_store.write().await.use_step(email, step).await {
+        Ok(_) => Ok(true),
+        Err(TotpStoreError::CodeReused) => Ok(false),
+        Err(e) => Err(AuthAPIError::UnexpectedError(e.into())),
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct TotpEnrollmentResponse {
+    pub message: String,
+    // Base32 secret for entering into an authenticator app by hand
+    pub secret: String,
+    #[serde(rename = "otpauthUri")]
+    pub otpauth_uri: String,
+    #[serde(rename = "qrCodeSvg")]
+    pub qr_code_svg: String,
+    // Base64-encoded PNG image
+    #[serde(rename = "qrCodePng")]
+    pub qr_code_png: String,
+}
+
+#[derive(Deserialize)]
+pub struct ConfirmTotpRequest {
+    pub code: String,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ConfirmTotpResponse {
+    pub message: String,
+    #[serde(
+        rename = "recoveryCodes",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub recovery_codes: Option<Vec<String>>,
+}
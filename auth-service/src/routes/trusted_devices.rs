@@ -0,0 +1,172 @@
+use crate::app_state::app_state::AppState;
+use crate::domain::data_store::TrustedDeviceStoreError;
+use crate::domain::error::AuthAPIError;
+use crate::domain::trusted_device::{TrustedDevice, TrustedDeviceId};
+use crate::domain::Email;
+use crate::utils::auth::{
+    generate_trusted_device_cookie, validate_purpose_token, TokenPurpose,
+    TRUSTED_DEVICE_TOKEN_TTL_SECONDS,
+};
+use crate::utils::constants::TRUSTED_DEVICE_COOKIE_NAME;
+use crate::utils::user_auth::AuthenticatedUser;
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use axum_extra::extract::cookie::Cookie;
+use axum_extra::extract::CookieJar;
+use chrono::{DateTime, Duration, Utc};
+use secrecy::Secret;
+use serde::{Deserialize, Serialize};
+
+#[tracing::instrument(name = "List trusted devices", skip_all)]
+pub async fn list_trusted_devices(
+    user: AuthenticatedUser,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let devices = state
+        .trusted_device_store
+        .read()
+        .await
+        .get_devices(&user.email)
+        .await
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+
+    Ok((
+        StatusCode::OK,
+        Json(TrustedDevicesResponse {
+            devices: devices.into_iter().map(TrustedDeviceView::from).collect(),
+        }),
+    ))
+}
+
+// The device has to go through 2FA again at its next login
+#[tracing::instrument(name = "Revoke trusted device", skip_all)]
+pub async fn revoke_trusted_device(
+    user: AuthenticatedUser,
+    State(state): State<AppState>,
+    Json(request): Json<RevokeTrustedDeviceRequest>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let id = TrustedDeviceId::parse(&request.id).map_err(|_| AuthAPIError::InvalidCredentials)?;
+
+    match state
+        .trusted_device_store
+        .write()
+        .await
+        .revoke_device(&user.email, &id)
+        .await
+    {
+        Ok(()) => {}
+        Err(TrustedDeviceStoreError::DeviceNotFound) => {
+            return Err(AuthAPIError::TrustedDeviceNotFound)
+        }
+        Err(e) => return Err(AuthAPIError::UnexpectedError(e.into())),
+    }
+
+    Ok((
+        StatusCode::OK,
+        Json(RevokeTrustedDeviceResponse {
+            message: "Trusted device revoked".to_string(),
+        }),
+    ))
+}
+
+// Remembers the device that just completed 2FA and returns the cookie that identifies it
+pub(crate) async fn remember_device(
+    state: &AppState,
+    email: &Email,
+    user_agent: Option<&str>,
+) -> Result<Cookie<'static>, AuthAPIError> {
+    let expires_at = Utc::now() + Duration::seconds(TRUSTED_DEVICE_TOKEN_TTL_SECONDS);
+    let device = TrustedDevice::new(user_agent, expires_at);
+    let cookie = generate_trusted_device_cookie(email, &device.id)
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+
+    state
+        .trusted_device_store
+        .write()
+        .await
+        .add_device(email, device)
+        .await
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+
+    Ok(cookie)
+}
+
+// Whether the request carries a device cookie issued to this user for a device that is still
+// trusted. A cookie that fails any check is ignored, so the login simply falls back to 2FA.
+pub(crate) async fn is_trusted_device(
+    state: &AppState,
+    jar: &CookieJar,
+    email: &Email,
+) -> Result<bool, AuthAPIError> {
+    let Some(cookie) = jar.get(TRUSTED_DEVICE_COOKIE_NAME) else {
+        return Ok(false);
+    };
+    let Ok(claims) = validate_purpose_token(
+        Secret::new(cookie.value().to_owned()),
+        TokenPurpose::TrustedDevice,
+        state.tokenstore.clone(),
+    )
+    .await
+    else {
+        return Ok(false);
+    };
+
+    let issued_to_user = Email::parse(Secret::new(claims.sub)).is_ok_and(|sub| &sub == email);
+    let Some(id) = claims
+        .jti
+        .filter(|_| issued_to_user)
+        .and_then(|jti| TrustedDeviceId::parse(&jti).ok())
+    else {
+        return Ok(false);
+    };
+
+    match state
+        .trusted_device_store
+        .write()
+        .await
+        .touch_device(email, &id)
+        .await
+    {
+        Ok(()) => Ok(true),
+        Err(TrustedDeviceStoreError::DeviceNotFound) => Ok(false),
+        Err(e) => Err(AuthAPIError::UnexpectedError(e.into())),
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct TrustedDevicesResponse {
+    pub devices: Vec<TrustedDeviceView>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct TrustedDeviceView {
+    pub id: String,
+    pub label: String,
+    #[serde(rename = "createdAt")]
+    pub created_at: DateTime<Utc>,
+    #[serde(rename = "lastUsedAt")]
+    pub last_used_at: Option<DateTime<Utc>>,
+    #[serde(rename = "expiresAt")]
+    pub expires_at: DateTime<Utc>,
+}
+
+impl From<TrustedDevice> for TrustedDeviceView {
+    fn from(device: TrustedDevice) -> Self {
+        Self {
+            id: device.id.to_string(),
+            label: device.label,
+            created_at: device.created_at,
+            last_used_at: device.last_used_at,
+            expires_at: device.expires_at,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RevokeTrustedDeviceRequest {
+    pub id: String,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct RevokeTrustedDeviceResponse {
+    pub message: String,
+}
@@ -0,0 +1,184 @@
+use crate::app_state::app_state::AppState;
+use crate::domain::authentication::AuthMethod;
+use crate::domain::error::AuthAPIError;
+use crate::domain::two_factor::{TwoFactorMethod, TwoFactorMethods};
+use crate::domain::user::User;
+use crate::domain::Email;
+use crate::routes::hotp::has_hotp;
+use crate::routes::sms::verified_phone;
+use crate::routes::totp::has_totp;
+use crate::utils::user_auth::{AuthenticatedUser, StepUpUser};
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use serde::{Deserialize, Serialize};
+
+#[tracing::instrument(name = "List 2FA methods", skip_all)]
+pub async fn list_two_factor_methods(
+    user: AuthenticatedUser,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let user = get_user(&state, &user.email).await?;
+    let methods = two_factor_methods(&state, &user).await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(TwoFactorMethodsResponse::from(methods)),
+    ))
+}
+
+// Picks the method logins ask for first. Only methods the user has set up can be picked.
+#[tracing::instrument(name = "Set preferred 2FA method", skip_all)]
+pub async fn set_preferred_two_factor_method(
+    user: StepUpUser,
+    State(state): State<AppState>,
+    Json(request): Json<PreferredMethodRequest>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let mut user = get_user(&state, &user.email).await?;
+    let methods = two_factor_methods(&state, &user).await?;
+    if !request.method.can_be_preferred() || !methods.contains(request.method) {
+        return Err(AuthAPIError::TwoFactorMethodUnavailable);
+    }
+
+    state
+        .userstore
+        .write()
+        .await
+        .set_preferred_2fa_method(&user.email, Some(request.method))
+        .await
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+    user.preferred_2fa_method = Some(request.method);
+
+    let methods = two_factor_methods(&state, &user).await?;
+    Ok((
+        StatusCode::OK,
+        Json(TwoFactorMethodsResponse::from(methods)),
+    ))
+}
+
+// The second factors the user has set up, the one to ask for first at the front. Emailed and
+// texted codes are not offered to users with an authenticator app or hardware token, so that
+// whoever learns their password can't fall back to a weaker factor.
+pub(crate) async fn two_factor_methods(
+    state: &AppState,
+    user: &User,
+) -> Result<TwoFactorMethods, AuthAPIError> {
+    let email = &user.email;
+    let mut enrolled = Vec::new();
+
+    let totp = has_totp(state, email).await?;
+    if totp {
+        enrolled.push(TwoFactorMethod::Totp);
+    }
+    let hotp = has_hotp(state, email).await?;
+    if hotp {
+        enrolled.push(TwoFactorMethod::Hotp);
+    }
+
+    let has_passkey = !state
+        .webauthn_credential_store
+        .read()
+        .await
+        .get_credentials(email)
+        .await
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?
+        .is_empty();
+    if has_passkey {
+        enrolled.push(TwoFactorMethod::Webauthn);
+    }
+
+    let phone = verified_phone(state, email).await?;
+    if !totp && !hotp {
+        if phone.is_some() {
+            enrolled.push(TwoFactorMethod::Sms);
+        }
+        enrolled.push(TwoFactorMethod::Email);
+    }
+
+    let recovery_codes = state
+        .recovery_code_store
+        .read()
+        .await
+        .count_unused(email)
+        .await
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+    if recovery_codes > 0 {
+        enrolled.push(TwoFactorMethod::RecoveryCode);
+    }
+
+    // Without a preference, logins go on as they did before users could pick one. Texted codes
+    // are only used once the user prefers them.
+    let fallback = if totp {
+        TwoFactorMethod::Totp
+    } else if hotp {
+        TwoFactorMethod::Hotp
+    } else {
+        TwoFactorMethod::Email
+    };
+
+    Ok(TwoFactorMethods::new(
+        &enrolled,
+        user.preferred_2fa_method,
+        fallback,
+    ))
+}
+
+// The methods that can complete a login attempt started with `first_factor`. A login started
+// from the user's inbox can't be finished from the same inbox, so email is left out then.
+pub(crate) async fn attempt_methods(
+    state: &AppState,
+    user: &User,
+    first_factor: Option<AuthMethod>,
+) -> Result<TwoFactorMethods, AuthAPIError> {
+    let methods = two_factor_methods(state, user).await?;
+    Ok(match first_factor {
+        Some(AuthMethod::Email) => methods.without(TwoFactorMethod::Email),
+        _ => methods,
+    })
+}
+
+// Like `attempt_methods`, by email. An unknown email is reported the same way as an unknown
+// login attempt.
+pub(crate) async fn login_methods(
+    state: &AppState,
+    email: &Email,
+    first_factor: Option<AuthMethod>,
+) -> Result<TwoFactorMethods, AuthAPIError> {
+    let user = state
+        .userstore
+        .read()
+        .await
+        .get_user(email)
+        .await
+        .map_err(|_| AuthAPIError::IncorrectCredentials)?;
+    attempt_methods(state, &user, first_factor).await
+}
+
+async fn get_user(state: &AppState, email: &Email) -> Result<User, AuthAPIError> {
+    state
+        .userstore
+        .read()
+        .await
+        .get_user(email)
+        .await
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))
+}
+
+#[derive(Deserialize)]
+pub struct PreferredMethodRequest {
+    pub method: TwoFactorMethod,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct TwoFactorMethodsResponse {
+    pub methods: Vec<TwoFactorMethod>,
+    #[serde(rename = "preferredMethod")]
+    pub preferred_method: Option<TwoFactorMethod>,
+}
+
+impl From<TwoFactorMethods> for TwoFactorMethodsResponse {
+    fn from(methods: TwoFactorMethods) -> Self {
+        Self {
+            preferred_method: methods.preferred(),
+            methods: methods.as_slice().to_vec(),
+        }
+    }
+}
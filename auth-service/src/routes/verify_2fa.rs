@@ -1,10 +1,27 @@
-use crate::domain::data_store::{LoginAttemptId, TwoFACode, TwoFaCodeStore};
+use crate::domain::authentication::AuthMethod;
+use crate::domain::data_store::{LoginAttemptId, TwoFACode, TwoFaCodeStore, TwoFaCodeStoreError};
+use crate::domain::hotp;
+use crate::domain::recovery_code::RecoveryCode;
+use crate::domain::totp::is_well_formed_code;
+use crate::domain::two_factor::TwoFactorMethod;
 use crate::domain::Email;
-use crate::routes::login::LoginResponse;
-use crate::routes::login::RegularAuth;
+use crate::routes::hotp::verify_hotp_code;
+use crate::routes::login::{
+    check_not_locked, handle_failed_login, handle_password_change_required, reset_failed_logins,
+    LoginResponse, RegularAuth,
+};
+use crate::routes::recovery_codes::redeem_recovery_code;
+use crate::routes::totp::verify_totp_code;
+use crate::routes::trusted_devices::remember_device;
+use crate::routes::two_factor_methods::login_methods;
 use crate::utils::auth::generate_auth_cookie;
 use crate::{AppState, AuthAPIError};
-use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use axum::{
+    extract::State,
+    http::{header::USER_AGENT, HeaderMap, StatusCode},
+    response::IntoResponse,
+    Json,
+};
 use axum_extra::extract::CookieJar;
 use secrecy::Secret;
 use serde::Deserialize;
@@ -13,6 +30,7 @@ use serde::Deserialize;
 pub async fn verify_2fa(
     State(state): State<AppState>,
     jar: CookieJar,
+    headers: HeaderMap,
     Json(request): Json<Verify2FARequest>,
 ) -> (CookieJar, Result<impl IntoResponse, AuthAPIError>) {
     let email = match Email::parse(Secret::new(request.email)) {
@@ -24,25 +42,173 @@ pub async fn verify_2fa(
         Err(_) => return (jar, Err(AuthAPIError::InvalidCredentials)),
     };
 
-    let two_fa_code = match TwoFACode::parse(Secret::new(request.two_fa_code)) {
-        Ok(code) => code,
-        Err(_) => return (jar, Err(AuthAPIError::InvalidCredentials)),
+    let tried_as = |methods: &[TwoFactorMethod]| {
+        request
+            .method
+            .is_none_or(|method| methods.contains(&method))
     };
 
-    let mut two_fa_code_store = state.two_fa_code_store.write().await;
+    // Depending on the configured format, an emailed code can look like an authenticator app
+    // code or a recovery code, so without a method the code is tried as each kind it could be.
+    // Passkeys are never checked here.
+    let sent_code = tried_as(&[TwoFactorMethod::Email, TwoFactorMethod::Sms])
+        .then(|| {
+            TwoFACode::parse(
+                Secret::new(request.two_fa_code.clone()),
+                &state.settings.two_fa.code_format,
+            )
+            .ok()
+        })
+        .flatten();
+    let totp_code = tried_as(&[TwoFactorMethod::Totp]) && is_well_formed_code(&request.two_fa_code);
+    let hotp_code =
+        tried_as(&[TwoFactorMethod::Hotp]) && hotp::is_well_formed_code(&request.two_fa_code);
+    let recovery_code = tried_as(&[TwoFactorMethod::RecoveryCode])
+        .then(|| RecoveryCode::parse(Secret::new(request.two_fa_code.clone())).ok())
+        .flatten();
+    if sent_code.is_none() && !totp_code && !hotp_code && recovery_code.is_none() {
+        return (jar, Err(AuthAPIError::InvalidCredentials));
+    }
 
-    let code_tuple = match two_fa_code_store.get_code(&email).await {
-        Ok(tuple) => tuple,
+    if let Err(e) = check_not_locked(&state, &email).await {
+        return (jar, Err(e));
+    }
+
+    let first_factor = match state
+        .two_fa_code_store
+        .read()
+        .await
+        .get_first_factor(&email, &login_attempt_id)
+        .await
+    {
+        Ok(method) => method,
         Err(_) => return (jar, Err(AuthAPIError::IncorrectCredentials)),
     };
+    let methods = match login_methods(&state, &email, first_factor).await {
+        Ok(methods) => methods,
+        Err(e) => return (jar, Err(e)),
+    };
 
-    if code_tuple.0 != login_attempt_id || code_tuple.1 != two_fa_code {
+    // A client that says which method the code came from only has it checked that way. Either
+    // way only the methods this login attempt offers are tried.
+    if request
+        .method
+        .is_some_and(|method| !methods.contains(method))
+    {
+        return (jar, Err(AuthAPIError::TwoFactorMethodUnavailable));
+    }
+    let offered =
+        |candidates: &[TwoFactorMethod]| candidates.iter().any(|method| methods.contains(*method));
+    let sent_code = sent_code.filter(|_| offered(&[TwoFactorMethod::Email, TwoFactorMethod::Sms]));
+    let totp_code = totp_code && offered(&[TwoFactorMethod::Totp]);
+    let hotp_code = hotp_code && offered(&[TwoFactorMethod::Hotp]);
+    let recovery_code = recovery_code.filter(|_| offered(&[TwoFactorMethod::RecoveryCode]));
+    if sent_code.is_none() && !totp_code && !hotp_code && recovery_code.is_none() {
         return (jar, Err(AuthAPIError::InvalidCredentials));
     }
 
-    let _ = two_fa_code_store.remove_code(&email).await;
+    let mut two_fa_code_store = state.two_fa_code_store.write().await;
+
+    // Each login attempt has its own code, so one the user started elsewhere can't be completed
+    // with this attempt's id
+    let stored_code = match two_fa_code_store.get_code(&email, &login_attempt_id).await {
+        Ok(code) => code,
+        Err(_) => return (jar, Err(AuthAPIError::IncorrectCredentials)),
+    };
+
+    // Resolves to the method the code was accepted by. The same code is emailed and texted, so
+    // it only counts as a texted one when the client says so or it can't have been emailed. An
+    // emailed code is recorded as the inbox it came from, not as a factor of its own.
+    let code_accepted = async {
+        if sent_code.is_some_and(|code| code == stored_code) {
+            let texted = request.method == Some(TwoFactorMethod::Sms)
+                || !methods.contains(TwoFactorMethod::Email);
+            return Ok(Some(if texted {
+                AuthMethod::Sms
+            } else {
+                AuthMethod::Email
+            }));
+        }
+        // A six digit code could be from either kind of token
+        if totp_code && verify_totp_code(&state, &email, &request.two_fa_code).await? {
+            return Ok(Some(AuthMethod::Otp));
+        }
+        if hotp_code && verify_hotp_code(&state, &email, &request.two_fa_code).await? {
+            return Ok(Some(AuthMethod::Hwk));
+        }
+        match &recovery_code {
+            Some(code) => Ok(redeem_recovery_code(&state, &email, code)
+                .await?
+                .then_some(AuthMethod::RecoveryCode)),
+            None => Ok(None),
+        }
+    }
+    .await;
+    let second_factor = match code_accepted {
+        Ok(Some(method)) => method,
+        Ok(None) => {
+            let error = reject_code(
+                &mut *two_fa_code_store,
+                &email,
+                &login_attempt_id,
+                state.settings.two_fa.max_attempts,
+                AuthAPIError::InvalidCredentials,
+            )
+            .await;
+            drop(two_fa_code_store);
+            // Wrong codes also count towards locking the account, so starting new login attempts
+            // doesn't give an attacker who knows the password unlimited guesses
+            let user_store = state.userstore.read().await;
+            let error = match handle_failed_login(&state, &*user_store, &email).await {
+                AuthAPIError::IncorrectCredentials => error,
+                locked_or_unexpected => locked_or_unexpected,
+            };
+            return (jar, Err(error));
+        }
+        Err(e) => return (jar, Err(e)),
+    };
 
-    let cookie = match generate_auth_cookie(&email) {
+    let _ = two_fa_code_store
+        .remove_code(&email, &login_attempt_id)
+        .await;
+    drop(two_fa_code_store);
+
+    let user = match state.userstore.read().await.get_user(&email).await {
+        Ok(user) => user,
+        Err(e) => return (jar, Err(AuthAPIError::UnexpectedError(e.into()))),
+    };
+
+    // The account may have been suspended since the code was sent
+    if !user.status.is_active() {
+        return (jar, Err(AuthAPIError::AccountInactive));
+    }
+
+    if let Err(e) = reset_failed_logins(&state, &email).await {
+        return (jar, Err(e));
+    }
+
+    // The device counts as having passed 2FA even if the password still has to be changed
+    let jar = if request.remember_device {
+        let user_agent = headers
+            .get(USER_AGENT)
+            .and_then(|value| value.to_str().ok());
+        match remember_device(&state, &email, user_agent).await {
+            Ok(cookie) => jar.add(cookie),
+            Err(e) => return (jar, Err(e)),
+        }
+    } else {
+        jar
+    };
+
+    if let Some(reason) = user.password_change_reason(state.settings.password_max_age) {
+        return handle_password_change_required(&email, reason, jar);
+    }
+
+    let methods = match first_factor {
+        Some(first_factor) => [first_factor, second_factor],
+        None => [second_factor, AuthMethod::Mfa],
+    };
+    let cookie = match generate_auth_cookie(&email, &methods) {
         Ok(cookie) => cookie,
         Err(e) => return (jar, Err(AuthAPIError::UnexpectedError(e.into()))),
     };
@@ -60,6 +226,29 @@ pub async fn verify_2fa(
     )
 }
 
+// Counts a wrong code against the login attempt's stored one. Returns `error` while guesses
+// remain, and asks the user to start over once the code has been thrown away.
+pub(crate) async fn reject_code(
+    code_store: &mut (dyn TwoFaCodeStore + Send + Sync),
+    email: &Email,
+    login_attempt_id: &LoginAttemptId,
+    max_attempts: u32,
+    error: AuthAPIError,
+) -> AuthAPIError {
+    match code_store
+        .record_failed_attempt(email, login_attempt_id, max_attempts)
+        .await
+    {
+        Ok(_) => error,
+        Err(TwoFaCodeStoreError::TooManyAttempts) => AuthAPIError::TooManyCodeAttempts,
+        // The code expired in the meantime
+        Err(TwoFaCodeStoreError::LoginAttempIdNotFound | TwoFaCodeStoreError::CodeNotFound) => {
+            AuthAPIError::IncorrectCredentials
+        }
+        Err(e) => AuthAPIError::UnexpectedError(e.into()),
+    }
+}
+
 #[derive(Deserialize, Debug)]
 pub struct Verify2FARequest {
     email: String,
@@ -67,4 +256,10 @@ pub struct Verify2FARequest {
     login_attempt_id: String,
     #[serde(rename = "2FACode")]
     two_fa_code: String,
+    // Skip 2FA on later logins from this browser
+    #[serde(rename = "rememberDevice", default)]
+    remember_device: bool,
+    // Which of the methods listed at login the code is from
+    #[serde(default)]
+    method: Option<TwoFactorMethod>,
 }
@@ -1,14 +1,10 @@
 use crate::app_state::app_state::AppState;
-use crate::domain::data_store::*;
 use crate::domain::error::AuthAPIError;
-use crate::domain::user::User;
-use crate::domain::{Email, Password};
+use crate::domain::Email;
 use crate::utils::auth;
-use crate::utils::auth::generate_auth_cookie;
 use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
-use axum_extra::extract::CookieJar;
 use secrecy::Secret;
-use serde::{Deserialize, Serialize};
+use serde::Deserialize;
 
 #[tracing::instrument(name = "Verify Token", skip_all)]
 pub async fn verify_token(
@@ -19,8 +15,23 @@ pub async fn verify_token(
         return Err(AuthAPIError::InvalidToken);
     }
     let token = Secret::new(request.token);
-    if let Err(_) = auth::validate_token(token, state.tokenstore).await {
-        return Err(AuthAPIError::InvalidToken);
+    let claims = match auth::validate_token(token, state.tokenstore).await {
+        Ok(claims) => claims,
+        Err(_) => return Err(AuthAPIError::InvalidToken),
+    };
+
+    // A token stays valid until it expires, so the account is checked on every use
+    let email = Email::parse(Secret::new(claims.sub)).map_err(|_| AuthAPIError::InvalidToken)?;
+    let user = state
+        .userstore
+        .read()
+        .await
+        .get_user(&email)
+        .await
+        .map_err(|_| AuthAPIError::InvalidToken)?;
+
+    if !user.status.is_active() {
+        return Err(AuthAPIError::AccountInactive);
     }
 
     Ok(StatusCode::OK.into_response())
@@ -0,0 +1,494 @@
+use crate::app_state::app_state::AppState;
+use crate::domain::authentication::AuthMethod;
+use crate::domain::data_store::{
+    LoginAttemptId, TwoFaCodeStore, WebAuthnCeremonyStoreError, WebAuthnCredentialStoreError,
+};
+use crate::domain::error::AuthAPIError;
+use crate::domain::webauthn::{
+    client_data_challenge, user_handle, verify_assertion, verify_registration, CeremonyPurpose,
+    CredentialId, WebAuthnCeremony, WebAuthnChallenge, WebAuthnCredential, WebAuthnError,
+    COSE_ALGORITHM_ES256,
+};
+use crate::domain::Email;
+use crate::routes::login::{handle_no_2fa, handle_password_change_required, reset_failed_logins};
+use crate::utils::constants::WEBAUTHN_CEREMONY_TTL_SECONDS;
+use crate::utils::user_auth::{AuthenticatedUser, StepUpUser};
+use axum::response::Response;
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use axum_extra::extract::CookieJar;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::Duration;
+use secrecy::{ExposeSecret, Secret};
+use serde::{Deserialize, Serialize};
+
+// Returns the options for navigator.credentials.create() to register a passkey or security key
+#[tracing::instrument(name = "Start WebAuthn registration", skip_all)]
+pub async fn start_webauthn_registration(
+    user: StepUpUser,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let existing = state
+        .webauthn_credential_store
+        .read()
+        .await
+        .get_credentials(&user.email)
+        .await
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+
+    let challenge = start_ceremony(
+        &state,
+        CeremonyPurpose::Registration {
+            email: user.email.clone(),
+        },
+    )
+    .await?;
+
+    let settings = &state.settings.webauthn;
+    let email = user.email.as_ref().expose_secret().to_owned();
+    let options = CreationOptions {
+        challenge: challenge.as_ref().to_owned(),
+        rp: RelyingPartyEntity {
+            id: settings.rp_id.clone(),
+            name: settings.rp_name.clone(),
+        },
+        user: UserEntity {
+            id: user_handle(&user.email),
+            name: email.clone(),
+            display_name: email,
+        },
+        pub_key_cred_params: vec![CredentialParameters {
+            credential_type: PUBLIC_KEY.to_owned(),
+            alg: COSE_ALGORITHM_ES256,
+        }],
+        timeout: WEBAUTHN_CEREMONY_TTL_SECONDS * 1000,
+        attestation: "none".to_owned(),
+        // Stops the same authenticator from being registered twice
+        exclude_credentials: descriptors(&existing),
+        authenticator_selection: AuthenticatorSelection {
+            resident_key: "preferred".to_owned(),
+            user_verification: "preferred".to_owned(),
+        },
+    };
+
+    Ok((
+        StatusCode::OK,
+        Json(RegistrationOptionsResponse {
+            public_key: options,
+        }),
+    ))
+}
+
+// Checks the authenticator's response and stores the new credential
+#[tracing::instrument(name = "Finish WebAuthn registration", skip_all)]
+pub async fn finish_webauthn_registration(
+    user: AuthenticatedUser,
+    State(state): State<AppState>,
+    Json(request): Json<FinishRegistrationRequest>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let client_data = decode(&request.client_data_json)?;
+    let attestation_object = decode(&request.attestation_object)?;
+
+    let ceremony = take_ceremony(&state, &client_data).await?;
+    match &ceremony.purpose {
+        CeremonyPurpose::Registration { email } if *email == user.email => {}
+        _ => return Err(AuthAPIError::InvalidCredentials),
+    }
+
+    let credential = verify_registration(
+        state.settings.webauthn.relying_party(),
+        &ceremony.challenge,
+        &client_data,
+        &attestation_object,
+    )
+    .map_err(webauthn_error)?;
+    let credential_id = credential.id.as_ref().to_owned();
+
+    match state
+        .webauthn_credential_store
+        .write()
+        .await
+        .add_credential(&user.email, credential)
+        .await
+    {
+        Ok(_) => Ok((
+            StatusCode::CREATED,
+            Json(FinishRegistrationResponse {
+                message: "Passkey registered".to_string(),
+                credential_id,
+            }),
+        )),
+        Err(WebAuthnCredentialStoreError::CredentialAlreadyExists) => {
+            Err(AuthAPIError::InvalidCredentials)
+        }
+        Err(e) => Err(AuthAPIError::UnexpectedError(e.into())),
+    }
+}
+
+// Returns the options for navigator.credentials.get(). With a login attempt from /login the
+// passkey completes 2FA; without one it logs the user in on its own.
+#[tracing::instrument(name = "Start WebAuthn login", skip_all)]
+pub async fn start_webauthn_login(
+    State(state): State<AppState>,
+    Json(request): Json<StartLoginRequest>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let email = request
+        .email
+        .map(|email| Email::parse(Secret::new(email)))
+        .transpose()
+        .map_err(|_| AuthAPIError::InvalidCredentials)?;
+    let login_attempt_id = request
+        .login_attempt_id
+        .map(|id| LoginAttemptId::parse(Secret::new(id)))
+        .transpose()
+        .map_err(|_| AuthAPIError::InvalidCredentials)?;
+    if login_attempt_id.is_some() && email.is_none() {
+        return Err(AuthAPIError::InvalidCredentials);
+    }
+
+    // Without an email the browser offers any passkey it holds for this site
+    let credentials = match &email {
+        Some(email) => state
+            .webauthn_credential_store
+            .read()
+            .await
+            .get_credentials(email)
+            .await
+            .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?,
+        None => Vec::new(),
+    };
+
+    // A passkey used as the only factor has to verify the user, e.g. with a PIN or biometric
+    let user_verification = match login_attempt_id {
+        Some(_) => "preferred",
+        None => "required",
+    };
+    let challenge = start_ceremony(
+        &state,
+        CeremonyPurpose::Authentication {
+            email,
+            login_attempt_id,
+        },
+    )
+    .await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(LoginOptionsResponse {
+            public_key: RequestOptions {
+                challenge: challenge.as_ref().to_owned(),
+                rp_id: state.settings.webauthn.rp_id.clone(),
+                timeout: WEBAUTHN_CEREMONY_TTL_SECONDS * 1000,
+                allow_credentials: descriptors(&credentials),
+                user_verification: user_verification.to_owned(),
+            },
+        }),
+    ))
+}
+
+#[tracing::instrument(name = "Finish WebAuthn login", skip_all)]
+pub async fn finish_webauthn_login(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Json(request): Json<FinishLoginRequest>,
+) -> (CookieJar, Result<impl IntoResponse, AuthAPIError>) {
+    match check_assertion(&state, request).await {
+        Ok((email, methods)) => complete_login(&state, &email, &methods, jar).await,
+        Err(e) => (jar, Err(e)),
+    }
+}
+
+// Verifies the assertion and returns the user it logs in, along with how they authenticated
+async fn check_assertion(
+    state: &AppState,
+    request: FinishLoginRequest,
+) -> Result<(Email, Vec<AuthMethod>), AuthAPIError> {
+    let credential_id =
+        CredentialId::parse(request.credential_id).map_err(|_| AuthAPIError::InvalidCredentials)?;
+    let client_data = decode(&request.client_data_json)?;
+    let authenticator_data = decode(&request.authenticator_data)?;
+    let signature = decode(&request.signature)?;
+
+    let ceremony = take_ceremony(state, &client_data).await?;
+    let CeremonyPurpose::Authentication {
+        email: expected_email,
+        login_attempt_id,
+    } = ceremony.purpose
+    else {
+        return Err(AuthAPIError::InvalidCredentials);
+    };
+
+    let (email, credential) = match state
+        .webauthn_credential_store
+        .read()
+        .await
+        .get_credential(&credential_id)
+        .await
+    {
+        Ok(found) => found,
+        Err(WebAuthnCredentialStoreError::CredentialNotFound) => {
+            return Err(AuthAPIError::IncorrectCredentials)
+        }
+        Err(e) => return Err(AuthAPIError::UnexpectedError(e.into())),
+    };
+    if expected_email
+        .as_ref()
+        .is_some_and(|expected| *expected != email)
+    {
+        return Err(AuthAPIError::IncorrectCredentials);
+    }
+
+    let sign_count = verify_assertion(
+        state.settings.webauthn.relying_party(),
+        &ceremony.challenge,
+        &credential,
+        &client_data,
+        &authenticator_data,
+        &signature,
+        login_attempt_id.is_none(),
+    )
+    .map_err(webauthn_error)?;
+
+    match state
+        .webauthn_credential_store
+        .write()
+        .await
+        .update_sign_count(&credential.id, credential.sign_count, sign_count)
+        .await
+    {
+        Ok(_) => {}
+        Err(WebAuthnCredentialStoreError::SignCountChanged) => {
+            return Err(webauthn_error(WebAuthnError::SignCountRegressed))
+        }
+        Err(e) => return Err(AuthAPIError::UnexpectedError(e.into())),
+    }
+
+    // A passkey on its own is a complete login. As a second factor it has to answer for the
+    // login attempt the password started.
+    let Some(login_attempt_id) = login_attempt_id else {
+        return Ok((email, vec![AuthMethod::Webauthn]));
+    };
+    let mut two_fa_code_store = state.two_fa_code_store.write().await;
+    let first_factor = match two_fa_code_store
+        .get_first_factor(&email, &login_attempt_id)
+        .await
+    {
+        Ok(method) => method,
+        Err(_) => return Err(AuthAPIError::IncorrectCredentials),
+    };
+    let _ = two_fa_code_store
+        .remove_code(&email, &login_attempt_id)
+        .await;
+
+    let methods = match first_factor {
+        Some(first_factor) => vec![first_factor, AuthMethod::Webauthn],
+        None => vec![AuthMethod::Webauthn, AuthMethod::Mfa],
+    };
+    Ok((email, methods))
+}
+
+async fn complete_login(
+    state: &AppState,
+    email: &Email,
+    methods: &[AuthMethod],
+    jar: CookieJar,
+) -> (CookieJar, Result<Response, AuthAPIError>) {
+    let user = match state.userstore.read().await.get_user(email).await {
+        Ok(user) => user,
+        Err(e) => return (jar, Err(AuthAPIError::UnexpectedError(e.into()))),
+    };
+    if !user.status.is_active() {
+        return (jar, Err(AuthAPIError::AccountInactive));
+    }
+    if let Err(e) = reset_failed_logins(state, email).await {
+        return (jar, Err(e));
+    }
+    if let Some(reason) = user.password_change_reason(state.settings.password_max_age) {
+        return handle_password_change_required(email, reason, jar);
+    }
+
+    handle_no_2fa(email, methods, jar).await
+}
+
+async fn start_ceremony(
+    state: &AppState,
+    purpose: CeremonyPurpose,
+) -> Result<WebAuthnChallenge, AuthAPIError> {
+    let challenge = WebAuthnChallenge::default();
+    state
+        .webauthn_ceremony_store
+        .write()
+        .await
+        .add_ceremony(
+            WebAuthnCeremony {
+                challenge: challenge.clone(),
+                purpose,
+            },
+            Duration::seconds(WEBAUTHN_CEREMONY_TTL_SECONDS),
+        )
+        .await
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+    Ok(challenge)
+}
+
+// Finds the ceremony a response answers through the challenge in its client data
+async fn take_ceremony(
+    state: &AppState,
+    client_data: &[u8],
+) -> Result<WebAuthnCeremony, AuthAPIError> {
+    let challenge = client_data_challenge(client_data).map_err(webauthn_error)?;
+    match state
+        .webauthn_ceremony_store
+        .write()
+        .await
+        .take_ceremony(&challenge)
+        .await
+    {
+        Ok(ceremony) => Ok(ceremony),
+        Err(WebAuthnCeremonyStoreError::CeremonyNotFound) => Err(AuthAPIError::InvalidCredentials),
+        Err(e) => Err(AuthAPIError::UnexpectedError(e.into())),
+    }
+}
+
+fn webauthn_error(e: WebAuthnError) -> AuthAPIError {
+    match e {
+        WebAuthnError::Malformed(_) => AuthAPIError::InvalidCredentials,
+        WebAuthnError::SignCountRegressed => {
+            tracing::warn!("WebAuthn signature counter went backwards, possible cloned credential");
+            AuthAPIError::IncorrectCredentials
+        }
+        _ => AuthAPIError::IncorrectCredentials,
+    }
+}
+
+fn decode(value: &str) -> Result<Vec<u8>, AuthAPIError> {
+    URL_SAFE_NO_PAD
+        .decode(value)
+        .map_err(|_| AuthAPIError::InvalidCredentials)
+}
+
+fn descriptors(credentials: &[WebAuthnCredential]) -> Vec<CredentialDescriptor> {
+    credentials
+        .iter()
+        .map(|credential| CredentialDescriptor {
+            credential_type: PUBLIC_KEY.to_owned(),
+            id: credential.id.as_ref().to_owned(),
+        })
+        .collect()
+}
+
+const PUBLIC_KEY: &str = "public-key";
+
+// The option and response types follow the JSON form of the WebAuthn API, with binary values
+// base64url-encoded without padding
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct RegistrationOptionsResponse {
+    #[serde(rename = "publicKey")]
+    pub public_key: CreationOptions,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct CreationOptions {
+    pub challenge: String,
+    pub rp: RelyingPartyEntity,
+    pub user: UserEntity,
+    #[serde(rename = "pubKeyCredParams")]
+    pub pub_key_cred_params: Vec<CredentialParameters>,
+    // Milliseconds
+    pub timeout: i64,
+    pub attestation: String,
+    #[serde(rename = "excludeCredentials")]
+    pub exclude_credentials: Vec<CredentialDescriptor>,
+    #[serde(rename = "authenticatorSelection")]
+    pub authenticator_selection: AuthenticatorSelection,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct RelyingPartyEntity {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct UserEntity {
+    pub id: String,
+    pub name: String,
+    #[serde(rename = "displayName")]
+    pub display_name: String,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct CredentialParameters {
+    #[serde(rename = "type")]
+    pub credential_type: String,
+    pub alg: i64,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct CredentialDescriptor {
+    #[serde(rename = "type")]
+    pub credential_type: String,
+    pub id: String,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct AuthenticatorSelection {
+    #[serde(rename = "residentKey")]
+    pub resident_key: String,
+    #[serde(rename = "userVerification")]
+    pub user_verification: String,
+}
+
+#[derive(Deserialize)]
+pub struct FinishRegistrationRequest {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    #[serde(rename = "attestationObject")]
+    pub attestation_object: String,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct FinishRegistrationResponse {
+    pub message: String,
+    #[serde(rename = "credentialId")]
+    pub credential_id: String,
+}
+
+#[derive(Deserialize)]
+pub struct StartLoginRequest {
+    #[serde(default)]
+    pub email: Option<String>,
+    // From the 2FA response of /login, to use the passkey as the second factor
+    #[serde(rename = "loginAttemptId", default)]
+    pub login_attempt_id: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct LoginOptionsResponse {
+    #[serde(rename = "publicKey")]
+    pub public_key: RequestOptions,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct RequestOptions {
+    pub challenge: String,
+    #[serde(rename = "rpId")]
+    pub rp_id: String,
+    // Milliseconds
+    pub timeout: i64,
+    #[serde(rename = "allowCredentials")]
+    pub allow_credentials: Vec<CredentialDescriptor>,
+    #[serde(rename = "userVerification")]
+    pub user_verification: String,
+}
+
+#[derive(Deserialize)]
+pub struct FinishLoginRequest {
+    #[serde(rename = "credentialId")]
+    pub credential_id: String,
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    #[serde(rename = "authenticatorData")]
+    pub authenticator_data: String,
+    pub signature: String,
+}
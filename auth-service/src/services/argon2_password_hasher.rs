@@ -0,0 +1,147 @@
+use argon2::{
+    password_hash::SaltString, Algorithm, Argon2, Params, PasswordHash, PasswordHasher as _,
+    PasswordVerifier, Version,
+};
+use color_eyre::eyre::{Context, Result};
+use rand_core::OsRng;
+use secrecy::{ExposeSecret, Secret};
+
+use crate::domain::PasswordHasher;
+
+#[derive(Clone)]
+pub struct Argon2PasswordHasher {
+    params: Params,
+}
+
+impl Argon2PasswordHasher {
+    pub fn new(params: Params) -> Self {
+        Self { params }
+    }
+}
+
+#[async_trait::async_trait]
+impl PasswordHasher for Argon2PasswordHasher {
+    #[tracing::instrument(name = "Computing password hash", skip_all)]
+    async fn hash(&self, password: Secret<String>) -> Result<Secret<String>> {
+        let current_span: tracing::Span = tracing::Span::current();
+        let params = self.params.clone();
+
+        let result = tokio::task::spawn_blocking(move || {
+            current_span.in_scope(|| {
+                let salt: SaltString = SaltString::generate(&mut OsRng);
+                let password_hash = Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+                    .hash_password(password.expose_secret().as_bytes(), &salt)?
+                    .to_string();
+                Ok(Secret::new(password_hash))
+            })
+        })
+        .await;
+
+        result?
+    }
+
+    #[tracing::instrument(name = "Verify password hash", skip_all)]
+    async fn verify(
+        &self,
+        expected_password_hash: Secret<String>,
+        password_candidate: Secret<String>,
+    ) -> Result<()> {
+        let current_span: tracing::Span = tracing::Span::current();
+        let result = tokio::task::spawn_blocking(move || {
+            current_span.in_scope(|| {
+                let expected_password_hash: PasswordHash<'_> =
+                    PasswordHash::new(expected_password_hash.expose_secret())?;
+
+                Argon2::default()
+                    .verify_password(
+                        password_candidate.expose_secret().as_bytes(),
+                        &expected_password_hash,
+                    )
+                    .wrap_err("failed to verify password")
+            })
+        })
+        .await;
+
+        result?
+    }
+
+    // A stored hash is outdated when it was not produced by Argon2id v0x13 with exactly the
+    // configured costs. Hashes we cannot parse are treated as outdated as well.
+    fn needs_rehash(&self, password_hash: &Secret<String>) -> bool {
+        let hash = match PasswordHash::new(password_hash.expose_secret()) {
+            Ok(hash) => hash,
+            Err(_) => return true,
+        };
+
+        if hash.algorithm != Algorithm::Argon2id.ident()
+            || hash.version != Some(Version::V0x13.into())
+        {
+            return true;
+        }
+
+        match Params::try_from(&hash) {
+            Ok(current) => {
+                current.m_cost() != self.params.m_cost()
+                    || current.t_cost() != self.params.t_cost()
+                    || current.p_cost() != self.params.p_cost()
+            }
+            Err(_) => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hasher(m_cost: u32, t_cost: u32) -> Argon2PasswordHasher {
+        Argon2PasswordHasher::new(Params::new(m_cost, t_cost, 1, None).unwrap())
+    }
+
+    fn hash_with(algorithm: Algorithm, params: Params) -> Secret<String> {
+        let salt = SaltString::generate(&mut OsRng);
+        let hash = Argon2::new(algorithm, Version::V0x13, params)
+            .hash_password(b"password123", &salt)
+            .unwrap()
+            .to_string();
+        Secret::new(hash)
+    }
+
+    #[tokio::test]
+    async fn hash_verifies_against_original_password() {
+        let hasher = hasher(64, 1);
+        let password = Secret::new("password123".to_owned());
+        let hash = hasher.hash(password.clone()).await.unwrap();
+
+        assert!(hasher.verify(hash.clone(), password).await.is_ok());
+        assert!(hasher
+            .verify(hash, Secret::new("wrongpassword".to_owned()))
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn hash_with_current_params_does_not_need_rehash() {
+        let params = Params::new(64, 1, 1, None).unwrap();
+        let hash = hash_with(Algorithm::Argon2id, params);
+        assert!(!hasher(64, 1).needs_rehash(&hash));
+    }
+
+    #[test]
+    fn hash_with_outdated_costs_needs_rehash() {
+        let hash = hash_with(Algorithm::Argon2id, Params::new(64, 1, 1, None).unwrap());
+        assert!(hasher(128, 1).needs_rehash(&hash));
+        assert!(hasher(64, 2).needs_rehash(&hash));
+    }
+
+    #[test]
+    fn hash_with_other_algorithm_needs_rehash() {
+        let hash = hash_with(Algorithm::Argon2i, Params::new(64, 1, 1, None).unwrap());
+        assert!(hasher(64, 1).needs_rehash(&hash));
+    }
+
+    #[test]
+    fn unparsable_hash_needs_rehash() {
+        assert!(hasher(64, 1).needs_rehash(&Secret::new("not-a-hash".to_owned())));
+    }
+}
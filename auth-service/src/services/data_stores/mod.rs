@@ -1,3 +1,13 @@
+pub mod postgres_email_outbox_store;
+pub mod postgres_hotp_store;
+pub mod postgres_invite_store;
+pub mod postgres_phone_store;
+pub mod postgres_recovery_code_store;
+pub mod postgres_totp_store;
+pub mod postgres_trusted_device_store;
 pub mod postgres_user_store;
+pub mod postgres_webauthn_credential_store;
 pub mod redis_banned_token_stores;
+pub mod redis_login_attempt_store;
 pub mod redis_two_fa_code_store;
+pub mod redis_webauthn_ceremony_store;
@@ -0,0 +1,266 @@
+use crate::domain::{
+    data_store::{EmailOutboxStore, EmailOutboxStoreError},
+    email_outbox::{OutboxEmail, OutboxEmailId, EXPIRED_BEFORE_SENDING},
+    Email, EmailMessage,
+};
+use chrono::{DateTime, Duration, Utc};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+
+pub struct PostgresEmailOutboxStore {
+    pool: PgPool,
+}
+
+impl PostgresEmailOutboxStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+struct OutboxRow {
+    id: String,
+    idempotency_key: String,
+    recipient: String,
+    subject: String,
+    html_body: String,
+    text_body: String,
+    status: String,
+    attempts: i32,
+    next_attempt_at: DateTime<Utc>,
+    last_error: Option<String>,
+    created_at: DateTime<Utc>,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+impl TryFrom<OutboxRow> for OutboxEmail {
+    type Error = EmailOutboxStoreError;
+
+    fn try_from(row: OutboxRow) -> Result<Self, Self::Error> {
+        Ok(OutboxEmail {
+            id: OutboxEmailId::parse(&row.id).map_err(EmailOutboxStoreError::UnexpectedError)?,
+            idempotency_key: row.idempotency_key.into(),
+            recipient: Email::parse(Secret::new(row.recipient))
+                .map_err(EmailOutboxStoreError::UnexpectedError)?,
+            message: EmailMessage {
+                subject: row.subject,
+                html_body: row.html_body,
+                text_body: row.text_body,
+            },
+            status: row
+                .status
+                .parse()
+                .map_err(EmailOutboxStoreError::UnexpectedError)?,
+            attempts: row.attempts as u32,
+            next_attempt_at: row.next_attempt_at,
+            last_error: row.last_error,
+            created_at: row.created_at,
+            expires_at: row.expires_at,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailOutboxStore for PostgresEmailOutboxStore {
+    #[tracing::instrument(name = "Queuing email in PostgreSQL", skip_all)]
+    async fn enqueue(&mut self, email: OutboxEmail) -> Result<(), EmailOutboxStoreError> {
+        sqlx::query!(
+            r#"
+                INSERT INTO email_outbox (
+                    id, idempotency_key, recipient, subject, html_body, text_body, status,
+                    attempts, next_attempt_at, created_at, expires_at
+                )
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+                ON CONFLICT (idempotency_key) DO NOTHING
+            "#,
+            email.id.to_string(),
+            email.idempotency_key.as_ref(),
+            email.recipient.as_ref().expose_secret(),
+            email.message.subject,
+            email.message.html_body,
+            email.message.text_body,
+            email.status.as_str(),
+            email.attempts as i32,
+            email.next_attempt_at,
+            email.created_at,
+            email.expires_at
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| EmailOutboxStoreError::UnexpectedError(e.into()))?;
+
+        Ok(())
+    }
+
+    // SKIP LOCKED lets several instances of the service claim from the outbox side by side
+    // without waiting on each other or taking the same emails
+    #[tracing::instrument(name = "Claiming due emails from PostgreSQL", skip_all)]
+    async fn claim_due(
+        &mut self,
+        limit: usize,
+        lease: Duration,
+    ) -> Result<Vec<OutboxEmail>, EmailOutboxStoreError> {
+        // Sending a code or link that no longer works would only confuse the user
+        sqlx::query!(
+            r#"
+                UPDATE email_outbox
+                SET status = 'dead', last_error = $1, html_body = '', text_body = ''
+                WHERE status = 'pending' AND expires_at <= NOW()
+            "#,
+            EXPIRED_BEFORE_SENDING
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| EmailOutboxStoreError::UnexpectedError(e.into()))?;
+
+        let rows = sqlx::query_as!(
+            OutboxRow,
+            r#"
+                UPDATE email_outbox SET next_attempt_at = NOW() + $2 * INTERVAL '1 millisecond'
+                WHERE id IN (
+                    SELECT id FROM email_outbox
+                    WHERE status = 'pending' AND next_attempt_at <= NOW()
+                        AND (expires_at IS NULL OR expires_at > NOW())
+                    ORDER BY next_attempt_at
+                    LIMIT $1
+                    FOR UPDATE SKIP LOCKED
+                )
+                RETURNING id, idempotency_key, recipient, subject, html_body, text_body, status,
+                    attempts, next_attempt_at, last_error, created_at, expires_at
+            "#,
+            limit as i64,
+            lease.num_milliseconds() as f64
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| EmailOutboxStoreError::UnexpectedError(e.into()))?;
+
+        let mut emails = rows
+            .into_iter()
+            .map(OutboxEmail::try_from)
+            .collect::<Result<Vec<_>, _>>()?;
+        // RETURNING does not keep the order of the subquery
+        emails.sort_by_key(|email| email.created_at);
+        Ok(emails)
+    }
+
+    // The bodies are dropped once they have been delivered, as they may hold codes and links
+    #[tracing::instrument(name = "Marking email sent in PostgreSQL", skip_all)]
+    async fn mark_sent(&mut self, id: &OutboxEmailId) -> Result<(), EmailOutboxStoreError> {
+        let result = sqlx::query!(
+            r#"
+                UPDATE email_outbox
+                SET status = 'sent', sent_at = NOW(), html_body = '', text_body = ''
+                WHERE id = $1
+            "#,
+            id.to_string()
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| EmailOutboxStoreError::UnexpectedError(e.into()))?;
+
+        if result.rows_affected() == 0 {
+            return Err(EmailOutboxStoreError::EmailNotFound);
+        }
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Marking email failed in PostgreSQL", skip_all)]
+    async fn mark_failed(
+        &mut self,
+        id: &OutboxEmailId,
+        error: &str,
+        retry_at: Option<DateTime<Utc>>,
+    ) -> Result<(), EmailOutboxStoreError> {
+        let result = sqlx::query!(
+            r#"
+                UPDATE email_outbox
+                SET attempts = attempts + 1, last_error = $2,
+                    status = CASE WHEN $3::TIMESTAMPTZ IS NULL THEN 'dead' ELSE status END,
+                    next_attempt_at = COALESCE($3, next_attempt_at)
+                WHERE id = $1
+            "#,
+            id.to_string(),
+            error,
+            retry_at
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| EmailOutboxStoreError::UnexpectedError(e.into()))?;
+
+        if result.rows_affected() == 0 {
+            return Err(EmailOutboxStoreError::EmailNotFound);
+        }
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Retrieving dead letters from PostgreSQL", skip_all)]
+    async fn get_dead_letters(&self) -> Result<Vec<OutboxEmail>, EmailOutboxStoreError> {
+        let rows = sqlx::query_as!(
+            OutboxRow,
+            r#"
+                SELECT id, idempotency_key, recipient, subject, html_body, text_body, status,
+                    attempts, next_attempt_at, last_error, created_at, expires_at
+                FROM email_outbox
+                WHERE status = 'dead'
+                ORDER BY created_at DESC
+            "#
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| EmailOutboxStoreError::UnexpectedError(e.into()))?;
+
+        rows.into_iter().map(OutboxEmail::try_from).collect()
+    }
+
+    #[tracing::instrument(name = "Replaying dead letter in PostgreSQL", skip_all)]
+    async fn replay(&mut self, id: &OutboxEmailId) -> Result<(), EmailOutboxStoreError> {
+        let result = sqlx::query!(
+            r#"
+                UPDATE email_outbox SET status = 'pending', attempts = 0, next_attempt_at = NOW()
+                WHERE id = $1 AND status = 'dead' AND (expires_at IS NULL OR expires_at > NOW())
+            "#,
+            id.to_string()
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| EmailOutboxStoreError::UnexpectedError(e.into()))?;
+
+        if result.rows_affected() > 0 {
+            return Ok(());
+        }
+
+        // Tell a dead letter that expired apart from one that doesn't exist
+        let dead = sqlx::query_scalar!(
+            r#"
+                SELECT EXISTS(SELECT 1 FROM email_outbox WHERE id = $1 AND status = 'dead')
+                    AS "dead!"
+            "#,
+            id.to_string()
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| EmailOutboxStoreError::UnexpectedError(e.into()))?;
+
+        match dead {
+            true => Err(EmailOutboxStoreError::EmailExpired),
+            false => Err(EmailOutboxStoreError::EmailNotFound),
+        }
+    }
+
+    #[tracing::instrument(name = "Clearing expired dead letters in PostgreSQL", skip_all)]
+    async fn clear_expired_dead_letters(&mut self) -> Result<usize, EmailOutboxStoreError> {
+        let result = sqlx::query!(
+            r#"
+                UPDATE email_outbox SET html_body = '', text_body = ''
+                WHERE status = 'dead' AND expires_at <= NOW() AND text_body <> ''
+            "#
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| EmailOutboxStoreError::UnexpectedError(e.into()))?;
+
+        Ok(result.rows_affected() as usize)
+    }
+}
@@ -0,0 +1,145 @@
+use crate::domain::{
+    data_store::{HotpStore, HotpStoreError},
+    hotp::{HotpSecret, HotpToken},
+    Email,
+};
+use color_eyre::eyre::eyre;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+
+pub struct PostgresHotpStore {
+    pool: PgPool,
+}
+
+impl PostgresHotpStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+fn to_db_counter(counter: u64) -> Result<i64, HotpStoreError> {
+    i64::try_from(counter)
+        .map_err(|_| HotpStoreError::UnexpectedError(eyre!("HOTP counter is too large")))
+}
+
+fn to_token(
+    serial: String,
+    secret: String,
+    digits: i16,
+    counter: i64,
+) -> Result<HotpToken, HotpStoreError> {
+    let secret = HotpSecret::parse(Secret::new(secret)).map_err(HotpStoreError::UnexpectedError)?;
+    HotpToken::new(&serial, secret, digits as usize, counter as u64)
+        .map_err(HotpStoreError::UnexpectedError)
+}
+
+#[async_trait::async_trait]
+impl HotpStore for PostgresHotpStore {
+    #[tracing::instrument(name = "Adding HOTP token to PostgreSQL", skip_all)]
+    async fn add_token(&mut self, token: HotpToken) -> Result<(), HotpStoreError> {
+        let result = sqlx::query!(
+            r#"
+                INSERT INTO hotp_tokens (serial, secret, digits, counter) VALUES ($1, $2, $3, $4)
+                ON CONFLICT (serial) DO NOTHING
+            "#,
+            token.serial,
+            token.secret.as_ref().expose_secret(),
+            token.digits as i16,
+            to_db_counter(token.counter)?
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| HotpStoreError::UnexpectedError(e.into()))?;
+
+        if result.rows_affected() == 0 {
+            return Err(HotpStoreError::TokenAlreadyExists);
+        }
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Retrieving unassigned HOTP token from PostgreSQL", skip_all)]
+    async fn get_unassigned_token(&self, serial: &str) -> Result<HotpToken, HotpStoreError> {
+        let row = sqlx::query!(
+            r#"
+                SELECT serial, secret, digits, counter FROM hotp_tokens
+                WHERE serial = $1 AND email IS NULL
+            "#,
+            serial
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| HotpStoreError::UnexpectedError(e.into()))?
+        .ok_or(HotpStoreError::TokenNotFound)?;
+
+        to_token(row.serial, row.secret, row.digits, row.counter)
+    }
+
+    #[tracing::instrument(name = "Assigning HOTP token in PostgreSQL", skip_all)]
+    async fn assign_token(
+        &mut self,
+        serial: &str,
+        email: &Email,
+        counter: u64,
+    ) -> Result<(), HotpStoreError> {
+        // The unique email column backs up the check against a second token
+        let result = sqlx::query!(
+            r#"
+                UPDATE hotp_tokens SET email = $2, counter = $3, assigned_at = NOW()
+                WHERE serial = $1 AND email IS NULL
+                AND NOT EXISTS (SELECT 1 FROM hotp_tokens WHERE email = $2)
+            "#,
+            serial,
+            email.as_ref().expose_secret(),
+            to_db_counter(counter)?
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| HotpStoreError::UnexpectedError(e.into()))?;
+
+        if result.rows_affected() == 0 {
+            return match self.get_token(email).await {
+                Ok(_) => Err(HotpStoreError::AlreadyEnrolled),
+                Err(HotpStoreError::NotEnrolled) => Err(HotpStoreError::TokenNotFound),
+                Err(e) => Err(e),
+            };
+        }
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Retrieving HOTP token from PostgreSQL", skip_all)]
+    async fn get_token(&self, email: &Email) -> Result<HotpToken, HotpStoreError> {
+        let row = sqlx::query!(
+            "SELECT serial, secret, digits, counter FROM hotp_tokens WHERE email = $1",
+            email.as_ref().expose_secret()
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| HotpStoreError::UnexpectedError(e.into()))?
+        .ok_or(HotpStoreError::NotEnrolled)?;
+
+        to_token(row.serial, row.secret, row.digits, row.counter)
+    }
+
+    #[tracing::instrument(name = "Advancing HOTP counter in PostgreSQL", skip_all)]
+    async fn advance_counter(&mut self, email: &Email, counter: u64) -> Result<(), HotpStoreError> {
+        // Checking and moving the counter in one statement keeps two concurrent requests from
+        // both accepting the same code
+        let result = sqlx::query!(
+            "UPDATE hotp_tokens SET counter = $2 WHERE email = $1 AND counter < $2",
+            email.as_ref().expose_secret(),
+            to_db_counter(counter)?
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| HotpStoreError::UnexpectedError(e.into()))?;
+
+        if result.rows_affected() == 0 {
+            self.get_token(email).await?;
+            return Err(HotpStoreError::CodeReused);
+        }
+
+        Ok(())
+    }
+}
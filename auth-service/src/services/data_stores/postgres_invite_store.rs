@@ -0,0 +1,108 @@
+use crate::domain::{
+    data_store::{InviteStore, InviteStoreError},
+    invite::{Invite, InviteCode},
+    Email,
+};
+use chrono::Utc;
+use color_eyre::eyre::eyre;
+use secrecy::ExposeSecret;
+use sqlx::PgPool;
+
+pub struct PostgresInviteStore {
+    pool: PgPool,
+}
+
+impl PostgresInviteStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait::async_trait]
+impl InviteStore for PostgresInviteStore {
+    #[tracing::instrument(name = "Adding invite to PostgreSQL", skip_all)]
+    async fn add_invite(&mut self, invite: Invite) -> Result<(), InviteStoreError> {
+        let max_uses = i32::try_from(invite.max_uses)
+            .map_err(|_| InviteStoreError::UnexpectedError(eyre!("max_uses is too large")))?;
+        let uses = i32::try_from(invite.uses)
+            .map_err(|_| InviteStoreError::UnexpectedError(eyre!("uses is too large")))?;
+
+        sqlx::query!(
+            "INSERT INTO invites (code, email, max_uses, uses, expires_at) VALUES ($1, $2, $3, $4, $5)",
+            invite.code.as_ref().expose_secret(),
+            invite.email.as_ref().map(|email| email.as_ref().expose_secret().as_str()),
+            max_uses,
+            uses,
+            invite.expires_at
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| InviteStoreError::UnexpectedError(e.into()))?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Consuming invite in PostgreSQL", skip_all)]
+    async fn consume_invite(
+        &mut self,
+        code: &InviteCode,
+        email: &Email,
+    ) -> Result<(), InviteStoreError> {
+        // Every condition is checked by the update itself, so two signups racing for the
+        // last use cannot both get it
+        let consumed = sqlx::query!(
+            r#"
+                UPDATE invites SET uses = uses + 1
+                WHERE code = $1
+                  AND uses < max_uses
+                  AND expires_at > NOW()
+                  AND (email IS NULL OR email = $2)
+                RETURNING code
+            "#,
+            code.as_ref().expose_secret(),
+            email.as_ref().expose_secret()
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| InviteStoreError::UnexpectedError(e.into()))?;
+
+        if consumed.is_some() {
+            return Ok(());
+        }
+
+        // Nothing was updated; look the invite up to tell the caller why
+        let invite = sqlx::query!(
+            "SELECT email, max_uses, uses, expires_at FROM invites WHERE code = $1",
+            code.as_ref().expose_secret()
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| InviteStoreError::UnexpectedError(e.into()))?
+        .ok_or(InviteStoreError::InviteNotFound)?;
+
+        if invite.expires_at <= Utc::now() {
+            Err(InviteStoreError::InviteExpired)
+        } else if invite.uses >= invite.max_uses {
+            Err(InviteStoreError::InviteExhausted)
+        } else {
+            Err(InviteStoreError::EmailMismatch)
+        }
+    }
+
+    #[tracing::instrument(name = "Releasing invite in PostgreSQL", skip_all)]
+    async fn release_invite(&mut self, code: &InviteCode) -> Result<(), InviteStoreError> {
+        let result = sqlx::query!(
+            "UPDATE invites SET uses = GREATEST(uses - 1, 0) WHERE code = $1",
+            code.as_ref().expose_secret()
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| InviteStoreError::UnexpectedError(e.into()))?;
+
+        if result.rows_affected() == 0 {
+            return Err(InviteStoreError::InviteNotFound);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,84 @@
+use crate::domain::{
+    data_store::{PhoneStore, PhoneStoreError},
+    phone_number::{PhoneNumber, UserPhone},
+    Email,
+};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+
+pub struct PostgresPhoneStore {
+    pool: PgPool,
+}
+
+impl PostgresPhoneStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait::async_trait]
+impl PhoneStore for PostgresPhoneStore {
+    #[tracing::instrument(name = "Storing pending phone number in PostgreSQL", skip_all)]
+    async fn set_pending_phone(
+        &mut self,
+        email: &Email,
+        number: PhoneNumber,
+    ) -> Result<(), PhoneStoreError> {
+        sqlx::query!(
+            r#"
+                INSERT INTO user_phones (email, pending_phone_number) VALUES ($1, $2)
+                ON CONFLICT (email) DO UPDATE
+                SET pending_phone_number = EXCLUDED.pending_phone_number, updated_at = NOW()
+            "#,
+            email.as_ref().expose_secret(),
+            number.as_ref().expose_secret()
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| PhoneStoreError::UnexpectedError(e.into()))?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Retrieving phone number from PostgreSQL", skip_all)]
+    async fn get_phone(&self, email: &Email) -> Result<UserPhone, PhoneStoreError> {
+        let row = sqlx::query!(
+            r#"
+                SELECT phone_number AS "phone_number!" FROM user_phones
+                WHERE email = $1 AND phone_number IS NOT NULL
+            "#,
+            email.as_ref().expose_secret()
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| PhoneStoreError::UnexpectedError(e.into()))?
+        .ok_or(PhoneStoreError::PhoneNotFound)?;
+
+        Ok(UserPhone {
+            number: PhoneNumber::parse(Secret::new(row.phone_number))
+                .map_err(PhoneStoreError::UnexpectedError)?,
+        })
+    }
+
+    #[tracing::instrument(name = "Verifying phone number in PostgreSQL", skip_all)]
+    async fn mark_verified(&mut self, email: &Email) -> Result<(), PhoneStoreError> {
+        let result = sqlx::query!(
+            r#"
+                UPDATE user_phones
+                SET phone_number = pending_phone_number, pending_phone_number = NULL,
+                    updated_at = NOW()
+                WHERE email = $1 AND pending_phone_number IS NOT NULL
+            "#,
+            email.as_ref().expose_secret()
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| PhoneStoreError::UnexpectedError(e.into()))?;
+
+        if result.rows_affected() == 0 {
+            return Err(PhoneStoreError::PhoneNotFound);
+        }
+
+        Ok(())
+    }
+}
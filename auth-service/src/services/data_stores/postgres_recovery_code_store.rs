@@ -0,0 +1,97 @@
+use crate::domain::{
+    data_store::{RecoveryCodeStore, RecoveryCodeStoreError},
+    recovery_code::RecoveryCode,
+    Email,
+};
+use secrecy::ExposeSecret;
+use sqlx::PgPool;
+
+pub struct PostgresRecoveryCodeStore {
+    pool: PgPool,
+}
+
+impl PostgresRecoveryCodeStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait::async_trait]
+impl RecoveryCodeStore for PostgresRecoveryCodeStore {
+    #[tracing::instrument(name = "Replacing recovery codes in PostgreSQL", skip_all)]
+    async fn replace_codes(
+        &mut self,
+        email: &Email,
+        codes: &[RecoveryCode],
+    ) -> Result<(), RecoveryCodeStoreError> {
+        let hashes: Vec<String> = codes.iter().map(RecoveryCode::hash).collect();
+
+        let mut transaction = self
+            .pool
+            .begin()
+            .await
+            .map_err(|e| RecoveryCodeStoreError::UnexpectedError(e.into()))?;
+
+        sqlx::query!(
+            "DELETE FROM recovery_codes WHERE email = $1",
+            email.as_ref().expose_secret()
+        )
+        .execute(&mut *transaction)
+        .await
+        .map_err(|e| RecoveryCodeStoreError::UnexpectedError(e.into()))?;
+
+        sqlx::query!(
+            "INSERT INTO recovery_codes (email, code_hash) SELECT $1, UNNEST($2::TEXT[])",
+            email.as_ref().expose_secret(),
+            &hashes
+        )
+        .execute(&mut *transaction)
+        .await
+        .map_err(|e| RecoveryCodeStoreError::UnexpectedError(e.into()))?;
+
+        transaction
+            .commit()
+            .await
+            .map_err(|e| RecoveryCodeStoreError::UnexpectedError(e.into()))
+    }
+
+    #[tracing::instrument(name = "Using recovery code in PostgreSQL", skip_all)]
+    async fn use_code(
+        &mut self,
+        email: &Email,
+        code: &RecoveryCode,
+    ) -> Result<usize, RecoveryCodeStoreError> {
+        // Marking the code used only if it is still unused makes concurrent attempts with
+        // the same code fail for all but one
+        let result = sqlx::query!(
+            r#"
+                UPDATE recovery_codes SET used_at = NOW()
+                WHERE email = $1 AND code_hash = $2 AND used_at IS NULL
+            "#,
+            email.as_ref().expose_secret(),
+            code.hash()
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| RecoveryCodeStoreError::UnexpectedError(e.into()))?;
+
+        if result.rows_affected() == 0 {
+            return Err(RecoveryCodeStoreError::InvalidCode);
+        }
+
+        self.count_unused(email).await
+    }
+
+    #[tracing::instrument(name = "Counting unused recovery codes in PostgreSQL", skip_all)]
+    async fn count_unused(&self, email: &Email) -> Result<usize, RecoveryCodeStoreError> {
+        let remaining = sqlx::query_scalar!(
+            r#"SELECT COUNT(*) AS "count!" FROM recovery_codes WHERE email = $1 AND used_at IS NULL"#,
+            email.as_ref().expose_secret()
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| RecoveryCodeStoreError::UnexpectedError(e.into()))?;
+
+        Ok(remaining as usize)
+    }
+}
@@ -0,0 +1,111 @@
+use crate::domain::{
+    data_store::{TotpStore, TotpStoreError},
+    totp::{TotpEnrollment, TotpSecret},
+    Email,
+};
+use color_eyre::eyre::eyre;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+
+pub struct PostgresTotpStore {
+    pool: PgPool,
+}
+
+impl PostgresTotpStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait::async_trait]
+impl TotpStore for PostgresTotpStore {
+    #[tracing::instrument(name = "Storing pending TOTP secret in PostgreSQL", skip_all)]
+    async fn set_pending_secret(
+        &mut self,
+        email: &Email,
+        secret: TotpSecret,
+    ) -> Result<(), TotpStoreError> {
+        // Confirmed secrets are left alone, so the upsert only touches pending ones
+        let result = sqlx::query!(
+            r#"
+                INSERT INTO totp_secrets (email, secret) VALUES ($1, $2)
+                ON CONFLICT (email) DO UPDATE
+                SET secret = EXCLUDED.secret, last_used_step = NULL, created_at = NOW()
+                WHERE totp_secrets.confirmed = FALSE
+            "#,
+            email.as_ref().expose_secret(),
+            secret.as_ref().expose_secret()
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| TotpStoreError::UnexpectedError(e.into()))?;
+
+        if result.rows_affected() == 0 {
+            return Err(TotpStoreError::AlreadyEnrolled);
+        }
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Retrieving TOTP secret from PostgreSQL", skip_all)]
+    async fn get_enrollment(&self, email: &Email) -> Result<TotpEnrollment, TotpStoreError> {
+        let row = sqlx::query!(
+            "SELECT secret, confirmed FROM totp_secrets WHERE email = $1",
+            email.as_ref().expose_secret()
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| TotpStoreError::UnexpectedError(e.into()))?
+        .ok_or(TotpStoreError::NotEnrolled)?;
+
+        Ok(TotpEnrollment {
+            secret: TotpSecret::parse(Secret::new(row.secret))
+                .map_err(TotpStoreError::UnexpectedError)?,
+            confirmed: row.confirmed,
+        })
+    }
+
+    #[tracing::instrument(name = "Confirming TOTP secret in PostgreSQL", skip_all)]
+    async fn confirm(&mut self, email: &Email) -> Result<(), TotpStoreError> {
+        let result = sqlx::query!(
+            "UPDATE totp_secrets SET confirmed = TRUE WHERE email = $1",
+            email.as_ref().expose_secret()
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| TotpStoreError::UnexpectedError(e.into()))?;
+
+        if result.rows_affected() == 0 {
+            return Err(TotpStoreError::NotEnrolled);
+        }
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Recording used TOTP step in PostgreSQL", skip_all)]
+    async fn use_step(&mut self, email: &Email, step: u64) -> Result<(), TotpStoreError> {
+        let step = i64::try_from(step)
+            .map_err(|_| TotpStoreError::UnexpectedError(eyre!("TOTP step is too large")))?;
+
+        // Checking and recording the step in one statement keeps two concurrent requests
+        // from both accepting the same code
+        let result = sqlx::query!(
+            r#"
+                UPDATE totp_secrets SET last_used_step = $2
+                WHERE email = $1 AND (last_used_step IS NULL OR last_used_step < $2)
+            "#,
+            email.as_ref().expose_secret(),
+            step
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| TotpStoreError::UnexpectedError(e.into()))?;
+
+        if result.rows_affected() == 0 {
+            self.get_enrollment(email).await?;
+            return Err(TotpStoreError::CodeReused);
+        }
+
+        Ok(())
+    }
+}
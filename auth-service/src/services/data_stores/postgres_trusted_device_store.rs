@@ -0,0 +1,123 @@
+use crate::domain::{
+    data_store::{TrustedDeviceStore, TrustedDeviceStoreError},
+    trusted_device::{TrustedDevice, TrustedDeviceId},
+    Email,
+};
+use secrecy::ExposeSecret;
+use sqlx::PgPool;
+
+pub struct PostgresTrustedDeviceStore {
+    pool: PgPool,
+}
+
+impl PostgresTrustedDeviceStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait::async_trait]
+impl TrustedDeviceStore for PostgresTrustedDeviceStore {
+    #[tracing::instrument(name = "Adding trusted device to PostgreSQL", skip_all)]
+    async fn add_device(
+        &mut self,
+        email: &Email,
+        device: TrustedDevice,
+    ) -> Result<(), TrustedDeviceStoreError> {
+        sqlx::query!(
+            r#"
+                INSERT INTO trusted_devices (id, email, label, created_at, last_used_at, expires_at)
+                VALUES ($1, $2, $3, $4, $5, $6)
+            "#,
+            device.id.to_string(),
+            email.as_ref().expose_secret(),
+            device.label,
+            device.created_at,
+            device.last_used_at,
+            device.expires_at
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| TrustedDeviceStoreError::UnexpectedError(e.into()))?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Recording trusted device use in PostgreSQL", skip_all)]
+    async fn touch_device(
+        &mut self,
+        email: &Email,
+        id: &TrustedDeviceId,
+    ) -> Result<(), TrustedDeviceStoreError> {
+        let result = sqlx::query!(
+            r#"
+                UPDATE trusted_devices SET last_used_at = NOW()
+                WHERE id = $1 AND email = $2 AND expires_at > NOW()
+            "#,
+            id.to_string(),
+            email.as_ref().expose_secret()
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| TrustedDeviceStoreError::UnexpectedError(e.into()))?;
+
+        if result.rows_affected() == 0 {
+            return Err(TrustedDeviceStoreError::DeviceNotFound);
+        }
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Retrieving trusted devices from PostgreSQL", skip_all)]
+    async fn get_devices(
+        &self,
+        email: &Email,
+    ) -> Result<Vec<TrustedDevice>, TrustedDeviceStoreError> {
+        let rows = sqlx::query!(
+            r#"
+                SELECT id, label, created_at, last_used_at, expires_at FROM trusted_devices
+                WHERE email = $1 AND expires_at > NOW()
+                ORDER BY created_at DESC
+            "#,
+            email.as_ref().expose_secret()
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| TrustedDeviceStoreError::UnexpectedError(e.into()))?;
+
+        rows.into_iter()
+            .map(|row| {
+                Ok(TrustedDevice {
+                    id: TrustedDeviceId::parse(&row.id)
+                        .map_err(TrustedDeviceStoreError::UnexpectedError)?,
+                    label: row.label,
+                    created_at: row.created_at,
+                    last_used_at: row.last_used_at,
+                    expires_at: row.expires_at,
+                })
+            })
+            .collect()
+    }
+
+    #[tracing::instrument(name = "Revoking trusted device in PostgreSQL", skip_all)]
+    async fn revoke_device(
+        &mut self,
+        email: &Email,
+        id: &TrustedDeviceId,
+    ) -> Result<(), TrustedDeviceStoreError> {
+        let result = sqlx::query!(
+            "DELETE FROM trusted_devices WHERE id = $1 AND email = $2",
+            id.to_string(),
+            email.as_ref().expose_secret()
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| TrustedDeviceStoreError::UnexpectedError(e.into()))?;
+
+        if result.rows_affected() == 0 {
+            return Err(TrustedDeviceStoreError::DeviceNotFound);
+        }
+
+        Ok(())
+    }
+}
@@ -1,24 +1,78 @@
-use crate::domain::user::User;
+use crate::domain::locale::Locale;
+use crate::domain::two_factor::TwoFactorMethod;
+use crate::domain::user::{AccountStatus, StatusChange, User};
 use crate::domain::{
     data_store::{UserStore, UserStoreError},
-    Email, Password,
+    Email, Password, PasswordHasher,
 };
-use argon2::{
-    password_hash::SaltString, Algorithm, Argon2, Params, PasswordHash, PasswordHasher,
-    PasswordVerifier, Version,
-};
-use color_eyre::eyre::{eyre, Context, Result};
-use rand_core::OsRng;
+use color_eyre::eyre::Result;
 use secrecy::{ExposeSecret, Secret};
+use std::sync::Arc;
+use tracing::Instrument;
 
 use sqlx::PgPool;
 pub struct PostgresUserStore {
     pool: PgPool,
+    hasher: Arc<dyn PasswordHasher + Send + Sync>,
+    password_history_size: usize,
 }
 
 impl PostgresUserStore {
-    pub fn new(pool: PgPool) -> Self {
-        Self { pool }
+    pub fn new(
+        pool: PgPool,
+        hasher: Arc<dyn PasswordHasher + Send + Sync>,
+        password_history_size: usize,
+    ) -> Self {
+        Self {
+            pool,
+            hasher,
+            password_history_size,
+        }
+    }
+
+    // Inserts a user whose password was hashed by another system. The hash is stored as-is
+    // and upgraded the first time the user logs in successfully.
+    #[tracing::instrument(name = "Importing user into PostgreSQL", skip_all)]
+    pub async fn import_user(
+        &self,
+        email: &Email,
+        password_hash: Secret<String>,
+        require_2fa: bool,
+    ) -> Result<(), UserStoreError> {
+        let result = sqlx::query!(
+            "INSERT INTO users (email, password_hash, requires_2fa) VALUES ($1, $2, $3) ON CONFLICT (email) DO NOTHING",
+            email.as_ref().expose_secret(),
+            password_hash.expose_secret(),
+            require_2fa
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| UserStoreError::UnexpectedError(e.into()))?;
+
+        if result.rows_affected() == 0 {
+            return Err(UserStoreError::UserAlreadyExists);
+        }
+
+        Ok(())
+    }
+
+    // Upgrades an outdated hash without making the caller wait for a second hashing round.
+    // The update only applies if the stored hash is still the one we verified against, so a
+    // password change racing with the rehash is never overwritten.
+    fn rehash_in_background(&self, email: Email, password: Password, old_hash: Secret<String>) {
+        let pool = self.pool.clone();
+        let hasher = self.hasher.clone();
+
+        tokio::spawn(
+            async move {
+                if let Err(e) =
+                    rehash_password(&pool, hasher.as_ref(), &email, password, old_hash).await
+                {
+                    tracing::error!("Failed to rehash password: {:?}", e);
+                }
+            }
+            .in_current_span(),
+        );
     }
 }
 
@@ -38,15 +92,18 @@ impl UserStore for PostgresUserStore {
             return Err(UserStoreError::UserAlreadyExists);
         }
 
-        let password_hash = compute_password_hash(user.password.as_ref().to_owned())
+        let password_hash = self
+            .hasher
+            .hash(user.password.as_ref().to_owned())
             .await
             .map_err(|e| UserStoreError::UnexpectedError(e.into()))?;
 
         sqlx::query!(
-            "INSERT INTO users (email, password_hash, requires_2fa) VALUES ($1, $2, $3)",
+            "INSERT INTO users (email, password_hash, requires_2fa, locale) VALUES ($1, $2, $3, $4)",
             user.email.as_ref().expose_secret(),
             &password_hash.expose_secret(),
-            user.require_2fa
+            user.require_2fa,
+            user.locale.as_ref().map(Locale::as_str)
         )
         .execute(&self.pool)
         .await
@@ -59,7 +116,8 @@ impl UserStore for PostgresUserStore {
     async fn get_user(&self, email: &Email) -> Result<User, UserStoreError> {
         let row = sqlx::query!(
             r#"
-                SELECT email, password_hash, requires_2fa
+                SELECT email, password_hash, requires_2fa, preferred_2fa_method, locale,
+                    password_changed_at, must_change_password, status
                 FROM users
                 WHERE email = $1
             "#,
@@ -75,6 +133,21 @@ impl UserStore for PostgresUserStore {
             password: Password::parse(Secret::new(row.password_hash))
                 .map_err(UserStoreError::UnexpectedError)?,
             require_2fa: row.requires_2fa,
+            preferred_2fa_method: row
+                .preferred_2fa_method
+                .as_deref()
+                .map(TwoFactorMethod::parse)
+                .transpose()
+                .map_err(UserStoreError::UnexpectedError)?,
+            locale: row
+                .locale
+                .as_deref()
+                .map(Locale::parse)
+                .transpose()
+                .map_err(UserStoreError::UnexpectedError)?,
+            password_changed_at: row.password_changed_at,
+            must_change_password: row.must_change_password,
+            status: AccountStatus::parse(&row.status).map_err(UserStoreError::UnexpectedError)?,
         };
 
         Ok(user)
@@ -99,59 +172,317 @@ impl UserStore for PostgresUserStore {
         .map_err(|e| UserStoreError::UnexpectedError(e.into()))?
         .ok_or(UserStoreError::InvalidCredentials)?;
 
-        verify_password_hash(
-            Secret::new(user.password_hash),
-            password.as_ref().to_owned(),
+        let password_hash = Secret::new(user.password_hash);
+
+        self.hasher
+            .verify(password_hash.clone(), password.as_ref().to_owned())
+            .await
+            .map_err(|_| UserStoreError::InvalidCredentials)?;
+
+        if self.hasher.needs_rehash(&password_hash) {
+            self.rehash_in_background(email.clone(), password.clone(), password_hash);
+        }
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Updating password in PostgreSQL", skip_all)]
+    async fn update_password(
+        &mut self,
+        email: &Email,
+        new_password: Password,
+    ) -> Result<(), UserStoreError> {
+        let mut transaction = self
+            .pool
+            .begin()
+            .await
+            .map_err(|e| UserStoreError::UnexpectedError(e.into()))?;
+
+        // Lock the row so two concurrent changes cannot both pass the history check
+        let current_hash = sqlx::query!(
+            "SELECT password_hash FROM users WHERE email = $1 FOR UPDATE",
+            email.as_ref().expose_secret()
+        )
+        .fetch_optional(&mut *transaction)
+        .await
+        .map_err(|e| UserStoreError::UnexpectedError(e.into()))?
+        .ok_or(UserStoreError::UserNotFound)?
+        .password_hash;
+
+        if self.password_history_size > 0 {
+            let previous_hashes = sqlx::query!(
+                r#"
+                    SELECT password_hash
+                    FROM password_history
+                    WHERE email = $1
+                    ORDER BY id DESC
+                    LIMIT $2
+                "#,
+                email.as_ref().expose_secret(),
+                (self.password_history_size - 1) as i64
+            )
+            .fetch_all(&mut *transaction)
+            .await
+            .map_err(|e| UserStoreError::UnexpectedError(e.into()))?;
+
+            let recent_hashes = std::iter::once(current_hash.clone())
+                .chain(previous_hashes.into_iter().map(|row| row.password_hash));
+
+            for hash in recent_hashes {
+                if self
+                    .hasher
+                    .verify(Secret::new(hash), new_password.as_ref().to_owned())
+                    .await
+                    .is_ok()
+                {
+                    return Err(UserStoreError::PasswordReused);
+                }
+            }
+        }
+
+        let new_hash = self
+            .hasher
+            .hash(new_password.as_ref().to_owned())
+            .await
+            .map_err(UserStoreError::UnexpectedError)?;
+
+        sqlx::query!(
+            "INSERT INTO password_history (email, password_hash) VALUES ($1, $2)",
+            email.as_ref().expose_secret(),
+            current_hash
+        )
+        .execute(&mut *transaction)
+        .await
+        .map_err(|e| UserStoreError::UnexpectedError(e.into()))?;
+
+        sqlx::query!(
+            r#"
+                UPDATE users
+                SET password_hash = $1, password_changed_at = NOW(), must_change_password = FALSE
+                WHERE email = $2
+            "#,
+            new_hash.expose_secret(),
+            email.as_ref().expose_secret()
+        )
+        .execute(&mut *transaction)
+        .await
+        .map_err(|e| UserStoreError::UnexpectedError(e.into()))?;
+
+        sqlx::query!(
+            r#"
+                DELETE FROM password_history
+                WHERE email = $1 AND id NOT IN (
+                    SELECT id FROM password_history
+                    WHERE email = $1
+                    ORDER BY id DESC
+                    LIMIT $2
+                )
+            "#,
+            email.as_ref().expose_secret(),
+            self.password_history_size as i64
+        )
+        .execute(&mut *transaction)
+        .await
+        .map_err(|e| UserStoreError::UnexpectedError(e.into()))?;
+
+        transaction
+            .commit()
+            .await
+            .map_err(|e| UserStoreError::UnexpectedError(e.into()))?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Flagging password change in PostgreSQL", skip_all)]
+    async fn set_must_change_password(
+        &mut self,
+        email: &Email,
+        must_change_password: bool,
+    ) -> Result<(), UserStoreError> {
+        let result = sqlx::query!(
+            "UPDATE users SET must_change_password = $1 WHERE email = $2",
+            must_change_password,
+            email.as_ref().expose_secret()
         )
+        .execute(&self.pool)
         .await
-        .map_err(|_| UserStoreError::InvalidCredentials)?;
+        .map_err(|e| UserStoreError::UnexpectedError(e.into()))?;
+
+        if result.rows_affected() == 0 {
+            return Err(UserStoreError::UserNotFound);
+        }
 
         Ok(())
     }
+
+    #[tracing::instrument(name = "Setting 2FA requirement in PostgreSQL", skip_all)]
+    async fn set_require_2fa(
+        &mut self,
+        email: &Email,
+        require_2fa: bool,
+    ) -> Result<(), UserStoreError> {
+        let result = sqlx::query!(
+            "UPDATE users SET requires_2fa = $1 WHERE email = $2",
+            require_2fa,
+            email.as_ref().expose_secret()
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| UserStoreError::UnexpectedError(e.into()))?;
+
+        if result.rows_affected() == 0 {
+            return Err(UserStoreError::UserNotFound);
+        }
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Setting preferred 2FA method in PostgreSQL", skip_all)]
+    async fn set_preferred_2fa_method(
+        &mut self,
+        email: &Email,
+        method: Option<TwoFactorMethod>,
+    ) -> Result<(), UserStoreError> {
+        let result = sqlx::query!(
+            "UPDATE users SET preferred_2fa_method = $1 WHERE email = $2",
+            method.as_ref().map(TwoFactorMethod::as_str),
+            email.as_ref().expose_secret()
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| UserStoreError::UnexpectedError(e.into()))?;
+
+        if result.rows_affected() == 0 {
+            return Err(UserStoreError::UserNotFound);
+        }
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Setting locale in PostgreSQL", skip_all)]
+    async fn set_locale(
+        &mut self,
+        email: &Email,
+        locale: Option<Locale>,
+    ) -> Result<(), UserStoreError> {
+        let result = sqlx::query!(
+            "UPDATE users SET locale = $1 WHERE email = $2",
+            locale.as_ref().map(Locale::as_str),
+            email.as_ref().expose_secret()
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| UserStoreError::UnexpectedError(e.into()))?;
+
+        if result.rows_affected() == 0 {
+            return Err(UserStoreError::UserNotFound);
+        }
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Setting account status in PostgreSQL", skip_all)]
+    async fn set_status(
+        &mut self,
+        email: &Email,
+        status: AccountStatus,
+        reason: String,
+    ) -> Result<(), UserStoreError> {
+        let mut transaction = self
+            .pool
+            .begin()
+            .await
+            .map_err(|e| UserStoreError::UnexpectedError(e.into()))?;
+
+        let result = sqlx::query!(
+            "UPDATE users SET status = $1 WHERE email = $2",
+            status.as_str(),
+            email.as_ref().expose_secret()
+        )
+        .execute(&mut *transaction)
+        .await
+        .map_err(|e| UserStoreError::UnexpectedError(e.into()))?;
+
+        if result.rows_affected() == 0 {
+            return Err(UserStoreError::UserNotFound);
+        }
+
+        sqlx::query!(
+            "INSERT INTO account_status_changes (email, status, reason) VALUES ($1, $2, $3)",
+            email.as_ref().expose_secret(),
+            status.as_str(),
+            reason
+        )
+        .execute(&mut *transaction)
+        .await
+        .map_err(|e| UserStoreError::UnexpectedError(e.into()))?;
+
+        transaction
+            .commit()
+            .await
+            .map_err(|e| UserStoreError::UnexpectedError(e.into()))?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Retrieving account status history from PostgreSQL", skip_all)]
+    async fn get_status_history(&self, email: &Email) -> Result<Vec<StatusChange>, UserStoreError> {
+        let user = sqlx::query!(
+            "SELECT email FROM users WHERE email = $1",
+            email.as_ref().expose_secret()
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| UserStoreError::UnexpectedError(e.into()))?;
+
+        if user.is_none() {
+            return Err(UserStoreError::UserNotFound);
+        }
+
+        let rows = sqlx::query!(
+            r#"
+                SELECT status, reason, changed_at
+                FROM account_status_changes
+                WHERE email = $1
+                ORDER BY id DESC
+            "#,
+            email.as_ref().expose_secret()
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| UserStoreError::UnexpectedError(e.into()))?;
+
+        rows.into_iter()
+            .map(|row| {
+                Ok(StatusChange {
+                    status: AccountStatus::parse(&row.status)
+                        .map_err(UserStoreError::UnexpectedError)?,
+                    reason: row.reason,
+                    changed_at: row.changed_at,
+                })
+            })
+            .collect()
+    }
 }
 
-#[tracing::instrument(name = "Verify password hash", skip_all)]
-async fn verify_password_hash(
-    expected_password_hash: Secret<String>,
-    password_candidate: Secret<String>,
+#[tracing::instrument(name = "Rehashing password", skip_all)]
+async fn rehash_password(
+    pool: &PgPool,
+    hasher: &(dyn PasswordHasher + Send + Sync),
+    email: &Email,
+    password: Password,
+    old_hash: Secret<String>,
 ) -> Result<()> {
-    let current_span: tracing::Span = tracing::Span::current();
-    let result = tokio::task::spawn_blocking(move || {
-        current_span.in_scope(|| {
-            let expected_password_hash: PasswordHash<'_> =
-                PasswordHash::new(expected_password_hash.expose_secret())?;
-
-            Argon2::default()
-                .verify_password(
-                    password_candidate.expose_secret().as_bytes(),
-                    &expected_password_hash,
-                )
-                .wrap_err("failed to verify password")
-        })
-    })
-    .await;
+    let new_hash = hasher.hash(password.as_ref().to_owned()).await?;
 
-    result?
-}
+    sqlx::query!(
+        "UPDATE users SET password_hash = $1 WHERE email = $2 AND password_hash = $3",
+        new_hash.expose_secret(),
+        email.as_ref().expose_secret(),
+        old_hash.expose_secret()
+    )
+    .execute(pool)
+    .await?;
 
-#[tracing::instrument(name = "Computing password hash", skip_all)]
-async fn compute_password_hash(password: Secret<String>) -> Result<Secret<String>> {
-    let current_span: tracing::Span = tracing::Span::current();
-
-    let result = tokio::task::spawn_blocking(move || {
-        current_span.in_scope(|| {
-            let salt: SaltString = SaltString::generate(&mut OsRng);
-            let password_hash = Argon2::new(
-                Algorithm::Argon2id,
-                Version::V0x13,
-                Params::new(15000, 2, 1, None)?,
-            )
-            .hash_password(password.expose_secret().as_bytes(), &salt)?
-            .to_string();
-            Ok(Secret::new(password_hash))
-        })
-    })
-    .await;
-
-    result?
+    Ok(())
 }
@@ -0,0 +1,131 @@
+use crate::domain::{
+    data_store::{WebAuthnCredentialStore, WebAuthnCredentialStoreError},
+    webauthn::{CredentialId, WebAuthnCredential},
+    Email,
+};
+use color_eyre::eyre::Report;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+
+pub struct PostgresWebAuthnCredentialStore {
+    pool: PgPool,
+}
+
+impl PostgresWebAuthnCredentialStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait::async_trait]
+impl WebAuthnCredentialStore for PostgresWebAuthnCredentialStore {
+    #[tracing::instrument(name = "Adding WebAuthn credential to PostgreSQL", skip_all)]
+    async fn add_credential(
+        &mut self,
+        email: &Email,
+        credential: WebAuthnCredential,
+    ) -> Result<(), WebAuthnCredentialStoreError> {
+        let result = sqlx::query!(
+            r#"
+                INSERT INTO webauthn_credentials (credential_id, email, public_key, sign_count)
+                VALUES ($1, $2, $3, $4)
+                ON CONFLICT (credential_id) DO NOTHING
+            "#,
+            credential.id.as_ref(),
+            email.as_ref().expose_secret(),
+            credential.public_key,
+            i64::from(credential.sign_count)
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| WebAuthnCredentialStoreError::UnexpectedError(e.into()))?;
+
+        if result.rows_affected() == 0 {
+            return Err(WebAuthnCredentialStoreError::CredentialAlreadyExists);
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Retrieving WebAuthn credentials from PostgreSQL", skip_all)]
+    async fn get_credentials(
+        &self,
+        email: &Email,
+    ) -> Result<Vec<WebAuthnCredential>, WebAuthnCredentialStoreError> {
+        let rows = sqlx::query!(
+            r#"
+                SELECT credential_id, public_key, sign_count FROM webauthn_credentials
+                WHERE email = $1 ORDER BY created_at
+            "#,
+            email.as_ref().expose_secret()
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| WebAuthnCredentialStoreError::UnexpectedError(e.into()))?;
+
+        rows.into_iter()
+            .map(|row| to_credential(row.credential_id, row.public_key, row.sign_count))
+            .collect()
+    }
+
+    #[tracing::instrument(name = "Retrieving WebAuthn credential from PostgreSQL", skip_all)]
+    async fn get_credential(
+        &self,
+        id: &CredentialId,
+    ) -> Result<(Email, WebAuthnCredential), WebAuthnCredentialStoreError> {
+        let row = sqlx::query!(
+            r#"
+                SELECT credential_id, email, public_key, sign_count FROM webauthn_credentials
+                WHERE credential_id = $1
+            "#,
+            id.as_ref()
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| WebAuthnCredentialStoreError::UnexpectedError(e.into()))?
+        .ok_or(WebAuthnCredentialStoreError::CredentialNotFound)?;
+
+        let email = Email::parse(Secret::new(row.email))
+            .map_err(WebAuthnCredentialStoreError::UnexpectedError)?;
+        let credential = to_credential(row.credential_id, row.public_key, row.sign_count)?;
+        Ok((email, credential))
+    }
+
+    #[tracing::instrument(name = "Updating WebAuthn sign count in PostgreSQL", skip_all)]
+    async fn update_sign_count(
+        &mut self,
+        id: &CredentialId,
+        previous: u32,
+        new: u32,
+    ) -> Result<(), WebAuthnCredentialStoreError> {
+        let result = sqlx::query!(
+            r#"
+                UPDATE webauthn_credentials SET sign_count = $3, last_used_at = NOW()
+                WHERE credential_id = $1 AND sign_count = $2
+            "#,
+            id.as_ref(),
+            i64::from(previous),
+            i64::from(new)
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| WebAuthnCredentialStoreError::UnexpectedError(e.into()))?;
+
+        if result.rows_affected() == 0 {
+            return Err(WebAuthnCredentialStoreError::SignCountChanged);
+        }
+        Ok(())
+    }
+}
+
+fn to_credential(
+    id: String,
+    public_key: Vec<u8>,
+    sign_count: i64,
+) -> Result<WebAuthnCredential, WebAuthnCredentialStoreError> {
+    Ok(WebAuthnCredential {
+        id: CredentialId::parse(id).map_err(WebAuthnCredentialStoreError::UnexpectedError)?,
+        public_key,
+        sign_count: u32::try_from(sign_count)
+            .map_err(|e| WebAuthnCredentialStoreError::UnexpectedError(Report::new(e)))?,
+    })
+}
@@ -0,0 +1,138 @@
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{eyre, Context, Result};
+use redis::{Commands, Connection};
+use secrecy::ExposeSecret;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+use crate::domain::{
+    data_store::{LoginAttemptStore, LoginAttemptStoreError},
+    Email,
+};
+use crate::services::hashmap_login_attempt_store::HashMapLoginAttemptStore;
+use crate::utils::constants::LOGIN_FAILURE_WINDOW_SECONDS;
+
+// Keeps failure counters in Redis so they are shared between instances. While Redis is
+// unreachable the store falls back to counting in memory, so an outage does not switch off
+// brute-force protection.
+pub struct RedisLoginAttemptStore {
+    conn: Arc<RwLock<Connection>>,
+    fallback: HashMapLoginAttemptStore,
+}
+
+impl RedisLoginAttemptStore {
+    pub fn new(conn: Arc<RwLock<Connection>>) -> Self {
+        Self {
+            conn,
+            fallback: HashMapLoginAttemptStore::new(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl LoginAttemptStore for RedisLoginAttemptStore {
+    #[tracing::instrument(name = "Record login failure - redis", skip_all)]
+    async fn record_failure(&mut self, email: &Email) -> Result<u32, LoginAttemptStoreError> {
+        let key = get_failures_key(email);
+        let result: Result<u32> = {
+            let mut conn = self.conn.write().await;
+            redis::pipe()
+                .atomic()
+                .incr(&key, 1)
+                .expire(&key, LOGIN_FAILURE_WINDOW_SECONDS)
+                .ignore()
+                .query::<(u32,)>(&mut *conn)
+                .map(|(count,)| count)
+                .wrap_err("failed to record login failure in Redis")
+        };
+
+        match result {
+            Ok(count) => Ok(count),
+            Err(e) => {
+                tracing::warn!("Falling back to in-memory login attempts: {:?}", e);
+                self.fallback.record_failure(email).await
+            }
+        }
+    }
+
+    #[tracing::instrument(name = "Lock account - redis", skip_all)]
+    async fn lock_account(
+        &mut self,
+        email: &Email,
+        until: DateTime<Utc>,
+    ) -> Result<(), LoginAttemptStoreError> {
+        let ttl_seconds = (until - Utc::now()).num_seconds().max(1) as u64;
+        let result = self
+            .conn
+            .write()
+            .await
+            .set_ex::<_, _, ()>(get_lockout_key(email), until.timestamp(), ttl_seconds)
+            .wrap_err("failed to lock account in Redis");
+
+        if let Err(e) = result {
+            tracing::warn!("Falling back to in-memory login attempts: {:?}", e);
+            return self.fallback.lock_account(email, until).await;
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Fetch account lockout - redis", skip_all)]
+    async fn locked_until(
+        &self,
+        email: &Email,
+    ) -> Result<Option<DateTime<Utc>>, LoginAttemptStoreError> {
+        // Lockouts recorded in memory during a Redis outage still apply afterwards
+        let fallback_until = self.fallback.locked_until(email).await?;
+
+        let result: Result<Option<DateTime<Utc>>> = self
+            .conn
+            .write()
+            .await
+            .get::<_, Option<i64>>(get_lockout_key(email))
+            .wrap_err("failed to fetch account lockout from Redis")
+            .and_then(|timestamp| {
+                timestamp
+                    .map(|timestamp| {
+                        DateTime::from_timestamp(timestamp, 0)
+                            .ok_or_else(|| eyre!("invalid lockout timestamp {}", timestamp))
+                    })
+                    .transpose()
+            });
+
+        match result {
+            Ok(until) => Ok(until
+                .filter(|until| *until > Utc::now())
+                .max(fallback_until)),
+            Err(e) => {
+                tracing::warn!("Falling back to in-memory login attempts: {:?}", e);
+                Ok(fallback_until)
+            }
+        }
+    }
+
+    #[tracing::instrument(name = "Reset login attempts - redis", skip_all)]
+    async fn reset(&mut self, email: &Email) -> Result<(), LoginAttemptStoreError> {
+        self.fallback.reset(email).await?;
+        self.conn
+            .write()
+            .await
+            .del::<_, ()>(&[get_failures_key(email), get_lockout_key(email)])
+            .wrap_err("failed to reset login attempts in Redis")
+            .map_err(LoginAttemptStoreError::UnexpectedError)
+    }
+}
+
+const LOGIN_FAILURES_PREFIX: &str = "login_failures:";
+const LOGIN_LOCKOUT_PREFIX: &str = "login_lockout:";
+
+fn get_failures_key(email: &Email) -> String {
+    format!(
+        "{}{}",
+        LOGIN_FAILURES_PREFIX,
+        email.as_ref().expose_secret()
+    )
+}
+
+fn get_lockout_key(email: &Email) -> String {
+    format!("{}{}", LOGIN_LOCKOUT_PREFIX, email.as_ref().expose_secret())
+}
@@ -1,25 +1,47 @@
+use chrono::{Duration, Utc};
 use color_eyre::eyre::Context;
 use redis::{Commands, Connection};
 use secrecy::{ExposeSecret, Secret};
-use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
 use crate::domain::{
+    authentication::AuthMethod,
     data_store::{LoginAttemptId, TwoFACode, TwoFaCodeStore, TwoFaCodeStoreError},
     Email,
 };
 
 pub struct RedisTwoFACodeStore {
     conn: Arc<RwLock<Connection>>,
+    key_prefix: &'static str,
+    code_ttl: Duration,
 }
 
 impl RedisTwoFACodeStore {
-    pub fn new(conn: Arc<RwLock<Connection>>) -> Self {
-        Self { conn }
+    pub fn new(conn: Arc<RwLock<Connection>>, code_ttl: Duration) -> Self {
+        Self::with_key_prefix(conn, TWO_FA_CODE_PREFIX, code_ttl)
+    }
+
+    // Keeps codes issued for another purpose apart from 2FA codes, so one can never be
+    // redeemed in place of the other
+    pub fn with_key_prefix(
+        conn: Arc<RwLock<Connection>>,
+        key_prefix: &'static str,
+        code_ttl: Duration,
+    ) -> Self {
+        Self {
+            conn,
+            key_prefix,
+            code_ttl,
+        }
     }
 }
 
+// Each code is a hash keyed by its login attempt id, holding the owner's email, the code, the
+// number of wrong guesses, when it was sent and how the login was started, so the counters can
+// be updated atomically and expire with the code. A set per user indexes the attempts that user
+// has outstanding.
 #[async_trait::async_trait]
 impl TwoFaCodeStore for RedisTwoFACodeStore {
     #[tracing::instrument(name = "Add two fa code - redis", skip_all)]
@@ -28,44 +50,60 @@ impl TwoFaCodeStore for RedisTwoFACodeStore {
         email: &Email,
         login_attempt_id: LoginAttemptId,
         code: TwoFACode,
+        max_pending: u32,
     ) -> Result<(), TwoFaCodeStoreError> {
-        // TODO:
-        // 1. Create a new key using the get_key helper function.
-        let new_key = get_key(&email);
-        // 2. Create a TwoFATuple instance
-        let tuple = TwoFATuple(
-            login_attempt_id.as_ref().expose_secret().to_owned(),
-            code.as_ref().expose_secret().to_owned(),
-        );
-        // 3. Use serde_json::to_string to serialize the TwoFATuple instance into a JSON string.
-        let json_string = serde_json::to_string(&tuple)
-            .wrap_err("Failed to serialize 2FA tuple")
-            .map_err(TwoFaCodeStoreError::UnexpectedError)?;
-        // Return TwoFaCodeStoreError::UnexpectedError if serialization fails.
-        // 4. Call the set_ex command on the Redis connection to set a new key/value pair with an expiration time (TTL).
+        let index_key = get_index_key(self.key_prefix, email);
+        let key = get_key(self.key_prefix, &login_attempt_id);
+        let now = Utc::now();
+        let fields = [
+            (EMAIL_FIELD, email.as_ref().expose_secret().to_owned()),
+            (CODE_FIELD, code.as_ref().expose_secret().to_owned()),
+            (FAILED_ATTEMPTS_FIELD, "0".to_owned()),
+            (SENT_AT_FIELD, now.timestamp().to_string()),
+            (RESENDS_FIELD, "0".to_owned()),
+            (CREATED_AT_FIELD, now.timestamp_millis().to_string()),
+        ];
+
         let mut conn = self.conn.write().await;
+        let (pending, stale) = pending_attempts(&mut conn, self.key_prefix, email)?;
 
-        conn.set_ex::<_, _, ()>(new_key, json_string, TEN_MINUTES_IN_SECONDS)
-            .wrap_err("failed to set 2FA code in Redis") // New!
-            .map_err(TwoFaCodeStoreError::UnexpectedError)?; // Updated!
+        // Expired attempts are dropped from the index, then the oldest live ones until there is
+        // room for the new attempt
+        let excess = (pending.len() + 1).saturating_sub(max_pending.max(1) as usize);
+        let evicted: Vec<String> = pending.into_iter().take(excess).map(|(_, id)| id).collect();
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for id in stale.iter().chain(&evicted) {
+            pipe.del(format!("{}{}", self.key_prefix, id))
+                .ignore()
+                .srem(&index_key, id)
+                .ignore();
+        }
+        pipe.hset_multiple(&key, &fields)
+            .ignore()
+            .expire(&key, self.code_ttl.num_seconds())
+            .ignore()
+            .sadd(&index_key, login_attempt_id.as_ref().expose_secret())
+            .ignore()
+            .expire(&index_key, self.code_ttl.num_seconds())
+            .ignore()
+            .query::<()>(&mut *conn)
+            .wrap_err("failed to set 2FA code in Redis")
+            .map_err(TwoFaCodeStoreError::UnexpectedError)?;
 
-        // The value should be the serialized 2FA tuple.
-        // The expiration time should be set to TEN_MINUTES_IN_SECONDS.
-        // Return TwoFaCodeStoreError::UnexpectedError if casting fails or the call to set_ex fails.
         Ok(())
     }
 
     #[tracing::instrument(name = "remove two fa code - redis", skip_all)]
-    async fn remove_code(&mut self, email: &Email) -> Result<(), TwoFaCodeStoreError> {
-        // TODO:
-        // 1. Create a new key using the get_key helper function.
-        // 2. Call the del command on the Redis connection to delete the 2FA code entry.
-        // Return TwoFaCodeStoreError::UnexpectedError if the operation fails.
-        let key = get_key(email);
+    async fn remove_code(
+        &mut self,
+        email: &Email,
+        login_attempt_id: &LoginAttemptId,
+    ) -> Result<(), TwoFaCodeStoreError> {
         let mut conn = self.conn.write().await;
-        conn.del::<_, ()>(key)
-            .wrap_err("failed to delete 2FA code from Redis")
-            .map_err(TwoFaCodeStoreError::UnexpectedError)?;
+        if get_fields(&mut conn, self.key_prefix, email, login_attempt_id)?.is_some() {
+            delete_attempt(&mut conn, self.key_prefix, email, login_attempt_id)?;
+        }
         Ok(())
     }
 
@@ -73,39 +111,296 @@ impl TwoFaCodeStore for RedisTwoFACodeStore {
     async fn get_code(
         &self,
         email: &Email,
-    ) -> Result<(LoginAttemptId, TwoFACode), TwoFaCodeStoreError> {
-        let key = get_key(email);
+        login_attempt_id: &LoginAttemptId,
+    ) -> Result<TwoFACode, TwoFaCodeStoreError> {
+        let mut conn = self.conn.write().await;
+        let code = get_fields(&mut conn, self.key_prefix, email, login_attempt_id)?
+            .and_then(|mut fields| fields.remove(CODE_FIELD))
+            .ok_or(TwoFaCodeStoreError::LoginAttempIdNotFound)?;
+
+        Ok(TwoFACode::from_stored(Secret::new(code)))
+    }
+
+    #[tracing::instrument(name = "set first factor - redis", skip_all)]
+    async fn set_first_factor(
+        &mut self,
+        email: &Email,
+        login_attempt_id: &LoginAttemptId,
+        method: AuthMethod,
+    ) -> Result<(), TwoFaCodeStoreError> {
+        let method = serde_json::to_string(&method)
+            .wrap_err("failed to serialize first factor")
+            .map_err(TwoFaCodeStoreError::UnexpectedError)?;
+        let mut conn = self.conn.write().await;
+        if get_fields(&mut conn, self.key_prefix, email, login_attempt_id)?.is_none() {
+            return Err(TwoFaCodeStoreError::LoginAttempIdNotFound);
+        }
+
+        let key = get_key(self.key_prefix, login_attempt_id);
+        let (exists,): (bool,) = redis::pipe()
+            .atomic()
+            .exists(&key)
+            .hset(&key, FIRST_FACTOR_FIELD, method)
+            .ignore()
+            .query(&mut *conn)
+            .wrap_err("failed to set first factor in Redis")
+            .map_err(TwoFaCodeStoreError::UnexpectedError)?;
+
+        // As in record_failed_attempt, a code that expired in the meantime leaves a stray hash
+        if !exists {
+            delete_attempt(&mut conn, self.key_prefix, email, login_attempt_id)?;
+            return Err(TwoFaCodeStoreError::LoginAttempIdNotFound);
+        }
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "fetch first factor - redis", skip_all)]
+    async fn get_first_factor(
+        &self,
+        email: &Email,
+        login_attempt_id: &LoginAttemptId,
+    ) -> Result<Option<AuthMethod>, TwoFaCodeStoreError> {
+        let mut conn = self.conn.write().await;
+        let fields = get_fields(&mut conn, self.key_prefix, email, login_attempt_id)?
+            .ok_or(TwoFaCodeStoreError::LoginAttempIdNotFound)?;
+
+        fields
+            .get(FIRST_FACTOR_FIELD)
+            .map(|method| serde_json::from_str(method))
+            .transpose()
+            .wrap_err("failed to deserialize first factor")
+            .map_err(TwoFaCodeStoreError::UnexpectedError)
+    }
+
+    #[tracing::instrument(name = "fetch login attempts - redis", skip_all)]
+    async fn get_login_attempts(
+        &self,
+        email: &Email,
+    ) -> Result<Vec<LoginAttemptId>, TwoFaCodeStoreError> {
+        let mut conn = self.conn.write().await;
+        let (pending, _) = pending_attempts(&mut conn, self.key_prefix, email)?;
+
+        pending
+            .into_iter()
+            .map(|(_, id)| {
+                LoginAttemptId::parse(Secret::new(id)).map_err(TwoFaCodeStoreError::UnexpectedError)
+            })
+            .collect()
+    }
+
+    #[tracing::instrument(name = "record failed two fa attempt - redis", skip_all)]
+    async fn record_failed_attempt(
+        &mut self,
+        email: &Email,
+        login_attempt_id: &LoginAttemptId,
+        max_attempts: u32,
+    ) -> Result<u32, TwoFaCodeStoreError> {
+        let key = get_key(self.key_prefix, login_attempt_id);
+        let mut conn = self.conn.write().await;
+        if get_fields(&mut conn, self.key_prefix, email, login_attempt_id)?.is_none() {
+            return Err(TwoFaCodeStoreError::LoginAttempIdNotFound);
+        }
+
+        let (exists, failed_attempts): (bool, u32) = redis::pipe()
+            .atomic()
+            .exists(&key)
+            .hincr(&key, FAILED_ATTEMPTS_FIELD, 1)
+            .query(&mut *conn)
+            .wrap_err("failed to record failed 2FA attempt in Redis")
+            .map_err(TwoFaCodeStoreError::UnexpectedError)?;
+
+        // HINCRBY creates the hash if the code has expired since it was read. That stray counter
+        // has no expiry of its own, so it is removed straight away.
+        if !exists || failed_attempts >= max_attempts {
+            delete_attempt(&mut conn, self.key_prefix, email, login_attempt_id)?;
+        }
+
+        if !exists {
+            return Err(TwoFaCodeStoreError::LoginAttempIdNotFound);
+        }
+        if failed_attempts >= max_attempts {
+            return Err(TwoFaCodeStoreError::TooManyAttempts);
+        }
+
+        Ok(max_attempts - failed_attempts)
+    }
+
+    #[tracing::instrument(name = "rotate two fa code - redis", skip_all)]
+    async fn rotate_code(
+        &mut self,
+        email: &Email,
+        login_attempt_id: &LoginAttemptId,
+        code: TwoFACode,
+        cooldown: Duration,
+        max_resends: u32,
+    ) -> Result<(), TwoFaCodeStoreError> {
+        let key = get_key(self.key_prefix, login_attempt_id);
+        // Holding the connection lock between the read and the write keeps two concurrent
+        // resends from both getting past the checks
+        let mut conn = self.conn.write().await;
 
-        match self.conn.write().await.get::<_, String>(&key) {
-            Ok(value) => {
-                let data: TwoFATuple = serde_json::from_str(&value)
-                    .wrap_err("failed to deserialize 2FA tuple") // New!
-                    .map_err(TwoFaCodeStoreError::UnexpectedError)?; // Updated!
+        let fields = get_fields(&mut conn, self.key_prefix, email, login_attempt_id)?
+            .ok_or(TwoFaCodeStoreError::LoginAttempIdNotFound)?;
 
-                let login_attempt_id = LoginAttemptId::parse(data.0.into())
-                    .map_err(TwoFaCodeStoreError::UnexpectedError)?; // Updated!
+        let number_field = |field: &str| {
+            fields
+                .get(field)
+                .and_then(|value| value.parse::<i64>().ok())
+                .unwrap_or_default()
+        };
+        let now = Utc::now().timestamp();
+        if now < number_field(SENT_AT_FIELD) + cooldown.num_seconds() {
+            return Err(TwoFaCodeStoreError::ResendTooSoon);
+        }
+        if number_field(RESENDS_FIELD) >= i64::from(max_resends) {
+            return Err(TwoFaCodeStoreError::TooManyResends);
+        }
 
-                let email_code = TwoFACode::parse(data.1.into())
-                    .map_err(TwoFaCodeStoreError::UnexpectedError)?; // Updated!
+        let fields = [
+            (CODE_FIELD, code.as_ref().expose_secret().to_owned()),
+            (SENT_AT_FIELD, now.to_string()),
+        ];
+        redis::pipe()
+            .atomic()
+            .hset_multiple(&key, &fields)
+            .ignore()
+            .hincr(&key, RESENDS_FIELD, 1)
+            .ignore()
+            .expire(&key, self.code_ttl.num_seconds())
+            .ignore()
+            // The index has to outlive the code, or the attempt would drop out of it while the
+            // new code still works
+            .expire(
+                get_index_key(self.key_prefix, email),
+                self.code_ttl.num_seconds(),
+            )
+            .ignore()
+            .query::<()>(&mut *conn)
+            .wrap_err("failed to rotate 2FA code in Redis")
+            .map_err(TwoFaCodeStoreError::UnexpectedError)?;
 
-                Ok((login_attempt_id, email_code))
-            }
-            Err(_) => Err(TwoFaCodeStoreError::LoginAttempIdNotFound),
+        Ok(())
+    }
+
+    // The key only exists while the cooldown runs, so SET NX claims the send atomically
+    #[tracing::instrument(name = "claim two fa send - redis", skip_all)]
+    async fn claim_send(
+        &mut self,
+        email: &Email,
+        cooldown: Duration,
+    ) -> Result<(), TwoFaCodeStoreError> {
+        if cooldown <= Duration::zero() {
+            return Ok(());
+        }
+
+        let mut conn = self.conn.write().await;
+        let claimed: Option<String> = redis::cmd("SET")
+            .arg(get_cooldown_key(self.key_prefix, email))
+            .arg(Utc::now().timestamp())
+            .arg("NX")
+            .arg("PX")
+            .arg(cooldown.num_milliseconds())
+            .query(&mut *conn)
+            .wrap_err("failed to claim 2FA send in Redis")
+            .map_err(TwoFaCodeStoreError::UnexpectedError)?;
+
+        match claimed {
+            Some(_) => Ok(()),
+            None => Err(TwoFaCodeStoreError::ResendTooSoon),
         }
     }
 }
 
-#[derive(Deserialize, Serialize)]
-struct TwoFATuple(pub String, pub String);
+// Reads an attempt's hash, treating one that has expired or belongs to someone else as missing
+fn get_fields(
+    conn: &mut Connection,
+    prefix: &str,
+    email: &Email,
+    login_attempt_id: &LoginAttemptId,
+) -> Result<Option<HashMap<String, String>>, TwoFaCodeStoreError> {
+    let fields: HashMap<String, String> = conn
+        .hgetall(get_key(prefix, login_attempt_id))
+        .wrap_err("failed to fetch 2FA code from Redis")
+        .map_err(TwoFaCodeStoreError::UnexpectedError)?;
+
+    let owned = fields.get(EMAIL_FIELD).map(String::as_str) == Some(email.as_ref().expose_secret());
+    Ok(owned.then_some(fields))
+}
+
+fn delete_attempt(
+    conn: &mut Connection,
+    prefix: &str,
+    email: &Email,
+    login_attempt_id: &LoginAttemptId,
+) -> Result<(), TwoFaCodeStoreError> {
+    redis::pipe()
+        .atomic()
+        .del(get_key(prefix, login_attempt_id))
+        .ignore()
+        .srem(
+            get_index_key(prefix, email),
+            login_attempt_id.as_ref().expose_secret(),
+        )
+        .ignore()
+        .query::<()>(conn)
+        .wrap_err("failed to delete 2FA code from Redis")
+        .map_err(TwoFaCodeStoreError::UnexpectedError)
+}
+
+// Splits the user's indexed attempts into the live ones, oldest first with their creation time,
+// and the ids whose codes have already expired
+#[allow(clippy::type_complexity)]
+fn pending_attempts(
+    conn: &mut Connection,
+    prefix: &str,
+    email: &Email,
+) -> Result<(Vec<(i64, String)>, Vec<String>), TwoFaCodeStoreError> {
+    let ids: Vec<String> = conn
+        .smembers(get_index_key(prefix, email))
+        .wrap_err("failed to fetch login attempts from Redis")
+        .map_err(TwoFaCodeStoreError::UnexpectedError)?;
+
+    let mut pending = Vec::new();
+    let mut stale = Vec::new();
+    for id in ids {
+        let created_at: Option<i64> = conn
+            .hget(format!("{}{}", prefix, id), CREATED_AT_FIELD)
+            .wrap_err("failed to fetch 2FA code from Redis")
+            .map_err(TwoFaCodeStoreError::UnexpectedError)?;
+        match created_at {
+            Some(created_at) => pending.push((created_at, id)),
+            None => stale.push(id),
+        }
+    }
+    pending.sort();
+
+    Ok((pending, stale))
+}
 
-const TEN_MINUTES_IN_SECONDS: u64 = 600;
 const TWO_FA_CODE_PREFIX: &str = "two_fa_code:";
+pub const LOGIN_CODE_PREFIX: &str = "login_code:";
+pub const PHONE_CODE_PREFIX: &str = "phone_code:";
+const EMAIL_FIELD: &str = "email";
+const CODE_FIELD: &str = "code";
+const FAILED_ATTEMPTS_FIELD: &str = "failed_attempts";
+// Unix timestamp of the last time the code was sent
+const SENT_AT_FIELD: &str = "sent_at";
+const RESENDS_FIELD: &str = "resends";
+// Unix timestamp in milliseconds, used to find the user's oldest attempts
+const CREATED_AT_FIELD: &str = "created_at";
+// The AuthMethod that started the login, as JSON
+const FIRST_FACTOR_FIELD: &str = "first_factor";
 
 #[tracing::instrument(name = "get key redis", skip_all)]
-fn get_key(email: &Email) -> String {
-    format!(
-        "{}{}",
-        TWO_FA_CODE_PREFIX,
-        email.as_ref().expose_secret().to_owned()
-    )
+fn get_key(prefix: &str, login_attempt_id: &LoginAttemptId) -> String {
+    format!("{}{}", prefix, login_attempt_id.as_ref().expose_secret())
+}
+
+fn get_index_key(prefix: &str, email: &Email) -> String {
+    format!("{}user:{}", prefix, email.as_ref().expose_secret())
+}
+
+fn get_cooldown_key(prefix: &str, email: &Email) -> String {
+    format!("{}cooldown:{}", prefix, email.as_ref().expose_secret())
 }
@@ -0,0 +1,131 @@
+use chrono::Duration;
+use color_eyre::eyre::Context;
+use redis::{Commands, Connection};
+use secrecy::{ExposeSecret, Secret};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+use crate::domain::{
+    data_store::{LoginAttemptId, WebAuthnCeremonyStore, WebAuthnCeremonyStoreError},
+    webauthn::{CeremonyPurpose, WebAuthnCeremony, WebAuthnChallenge},
+    Email,
+};
+
+pub struct RedisWebAuthnCeremonyStore {
+    conn: Arc<RwLock<Connection>>,
+}
+
+impl RedisWebAuthnCeremonyStore {
+    pub fn new(conn: Arc<RwLock<Connection>>) -> Self {
+        Self { conn }
+    }
+}
+
+#[async_trait::async_trait]
+impl WebAuthnCeremonyStore for RedisWebAuthnCeremonyStore {
+    #[tracing::instrument(name = "Add WebAuthn ceremony - redis", skip_all)]
+    async fn add_ceremony(
+        &mut self,
+        ceremony: WebAuthnCeremony,
+        ttl: Duration,
+    ) -> Result<(), WebAuthnCeremonyStoreError> {
+        let key = get_key(&ceremony.challenge);
+        let json = serde_json::to_string(&StoredPurpose::from(ceremony.purpose))
+            .wrap_err("Failed to serialize WebAuthn ceremony")
+            .map_err(WebAuthnCeremonyStoreError::UnexpectedError)?;
+
+        self.conn
+            .write()
+            .await
+            .set_ex::<_, _, ()>(key, json, ttl.num_seconds().max(1) as u64)
+            .wrap_err("Failed to set WebAuthn ceremony in Redis")
+            .map_err(WebAuthnCeremonyStoreError::UnexpectedError)
+    }
+
+    #[tracing::instrument(name = "Take WebAuthn ceremony - redis", skip_all)]
+    async fn take_ceremony(
+        &mut self,
+        challenge: &WebAuthnChallenge,
+    ) -> Result<WebAuthnCeremony, WebAuthnCeremonyStoreError> {
+        // GETDEL makes sure only one request gets the ceremony
+        let json = self
+            .conn
+            .write()
+            .await
+            .get_del::<_, Option<String>>(get_key(challenge))
+            .wrap_err("Failed to take WebAuthn ceremony from Redis")
+            .map_err(WebAuthnCeremonyStoreError::UnexpectedError)?
+            .ok_or(WebAuthnCeremonyStoreError::CeremonyNotFound)?;
+
+        let stored: StoredPurpose = serde_json::from_str(&json)
+            .wrap_err("Failed to deserialize WebAuthn ceremony")
+            .map_err(WebAuthnCeremonyStoreError::UnexpectedError)?;
+        let purpose = stored
+            .try_into()
+            .map_err(WebAuthnCeremonyStoreError::UnexpectedError)?;
+
+        Ok(WebAuthnCeremony {
+            challenge: challenge.clone(),
+            purpose,
+        })
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(tag = "purpose")]
+enum StoredPurpose {
+    Registration {
+        email: String,
+    },
+    Authentication {
+        email: Option<String>,
+        login_attempt_id: Option<String>,
+    },
+}
+
+impl From<CeremonyPurpose> for StoredPurpose {
+    fn from(purpose: CeremonyPurpose) -> Self {
+        match purpose {
+            CeremonyPurpose::Registration { email } => Self::Registration {
+                email: email.as_ref().expose_secret().to_owned(),
+            },
+            CeremonyPurpose::Authentication {
+                email,
+                login_attempt_id,
+            } => Self::Authentication {
+                email: email.map(|email| email.as_ref().expose_secret().to_owned()),
+                login_attempt_id: login_attempt_id.map(|id| id.as_ref().expose_secret().to_owned()),
+            },
+        }
+    }
+}
+
+impl TryFrom<StoredPurpose> for CeremonyPurpose {
+    type Error = color_eyre::eyre::Report;
+
+    fn try_from(stored: StoredPurpose) -> Result<Self, Self::Error> {
+        Ok(match stored {
+            StoredPurpose::Registration { email } => Self::Registration {
+                email: Email::parse(Secret::new(email))?,
+            },
+            StoredPurpose::Authentication {
+                email,
+                login_attempt_id,
+            } => Self::Authentication {
+                email: email
+                    .map(|email| Email::parse(Secret::new(email)))
+                    .transpose()?,
+                login_attempt_id: login_attempt_id
+                    .map(|id| LoginAttemptId::parse(Secret::new(id)))
+                    .transpose()?,
+            },
+        })
+    }
+}
+
+const WEBAUTHN_CEREMONY_PREFIX: &str = "webauthn_ceremony:";
+
+fn get_key(challenge: &WebAuthnChallenge) -> String {
+    format!("{}{}", WEBAUTHN_CEREMONY_PREFIX, challenge.as_ref())
+}
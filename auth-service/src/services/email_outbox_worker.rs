@@ -0,0 +1,215 @@
+use chrono::{Duration, Utc};
+use std::sync::Arc;
+use tokio::sync::Notify;
+
+use crate::app_state::app_state::{EmailClientType, EmailOutboxStoreType};
+use crate::app_state::settings::EmailOutboxSettings;
+use crate::domain::{data_store::EmailOutboxStoreError, email_outbox::OutboxEmail};
+use crate::utils::constants::{EMAIL_OUTBOX_BATCH_SIZE, EMAIL_OUTBOX_LEASE_SECONDS};
+
+// Delivers the emails the route handlers leave in the outbox, retrying failures with backoff
+pub struct EmailOutboxWorker {
+    store: EmailOutboxStoreType,
+    email_client: EmailClientType,
+    settings: EmailOutboxSettings,
+    // Notified whenever an email is queued
+    wakeup: Arc<Notify>,
+}
+
+impl EmailOutboxWorker {
+    pub fn new(
+        store: EmailOutboxStoreType,
+        email_client: EmailClientType,
+        settings: EmailOutboxSettings,
+        wakeup: Arc<Notify>,
+    ) -> Self {
+        Self {
+            store,
+            email_client,
+            settings,
+            wakeup,
+        }
+    }
+
+    pub async fn run(self) {
+        let poll_interval = self
+            .settings
+            .poll_interval
+            .to_std()
+            .unwrap_or(std::time::Duration::from_secs(1));
+        loop {
+            if let Err(e) = self.deliver_due().await {
+                tracing::error!("Failed to deliver queued emails: {:?}", e);
+            }
+            if let Err(e) = self.store.write().await.clear_expired_dead_letters().await {
+                tracing::error!("Failed to clear expired dead letters: {:?}", e);
+            }
+            tokio::select! {
+                _ = self.wakeup.notified() => {}
+                _ = tokio::time::sleep(poll_interval) => {}
+            }
+        }
+    }
+
+    // Works through every email that is due, returning how many were sent
+    #[tracing::instrument(name = "Deliver Queued Emails", skip_all)]
+    pub async fn deliver_due(&self) -> Result<usize, EmailOutboxStoreError> {
+        let mut sent = 0;
+        loop {
+            let emails = self
+                .store
+                .write()
+                .await
+                .claim_due(
+                    EMAIL_OUTBOX_BATCH_SIZE,
+                    Duration::seconds(EMAIL_OUTBOX_LEASE_SECONDS),
+                )
+                .await?;
+            if emails.is_empty() {
+                return Ok(sent);
+            }
+
+            for email in emails {
+                if self.deliver(email).await? {
+                    sent += 1;
+                }
+            }
+        }
+    }
+
+    async fn deliver(&self, email: OutboxEmail) -> Result<bool, EmailOutboxStoreError> {
+        let error = match self
+            .email_client
+            .send_email(&email.recipient, &email.message)
+            .await
+        {
+            Ok(()) => {
+                self.store.write().await.mark_sent(&email.id).await?;
+                return Ok(true);
+            }
+            Err(e) => format!("{:#}", e),
+        };
+
+        let retry_at = self
+            .settings
+            .retry_delay(email.attempts + 1)
+            .map(|delay| Utc::now() + delay);
+        match retry_at {
+            Some(retry_at) => tracing::warn!(
+                "Failed to send email {}, retrying at {}: {}",
+                email.id,
+                retry_at,
+                error
+            ),
+            None => tracing::error!(
+                "Giving up on email {} after {} attempts: {}",
+                email.id,
+                email.attempts + 1,
+                error
+            ),
+        }
+
+        self.store
+            .write()
+            .await
+            .mark_failed(&email.id, &error, retry_at)
+            .await?;
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::email_outbox::IdempotencyKey;
+    use crate::domain::{Email, EmailClient, EmailMessage};
+    use crate::services::hashmap_email_outbox_store::HashMapEmailOutboxStore;
+    use color_eyre::eyre::{eyre, Result};
+    use secrecy::Secret;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::sync::RwLock;
+
+    // Fails the first `failures` sends and accepts the rest
+    struct FlakyEmailClient {
+        failures: usize,
+        calls: AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl EmailClient for FlakyEmailClient {
+        async fn send_email(&self, _: &Email, _: &EmailMessage) -> Result<()> {
+            if self.calls.fetch_add(1, Ordering::SeqCst) < self.failures {
+                return Err(eyre!("Provider unavailable"));
+            }
+            Ok(())
+        }
+    }
+
+    fn worker(failures: usize) -> (EmailOutboxWorker, Arc<FlakyEmailClient>) {
+        let client = Arc::new(FlakyEmailClient {
+            failures,
+            calls: AtomicUsize::new(0),
+        });
+        let settings = EmailOutboxSettings {
+            max_attempts: 2,
+            base_delay: Duration::zero(),
+            ..EmailOutboxSettings::default()
+        };
+        let worker = EmailOutboxWorker::new(
+            Arc::new(RwLock::new(HashMapEmailOutboxStore::new())),
+            client.clone(),
+            settings,
+            Arc::new(Notify::new()),
+        );
+        (worker, client)
+    }
+
+    async fn enqueue(worker: &EmailOutboxWorker) {
+        let recipient = Email::parse(Secret::new("test@example.com".to_owned())).unwrap();
+        let message = EmailMessage {
+            subject: "Your 2FA code".to_owned(),
+            html_body: "<p>123456</p>".to_owned(),
+            text_body: "123456".to_owned(),
+        };
+        let key = IdempotencyKey::derive(&recipient, &["login"]);
+        worker
+            .store
+            .write()
+            .await
+            .enqueue(OutboxEmail::new(recipient, message, key, None))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn failed_sends_are_retried() {
+        let (worker, client) = worker(1);
+        enqueue(&worker).await;
+
+        // Without a delay the retry is due straight away
+        assert_eq!(worker.deliver_due().await.unwrap(), 1);
+        assert_eq!(client.calls.load(Ordering::SeqCst), 2);
+        assert!(worker
+            .store
+            .read()
+            .await
+            .get_dead_letters()
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn emails_become_dead_letters_after_max_attempts() {
+        let (worker, client) = worker(usize::MAX);
+        enqueue(&worker).await;
+
+        assert_eq!(worker.deliver_due().await.unwrap(), 0);
+        assert_eq!(client.calls.load(Ordering::SeqCst), 2);
+
+        let dead = worker.store.read().await.get_dead_letters().await.unwrap();
+        assert_eq!(dead.len(), 1);
+        assert_eq!(dead[0].attempts, 2);
+        assert_eq!(dead[0].last_error.as_deref(), Some("Provider unavailable"));
+    }
+}
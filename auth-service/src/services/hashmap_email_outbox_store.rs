@@ -0,0 +1,305 @@
+use chrono::{DateTime, Duration, Utc};
+use std::collections::HashMap;
+
+use crate::domain::{
+    data_store::{EmailOutboxStore, EmailOutboxStoreError},
+    email_outbox::{
+        IdempotencyKey, OutboxEmail, OutboxEmailId, OutboxStatus, EXPIRED_BEFORE_SENDING,
+    },
+};
+
+#[derive(Default)]
+pub struct HashMapEmailOutboxStore {
+    emails: HashMap<OutboxEmailId, OutboxEmail>,
+    keys: HashMap<IdempotencyKey, OutboxEmailId>,
+}
+
+impl HashMapEmailOutboxStore {
+    pub fn new() -> Self {
+        Self {
+            emails: HashMap::new(),
+            keys: HashMap::new(),
+        }
+    }
+
+    fn get_mut(&mut self, id: &OutboxEmailId) -> Result<&mut OutboxEmail, EmailOutboxStoreError> {
+        self.emails
+            .get_mut(id)
+            .ok_or(EmailOutboxStoreError::EmailNotFound)
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailOutboxStore for HashMapEmailOutboxStore {
+    async fn enqueue(&mut self, email: OutboxEmail) -> Result<(), EmailOutboxStoreError> {
+        if self.keys.contains_key(&email.idempotency_key) {
+            return Ok(());
+        }
+        self.keys
+            .insert(email.idempotency_key.clone(), email.id.clone());
+        self.emails.insert(email.id.clone(), email);
+        Ok(())
+    }
+
+    async fn claim_due(
+        &mut self,
+        limit: usize,
+        lease: Duration,
+    ) -> Result<Vec<OutboxEmail>, EmailOutboxStoreError> {
+        let now = Utc::now();
+        for email in self.emails.values_mut() {
+            if email.status == OutboxStatus::Pending && is_expired(email, now) {
+                email.status = OutboxStatus::Dead;
+                email.last_error = Some(EXPIRED_BEFORE_SENDING.to_owned());
+                email.message.html_body.clear();
+                email.message.text_body.clear();
+            }
+        }
+
+        let mut due: Vec<&mut OutboxEmail> = self
+            .emails
+            .values_mut()
+            .filter(|email| email.status == OutboxStatus::Pending && email.next_attempt_at <= now)
+            .collect();
+        due.sort_by_key(|email| email.next_attempt_at);
+
+        Ok(due
+            .into_iter()
+            .take(limit)
+            .map(|email| {
+                email.next_attempt_at = now + lease;
+                email.clone()
+            })
+            .collect())
+    }
+
+    async fn mark_sent(&mut self, id: &OutboxEmailId) -> Result<(), EmailOutboxStoreError> {
+        let email = self.get_mut(id)?;
+        email.status = OutboxStatus::Sent;
+        email.message.html_body.clear();
+        email.message.text_body.clear();
+        Ok(())
+    }
+
+    async fn mark_failed(
+        &mut self,
+        id: &OutboxEmailId,
+        error: &str,
+        retry_at: Option<DateTime<Utc>>,
+    ) -> Result<(), EmailOutboxStoreError> {
+        let email = self.get_mut(id)?;
+        email.attempts += 1;
+        email.last_error = Some(error.to_owned());
+        match retry_at {
+            Some(retry_at) => email.next_attempt_at = retry_at,
+            None => email.status = OutboxStatus::Dead,
+        }
+        Ok(())
+    }
+
+    async fn get_dead_letters(&self) -> Result<Vec<OutboxEmail>, EmailOutboxStoreError> {
+        let mut dead: Vec<OutboxEmail> = self
+            .emails
+            .values()
+            .filter(|email| email.status == OutboxStatus::Dead)
+            .cloned()
+            .collect();
+        dead.sort_by_key(|email| std::cmp::Reverse(email.created_at));
+        Ok(dead)
+    }
+
+    async fn replay(&mut self, id: &OutboxEmailId) -> Result<(), EmailOutboxStoreError> {
+        let now = Utc::now();
+        let email = self
+            .emails
+            .get_mut(id)
+            .filter(|email| email.status == OutboxStatus::Dead)
+            .ok_or(EmailOutboxStoreError::EmailNotFound)?;
+        if is_expired(email, now) {
+            return Err(EmailOutboxStoreError::EmailExpired);
+        }
+        email.status = OutboxStatus::Pending;
+        email.attempts = 0;
+        email.next_attempt_at = now;
+        Ok(())
+    }
+
+    async fn clear_expired_dead_letters(&mut self) -> Result<usize, EmailOutboxStoreError> {
+        let now = Utc::now();
+        let mut cleared = 0;
+        for email in self.emails.values_mut() {
+            if email.status == OutboxStatus::Dead
+                && is_expired(email, now)
+                && !email.message.text_body.is_empty()
+            {
+                email.message.html_body.clear();
+                email.message.text_body.clear();
+                cleared += 1;
+            }
+        }
+        Ok(cleared)
+    }
+}
+
+fn is_expired(email: &OutboxEmail, now: DateTime<Utc>) -> bool {
+    email.expires_at.is_some_and(|expires_at| expires_at <= now)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::{Email, EmailMessage};
+    use secrecy::Secret;
+
+    fn outbox_email(event: &str) -> OutboxEmail {
+        expiring_outbox_email(event, None)
+    }
+
+    fn expiring_outbox_email(event: &str, expires_at: Option<DateTime<Utc>>) -> OutboxEmail {
+        let recipient = Email::parse(Secret::new("test@example.com".to_owned())).unwrap();
+        let key = IdempotencyKey::derive(&recipient, &[event]);
+        let message = EmailMessage {
+            subject: "Your 2FA code".to_owned(),
+            html_body: "<p>123456</p>".to_owned(),
+            text_body: "123456".to_owned(),
+        };
+        OutboxEmail::new(recipient, message, key, expires_at)
+    }
+
+    #[tokio::test]
+    async fn emails_with_the_same_key_are_queued_once() {
+        let mut store = HashMapEmailOutboxStore::new();
+        store.enqueue(outbox_email("login")).await.unwrap();
+        store.enqueue(outbox_email("login")).await.unwrap();
+        store.enqueue(outbox_email("another login")).await.unwrap();
+
+        let claimed = store.claim_due(10, Duration::seconds(60)).await.unwrap();
+        assert_eq!(claimed.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn claimed_emails_are_leased() {
+        let mut store = HashMapEmailOutboxStore::new();
+        store.enqueue(outbox_email("login")).await.unwrap();
+
+        assert_eq!(
+            store.claim_due(10, Duration::zero()).await.unwrap().len(),
+            1
+        );
+        // A lease that ran out lets the email be claimed again
+        assert_eq!(
+            store
+                .claim_due(10, Duration::seconds(60))
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+        assert!(store
+            .claim_due(10, Duration::seconds(60))
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn sent_emails_are_not_claimed_again() {
+        let mut store = HashMapEmailOutboxStore::new();
+        let email = outbox_email("login");
+        let id = email.id.clone();
+        store.enqueue(email).await.unwrap();
+
+        store.claim_due(10, Duration::zero()).await.unwrap();
+        store.mark_sent(&id).await.unwrap();
+
+        assert!(store
+            .claim_due(10, Duration::zero())
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(store.emails[&id].message.text_body.is_empty());
+    }
+
+    #[tokio::test]
+    async fn failed_emails_are_retried_then_dead_lettered_and_replayed() {
+        let mut store = HashMapEmailOutboxStore::new();
+        let email = outbox_email("login");
+        let id = email.id.clone();
+        store.enqueue(email).await.unwrap();
+
+        store
+            .mark_failed(&id, "timed out", Some(Utc::now()))
+            .await
+            .unwrap();
+        let retried = store.claim_due(10, Duration::zero()).await.unwrap();
+        assert_eq!(retried[0].attempts, 1);
+        assert!(store.replay(&id).await.is_err());
+
+        store.mark_failed(&id, "timed out", None).await.unwrap();
+        assert!(store
+            .claim_due(10, Duration::zero())
+            .await
+            .unwrap()
+            .is_empty());
+        let dead = store.get_dead_letters().await.unwrap();
+        assert_eq!(dead.len(), 1);
+        assert_eq!(dead[0].attempts, 2);
+        assert_eq!(dead[0].last_error.as_deref(), Some("timed out"));
+
+        store.replay(&id).await.unwrap();
+        assert!(store.get_dead_letters().await.unwrap().is_empty());
+        let replayed = store.claim_due(10, Duration::zero()).await.unwrap();
+        assert_eq!(replayed[0].attempts, 0);
+    }
+
+    #[tokio::test]
+    async fn expired_emails_are_dead_lettered_instead_of_sent() {
+        let mut store = HashMapEmailOutboxStore::new();
+        let expired = expiring_outbox_email("login", Some(Utc::now()));
+        let expired_id = expired.id.clone();
+        store.enqueue(expired).await.unwrap();
+        store
+            .enqueue(expiring_outbox_email(
+                "another login",
+                Some(Utc::now() + Duration::minutes(10)),
+            ))
+            .await
+            .unwrap();
+
+        let claimed = store.claim_due(10, Duration::zero()).await.unwrap();
+        assert_eq!(claimed.len(), 1);
+        assert_ne!(claimed[0].id, expired_id);
+
+        let dead = store.get_dead_letters().await.unwrap();
+        assert_eq!(dead.len(), 1);
+        assert_eq!(dead[0].id, expired_id);
+        assert_eq!(dead[0].last_error.as_deref(), Some(EXPIRED_BEFORE_SENDING));
+        assert!(dead[0].message.text_body.is_empty());
+        assert!(dead[0].message.html_body.is_empty());
+    }
+
+    #[tokio::test]
+    async fn expired_dead_letters_are_emptied_and_not_replayed() {
+        let mut store = HashMapEmailOutboxStore::new();
+        let expired = expiring_outbox_email("login", Some(Utc::now()));
+        let expired_id = expired.id.clone();
+        let live = expiring_outbox_email("another login", Some(Utc::now() + Duration::minutes(10)));
+        let live_id = live.id.clone();
+        for email in [expired, live] {
+            let id = email.id.clone();
+            store.enqueue(email).await.unwrap();
+            store.mark_failed(&id, "timed out", None).await.unwrap();
+        }
+
+        assert_eq!(store.clear_expired_dead_letters().await, Ok(1));
+        assert_eq!(store.clear_expired_dead_letters().await, Ok(0));
+        assert!(store.emails[&expired_id].message.text_body.is_empty());
+        assert!(!store.emails[&live_id].message.text_body.is_empty());
+
+        assert_eq!(
+            store.replay(&expired_id).await,
+            Err(EmailOutboxStoreError::EmailExpired)
+        );
+        assert_eq!(store.replay(&live_id).await, Ok(()));
+    }
+}
@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+
+use crate::domain::{
+    data_store::{HotpStore, HotpStoreError},
+    hotp::HotpToken,
+    Email,
+};
+
+#[derive(Debug, Clone)]
+struct StoredHotp {
+    token: HotpToken,
+    owner: Option<Email>,
+}
+
+#[derive(Default)]
+pub struct HashMapHotpStore {
+    // Keyed by serial
+    tokens: HashMap<String, StoredHotp>,
+}
+
+impl HashMapHotpStore {
+    pub fn new() -> Self {
+        Self {
+            tokens: HashMap::new(),
+        }
+    }
+
+    fn owned_by(&mut self, email: &Email) -> Option<&mut StoredHotp> {
+        self.tokens
+            .values_mut()
+            .find(|stored| stored.owner.as_ref() == Some(email))
+    }
+}
+
+#[async_trait::async_trait]
+impl HotpStore for HashMapHotpStore {
+    async fn add_token(&mut self, token: HotpToken) -> Result<(), HotpStoreError> {
+        if self.tokens.contains_key(&token.serial) {
+            return Err(HotpStoreError::TokenAlreadyExists);
+        }
+        self.tokens
+            .insert(token.serial.clone(), StoredHotp { token, owner: None });
+        Ok(())
+    }
+
+    async fn get_unassigned_token(&self, serial: &str) -> Result<HotpToken, HotpStoreError> {
+        self.tokens
+            .get(serial)
+            .filter(|stored| stored.owner.is_none())
+            .map(|stored| stored.token.clone())
+            .ok_or(HotpStoreError::TokenNotFound)
+    }
+
+    async fn assign_token(
+        &mut self,
+        serial: &str,
+        email: &Email,
+        counter: u64,
+    ) -> Result<(), HotpStoreError> {
+        if self.owned_by(email).is_some() {
+            return Err(HotpStoreError::AlreadyEnrolled);
+        }
+        let stored = self
+            .tokens
+            .get_mut(serial)
+            .filter(|stored| stored.owner.is_none())
+            .ok_or(HotpStoreError::TokenNotFound)?;
+        stored.owner = Some(email.clone());
+        stored.token.counter = counter;
+        Ok(())
+    }
+
+    async fn get_token(&self, email: &Email) -> Result<HotpToken, HotpStoreError> {
+        self.tokens
+            .values()
+            .find(|stored| stored.owner.as_ref() == Some(email))
+            .map(|stored| stored.token.clone())
+            .ok_or(HotpStoreError::NotEnrolled)
+    }
+
+    async fn advance_counter(&mut self, email: &Email, counter: u64) -> Result<(), HotpStoreError> {
+        let stored = self.owned_by(email).ok_or(HotpStoreError::NotEnrolled)?;
+        if counter <= stored.token.counter {
+            return Err(HotpStoreError::CodeReused);
+        }
+        stored.token.counter = counter;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::hotp::HotpSecret;
+    use secrecy::Secret;
+
+    fn email(name: &str) -> Email {
+        Email::parse(Secret::new(format!("{}@example.com", name))).unwrap()
+    }
+
+    fn token(serial: &str) -> HotpToken {
+        let secret = HotpSecret::parse(Secret::new(
+            "3132333435363738393031323334353637383930".to_owned(),
+        ))
+        .unwrap();
+        HotpToken::new(serial, secret, 6, 0).unwrap()
+    }
+
+    #[tokio::test]
+    async fn tokens_can_be_enrolled_once() {
+        let mut store = HashMapHotpStore::new();
+        store.add_token(token("FOB-1")).await.unwrap();
+        store.add_token(token("FOB-2")).await.unwrap();
+        assert_eq!(
+            store.add_token(token("FOB-1")).await,
+            Err(HotpStoreError::TokenAlreadyExists)
+        );
+
+        store.assign_token("FOB-1", &email("a"), 3).await.unwrap();
+        assert_eq!(store.get_token(&email("a")).await.unwrap().counter, 3);
+        assert_eq!(
+            store.get_unassigned_token("FOB-1").await.err(),
+            Some(HotpStoreError::TokenNotFound)
+        );
+        assert_eq!(
+            store.assign_token("FOB-1", &email("b"), 1).await,
+            Err(HotpStoreError::TokenNotFound)
+        );
+        assert_eq!(
+            store.assign_token("FOB-2", &email("a"), 1).await,
+            Err(HotpStoreError::AlreadyEnrolled)
+        );
+    }
+
+    #[tokio::test]
+    async fn counters_only_move_forward() {
+        let mut store = HashMapHotpStore::new();
+        assert_eq!(
+            store.advance_counter(&email("a"), 1).await,
+            Err(HotpStoreError::NotEnrolled)
+        );
+
+        store.add_token(token("FOB-1")).await.unwrap();
+        store.assign_token("FOB-1", &email("a"), 5).await.unwrap();
+        assert_eq!(
+            store.advance_counter(&email("a"), 5).await,
+            Err(HotpStoreError::CodeReused)
+        );
+        assert!(store.advance_counter(&email("a"), 8).await.is_ok());
+        assert_eq!(
+            store.advance_counter(&email("a"), 7).await,
+            Err(HotpStoreError::CodeReused)
+        );
+    }
+}
@@ -0,0 +1,136 @@
+use chrono::Utc;
+use std::collections::HashMap;
+
+use crate::domain::{
+    data_store::{InviteStore, InviteStoreError},
+    invite::{Invite, InviteCode},
+    Email,
+};
+
+#[derive(Default)]
+pub struct HashMapInviteStore {
+    invites: HashMap<InviteCode, Invite>,
+}
+
+impl HashMapInviteStore {
+    pub fn new() -> Self {
+        Self {
+            invites: HashMap::new(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl InviteStore for HashMapInviteStore {
+    async fn add_invite(&mut self, invite: Invite) -> Result<(), InviteStoreError> {
+        self.invites.insert(invite.code.clone(), invite);
+        Ok(())
+    }
+
+    async fn consume_invite(
+        &mut self,
+        code: &InviteCode,
+        email: &Email,
+    ) -> Result<(), InviteStoreError> {
+        let invite = self
+            .invites
+            .get_mut(code)
+            .ok_or(InviteStoreError::InviteNotFound)?;
+
+        if invite.expires_at <= Utc::now() {
+            return Err(InviteStoreError::InviteExpired);
+        }
+        if invite.uses >= invite.max_uses {
+            return Err(InviteStoreError::InviteExhausted);
+        }
+        if invite.email.as_ref().is_some_and(|bound| bound != email) {
+            return Err(InviteStoreError::EmailMismatch);
+        }
+
+        invite.uses += 1;
+        Ok(())
+    }
+
+    async fn release_invite(&mut self, code: &InviteCode) -> Result<(), InviteStoreError> {
+        let invite = self
+            .invites
+            .get_mut(code)
+            .ok_or(InviteStoreError::InviteNotFound)?;
+        invite.uses = invite.uses.saturating_sub(1);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use secrecy::Secret;
+
+    fn email(address: &str) -> Email {
+        Email::parse(Secret::new(address.to_owned())).unwrap()
+    }
+
+    #[tokio::test]
+    async fn invite_can_be_used_up_to_max_uses() {
+        let mut store = HashMapInviteStore::new();
+        let invite = Invite::new(None, 2, Duration::hours(1));
+        let code = invite.code.clone();
+        store.add_invite(invite).await.unwrap();
+
+        assert!(store
+            .consume_invite(&code, &email("a@example.com"))
+            .await
+            .is_ok());
+        assert!(store
+            .consume_invite(&code, &email("b@example.com"))
+            .await
+            .is_ok());
+        assert_eq!(
+            store.consume_invite(&code, &email("c@example.com")).await,
+            Err(InviteStoreError::InviteExhausted)
+        );
+
+        store.release_invite(&code).await.unwrap();
+        assert!(store
+            .consume_invite(&code, &email("c@example.com"))
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn unknown_and_expired_invites_are_rejected() {
+        let mut store = HashMapInviteStore::new();
+        assert_eq!(
+            store
+                .consume_invite(&InviteCode::default(), &email("a@example.com"))
+                .await,
+            Err(InviteStoreError::InviteNotFound)
+        );
+
+        let invite = Invite::new(None, 1, Duration::seconds(-1));
+        let code = invite.code.clone();
+        store.add_invite(invite).await.unwrap();
+        assert_eq!(
+            store.consume_invite(&code, &email("a@example.com")).await,
+            Err(InviteStoreError::InviteExpired)
+        );
+    }
+
+    #[tokio::test]
+    async fn bound_invite_only_works_for_its_email() {
+        let mut store = HashMapInviteStore::new();
+        let invite = Invite::new(Some(email("a@example.com")), 1, Duration::hours(1));
+        let code = invite.code.clone();
+        store.add_invite(invite).await.unwrap();
+
+        assert_eq!(
+            store.consume_invite(&code, &email("b@example.com")).await,
+            Err(InviteStoreError::EmailMismatch)
+        );
+        assert!(store
+            .consume_invite(&code, &email("a@example.com"))
+            .await
+            .is_ok());
+    }
+}
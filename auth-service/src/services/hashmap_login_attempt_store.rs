@@ -0,0 +1,134 @@
+use chrono::{DateTime, Duration, Utc};
+use std::collections::HashMap;
+
+use crate::domain::{
+    data_store::{LoginAttemptStore, LoginAttemptStoreError},
+    Email,
+};
+use crate::utils::constants::LOGIN_FAILURE_WINDOW_SECONDS;
+
+#[derive(Debug, Clone)]
+struct FailedAttempts {
+    count: u32,
+    last_failure_at: DateTime<Utc>,
+    locked_until: Option<DateTime<Utc>>,
+}
+
+#[derive(Default)]
+pub struct HashMapLoginAttemptStore {
+    attempts: HashMap<Email, FailedAttempts>,
+}
+
+impl HashMapLoginAttemptStore {
+    pub fn new() -> Self {
+        Self {
+            attempts: HashMap::new(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl LoginAttemptStore for HashMapLoginAttemptStore {
+    async fn record_failure(&mut self, email: &Email) -> Result<u32, LoginAttemptStoreError> {
+        let now = Utc::now();
+        let attempts = self
+            .attempts
+            .entry(email.clone())
+            .or_insert(FailedAttempts {
+                count: 0,
+                last_failure_at: now,
+                locked_until: None,
+            });
+
+        // Failures spread further apart than the window start a fresh count
+        if now - attempts.last_failure_at > Duration::seconds(LOGIN_FAILURE_WINDOW_SECONDS) {
+            attempts.count = 0;
+        }
+
+        attempts.count += 1;
+        attempts.last_failure_at = now;
+        Ok(attempts.count)
+    }
+
+    async fn lock_account(
+        &mut self,
+        email: &Email,
+        until: DateTime<Utc>,
+    ) -> Result<(), LoginAttemptStoreError> {
+        let attempts = self
+            .attempts
+            .entry(email.clone())
+            .or_insert(FailedAttempts {
+                count: 0,
+                last_failure_at: Utc::now(),
+                locked_until: None,
+            });
+        attempts.locked_until = Some(until);
+        Ok(())
+    }
+
+    async fn locked_until(
+        &self,
+        email: &Email,
+    ) -> Result<Option<DateTime<Utc>>, LoginAttemptStoreError> {
+        Ok(self
+            .attempts
+            .get(email)
+            .and_then(|attempts| attempts.locked_until)
+            .filter(|until| *until > Utc::now()))
+    }
+
+    async fn reset(&mut self, email: &Email) -> Result<(), LoginAttemptStoreError> {
+        self.attempts.remove(email);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use secrecy::Secret;
+
+    fn email() -> Email {
+        Email::parse(Secret::new("test@example.com".to_owned())).unwrap()
+    }
+
+    #[tokio::test]
+    async fn failures_are_counted_until_reset() {
+        let mut store = HashMapLoginAttemptStore::new();
+
+        assert_eq!(store.record_failure(&email()).await.unwrap(), 1);
+        assert_eq!(store.record_failure(&email()).await.unwrap(), 2);
+
+        store.reset(&email()).await.unwrap();
+        assert_eq!(store.record_failure(&email()).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn lockout_ends_at_the_given_time() {
+        let mut store = HashMapLoginAttemptStore::new();
+        assert_eq!(store.locked_until(&email()).await.unwrap(), None);
+
+        let until = Utc::now() + Duration::minutes(1);
+        store.lock_account(&email(), until).await.unwrap();
+        assert_eq!(store.locked_until(&email()).await.unwrap(), Some(until));
+
+        store
+            .lock_account(&email(), Utc::now() - Duration::seconds(1))
+            .await
+            .unwrap();
+        assert_eq!(store.locked_until(&email()).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn reset_clears_lockout() {
+        let mut store = HashMapLoginAttemptStore::new();
+        store
+            .lock_account(&email(), Utc::now() + Duration::minutes(1))
+            .await
+            .unwrap();
+
+        store.reset(&email()).await.unwrap();
+        assert_eq!(store.locked_until(&email()).await.unwrap(), None);
+    }
+}
@@ -0,0 +1,116 @@
+use std::collections::HashMap;
+
+use crate::domain::{
+    data_store::{PhoneStore, PhoneStoreError},
+    phone_number::{PhoneNumber, UserPhone},
+    Email,
+};
+
+#[derive(Default)]
+struct StoredPhone {
+    verified: Option<UserPhone>,
+    pending: Option<PhoneNumber>,
+}
+
+#[derive(Default)]
+pub struct HashMapPhoneStore {
+    phones: HashMap<Email, StoredPhone>,
+}
+
+impl HashMapPhoneStore {
+    pub fn new() -> Self {
+        Self {
+            phones: HashMap::new(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl PhoneStore for HashMapPhoneStore {
+    async fn set_pending_phone(
+        &mut self,
+        email: &Email,
+        number: PhoneNumber,
+    ) -> Result<(), PhoneStoreError> {
+        self.phones.entry(email.clone()).or_default().pending = Some(number);
+        Ok(())
+    }
+
+    async fn get_phone(&self, email: &Email) -> Result<UserPhone, PhoneStoreError> {
+        self.phones
+            .get(email)
+            .and_then(|phone| phone.verified.clone())
+            .ok_or(PhoneStoreError::PhoneNotFound)
+    }
+
+    async fn mark_verified(&mut self, email: &Email) -> Result<(), PhoneStoreError> {
+        let phone = self
+            .phones
+            .get_mut(email)
+            .ok_or(PhoneStoreError::PhoneNotFound)?;
+        let number = phone.pending.take().ok_or(PhoneStoreError::PhoneNotFound)?;
+        phone.verified = Some(UserPhone { number });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use secrecy::Secret;
+
+    fn email() -> Email {
+        Email::parse(Secret::new("test@example.com".to_owned())).unwrap()
+    }
+
+    fn number(number: &str) -> PhoneNumber {
+        PhoneNumber::parse(Secret::new(number.to_owned())).unwrap()
+    }
+
+    #[tokio::test]
+    async fn numbers_are_only_used_once_verified() {
+        let mut store = HashMapPhoneStore::new();
+        assert_eq!(
+            store.get_phone(&email()).await,
+            Err(PhoneStoreError::PhoneNotFound)
+        );
+
+        store
+            .set_pending_phone(&email(), number("+447700900123"))
+            .await
+            .unwrap();
+        assert_eq!(
+            store.get_phone(&email()).await,
+            Err(PhoneStoreError::PhoneNotFound)
+        );
+
+        store.mark_verified(&email()).await.unwrap();
+        let phone = store.get_phone(&email()).await.unwrap();
+        assert_eq!(phone.number, number("+447700900123"));
+    }
+
+    #[tokio::test]
+    async fn verified_number_stays_until_the_new_one_is_verified() {
+        let mut store = HashMapPhoneStore::new();
+        store
+            .set_pending_phone(&email(), number("+447700900123"))
+            .await
+            .unwrap();
+        store.mark_verified(&email()).await.unwrap();
+
+        store
+            .set_pending_phone(&email(), number("+447700900456"))
+            .await
+            .unwrap();
+        let phone = store.get_phone(&email()).await.unwrap();
+        assert_eq!(phone.number, number("+447700900123"));
+
+        store.mark_verified(&email()).await.unwrap();
+        let phone = store.get_phone(&email()).await.unwrap();
+        assert_eq!(phone.number, number("+447700900456"));
+        assert_eq!(
+            store.mark_verified(&email()).await,
+            Err(PhoneStoreError::PhoneNotFound)
+        );
+    }
+}
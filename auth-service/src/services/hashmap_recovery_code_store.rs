@@ -0,0 +1,100 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::domain::{
+    data_store::{RecoveryCodeStore, RecoveryCodeStoreError},
+    recovery_code::RecoveryCode,
+    Email,
+};
+
+// Keeps the hashes of each user's unused codes
+#[derive(Default)]
+pub struct HashMapRecoveryCodeStore {
+    codes: HashMap<Email, HashSet<String>>,
+}
+
+impl HashMapRecoveryCodeStore {
+    pub fn new() -> Self {
+        Self {
+            codes: HashMap::new(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl RecoveryCodeStore for HashMapRecoveryCodeStore {
+    async fn replace_codes(
+        &mut self,
+        email: &Email,
+        codes: &[RecoveryCode],
+    ) -> Result<(), RecoveryCodeStoreError> {
+        self.codes.insert(
+            email.clone(),
+            codes.iter().map(RecoveryCode::hash).collect(),
+        );
+        Ok(())
+    }
+
+    async fn use_code(
+        &mut self,
+        email: &Email,
+        code: &RecoveryCode,
+    ) -> Result<usize, RecoveryCodeStoreError> {
+        let unused = self
+            .codes
+            .get_mut(email)
+            .ok_or(RecoveryCodeStoreError::InvalidCode)?;
+
+        if !unused.remove(&code.hash()) {
+            return Err(RecoveryCodeStoreError::InvalidCode);
+        }
+        Ok(unused.len())
+    }
+
+    async fn count_unused(&self, email: &Email) -> Result<usize, RecoveryCodeStoreError> {
+        Ok(self.codes.get(email).map_or(0, HashSet::len))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use secrecy::Secret;
+
+    fn email() -> Email {
+        Email::parse(Secret::new("test@example.com".to_owned())).unwrap()
+    }
+
+    #[tokio::test]
+    async fn codes_can_only_be_used_once() {
+        let mut store = HashMapRecoveryCodeStore::new();
+        let codes = vec![RecoveryCode::default(), RecoveryCode::default()];
+        store.replace_codes(&email(), &codes).await.unwrap();
+
+        assert_eq!(store.use_code(&email(), &codes[0]).await, Ok(1));
+        assert_eq!(
+            store.use_code(&email(), &codes[0]).await,
+            Err(RecoveryCodeStoreError::InvalidCode)
+        );
+        assert_eq!(store.use_code(&email(), &codes[1]).await, Ok(0));
+        assert_eq!(store.count_unused(&email()).await, Ok(0));
+    }
+
+    #[tokio::test]
+    async fn replacing_codes_invalidates_the_old_ones() {
+        let mut store = HashMapRecoveryCodeStore::new();
+        let old = RecoveryCode::default();
+        store
+            .replace_codes(&email(), std::slice::from_ref(&old))
+            .await
+            .unwrap();
+        store
+            .replace_codes(&email(), &[RecoveryCode::default()])
+            .await
+            .unwrap();
+
+        assert_eq!(
+            store.use_code(&email(), &old).await,
+            Err(RecoveryCodeStoreError::InvalidCode)
+        );
+    }
+}
@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+
+use crate::domain::{
+    data_store::{TotpStore, TotpStoreError},
+    totp::{TotpEnrollment, TotpSecret},
+    Email,
+};
+
+#[derive(Debug, Clone)]
+struct StoredTotp {
+    enrollment: TotpEnrollment,
+    last_used_step: Option<u64>,
+}
+
+#[derive(Default)]
+pub struct HashMapTotpStore {
+    secrets: HashMap<Email, StoredTotp>,
+}
+
+impl HashMapTotpStore {
+    pub fn new() -> Self {
+        Self {
+            secrets: HashMap::new(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl TotpStore for HashMapTotpStore {
+    async fn set_pending_secret(
+        &mut self,
+        email: &Email,
+        secret: TotpSecret,
+    ) -> Result<(), TotpStoreError> {
+        if self
+            .secrets
+            .get(email)
+            .is_some_and(|stored| stored.enrollment.confirmed)
+        {
+            return Err(TotpStoreError::AlreadyEnrolled);
+        }
+
+        self.secrets.insert(
+            email.clone(),
+            StoredTotp {
+                enrollment: TotpEnrollment {
+                    secret,
+                    confirmed: false,
+                },
+                last_used_step: None,
+            },
+        );
+        Ok(())
+    }
+
+    async fn get_enrollment(&self, email: &Email) -> Result<TotpEnrollment, TotpStoreError> {
+        self.secrets
+            .get(email)
+            .map(|stored| stored.enrollment.clone())
+            .ok_or(TotpStoreError::NotEnrolled)
+    }
+
+    async fn confirm(&mut self, email: &Email) -> Result<(), TotpStoreError> {
+        let stored = self
+            .secrets
+            .get_mut(email)
+            .ok_or(TotpStoreError::NotEnrolled)?;
+        stored.enrollment.confirmed = true;
+        Ok(())
+    }
+
+    async fn use_step(&mut self, email: &Email, step: u64) -> Result<(), TotpStoreError> {
+        let stored = self
+            .secrets
+            .get_mut(email)
+            .ok_or(TotpStoreError::NotEnrolled)?;
+
+        if stored.last_used_step.is_some_and(|last| step <= last) {
+            return Err(TotpStoreError::CodeReused);
+        }
+
+        stored.last_used_step = Some(step);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use secrecy::Secret;
+
+    fn email() -> Email {
+        Email::parse(Secret::new("test@example.com".to_owned())).unwrap()
+    }
+
+    #[tokio::test]
+    async fn confirmed_secret_cannot_be_replaced() {
+        let mut store = HashMapTotpStore::new();
+        store
+            .set_pending_secret(&email(), TotpSecret::generate())
+            .await
+            .unwrap();
+        store
+            .set_pending_secret(&email(), TotpSecret::generate())
+            .await
+            .unwrap();
+
+        store.confirm(&email()).await.unwrap();
+        assert!(store.get_enrollment(&email()).await.unwrap().confirmed);
+        assert_eq!(
+            store
+                .set_pending_secret(&email(), TotpSecret::generate())
+                .await,
+            Err(TotpStoreError::AlreadyEnrolled)
+        );
+    }
+
+    #[tokio::test]
+    async fn steps_can_only_be_used_once_and_in_order() {
+        let mut store = HashMapTotpStore::new();
+        assert_eq!(
+            store.use_step(&email(), 10).await,
+            Err(TotpStoreError::NotEnrolled)
+        );
+
+        store
+            .set_pending_secret(&email(), TotpSecret::generate())
+            .await
+            .unwrap();
+        assert!(store.use_step(&email(), 10).await.is_ok());
+        assert_eq!(
+            store.use_step(&email(), 10).await,
+            Err(TotpStoreError::CodeReused)
+        );
+        assert_eq!(
+            store.use_step(&email(), 9).await,
+            Err(TotpStoreError::CodeReused)
+        );
+        assert!(store.use_step(&email(), 11).await.is_ok());
+    }
+}
@@ -0,0 +1,156 @@
+use chrono::Utc;
+use std::collections::HashMap;
+
+use crate::domain::{
+    data_store::{TrustedDeviceStore, TrustedDeviceStoreError},
+    trusted_device::{TrustedDevice, TrustedDeviceId},
+    Email,
+};
+
+#[derive(Default)]
+pub struct HashMapTrustedDeviceStore {
+    devices: HashMap<Email, Vec<TrustedDevice>>,
+}
+
+impl HashMapTrustedDeviceStore {
+    pub fn new() -> Self {
+        Self {
+            devices: HashMap::new(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl TrustedDeviceStore for HashMapTrustedDeviceStore {
+    async fn add_device(
+        &mut self,
+        email: &Email,
+        device: TrustedDevice,
+    ) -> Result<(), TrustedDeviceStoreError> {
+        self.devices.entry(email.clone()).or_default().push(device);
+        Ok(())
+    }
+
+    async fn touch_device(
+        &mut self,
+        email: &Email,
+        id: &TrustedDeviceId,
+    ) -> Result<(), TrustedDeviceStoreError> {
+        let now = Utc::now();
+        let device = self
+            .devices
+            .get_mut(email)
+            .and_then(|devices| devices.iter_mut().find(|device| &device.id == id))
+            .filter(|device| device.expires_at > now)
+            .ok_or(TrustedDeviceStoreError::DeviceNotFound)?;
+        device.last_used_at = Some(now);
+        Ok(())
+    }
+
+    async fn get_devices(
+        &self,
+        email: &Email,
+    ) -> Result<Vec<TrustedDevice>, TrustedDeviceStoreError> {
+        let now = Utc::now();
+        let mut devices: Vec<TrustedDevice> = self
+            .devices
+            .get(email)
+            .into_iter()
+            .flatten()
+            .filter(|device| device.expires_at > now)
+            .cloned()
+            .collect();
+        devices.sort_by_key(|device| std::cmp::Reverse(device.created_at));
+        Ok(devices)
+    }
+
+    async fn revoke_device(
+        &mut self,
+        email: &Email,
+        id: &TrustedDeviceId,
+    ) -> Result<(), TrustedDeviceStoreError> {
+        let devices = self
+            .devices
+            .get_mut(email)
+            .ok_or(TrustedDeviceStoreError::DeviceNotFound)?;
+        let count = devices.len();
+        devices.retain(|device| &device.id != id);
+        if devices.len() == count {
+            return Err(TrustedDeviceStoreError::DeviceNotFound);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use secrecy::Secret;
+
+    fn email(address: &str) -> Email {
+        Email::parse(Secret::new(address.to_owned())).unwrap()
+    }
+
+    fn device(ttl: Duration) -> TrustedDevice {
+        TrustedDevice::new(Some("Firefox"), Utc::now() + ttl)
+    }
+
+    #[tokio::test]
+    async fn revoked_devices_are_no_longer_trusted() {
+        let mut store = HashMapTrustedDeviceStore::new();
+        let user = email("test@example.com");
+        let device = device(Duration::days(30));
+        store.add_device(&user, device.clone()).await.unwrap();
+
+        store.touch_device(&user, &device.id).await.unwrap();
+        assert!(store.get_devices(&user).await.unwrap()[0]
+            .last_used_at
+            .is_some());
+
+        store.revoke_device(&user, &device.id).await.unwrap();
+        assert_eq!(
+            store.touch_device(&user, &device.id).await,
+            Err(TrustedDeviceStoreError::DeviceNotFound)
+        );
+        assert_eq!(
+            store.revoke_device(&user, &device.id).await,
+            Err(TrustedDeviceStoreError::DeviceNotFound)
+        );
+    }
+
+    #[tokio::test]
+    async fn devices_belong_to_one_user() {
+        let mut store = HashMapTrustedDeviceStore::new();
+        let device = device(Duration::days(30));
+        store
+            .add_device(&email("test@example.com"), device.clone())
+            .await
+            .unwrap();
+
+        let other = email("other@example.com");
+        assert_eq!(
+            store.touch_device(&other, &device.id).await,
+            Err(TrustedDeviceStoreError::DeviceNotFound)
+        );
+        assert_eq!(
+            store.revoke_device(&other, &device.id).await,
+            Err(TrustedDeviceStoreError::DeviceNotFound)
+        );
+        assert!(store.get_devices(&other).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn expired_devices_are_not_trusted_or_listed() {
+        let mut store = HashMapTrustedDeviceStore::new();
+        let user = email("test@example.com");
+        let device = device(Duration::seconds(-1));
+        store.add_device(&user, device.clone()).await.unwrap();
+
+        assert_eq!(
+            store.touch_device(&user, &device.id).await,
+            Err(TrustedDeviceStoreError::DeviceNotFound)
+        );
+        assert!(store.get_devices(&user).await.unwrap().is_empty());
+    }
+}
@@ -1,19 +1,89 @@
+use chrono::{DateTime, Duration, Utc};
 use std::collections::HashMap;
 
 use crate::domain::{
+    authentication::AuthMethod,
     data_store::{LoginAttemptId, TwoFACode, TwoFaCodeStore, TwoFaCodeStoreError},
     Email,
 };
 
-#[derive(Default)]
+#[derive(Debug, Clone)]
+struct StoredCode {
+    email: Email,
+    code: TwoFACode,
+    failed_attempts: u32,
+    sent_at: DateTime<Utc>,
+    resends: u32,
+    expires_at: DateTime<Utc>,
+    first_factor: Option<AuthMethod>,
+}
+
+impl StoredCode {
+    fn is_live(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at > now
+    }
+}
+
 pub struct HashMapTwoFACodeStore {
-    pub codes: HashMap<Email, (LoginAttemptId, TwoFACode)>,
+    codes: HashMap<LoginAttemptId, StoredCode>,
+    // Each user's outstanding login attempts, oldest first
+    attempts: HashMap<Email, Vec<LoginAttemptId>>,
+    // When each user was last sent a code for a new login attempt
+    last_sends: HashMap<Email, DateTime<Utc>>,
+    code_ttl: Duration,
 }
 
 impl HashMapTwoFACodeStore {
-    pub fn new() -> Self {
+    pub fn new(code_ttl: Duration) -> Self {
         Self {
             codes: HashMap::new(),
+            attempts: HashMap::new(),
+            last_sends: HashMap::new(),
+            code_ttl,
+        }
+    }
+
+    // Expired codes are treated as gone but only dropped from the maps here, when the user
+    // starts another login
+    fn stored_code_mut(
+        &mut self,
+        email: &Email,
+        login_attempt_id: &LoginAttemptId,
+    ) -> Result<&mut StoredCode, TwoFaCodeStoreError> {
+        let now = Utc::now();
+        self.codes
+            .get_mut(login_attempt_id)
+            .filter(|stored| &stored.email == email && stored.is_live(now))
+            .ok_or(TwoFaCodeStoreError::LoginAttempIdNotFound)
+    }
+
+    fn forget_expired(&mut self, email: &Email) {
+        let now = Utc::now();
+        let expired: Vec<LoginAttemptId> = self
+            .attempts
+            .get(email)
+            .into_iter()
+            .flatten()
+            .filter(|id| {
+                !self
+                    .codes
+                    .get(*id)
+                    .is_some_and(|stored| stored.is_live(now))
+            })
+            .cloned()
+            .collect();
+        for id in expired {
+            self.forget(email, &id);
+        }
+    }
+
+    fn forget(&mut self, email: &Email, login_attempt_id: &LoginAttemptId) {
+        self.codes.remove(login_attempt_id);
+        if let Some(attempts) = self.attempts.get_mut(email) {
+            attempts.retain(|id| id != login_attempt_id);
+            if attempts.is_empty() {
+                self.attempts.remove(email);
+            }
         }
     }
 }
@@ -25,26 +95,401 @@ impl TwoFaCodeStore for HashMapTwoFACodeStore {
         email: &Email,
         login_attempt_id: LoginAttemptId,
         code: TwoFACode,
+        max_pending: u32,
     ) -> Result<(), TwoFaCodeStoreError> {
-        if self.codes.contains_key(&email) {
-            return Err(TwoFaCodeStoreError::UserHasCode);
+        self.forget_expired(email);
+        let attempts = self.attempts.entry(email.clone()).or_default();
+        while !attempts.is_empty() && attempts.len() >= max_pending as usize {
+            let oldest = attempts.remove(0);
+            self.codes.remove(&oldest);
         }
-        self.codes.insert(email.clone(), (login_attempt_id, code));
+        attempts.push(login_attempt_id.clone());
+
+        let now = Utc::now();
+        self.codes.insert(
+            login_attempt_id,
+            StoredCode {
+                email: email.clone(),
+                code,
+                failed_attempts: 0,
+                sent_at: now,
+                resends: 0,
+                expires_at: now + self.code_ttl,
+                first_factor: None,
+            },
+        );
         Ok(())
     }
 
-    async fn remove_code(&mut self, email: &Email) -> Result<(), TwoFaCodeStoreError> {
-        self.codes.remove(email);
+    async fn remove_code(
+        &mut self,
+        email: &Email,
+        login_attempt_id: &LoginAttemptId,
+    ) -> Result<(), TwoFaCodeStoreError> {
+        if self.stored_code_mut(email, login_attempt_id).is_ok() {
+            self.forget(email, login_attempt_id);
+        }
         Ok(())
     }
 
     async fn get_code(
         &self,
         email: &Email,
-    ) -> Result<(LoginAttemptId, TwoFACode), TwoFaCodeStoreError> {
+        login_attempt_id: &LoginAttemptId,
+    ) -> Result<TwoFACode, TwoFaCodeStoreError> {
+        let now = Utc::now();
+        self.codes
+            .get(login_attempt_id)
+            .filter(|stored| &stored.email == email && stored.is_live(now))
+            .map(|stored| stored.code.clone())
+            .ok_or(TwoFaCodeStoreError::LoginAttempIdNotFound)
+    }
+
+    async fn set_first_factor(
+        &mut self,
+        email: &Email,
+        login_attempt_id: &LoginAttemptId,
+        method: AuthMethod,
+    ) -> Result<(), TwoFaCodeStoreError> {
+        self.stored_code_mut(email, login_attempt_id)?.first_factor = Some(method);
+        Ok(())
+    }
+
+    async fn get_first_factor(
+        &self,
+        email: &Email,
+        login_attempt_id: &LoginAttemptId,
+    ) -> Result<Option<AuthMethod>, TwoFaCodeStoreError> {
+        let now = Utc::now();
         self.codes
+            .get(login_attempt_id)
+            .filter(|stored| &stored.email == email && stored.is_live(now))
+            .map(|stored| stored.first_factor)
+            .ok_or(TwoFaCodeStoreError::LoginAttempIdNotFound)
+    }
+
+    async fn get_login_attempts(
+        &self,
+        email: &Email,
+    ) -> Result<Vec<LoginAttemptId>, TwoFaCodeStoreError> {
+        let now = Utc::now();
+        Ok(self
+            .attempts
             .get(email)
+            .into_iter()
+            .flatten()
+            .filter(|id| {
+                self.codes
+                    .get(*id)
+                    .is_some_and(|stored| stored.is_live(now))
+            })
             .cloned()
-            .ok_or(TwoFaCodeStoreError::CodeNotFound)
+            .collect())
+    }
+
+    async fn record_failed_attempt(
+        &mut self,
+        email: &Email,
+        login_attempt_id: &LoginAttemptId,
+        max_attempts: u32,
+    ) -> Result<u32, TwoFaCodeStoreError> {
+        let stored = self.stored_code_mut(email, login_attempt_id)?;
+        stored.failed_attempts += 1;
+        let failed_attempts = stored.failed_attempts;
+
+        if failed_attempts >= max_attempts {
+            self.forget(email, login_attempt_id);
+            return Err(TwoFaCodeStoreError::TooManyAttempts);
+        }
+
+        Ok(max_attempts - failed_attempts)
+    }
+
+    async fn rotate_code(
+        &mut self,
+        email: &Email,
+        login_attempt_id: &LoginAttemptId,
+        code: TwoFACode,
+        cooldown: Duration,
+        max_resends: u32,
+    ) -> Result<(), TwoFaCodeStoreError> {
+        let code_ttl = self.code_ttl;
+        let stored = self.stored_code_mut(email, login_attempt_id)?;
+
+        let now = Utc::now();
+        if now < stored.sent_at + cooldown {
+            return Err(TwoFaCodeStoreError::ResendTooSoon);
+        }
+        if stored.resends >= max_resends {
+            return Err(TwoFaCodeStoreError::TooManyResends);
+        }
+
+        stored.code = code;
+        stored.sent_at = now;
+        stored.resends += 1;
+        stored.expires_at = now + code_ttl;
+        Ok(())
+    }
+
+    async fn claim_send(
+        &mut self,
+        email: &Email,
+        cooldown: Duration,
+    ) -> Result<(), TwoFaCodeStoreError> {
+        let now = Utc::now();
+        if self
+            .last_sends
+            .get(email)
+            .is_some_and(|sent_at| now < *sent_at + cooldown)
+        {
+            return Err(TwoFaCodeStoreError::ResendTooSoon);
+        }
+
+        self.last_sends.insert(email.clone(), now);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app_state::settings::TwoFaCodeFormat;
+    use secrecy::Secret;
+
+    fn new_code() -> TwoFACode {
+        TwoFACode::generate(&TwoFaCodeFormat::default())
+    }
+
+    fn email() -> Email {
+        Email::parse(Secret::new("test@example.com".to_owned())).unwrap()
+    }
+
+    async fn store_with_attempt() -> (HashMapTwoFACodeStore, LoginAttemptId) {
+        let mut store = HashMapTwoFACodeStore::new(Duration::minutes(10));
+        let login_attempt_id = LoginAttemptId::default();
+        store
+            .add_code(&email(), login_attempt_id.clone(), new_code(), 5)
+            .await
+            .unwrap();
+        (store, login_attempt_id)
+    }
+
+    #[tokio::test]
+    async fn code_is_removed_after_max_failed_attempts() {
+        let (mut store, id) = store_with_attempt().await;
+
+        assert_eq!(store.record_failed_attempt(&email(), &id, 3).await, Ok(2));
+        assert_eq!(store.record_failed_attempt(&email(), &id, 3).await, Ok(1));
+        assert_eq!(
+            store.record_failed_attempt(&email(), &id, 3).await,
+            Err(TwoFaCodeStoreError::TooManyAttempts)
+        );
+        assert_eq!(
+            store.get_code(&email(), &id).await,
+            Err(TwoFaCodeStoreError::LoginAttempIdNotFound)
+        );
+        assert_eq!(
+            store.record_failed_attempt(&email(), &id, 3).await,
+            Err(TwoFaCodeStoreError::LoginAttempIdNotFound)
+        );
+        assert_eq!(store.get_login_attempts(&email()).await, Ok(vec![]));
+    }
+
+    #[tokio::test]
+    async fn first_factor_is_kept_with_its_attempt() {
+        let (mut store, id) = store_with_attempt().await;
+        assert_eq!(store.get_first_factor(&email(), &id).await, Ok(None));
+
+        store
+            .set_first_factor(&email(), &id, AuthMethod::Pwd)
+            .await
+            .unwrap();
+        assert_eq!(
+            store.get_first_factor(&email(), &id).await,
+            Ok(Some(AuthMethod::Pwd))
+        );
+        assert_eq!(
+            store
+                .get_first_factor(&email(), &LoginAttemptId::default())
+                .await,
+            Err(TwoFaCodeStoreError::LoginAttempIdNotFound)
+        );
+    }
+
+    #[tokio::test]
+    async fn parallel_attempts_are_independent() {
+        let (mut store, first) = store_with_attempt().await;
+        let second = LoginAttemptId::default();
+        let code = new_code();
+        store
+            .add_code(&email(), second.clone(), code.clone(), 5)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            store.get_login_attempts(&email()).await,
+            Ok(vec![first.clone(), second.clone()])
+        );
+        assert_eq!(
+            store.record_failed_attempt(&email(), &first, 3).await,
+            Ok(2)
+        );
+
+        store.remove_code(&email(), &first).await.unwrap();
+        assert_eq!(store.get_code(&email(), &second).await, Ok(code));
+        assert_eq!(
+            store.record_failed_attempt(&email(), &second, 3).await,
+            Ok(2)
+        );
+    }
+
+    #[tokio::test]
+    async fn oldest_attempts_are_dropped_at_the_cap() {
+        let mut store = HashMapTwoFACodeStore::new(Duration::minutes(10));
+        let ids: Vec<_> = (0..3).map(|_| LoginAttemptId::default()).collect();
+        for id in &ids {
+            store
+                .add_code(&email(), id.clone(), new_code(), 2)
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(
+            store.get_login_attempts(&email()).await,
+            Ok(ids[1..].to_vec())
+        );
+        assert_eq!(
+            store.get_code(&email(), &ids[0]).await,
+            Err(TwoFaCodeStoreError::LoginAttempIdNotFound)
+        );
+    }
+
+    #[tokio::test]
+    async fn attempt_must_belong_to_the_user() {
+        let (mut store, id) = store_with_attempt().await;
+        let other = Email::parse(Secret::new("other@example.com".to_owned())).unwrap();
+
+        assert_eq!(
+            store.get_code(&other, &id).await,
+            Err(TwoFaCodeStoreError::LoginAttempIdNotFound)
+        );
+        store.remove_code(&other, &id).await.unwrap();
+        assert!(store.get_code(&email(), &id).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn rotating_keeps_the_attempt_and_its_failures() {
+        let (mut store, login_attempt_id) = store_with_attempt().await;
+        assert_eq!(
+            store
+                .record_failed_attempt(&email(), &login_attempt_id, 3)
+                .await,
+            Ok(2)
+        );
+
+        let code = new_code();
+        store
+            .rotate_code(
+                &email(),
+                &login_attempt_id,
+                code.clone(),
+                Duration::zero(),
+                1,
+            )
+            .await
+            .unwrap();
+        assert_eq!(store.get_code(&email(), &login_attempt_id).await, Ok(code));
+        assert_eq!(
+            store
+                .record_failed_attempt(&email(), &login_attempt_id, 3)
+                .await,
+            Ok(1)
+        );
+
+        assert_eq!(
+            store
+                .rotate_code(&email(), &login_attempt_id, new_code(), Duration::zero(), 1)
+                .await,
+            Err(TwoFaCodeStoreError::TooManyResends)
+        );
+        assert_eq!(
+            store
+                .rotate_code(
+                    &email(),
+                    &LoginAttemptId::default(),
+                    new_code(),
+                    Duration::zero(),
+                    1
+                )
+                .await,
+            Err(TwoFaCodeStoreError::LoginAttempIdNotFound)
+        );
+    }
+
+    #[tokio::test]
+    async fn rotating_waits_for_the_cooldown() {
+        let (mut store, login_attempt_id) = store_with_attempt().await;
+
+        assert_eq!(
+            store
+                .rotate_code(
+                    &email(),
+                    &login_attempt_id,
+                    new_code(),
+                    Duration::seconds(30),
+                    3
+                )
+                .await,
+            Err(TwoFaCodeStoreError::ResendTooSoon)
+        );
+    }
+
+    #[tokio::test]
+    async fn sends_wait_for_the_cooldown() {
+        let mut store = HashMapTwoFACodeStore::new(Duration::minutes(10));
+        let other = Email::parse(Secret::new("other@example.com".to_owned())).unwrap();
+
+        assert_eq!(
+            store.claim_send(&email(), Duration::seconds(30)).await,
+            Ok(())
+        );
+        assert_eq!(
+            store.claim_send(&email(), Duration::seconds(30)).await,
+            Err(TwoFaCodeStoreError::ResendTooSoon)
+        );
+        assert_eq!(
+            store.claim_send(&other, Duration::seconds(30)).await,
+            Ok(())
+        );
+        assert_eq!(store.claim_send(&email(), Duration::zero()).await, Ok(()));
+    }
+
+    #[tokio::test]
+    async fn expired_codes_are_gone_and_make_room() {
+        let mut store = HashMapTwoFACodeStore::new(Duration::zero());
+        let expired = LoginAttemptId::default();
+        store
+            .add_code(&email(), expired.clone(), new_code(), 1)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            store.get_code(&email(), &expired).await,
+            Err(TwoFaCodeStoreError::LoginAttempIdNotFound)
+        );
+        assert_eq!(
+            store.record_failed_attempt(&email(), &expired, 3).await,
+            Err(TwoFaCodeStoreError::LoginAttempIdNotFound)
+        );
+        assert_eq!(store.get_login_attempts(&email()).await, Ok(vec![]));
+
+        store.code_ttl = Duration::minutes(10);
+        let live = LoginAttemptId::default();
+        store
+            .add_code(&email(), live.clone(), new_code(), 1)
+            .await
+            .unwrap();
+        assert_eq!(store.get_login_attempts(&email()).await, Ok(vec![live]));
+        assert!(!store.codes.contains_key(&expired));
     }
 }
@@ -1,21 +1,43 @@
 use crate::domain::data_store::{UserStore, UserStoreError};
+use crate::domain::locale::Locale;
+use crate::domain::two_factor::TwoFactorMethod;
 use crate::domain::user::*;
 use crate::domain::{Email, Password};
+use crate::utils::constants::DEFAULT_PASSWORD_HISTORY_SIZE;
+use chrono::Utc;
 use secrecy::Secret;
-use std::collections::HashMap;
-#[derive(Debug, Default)]
+use std::collections::{HashMap, VecDeque};
+#[derive(Debug)]
 pub struct HashmapUserStore {
     users: HashMap<Email, User>,
+    // Previous passwords per user, most recent first
+    password_history: HashMap<Email, VecDeque<Password>>,
+    password_history_size: usize,
+    // Status changes per user, most recent first
+    status_history: HashMap<Email, Vec<StatusChange>>,
 }
 
 impl HashmapUserStore {
     pub fn new() -> Self {
+        Self::with_password_history_size(DEFAULT_PASSWORD_HISTORY_SIZE)
+    }
+
+    pub fn with_password_history_size(password_history_size: usize) -> Self {
         Self {
             users: HashMap::new(),
+            password_history: HashMap::new(),
+            password_history_size,
+            status_history: HashMap::new(),
         }
     }
 }
 
+impl Default for HashmapUserStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[async_trait::async_trait]
 impl UserStore for HashmapUserStore {
     async fn add_user(&mut self, user: User) -> Result<(), UserStoreError> {
@@ -45,6 +67,121 @@ impl UserStore for HashmapUserStore {
             None => Err(UserStoreError::InvalidCredentials),
         }
     }
+
+    async fn update_password(
+        &mut self,
+        email: &Email,
+        new_password: Password,
+    ) -> Result<(), UserStoreError> {
+        let user = self
+            .users
+            .get_mut(email)
+            .ok_or(UserStoreError::UserNotFound)?;
+        let history = self.password_history.entry(email.clone()).or_default();
+
+        if self.password_history_size > 0
+            && (user.password == new_password
+                || history
+                    .iter()
+                    .take(self.password_history_size - 1)
+                    .any(|old| old == &new_password))
+        {
+            return Err(UserStoreError::PasswordReused);
+        }
+
+        let old_password = std::mem::replace(&mut user.password, new_password);
+        history.push_front(old_password);
+        history.truncate(self.password_history_size);
+        user.password_changed_at = Utc::now();
+        user.must_change_password = false;
+
+        Ok(())
+    }
+
+    async fn set_must_change_password(
+        &mut self,
+        email: &Email,
+        must_change_password: bool,
+    ) -> Result<(), UserStoreError> {
+        let user = self
+            .users
+            .get_mut(email)
+            .ok_or(UserStoreError::UserNotFound)?;
+        user.must_change_password = must_change_password;
+        Ok(())
+    }
+
+    async fn set_require_2fa(
+        &mut self,
+        email: &Email,
+        require_2fa: bool,
+    ) -> Result<(), UserStoreError> {
+        let user = self
+            .users
+            .get_mut(email)
+            .ok_or(UserStoreError::UserNotFound)?;
+        user.require_2fa = require_2fa;
+        Ok(())
+    }
+
+    async fn set_preferred_2fa_method(
+        &mut self,
+        email: &Email,
+        method: Option<TwoFactorMethod>,
+    ) -> Result<(), UserStoreError> {
+        let user = self
+            .users
+            .get_mut(email)
+            .ok_or(UserStoreError::UserNotFound)?;
+        user.preferred_2fa_method = method;
+        Ok(())
+    }
+
+    async fn set_locale(
+        &mut self,
+        email: &Email,
+        locale: Option<Locale>,
+    ) -> Result<(), UserStoreError> {
+        let user = self
+            .users
+            .get_mut(email)
+            .ok_or(UserStoreError::UserNotFound)?;
+        user.locale = locale;
+        Ok(())
+    }
+
+    async fn set_status(
+        &mut self,
+        email: &Email,
+        status: AccountStatus,
+        reason: String,
+    ) -> Result<(), UserStoreError> {
+        let user = self
+            .users
+            .get_mut(email)
+            .ok_or(UserStoreError::UserNotFound)?;
+        user.status = status;
+
+        self.status_history
+            .entry(email.clone())
+            .or_default()
+            .insert(
+                0,
+                StatusChange {
+                    status,
+                    reason,
+                    changed_at: Utc::now(),
+                },
+            );
+        Ok(())
+    }
+
+    async fn get_status_history(&self, email: &Email) -> Result<Vec<StatusChange>, UserStoreError> {
+        if !self.users.contains_key(email) {
+            return Err(UserStoreError::UserNotFound);
+        }
+        Ok(self.status_history.get(email).cloned().unwrap_or_default())
+    }
 }
 
 #[cfg(test)]
@@ -87,6 +224,151 @@ mod tests {
         assert_eq!(result_not_found, Err(UserStoreError::UserNotFound));
     }
 
+    #[tokio::test]
+    async fn test_update_password() {
+        let email = Email::parse(Secret::new("test@mail.com".to_string())).expect("Valid email");
+        let new_password =
+            Password::parse(Secret::new("newpassword123".to_string())).expect("Valid password");
+        let mut user_store = HashmapUserStore::new();
+
+        let result = user_store
+            .update_password(&email, new_password.clone())
+            .await;
+        assert_eq!(result, Err(UserStoreError::UserNotFound));
+
+        user_store
+            .add_user(create_test_user("test@mail.com", "password123"))
+            .await
+            .unwrap();
+
+        let result = user_store
+            .update_password(&email, new_password.clone())
+            .await;
+        assert!(result.is_ok());
+        assert!(user_store
+            .validate_user(&email, &new_password)
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_update_password_rejects_recent_passwords() {
+        let email = Email::parse(Secret::new("test@mail.com".to_string())).expect("Valid email");
+        let password = |p: &str| Password::parse(Secret::new(p.to_owned())).unwrap();
+        let mut user_store = HashmapUserStore::with_password_history_size(3);
+
+        user_store
+            .add_user(create_test_user("test@mail.com", "password-1"))
+            .await
+            .unwrap();
+
+        // The current password counts towards the history
+        let result = user_store
+            .update_password(&email, password("password-1"))
+            .await;
+        assert_eq!(result, Err(UserStoreError::PasswordReused));
+
+        for new_password in ["password-2", "password-3"] {
+            user_store
+                .update_password(&email, password(new_password))
+                .await
+                .unwrap();
+        }
+
+        for reused in ["password-1", "password-2", "password-3"] {
+            let result = user_store.update_password(&email, password(reused)).await;
+            assert_eq!(result, Err(UserStoreError::PasswordReused));
+        }
+
+        // Once a password falls out of the last three it may be used again
+        user_store
+            .update_password(&email, password("password-4"))
+            .await
+            .unwrap();
+        let result = user_store
+            .update_password(&email, password("password-1"))
+            .await;
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_update_password_clears_must_change_password() {
+        let email = Email::parse(Secret::new("test@mail.com".to_string())).expect("Valid email");
+        let mut user_store = HashmapUserStore::new();
+
+        user_store
+            .add_user(create_test_user("test@mail.com", "password123"))
+            .await
+            .unwrap();
+        user_store
+            .set_must_change_password(&email, true)
+            .await
+            .unwrap();
+        assert!(
+            user_store
+                .get_user(&email)
+                .await
+                .unwrap()
+                .must_change_password
+        );
+
+        let new_password =
+            Password::parse(Secret::new("newpassword123".to_string())).expect("Valid password");
+        user_store
+            .update_password(&email, new_password)
+            .await
+            .unwrap();
+        assert!(
+            !user_store
+                .get_user(&email)
+                .await
+                .unwrap()
+                .must_change_password
+        );
+    }
+
+    #[tokio::test]
+    async fn test_set_status_records_history() {
+        let email = Email::parse(Secret::new("test@mail.com".to_string())).expect("Valid email");
+        let mut user_store = HashmapUserStore::new();
+
+        let result = user_store
+            .set_status(&email, AccountStatus::Suspended, "abuse".to_owned())
+            .await;
+        assert_eq!(result, Err(UserStoreError::UserNotFound));
+
+        user_store
+            .add_user(create_test_user("test@mail.com", "password123"))
+            .await
+            .unwrap();
+        user_store
+            .set_status(&email, AccountStatus::Suspended, "abuse".to_owned())
+            .await
+            .unwrap();
+        user_store
+            .set_status(&email, AccountStatus::Active, "appeal accepted".to_owned())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            user_store.get_user(&email).await.unwrap().status,
+            AccountStatus::Active
+        );
+
+        let history = user_store.get_status_history(&email).await.unwrap();
+        let history: Vec<_> = history
+            .iter()
+            .map(|change| (change.status, change.reason.as_str()))
+            .collect();
+        assert_eq!(
+            history,
+            vec![
+                (AccountStatus::Active, "appeal accepted"),
+                (AccountStatus::Suspended, "abuse"),
+            ]
+        );
+    }
+
     #[tokio::test]
     async fn test_validate_user() {
         let email = Email::parse(Secret::new("test@mail.com".to_string())).expect("Valid email");
@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, Utc};
+
+use crate::domain::{
+    data_store::{WebAuthnCeremonyStore, WebAuthnCeremonyStoreError},
+    webauthn::{WebAuthnCeremony, WebAuthnChallenge},
+};
+
+#[derive(Default)]
+pub struct HashMapWebAuthnCeremonyStore {
+    ceremonies: HashMap<WebAuthnChallenge, (WebAuthnCeremony, DateTime<Utc>)>,
+}
+
+impl HashMapWebAuthnCeremonyStore {
+    pub fn new() -> Self {
+        Self {
+            ceremonies: HashMap::new(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl WebAuthnCeremonyStore for HashMapWebAuthnCeremonyStore {
+    async fn add_ceremony(
+        &mut self,
+        ceremony: WebAuthnCeremony,
+        ttl: Duration,
+    ) -> Result<(), WebAuthnCeremonyStoreError> {
+        // Drops ceremonies that were never finished
+        let now = Utc::now();
+        self.ceremonies
+            .retain(|_, (_, expires_at)| *expires_at > now);
+
+        self.ceremonies
+            .insert(ceremony.challenge.clone(), (ceremony, now + ttl));
+        Ok(())
+    }
+
+    async fn take_ceremony(
+        &mut self,
+        challenge: &WebAuthnChallenge,
+    ) -> Result<WebAuthnCeremony, WebAuthnCeremonyStoreError> {
+        match self.ceremonies.remove(challenge) {
+            Some((ceremony, expires_at)) if expires_at > Utc::now() => Ok(ceremony),
+            _ => Err(WebAuthnCeremonyStoreError::CeremonyNotFound),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::{webauthn::CeremonyPurpose, Email};
+    use secrecy::Secret;
+
+    fn ceremony() -> WebAuthnCeremony {
+        WebAuthnCeremony {
+            challenge: WebAuthnChallenge::default(),
+            purpose: CeremonyPurpose::Registration {
+                email: Email::parse(Secret::new("test@example.com".to_owned())).unwrap(),
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn ceremonies_can_only_be_taken_once() {
+        let mut store = HashMapWebAuthnCeremonyStore::new();
+        let ceremony = ceremony();
+        store
+            .add_ceremony(ceremony.clone(), Duration::minutes(5))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            store.take_ceremony(&ceremony.challenge).await,
+            Ok(ceremony.clone())
+        );
+        assert_eq!(
+            store.take_ceremony(&ceremony.challenge).await,
+            Err(WebAuthnCeremonyStoreError::CeremonyNotFound)
+        );
+    }
+
+    #[tokio::test]
+    async fn expired_ceremonies_are_not_returned() {
+        let mut store = HashMapWebAuthnCeremonyStore::new();
+        let ceremony = ceremony();
+        store
+            .add_ceremony(ceremony.clone(), Duration::seconds(-1))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            store.take_ceremony(&ceremony.challenge).await,
+            Err(WebAuthnCeremonyStoreError::CeremonyNotFound)
+        );
+    }
+}
@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+
+use crate::domain::{
+    data_store::{WebAuthnCredentialStore, WebAuthnCredentialStoreError},
+    webauthn::{CredentialId, WebAuthnCredential},
+    Email,
+};
+
+#[derive(Default)]
+pub struct HashMapWebAuthnCredentialStore {
+    credentials: HashMap<CredentialId, (Email, WebAuthnCredential)>,
+}
+
+impl HashMapWebAuthnCredentialStore {
+    pub fn new() -> Self {
+        Self {
+            credentials: HashMap::new(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl WebAuthnCredentialStore for HashMapWebAuthnCredentialStore {
+    async fn add_credential(
+        &mut self,
+        email: &Email,
+        credential: WebAuthnCredential,
+    ) -> Result<(), WebAuthnCredentialStoreError> {
+        if self.credentials.contains_key(&credential.id) {
+            return Err(WebAuthnCredentialStoreError::CredentialAlreadyExists);
+        }
+        self.credentials
+            .insert(credential.id.clone(), (email.clone(), credential));
+        Ok(())
+    }
+
+    async fn get_credentials(
+        &self,
+        email: &Email,
+    ) -> Result<Vec<WebAuthnCredential>, WebAuthnCredentialStoreError> {
+        Ok(self
+            .credentials
+            .values()
+            .filter(|(owner, _)| owner == email)
+            .map(|(_, credential)| credential.clone())
+            .collect())
+    }
+
+    async fn get_credential(
+        &self,
+        id: &CredentialId,
+    ) -> Result<(Email, WebAuthnCredential), WebAuthnCredentialStoreError> {
+        self.credentials
+            .get(id)
+            .cloned()
+            .ok_or(WebAuthnCredentialStoreError::CredentialNotFound)
+    }
+
+    async fn update_sign_count(
+        &mut self,
+        id: &CredentialId,
+        previous: u32,
+        new: u32,
+    ) -> Result<(), WebAuthnCredentialStoreError> {
+        let (_, credential) = self
+            .credentials
+            .get_mut(id)
+            .ok_or(WebAuthnCredentialStoreError::CredentialNotFound)?;
+        if credential.sign_count != previous {
+            return Err(WebAuthnCredentialStoreError::SignCountChanged);
+        }
+        credential.sign_count = new;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use secrecy::Secret;
+
+    fn email() -> Email {
+        Email::parse(Secret::new("test@example.com".to_owned())).unwrap()
+    }
+
+    fn credential() -> WebAuthnCredential {
+        WebAuthnCredential {
+            id: CredentialId::parse("Y3JlZGVudGlhbA".to_owned()).unwrap(),
+            public_key: vec![4; 65],
+            sign_count: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn credentials_are_found_by_owner_and_id() {
+        let mut store = HashMapWebAuthnCredentialStore::new();
+        store.add_credential(&email(), credential()).await.unwrap();
+
+        assert_eq!(
+            store.add_credential(&email(), credential()).await,
+            Err(WebAuthnCredentialStoreError::CredentialAlreadyExists)
+        );
+        assert_eq!(
+            store.get_credentials(&email()).await.unwrap(),
+            vec![credential()]
+        );
+        assert_eq!(
+            store.get_credential(&credential().id).await.unwrap(),
+            (email(), credential())
+        );
+    }
+
+    #[tokio::test]
+    async fn sign_count_only_moves_from_the_expected_value() {
+        let mut store = HashMapWebAuthnCredentialStore::new();
+        store.add_credential(&email(), credential()).await.unwrap();
+
+        store
+            .update_sign_count(&credential().id, 0, 5)
+            .await
+            .unwrap();
+        assert_eq!(
+            store.update_sign_count(&credential().id, 0, 6).await,
+            Err(WebAuthnCredentialStoreError::SignCountChanged)
+        );
+        let (_, stored) = store.get_credential(&credential().id).await.unwrap();
+        assert_eq!(stored.sign_count, 5);
+    }
+}
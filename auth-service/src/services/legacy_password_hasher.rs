@@ -0,0 +1,219 @@
+use argon2::password_hash::{PasswordHash, PasswordVerifier};
+use color_eyre::eyre::{eyre, Context, Result};
+use pbkdf2::Pbkdf2;
+use scrypt::Scrypt;
+use secrecy::{ExposeSecret, Secret};
+
+use crate::domain::PasswordHasher;
+
+// Verifies hashes imported from systems that predate Argon2 (bcrypt, PBKDF2-SHA256 and
+// scrypt) on top of the Argon2 hashes we produce ourselves. New hashes are always computed
+// by the wrapped hasher, and every legacy hash is reported as needing a rehash so it is
+// upgraded on the next successful login.
+pub struct LegacyPasswordHasher<H> {
+    inner: H,
+}
+
+impl<H> LegacyPasswordHasher<H> {
+    pub fn new(inner: H) -> Self {
+        Self { inner }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum HashFormat {
+    Argon2,
+    Bcrypt,
+    Pbkdf2Sha256,
+    Scrypt,
+    Unknown,
+}
+
+fn hash_format(password_hash: &str) -> HashFormat {
+    if password_hash.starts_with("$argon2") {
+        HashFormat::Argon2
+    } else if ["$2a$", "$2b$", "$2x$", "$2y$"]
+        .iter()
+        .any(|prefix| password_hash.starts_with(prefix))
+    {
+        HashFormat::Bcrypt
+    } else if password_hash.starts_with("$pbkdf2-sha256$") {
+        HashFormat::Pbkdf2Sha256
+    } else if password_hash.starts_with("$scrypt$") {
+        HashFormat::Scrypt
+    } else {
+        HashFormat::Unknown
+    }
+}
+
+#[async_trait::async_trait]
+impl<H> PasswordHasher for LegacyPasswordHasher<H>
+where
+    H: PasswordHasher + Send + Sync,
+{
+    async fn hash(&self, password: Secret<String>) -> Result<Secret<String>> {
+        self.inner.hash(password).await
+    }
+
+    #[tracing::instrument(name = "Verify legacy password hash", skip_all)]
+    async fn verify(
+        &self,
+        expected_password_hash: Secret<String>,
+        password_candidate: Secret<String>,
+    ) -> Result<()> {
+        let format = hash_format(expected_password_hash.expose_secret());
+        if format == HashFormat::Argon2 {
+            return self
+                .inner
+                .verify(expected_password_hash, password_candidate)
+                .await;
+        }
+
+        let current_span: tracing::Span = tracing::Span::current();
+        let result = tokio::task::spawn_blocking(move || {
+            current_span.in_scope(|| {
+                let expected_password_hash = expected_password_hash.expose_secret();
+                let password_candidate = password_candidate.expose_secret().as_bytes();
+
+                match format {
+                    HashFormat::Bcrypt => {
+                        if bcrypt::verify(password_candidate, expected_password_hash)
+                            .wrap_err("failed to verify bcrypt password")?
+                        {
+                            Ok(())
+                        } else {
+                            Err(eyre!("failed to verify password"))
+                        }
+                    }
+                    HashFormat::Pbkdf2Sha256 => Pbkdf2
+                        .verify_password(
+                            password_candidate,
+                            &PasswordHash::new(expected_password_hash)?,
+                        )
+                        .wrap_err("failed to verify password"),
+                    HashFormat::Scrypt => Scrypt
+                        .verify_password(
+                            password_candidate,
+                            &PasswordHash::new(expected_password_hash)?,
+                        )
+                        .wrap_err("failed to verify password"),
+                    HashFormat::Argon2 | HashFormat::Unknown => {
+                        Err(eyre!("unsupported password hash format"))
+                    }
+                }
+            })
+        })
+        .await;
+
+        result?
+    }
+
+    fn needs_rehash(&self, password_hash: &Secret<String>) -> bool {
+        match hash_format(password_hash.expose_secret()) {
+            HashFormat::Argon2 => self.inner.needs_rehash(password_hash),
+            _ => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::argon2_password_hasher::Argon2PasswordHasher;
+    use argon2::password_hash::{PasswordHasher as _, SaltString};
+    use argon2::Params;
+    use rand_core::OsRng;
+
+    const PASSWORD: &str = "password123";
+
+    fn hasher() -> LegacyPasswordHasher<Argon2PasswordHasher> {
+        LegacyPasswordHasher::new(Argon2PasswordHasher::new(
+            Params::new(64, 1, 1, None).unwrap(),
+        ))
+    }
+
+    fn password(password: &str) -> Secret<String> {
+        Secret::new(password.to_owned())
+    }
+
+    async fn assert_verifies(hash: String) {
+        let hasher = hasher();
+        let hash = Secret::new(hash);
+
+        assert!(hasher
+            .verify(hash.clone(), password(PASSWORD))
+            .await
+            .is_ok());
+        assert!(hasher
+            .verify(hash.clone(), password("wrongpassword"))
+            .await
+            .is_err());
+        assert!(hasher.needs_rehash(&hash));
+    }
+
+    #[tokio::test]
+    async fn verifies_bcrypt_hashes() {
+        assert_verifies(bcrypt::hash(PASSWORD, 4).unwrap()).await;
+    }
+
+    #[tokio::test]
+    async fn verifies_pbkdf2_sha256_hashes() {
+        let salt = SaltString::generate(&mut OsRng);
+        let hash = Pbkdf2
+            .hash_password_customized(
+                PASSWORD.as_bytes(),
+                Some(pbkdf2::Algorithm::Pbkdf2Sha256.ident()),
+                None,
+                pbkdf2::Params {
+                    rounds: 1_000,
+                    output_length: 32,
+                },
+                &salt,
+            )
+            .unwrap()
+            .to_string();
+
+        assert!(hash.starts_with("$pbkdf2-sha256$"));
+        assert_verifies(hash).await;
+    }
+
+    #[tokio::test]
+    async fn verifies_scrypt_hashes() {
+        let salt = SaltString::generate(&mut OsRng);
+        let hash = Scrypt
+            .hash_password_customized(
+                PASSWORD.as_bytes(),
+                None,
+                None,
+                scrypt::Params::new(4, 8, 1, 32).unwrap(),
+                &salt,
+            )
+            .unwrap()
+            .to_string();
+
+        assert_verifies(hash).await;
+    }
+
+    #[tokio::test]
+    async fn new_hashes_are_argon2_and_do_not_need_rehash() {
+        let hasher = hasher();
+        let hash = hasher.hash(password(PASSWORD)).await.unwrap();
+
+        assert!(hash.expose_secret().starts_with("$argon2id$"));
+        assert!(hasher
+            .verify(hash.clone(), password(PASSWORD))
+            .await
+            .is_ok());
+        assert!(!hasher.needs_rehash(&hash));
+    }
+
+    #[tokio::test]
+    async fn unknown_formats_are_rejected() {
+        let hasher = hasher();
+        let result = hasher
+            .verify(password("md5$abcdef"), password(PASSWORD))
+            .await;
+
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,18 @@
+use crate::domain::{phone_number::PhoneNumber, SmsClient};
+use color_eyre::eyre::Result;
+use secrecy::ExposeSecret;
+
+pub struct MockSmsClient;
+
+#[async_trait::async_trait]
+impl SmsClient for MockSmsClient {
+    async fn send_sms(&self, recipient: &PhoneNumber, content: &str) -> Result<()> {
+        tracing::debug!(
+            "Sending SMS to {} with content: {}",
+            recipient.as_ref().expose_secret(),
+            content
+        );
+
+        Ok(())
+    }
+}
@@ -1,6 +1,22 @@
+pub mod argon2_password_hasher;
 pub mod data_stores;
+pub mod email_outbox_worker;
+pub mod hashmap_email_outbox_store;
+pub mod hashmap_hotp_store;
+pub mod hashmap_invite_store;
+pub mod hashmap_login_attempt_store;
+pub mod hashmap_phone_store;
+pub mod hashmap_recovery_code_store;
+pub mod hashmap_totp_store;
+pub mod hashmap_trusted_device_store;
 pub mod hashmap_two_fa_code_store;
 pub mod hashmap_user_store;
+pub mod hashmap_webauthn_ceremony_store;
+pub mod hashmap_webauthn_credential_store;
 pub mod hashset_banned_token_store;
+pub mod legacy_password_hasher;
 pub mod mock_email_client;
+pub mod mock_sms_client;
 pub mod postmark_email_client;
+pub mod smtp_email_client;
+pub mod twilio_sms_client;
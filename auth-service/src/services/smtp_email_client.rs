@@ -0,0 +1,339 @@
+use color_eyre::eyre::{eyre, Report, Result};
+use lettre::message::{Mailbox, MultiPart};
+use lettre::transport::smtp::{authentication::Credentials, PoolConfig};
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use secrecy::{ExposeSecret, Secret};
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::domain::{Email, EmailClient, EmailMessage};
+
+// How the connection to the SMTP server is secured
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SmtpTls {
+    // Plain connection upgraded with STARTTLS. Servers that don't offer it are refused, so
+    // credentials and emails are never sent in the clear.
+    #[default]
+    StartTls,
+    // TLS from the start, usually on port 465
+    Implicit,
+    // No encryption, only for relays on the same host or network
+    None,
+}
+
+impl SmtpTls {
+    pub fn default_port(&self) -> u16 {
+        match self {
+            SmtpTls::StartTls => 587,
+            SmtpTls::Implicit => 465,
+            SmtpTls::None => 25,
+        }
+    }
+}
+
+impl FromStr for SmtpTls {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "starttls" => Ok(Self::StartTls),
+            "tls" => Ok(Self::Implicit),
+            "none" => Ok(Self::None),
+            _ => Err(eyre!("Unknown SMTP TLS mode: {}", s)),
+        }
+    }
+}
+
+pub struct SmtpConfig {
+    pub host: String,
+    pub port: u16,
+    pub tls: SmtpTls,
+    // Username and password to log in with AUTH; relays that trust the network need none
+    pub credentials: Option<(String, Secret<String>)>,
+    // Connections kept open for reuse
+    pub pool_size: u32,
+    // Limit for sending one email, connecting included
+    pub timeout: Duration,
+}
+
+pub struct SmtpEmailClient {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    sender: Mailbox,
+    timeout: Duration,
+}
+
+impl SmtpEmailClient {
+    pub fn new(config: SmtpConfig, sender: Email) -> Result<Self> {
+        let builder = match config.tls {
+            SmtpTls::StartTls => {
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)?
+            }
+            SmtpTls::Implicit => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host)?,
+            SmtpTls::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.host),
+        };
+        let mut builder = builder
+            .port(config.port)
+            .timeout(Some(config.timeout))
+            .pool_config(PoolConfig::new().max_size(config.pool_size));
+        if let Some((username, password)) = config.credentials {
+            builder = builder.credentials(Credentials::new(
+                username,
+                password.expose_secret().to_owned(),
+            ));
+        }
+
+        Ok(Self {
+            transport: builder.build(),
+            sender: mailbox(&sender)?,
+            timeout: config.timeout,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailClient for SmtpEmailClient {
+    #[tracing::instrument(name = "Sending email over SMTP", skip_all)]
+    async fn send_email(&self, recipient: &Email, message: &EmailMessage) -> Result<()> {
+        let email = Message::builder()
+            .from(self.sender.clone())
+            .to(mailbox(recipient)?)
+            .subject(&message.subject)
+            .multipart(MultiPart::alternative_plain_html(
+                message.text_body.clone(),
+                message.html_body.clone(),
+            ))?;
+
+        // lettre only limits how long connecting may take, so a server that stops answering
+        // halfway through would otherwise hold the request up indefinitely
+        tokio::time::timeout(self.timeout, self.transport.send(email))
+            .await
+            .map_err(|_| eyre!("Timed out sending email over SMTP"))??;
+
+        Ok(())
+    }
+}
+
+fn mailbox(email: &Email) -> Result<Mailbox> {
+    email
+        .as_ref()
+        .expose_secret()
+        .parse()
+        .map_err(|e| eyre!("Invalid email address: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::constants::test;
+    use std::net::SocketAddr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::{TcpListener, TcpStream};
+
+    #[derive(Clone, Copy, PartialEq)]
+    enum Behaviour {
+        Accept,
+        RejectRecipients,
+        // Accepts connections but never greets the client
+        Silent,
+    }
+
+    // Just enough of an SMTP server to receive mail from the client, recording the commands
+    // and messages it was sent
+    #[derive(Clone)]
+    struct SmtpStandIn {
+        address: SocketAddr,
+        connections: Arc<AtomicUsize>,
+        commands: Arc<Mutex<Vec<String>>>,
+        messages: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl SmtpStandIn {
+        async fn start(behaviour: Behaviour) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let stand_in = Self {
+                address: listener.local_addr().unwrap(),
+                connections: Arc::default(),
+                commands: Arc::default(),
+                messages: Arc::default(),
+            };
+
+            let server = stand_in.clone();
+            tokio::spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    server.connections.fetch_add(1, Ordering::SeqCst);
+                    tokio::spawn(server.clone().serve(stream, behaviour));
+                }
+            });
+            stand_in
+        }
+
+        async fn serve(self, stream: TcpStream, behaviour: Behaviour) {
+            if behaviour == Behaviour::Silent {
+                tokio::time::sleep(Duration::from_secs(180)).await;
+                return;
+            }
+
+            let (reader, mut writer) = stream.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            let _ = writer.write_all(b"220 stand-in ESMTP\r\n").await;
+
+            while let Ok(Some(line)) = lines.next_line().await {
+                self.commands.lock().unwrap().push(line.clone());
+                let verb = line
+                    .split(' ')
+                    .next()
+                    .unwrap_or_default()
+                    .to_ascii_uppercase();
+                let reply: &[u8] = match verb.as_str() {
+                    "EHLO" => b"250-stand-in\r\n250-AUTH PLAIN LOGIN\r\n250 8BITMIME\r\n",
+                    "AUTH" => b"235 2.7.0 Authentication successful\r\n",
+                    "RCPT" if behaviour == Behaviour::RejectRecipients => {
+                        b"550 5.1.1 No such user\r\n"
+                    }
+                    "MAIL" | "RCPT" | "RSET" | "NOOP" => b"250 OK\r\n",
+                    "DATA" => {
+                        let _ = writer
+                            .write_all(b"354 End data with <CR><LF>.<CR><LF>\r\n")
+                            .await;
+                        let mut message = Vec::new();
+                        while let Ok(Some(line)) = lines.next_line().await {
+                            if line == "." {
+                                break;
+                            }
+                            message.push(line);
+                        }
+                        self.messages.lock().unwrap().push(message.join("\n"));
+                        b"250 OK\r\n"
+                    }
+                    "QUIT" => {
+                        let _ = writer.write_all(b"221 Bye\r\n").await;
+                        return;
+                    }
+                    _ => b"500 Unknown command\r\n",
+                };
+                let _ = writer.write_all(reply).await;
+            }
+        }
+
+        fn client(&self, tls: SmtpTls) -> SmtpEmailClient {
+            let config = SmtpConfig {
+                host: self.address.ip().to_string(),
+                port: self.address.port(),
+                tls,
+                credentials: Some(("mailer".to_owned(), Secret::new("hunter22".to_owned()))),
+                pool_size: 2,
+                timeout: test::email_client::TIMEOUT,
+            };
+            let sender = Email::parse(Secret::new(test::email_client::SENDER.to_owned())).unwrap();
+            SmtpEmailClient::new(config, sender).unwrap()
+        }
+
+        fn commands_starting_with(&self, verb: &str) -> Vec<String> {
+            self.commands
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|command| command.starts_with(verb))
+                .cloned()
+                .collect()
+        }
+    }
+
+    fn recipient() -> Email {
+        Email::parse(Secret::new("someone@example.com".to_owned())).unwrap()
+    }
+
+    fn message() -> EmailMessage {
+        EmailMessage {
+            subject: "Your login code".to_owned(),
+            html_body: "<p>Your login code is: <strong>123456</strong></p>".to_owned(),
+            text_body: "Your login code is: 123456".to_owned(),
+        }
+    }
+
+    #[tokio::test]
+    async fn send_email_delivers_text_and_html_alternatives() {
+        let server = SmtpStandIn::start(Behaviour::Accept).await;
+        let client = server.client(SmtpTls::None);
+
+        client.send_email(&recipient(), &message()).await.unwrap();
+
+        assert_eq!(server.commands_starting_with("AUTH PLAIN").len(), 1);
+        assert_eq!(
+            server.commands_starting_with("MAIL FROM"),
+            [format!("MAIL FROM:<{}>", test::email_client::SENDER)]
+        );
+        assert_eq!(
+            server.commands_starting_with("RCPT TO"),
+            ["RCPT TO:<someone@example.com>"]
+        );
+
+        let messages = server.messages.lock().unwrap();
+        let data = &messages[0];
+        assert!(data.contains("Subject: Your login code"));
+        assert!(data.contains("multipart/alternative"));
+        assert!(data.contains("Content-Type: text/plain"));
+        assert!(data.contains("Your login code is: 123456"));
+        assert!(data.contains("Content-Type: text/html"));
+        assert!(data.contains("<strong>123456</strong>"));
+    }
+
+    #[tokio::test]
+    async fn connections_are_reused() {
+        let server = SmtpStandIn::start(Behaviour::Accept).await;
+        let client = server.client(SmtpTls::None);
+
+        for _ in 0..3 {
+            client.send_email(&recipient(), &message()).await.unwrap();
+            // Connections go back to the pool in the background
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        assert_eq!(server.messages.lock().unwrap().len(), 3);
+        assert_eq!(server.connections.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_if_the_server_rejects_the_recipient() {
+        let server = SmtpStandIn::start(Behaviour::RejectRecipients).await;
+        let client = server.client(SmtpTls::None);
+
+        assert!(client.send_email(&recipient(), &message()).await.is_err());
+        assert!(server.messages.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn nothing_is_sent_when_starttls_is_not_offered() {
+        let server = SmtpStandIn::start(Behaviour::Accept).await;
+        let client = server.client(SmtpTls::StartTls);
+
+        assert!(client.send_email(&recipient(), &message()).await.is_err());
+        assert!(server.commands_starting_with("AUTH").is_empty());
+        assert!(server.commands_starting_with("MAIL").is_empty());
+    }
+
+    #[tokio::test]
+    async fn send_email_times_out_if_the_server_takes_too_long() {
+        let server = SmtpStandIn::start(Behaviour::Silent).await;
+        let client = server.client(SmtpTls::None);
+
+        let outcome = tokio::time::timeout(
+            Duration::from_secs(5),
+            client.send_email(&recipient(), &message()),
+        )
+        .await
+        .expect("The client did not give up on its own");
+
+        assert!(outcome.is_err());
+    }
+
+    #[test]
+    fn tls_mode_parses_from_env_values() {
+        assert_eq!("starttls".parse::<SmtpTls>().unwrap(), SmtpTls::StartTls);
+        assert_eq!("tls".parse::<SmtpTls>().unwrap(), SmtpTls::Implicit);
+        assert_eq!("none".parse::<SmtpTls>().unwrap(), SmtpTls::None);
+        assert!("ssl".parse::<SmtpTls>().is_err());
+    }
+}
@@ -0,0 +1,175 @@
+use color_eyre::eyre::Result;
+use reqwest::{Client, Url};
+use secrecy::{ExposeSecret, Secret};
+
+use crate::domain::{phone_number::PhoneNumber, SmsClient};
+
+pub struct TwilioSmsClient {
+    http_client: Client,
+    base_url: String,
+    account_sid: String,
+    auth_token: Secret<String>,
+    sender: PhoneNumber,
+}
+
+impl TwilioSmsClient {
+    pub fn new(
+        base_url: String,
+        account_sid: String,
+        auth_token: Secret<String>,
+        sender: PhoneNumber,
+        http_client: Client,
+    ) -> Self {
+        Self {
+            http_client,
+            base_url,
+            account_sid,
+            auth_token,
+            sender,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl SmsClient for TwilioSmsClient {
+    #[tracing::instrument(name = "Sending SMS", skip_all)]
+    async fn send_sms(&self, recipient: &PhoneNumber, content: &str) -> Result<()> {
+        let base = Url::parse(&self.base_url)?;
+        let url = base.join(&format!(
+            "/2010-04-01/Accounts/{}/Messages.json",
+            self.account_sid
+        ))?;
+
+        // Twilio takes form-encoded parameters rather than JSON
+        // For more information see the API docs: https://www.twilio.com/docs/messaging/api/message-resource#create-a-message-resource
+        let request_body = SendSmsRequest {
+            from: self.sender.as_ref().expose_secret(),
+            to: recipient.as_ref().expose_secret(),
+            body: content,
+        };
+
+        self.http_client
+            .post(url)
+            .basic_auth(&self.account_sid, Some(self.auth_token.expose_secret()))
+            .form(&request_body)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+}
+
+#[derive(serde::Serialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct SendSmsRequest<'a> {
+    from: &'a str,
+    to: &'a str,
+    body: &'a str,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::constants::test;
+
+    use super::*;
+    use fake::faker::lorem::en::Sentence;
+    use fake::{Fake, Faker};
+    use wiremock::matchers::{any, header, header_exists, method, path};
+    use wiremock::{Mock, MockServer, Request, ResponseTemplate};
+
+    fn content() -> String {
+        Sentence(1..5).fake()
+    }
+
+    fn phone_number() -> PhoneNumber {
+        let number = format!("+4477009{:05}", (0..100_000).fake::<u32>());
+        PhoneNumber::parse(Secret::new(number)).unwrap()
+    }
+
+    fn sms_client(base_url: String) -> TwilioSmsClient {
+        let http_client = Client::builder()
+            .timeout(test::sms_client::TIMEOUT)
+            .build()
+            .unwrap();
+        TwilioSmsClient::new(
+            base_url,
+            test::sms_client::ACCOUNT_SID.to_owned(),
+            Secret::new(Faker.fake()),
+            phone_number(),
+            http_client,
+        )
+    }
+
+    // Checks the form body carries the fields Twilio requires
+    struct SendSmsBodyMatcher;
+
+    impl wiremock::Match for SendSmsBodyMatcher {
+        fn matches(&self, request: &Request) -> bool {
+            let body = String::from_utf8_lossy(&request.body);
+            let fields: Vec<&str> = body
+                .split('&')
+                .filter_map(|pair| pair.split('=').next())
+                .collect();
+            ["From", "To", "Body"]
+                .iter()
+                .all(|field| fields.contains(field))
+        }
+    }
+
+    #[tokio::test]
+    async fn send_sms_sends_the_expected_request() {
+        let mock_server = MockServer::start().await;
+        let sms_client = sms_client(mock_server.uri());
+
+        Mock::given(header_exists("Authorization"))
+            .and(header("Content-Type", "application/x-www-form-urlencoded"))
+            .and(path(format!(
+                "/2010-04-01/Accounts/{}/Messages.json",
+                test::sms_client::ACCOUNT_SID
+            )))
+            .and(method("POST"))
+            .and(SendSmsBodyMatcher)
+            .respond_with(ResponseTemplate::new(201))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = sms_client.send_sms(&phone_number(), &content()).await;
+
+        assert!(outcome.is_ok());
+    }
+
+    #[tokio::test]
+    async fn send_sms_fails_if_the_server_returns_500() {
+        let mock_server = MockServer::start().await;
+        let sms_client = sms_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = sms_client.send_sms(&phone_number(), &content()).await;
+
+        assert!(outcome.is_err());
+    }
+
+    #[tokio::test]
+    async fn send_sms_times_out_if_the_server_takes_too_long() {
+        let mock_server = MockServer::start().await;
+        let sms_client = sms_client(mock_server.uri());
+
+        let response = ResponseTemplate::new(201).set_delay(std::time::Duration::from_secs(180));
+        Mock::given(any())
+            .respond_with(response)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = sms_client.send_sms(&phone_number(), &content()).await;
+
+        assert!(outcome.is_err());
+    }
+}
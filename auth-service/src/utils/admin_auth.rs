@@ -0,0 +1,38 @@
+use crate::app_state::app_state::AppState;
+use crate::domain::error::AuthAPIError;
+use axum::{async_trait, extract::FromRequestParts, http::header, http::request::Parts};
+use secrecy::ExposeSecret;
+use subtle::ConstantTimeEq;
+
+// Extractor guarding the /admin routes. Requests must carry the configured admin API token
+// as `Authorization: Bearer <token>`.
+pub struct AdminAuth;
+
+#[async_trait]
+impl FromRequestParts<AppState> for AdminAuth {
+    type Rejection = AuthAPIError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let token = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or(AuthAPIError::MissingToken)?;
+
+        let expected = state
+            .settings
+            .admin_api_token
+            .as_ref()
+            .ok_or(AuthAPIError::InvalidToken)?;
+
+        if bool::from(token.as_bytes().ct_eq(expected.expose_secret().as_bytes())) {
+            Ok(AdminAuth)
+        } else {
+            Err(AuthAPIError::InvalidToken)
+        }
+    }
+}
@@ -1,6 +1,8 @@
-use super::constants::{JWT_COOKIE_NAME, JWT_SECRET};
+use super::constants::{JWT_COOKIE_NAME, JWT_SECRET, TRUSTED_DEVICE_COOKIE_NAME};
 use crate::app_state::app_state::TokenStore;
-use crate::domain::data_store::BannedTokenStore;
+use crate::domain::authentication::{AuthLevel, AuthMethod};
+use crate::domain::data_store::LoginAttemptId;
+use crate::domain::trusted_device::TrustedDeviceId;
 use crate::domain::Email;
 use axum_extra::extract::cookie::{Cookie, SameSite};
 use chrono::Utc;
@@ -9,10 +11,16 @@ use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Validation};
 use secrecy::{ExposeSecret, Secret};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
+use uuid::Uuid;
 
+// `methods` records how the user logged in, so that sensitive operations can ask for a recent,
+// strong enough login
 #[tracing::instrument(name = "Generate Auth Cookie", skip_all)]
-pub fn generate_auth_cookie(email: &Email) -> Result<Cookie<'static>, GenerateTokenError> {
-    let token = generate_auth_token(email)?;
+pub fn generate_auth_cookie(
+    email: &Email,
+    methods: &[AuthMethod],
+) -> Result<Cookie<'static>, GenerateTokenError> {
+    let token = generate_auth_token(email, methods)?;
     Ok(create_auth_cookie(token))
 }
 
@@ -29,6 +37,30 @@ fn create_auth_cookie(token: String) -> Cookie<'static> {
     cookie
 }
 
+// The device cookie outlives the browser session so the device is still recognised at the next
+// login
+#[tracing::instrument(name = "Generate Trusted Device Cookie", skip_all)]
+pub fn generate_trusted_device_cookie(
+    email: &Email,
+    device_id: &TrustedDeviceId,
+) -> Result<Cookie<'static>, GenerateTokenError> {
+    let purpose = TokenPurpose::TrustedDevice;
+    let token = generate_token(
+        email,
+        purpose.ttl_seconds(),
+        Some(purpose),
+        Some(device_id.to_string()),
+        &[],
+    )?;
+
+    Ok(Cookie::build((TRUSTED_DEVICE_COOKIE_NAME, token))
+        .path("/")
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .max_age(time::Duration::seconds(TRUSTED_DEVICE_TOKEN_TTL_SECONDS))
+        .build())
+}
+
 #[derive(Debug, Error)]
 pub enum GenerateTokenError {
     #[error("Token Error")]
@@ -39,20 +71,59 @@ pub enum GenerateTokenError {
 
 // This value determines how long the JWT auth token is valid for
 pub const TOKEN_TTL_SECONDS: i64 = 600; // 10 minutes
+pub const PASSWORD_CHANGE_TOKEN_TTL_SECONDS: i64 = 300; // 5 minutes
+pub const TRUSTED_DEVICE_TOKEN_TTL_SECONDS: i64 = 60 * 60 * 24 * 30; // 30 days
 
 // Create JWT auth token
 
 #[tracing::instrument(name = "Generate Auth Token", skip_all)]
-fn generate_auth_token(email: &Email) -> Result<String, GenerateTokenError> {
-    let delta = chrono::Duration::try_seconds(TOKEN_TTL_SECONDS).ok_or(
-        GenerateTokenError::UnexpectedError(eyre!(
-            "Failed to create duration from {:?} seconds",
-            TOKEN_TTL_SECONDS
-        )),
-    )?;
+fn generate_auth_token(
+    email: &Email,
+    methods: &[AuthMethod],
+) -> Result<String, GenerateTokenError> {
+    generate_token(email, TOKEN_TTL_SECONDS, None, None, methods)
+}
+
+// Create a JWT that is only accepted for the given purpose, never as an auth token
+#[tracing::instrument(name = "Generate Purpose Token", skip_all)]
+pub fn generate_purpose_token(
+    email: &Email,
+    purpose: TokenPurpose,
+) -> Result<String, GenerateTokenError> {
+    generate_token(email, purpose.ttl_seconds(), Some(purpose), None, &[])
+}
+
+// Create a single-use magic link token tied to a login attempt
+#[tracing::instrument(name = "Generate Magic Link Token", skip_all)]
+pub fn generate_magic_link_token(
+    email: &Email,
+    login_attempt_id: &LoginAttemptId,
+) -> Result<String, GenerateTokenError> {
+    let purpose = TokenPurpose::MagicLink;
+    generate_token(
+        email,
+        purpose.ttl_seconds(),
+        Some(purpose),
+        Some(login_attempt_id.as_ref().expose_secret().to_owned()),
+        &[],
+    )
+}
+
+fn generate_token(
+    email: &Email,
+    ttl_seconds: i64,
+    purpose: Option<TokenPurpose>,
+    jti: Option<String>,
+    methods: &[AuthMethod],
+) -> Result<String, GenerateTokenError> {
+    let delta =
+        chrono::Duration::try_seconds(ttl_seconds).ok_or(GenerateTokenError::UnexpectedError(
+            eyre!("Failed to create duration from {:?} seconds", ttl_seconds),
+        ))?;
 
     // Create JWT expiration time
-    let exp = Utc::now()
+    let now = Utc::now();
+    let exp = now
         .checked_add_signed(delta)
         .ok_or(GenerateTokenError::UnexpectedError(eyre!(
             "Failed to create JWT expiration time"
@@ -66,7 +137,27 @@ fn generate_auth_token(email: &Email) -> Result<String, GenerateTokenError> {
 
     let sub = email.as_ref().expose_secret().to_owned();
 
-    let claims = Claims { sub, exp };
+    let (acr, auth_time) = match methods {
+        [] => (None, None),
+        _ => (
+            Some(AuthLevel::from_methods(methods)),
+            Some(now.timestamp() as usize),
+        ),
+    };
+
+    // A random id keeps tokens issued for the same user within the same second apart, so that
+    // e.g. banning one at logout doesn't ban the next login's too
+    let jti = jti.unwrap_or_else(|| Uuid::new_v4().to_string());
+
+    let claims = Claims {
+        sub,
+        exp,
+        purpose,
+        jti: Some(jti),
+        amr: methods.to_vec(),
+        acr,
+        auth_time,
+    };
 
     create_token(&claims).map_err(GenerateTokenError::TokenError)
 }
@@ -75,6 +166,30 @@ fn generate_auth_token(email: &Email) -> Result<String, GenerateTokenError> {
 pub async fn validate_token(
     token: Secret<String>,
     banned_token_store: TokenStore,
+) -> Result<Claims, jsonwebtoken::errors::Error> {
+    let claims = decode_token(token, banned_token_store).await?;
+    if claims.purpose.is_some() {
+        return Err(jsonwebtoken::errors::ErrorKind::InvalidToken.into());
+    }
+    Ok(claims)
+}
+
+#[tracing::instrument(name = "Validate Purpose Token", skip_all)]
+pub async fn validate_purpose_token(
+    token: Secret<String>,
+    purpose: TokenPurpose,
+    banned_token_store: TokenStore,
+) -> Result<Claims, jsonwebtoken::errors::Error> {
+    let claims = decode_token(token, banned_token_store).await?;
+    if claims.purpose != Some(purpose) {
+        return Err(jsonwebtoken::errors::ErrorKind::InvalidToken.into());
+    }
+    Ok(claims)
+}
+
+async fn decode_token(
+    token: Secret<String>,
+    banned_token_store: TokenStore,
 ) -> Result<Claims, jsonwebtoken::errors::Error> {
     let token_store = banned_token_store.read().await;
     if token_store.check_token(&token).await.is_ok() {
@@ -103,11 +218,49 @@ fn create_token(claims: &Claims) -> Result<String, jsonwebtoken::errors::Error>
 pub struct Claims {
     pub sub: String,
     pub exp: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub purpose: Option<TokenPurpose>,
+    // Identifies the login attempt a magic link token belongs to, or the device a trusted
+    // device token was issued for. Random for other tokens.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jti: Option<String>,
+    // How, how strongly and when (Unix time) the user logged in. Only auth tokens carry these;
+    // tokens issued before they were added count as a single factor login of unknown age.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub amr: Vec<AuthMethod>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub acr: Option<AuthLevel>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth_time: Option<usize>,
+}
+
+// Restricted tokens carry a purpose and are rejected everywhere a regular auth token is
+// expected.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenPurpose {
+    PasswordReset,
+    // Handed out by login instead of an auth cookie when the password has to be changed
+    PasswordChange,
+    MagicLink,
+    TrustedDevice,
+}
+
+impl TokenPurpose {
+    fn ttl_seconds(self) -> i64 {
+        match self {
+            // Single-use tokens must not outlive their entry in the banned token store
+            TokenPurpose::PasswordReset | TokenPurpose::MagicLink => TOKEN_TTL_SECONDS,
+            TokenPurpose::PasswordChange => PASSWORD_CHANGE_TOKEN_TTL_SECONDS,
+            TokenPurpose::TrustedDevice => TRUSTED_DEVICE_TOKEN_TTL_SECONDS,
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::domain::data_store::BannedTokenStore;
     use crate::services::hashset_banned_token_store::HashsetBannedTokenStore;
     use std::sync::Arc;
     use tokio::sync::RwLock;
@@ -115,7 +268,7 @@ mod tests {
     #[tokio::test]
     async fn test_generate_auth_cookie() {
         let email = Email::parse(Secret::new("test@example.com".to_owned())).unwrap();
-        let cookie = generate_auth_cookie(&email).unwrap();
+        let cookie = generate_auth_cookie(&email, &[AuthMethod::Pwd]).unwrap();
         assert_eq!(cookie.name(), JWT_COOKIE_NAME);
         assert_eq!(cookie.value().split('.').count(), 3);
         assert_eq!(cookie.path(), Some("/"));
@@ -137,14 +290,14 @@ mod tests {
     #[tokio::test]
     async fn test_generate_auth_token() {
         let email = Email::parse(Secret::new("test@example.com".to_owned())).unwrap();
-        let result = generate_auth_token(&email).unwrap();
+        let result = generate_auth_token(&email, &[AuthMethod::Pwd]).unwrap();
         assert_eq!(result.split('.').count(), 3);
     }
 
     #[tokio::test]
     async fn test_validate_token_with_valid_token() {
         let email = Email::parse(Secret::new("test@example.com".to_owned())).unwrap();
-        let token = generate_auth_token(&email).unwrap();
+        let token = generate_auth_token(&email, &[AuthMethod::Pwd]).unwrap();
 
         // Create an empty banned token store
         let banned_token_store = Arc::new(RwLock::new(HashsetBannedTokenStore::new()));
@@ -162,6 +315,68 @@ mod tests {
         assert!(result.exp > exp as usize);
     }
 
+    #[tokio::test]
+    async fn test_auth_token_records_how_and_when_the_user_logged_in() {
+        let email = Email::parse(Secret::new("test@example.com".to_owned())).unwrap();
+        let token = generate_auth_token(&email, &[AuthMethod::Pwd, AuthMethod::Otp]).unwrap();
+        let banned_token_store = Arc::new(RwLock::new(HashsetBannedTokenStore::new()));
+
+        let claims = validate_token(Secret::new(token), banned_token_store)
+            .await
+            .unwrap();
+        assert_eq!(claims.amr, vec![AuthMethod::Pwd, AuthMethod::Otp]);
+        assert_eq!(claims.acr, Some(AuthLevel::Aal2));
+        let auth_time = claims.auth_time.unwrap() as i64;
+        assert!((Utc::now().timestamp() - auth_time).abs() <= 1);
+    }
+
+    #[tokio::test]
+    async fn test_validate_token_rejects_purpose_token() {
+        let email = Email::parse(Secret::new("test@example.com".to_owned())).unwrap();
+        let token = generate_purpose_token(&email, TokenPurpose::PasswordReset).unwrap();
+        let banned_token_store = Arc::new(RwLock::new(HashsetBannedTokenStore::new()));
+
+        let result = validate_token(Secret::new(token.clone()), banned_token_store.clone()).await;
+        assert!(result.is_err());
+
+        let result = validate_purpose_token(
+            Secret::new(token),
+            TokenPurpose::PasswordReset,
+            banned_token_store,
+        )
+        .await
+        .unwrap();
+        assert_eq!(result.sub, "test@example.com");
+    }
+
+    #[tokio::test]
+    async fn test_tokens_issued_together_differ() {
+        let email = Email::parse(Secret::new("test@example.com".to_owned())).unwrap();
+        assert_ne!(
+            generate_purpose_token(&email, TokenPurpose::PasswordReset).unwrap(),
+            generate_purpose_token(&email, TokenPurpose::PasswordReset).unwrap()
+        );
+        assert_ne!(
+            generate_auth_token(&email, &[AuthMethod::Pwd]).unwrap(),
+            generate_auth_token(&email, &[AuthMethod::Pwd]).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_validate_purpose_token_rejects_auth_token() {
+        let email = Email::parse(Secret::new("test@example.com".to_owned())).unwrap();
+        let token = generate_auth_token(&email, &[AuthMethod::Pwd]).unwrap();
+        let banned_token_store = Arc::new(RwLock::new(HashsetBannedTokenStore::new()));
+
+        let result = validate_purpose_token(
+            Secret::new(token),
+            TokenPurpose::PasswordReset,
+            banned_token_store,
+        )
+        .await;
+        assert!(result.is_err());
+    }
+
     #[tokio::test]
     async fn test_validate_token_with_invalid_token() {
         let token = Secret::new("invalid_token".to_owned());
@@ -175,7 +390,7 @@ mod tests {
     #[tokio::test]
     async fn test_validate_token_with_banned_token() {
         let email = Email::parse(Secret::new("test@example.com".to_owned())).unwrap();
-        let token = generate_auth_token(&email).unwrap();
+        let token = generate_auth_token(&email, &[AuthMethod::Pwd]).unwrap();
 
         let mut token_store = HashsetBannedTokenStore::new();
         let secret_token = Secret::new(token);
@@ -1,7 +1,13 @@
+use crate::app_state::settings::{EmailProvider, SignupMode, TwoFaCodeAlphabet, TwoFaCodeFormat};
+use crate::domain::locale::Locale;
+use crate::services::smtp_email_client::SmtpTls;
+use argon2::Params;
 use dotenvy::dotenv;
 use lazy_static::lazy_static;
 use secrecy::Secret;
 use std::env as std_env;
+use std::path::PathBuf;
+use std::str::FromStr;
 
 // Define a lazily evaluated static
 lazy_static! {
@@ -9,6 +15,55 @@ lazy_static! {
     pub static ref DATABASE_URL: Secret<String> = set_database_url();
     pub static ref REDIS_HOST_NAME: String = set_redis_host();
     pub static ref POSTMARK_AUTH_TOKEN: Secret<String> = set_postmark_auth_token();
+    pub static ref EMAIL_PROVIDER: EmailProvider = set_email_provider();
+    pub static ref SMTP_HOST: Option<String> = set_optional(env::SMTP_HOST_ENV_VAR);
+    pub static ref SMTP_PORT: Option<u16> = set_smtp_port();
+    pub static ref SMTP_TLS: SmtpTls = set_smtp_tls();
+    pub static ref SMTP_USERNAME: Option<String> = set_optional(env::SMTP_USERNAME_ENV_VAR);
+    pub static ref SMTP_PASSWORD: Option<Secret<String>> =
+        set_optional(env::SMTP_PASSWORD_ENV_VAR).map(Secret::new);
+    pub static ref SMTP_POOL_SIZE: u32 = set_smtp_pool_size();
+    pub static ref EMAIL_TEMPLATE_DIRS: Vec<PathBuf> = set_email_template_dirs();
+    pub static ref EMAIL_DEFAULT_LOCALE: Locale = set_email_default_locale();
+    pub static ref EMAIL_OUTBOX_MAX_ATTEMPTS: u32 = set_email_outbox_max_attempts();
+    pub static ref EMAIL_OUTBOX_RETRY_BASE_DELAY: chrono::Duration =
+        set_email_outbox_retry_base_delay();
+    pub static ref EMAIL_OUTBOX_RETRY_MAX_DELAY: chrono::Duration =
+        set_email_outbox_retry_max_delay();
+    pub static ref EMAIL_OUTBOX_POLL_INTERVAL: chrono::Duration = set_email_outbox_poll_interval();
+    pub static ref ARGON2_PARAMS: Params = set_argon2_params();
+    pub static ref PASSWORD_HISTORY_SIZE: usize = set_password_history_size();
+    pub static ref PASSWORD_MAX_AGE: Option<chrono::Duration> = set_password_max_age();
+    pub static ref ADMIN_API_TOKEN: Option<Secret<String>> = set_admin_api_token();
+    pub static ref LOCKOUT_MAX_FAILURES: u32 = set_lockout_max_failures();
+    pub static ref LOCKOUT_BASE_DURATION: chrono::Duration = set_lockout_base_duration();
+    pub static ref LOCKOUT_MAX_DURATION: chrono::Duration = set_lockout_max_duration();
+    pub static ref PUBLIC_URL: String = set_public_url();
+    pub static ref SIGNUP_MODE: SignupMode = set_signup_mode();
+    pub static ref EMAIL_DOMAIN_ALLOWLIST_PATH: Option<PathBuf> =
+        set_optional_path(env::EMAIL_DOMAIN_ALLOWLIST_PATH_ENV_VAR);
+    pub static ref EMAIL_DOMAIN_BLOCKLIST_PATH: Option<PathBuf> =
+        set_optional_path(env::EMAIL_DOMAIN_BLOCKLIST_PATH_ENV_VAR);
+    pub static ref BLOCK_DISPOSABLE_EMAILS: bool = set_block_disposable_emails();
+    pub static ref TOTP_ISSUER: String = set_totp_issuer();
+    pub static ref TOTP_SKEW_STEPS: u8 = set_totp_skew_steps();
+    pub static ref HOTP_LOOK_AHEAD: u64 = set_hotp_look_ahead();
+    pub static ref WEBAUTHN_RP_ID: String = set_webauthn_rp_id();
+    pub static ref WEBAUTHN_RP_NAME: String = set_webauthn_rp_name();
+    pub static ref WEBAUTHN_ORIGIN: String = set_webauthn_origin();
+    pub static ref TWO_FA_MAX_ATTEMPTS: u32 = set_two_fa_max_attempts();
+    pub static ref TWO_FA_RESEND_COOLDOWN: chrono::Duration = set_two_fa_resend_cooldown();
+    pub static ref TWO_FA_MAX_RESENDS: u32 = set_two_fa_max_resends();
+    pub static ref TWO_FA_MAX_PENDING_LOGINS: u32 = set_two_fa_max_pending_logins();
+    pub static ref TWO_FA_CODE_TTL: chrono::Duration = set_two_fa_code_ttl();
+    pub static ref TWO_FA_CODE_FORMAT: TwoFaCodeFormat = set_two_fa_code_format();
+    pub static ref STEP_UP_MAX_AGE: chrono::Duration = set_step_up_max_age();
+    pub static ref TWILIO_ACCOUNT_SID: Option<String> =
+        set_optional(env::TWILIO_ACCOUNT_SID_ENV_VAR);
+    pub static ref TWILIO_AUTH_TOKEN: Option<Secret<String>> =
+        set_optional(env::TWILIO_AUTH_TOKEN_ENV_VAR).map(Secret::new);
+    pub static ref TWILIO_FROM_NUMBER: Option<String> =
+        set_optional(env::TWILIO_FROM_NUMBER_ENV_VAR);
 }
 
 fn set_token() -> Secret<String> {
@@ -33,20 +88,404 @@ fn set_postmark_auth_token() -> Secret<String> {
     )
 }
 
+fn set_email_provider() -> EmailProvider {
+    dotenv().ok();
+    env_or_default(env::EMAIL_PROVIDER_ENV_VAR, EmailProvider::Postmark)
+}
+
+// Unset means the usual port for the TLS mode
+fn set_smtp_port() -> Option<u16> {
+    set_optional(env::SMTP_PORT_ENV_VAR).map(|port| {
+        port.parse()
+            .unwrap_or_else(|_| panic!("{} must be a valid value.", env::SMTP_PORT_ENV_VAR))
+    })
+}
+
+fn set_smtp_tls() -> SmtpTls {
+    dotenv().ok();
+    env_or_default(env::SMTP_TLS_ENV_VAR, SmtpTls::StartTls)
+}
+
+fn set_smtp_pool_size() -> u32 {
+    dotenv().ok();
+    env_or_default(env::SMTP_POOL_SIZE_ENV_VAR, DEFAULT_SMTP_POOL_SIZE)
+}
+
+// A list in the platform's PATH format, searched in order before the built-in templates
+fn set_email_template_dirs() -> Vec<PathBuf> {
+    dotenv().ok();
+    std_env::var_os(env::EMAIL_TEMPLATE_DIRS_ENV_VAR)
+        .map(|dirs| std_env::split_paths(&dirs).collect())
+        .unwrap_or_default()
+}
+
+fn set_email_default_locale() -> Locale {
+    dotenv().ok();
+    env_or_default(env::EMAIL_DEFAULT_LOCALE_ENV_VAR, Locale::default())
+}
+
+fn set_email_outbox_max_attempts() -> u32 {
+    dotenv().ok();
+    env_or_default(
+        env::EMAIL_OUTBOX_MAX_ATTEMPTS_ENV_VAR,
+        DEFAULT_EMAIL_OUTBOX_MAX_ATTEMPTS,
+    )
+}
+
+fn set_email_outbox_retry_base_delay() -> chrono::Duration {
+    dotenv().ok();
+    chrono::Duration::seconds(env_or_default(
+        env::EMAIL_OUTBOX_RETRY_BASE_SECONDS_ENV_VAR,
+        DEFAULT_EMAIL_OUTBOX_RETRY_BASE_SECONDS,
+    ))
+}
+
+fn set_email_outbox_retry_max_delay() -> chrono::Duration {
+    dotenv().ok();
+    chrono::Duration::seconds(env_or_default(
+        env::EMAIL_OUTBOX_RETRY_MAX_SECONDS_ENV_VAR,
+        DEFAULT_EMAIL_OUTBOX_RETRY_MAX_SECONDS,
+    ))
+}
+
+fn set_email_outbox_poll_interval() -> chrono::Duration {
+    dotenv().ok();
+    chrono::Duration::seconds(env_or_default(
+        env::EMAIL_OUTBOX_POLL_INTERVAL_SECONDS_ENV_VAR,
+        DEFAULT_EMAIL_OUTBOX_POLL_INTERVAL_SECONDS,
+    ))
+}
+
 fn set_redis_host() -> String {
     dotenv().ok();
     std_env::var(env::REDIS_HOST_NAME_ENV_VAR).expect("REDIS_HOST_NAME must be set.")
 }
 
+fn set_argon2_params() -> Params {
+    dotenv().ok();
+    Params::new(
+        env_or_default(env::ARGON2_M_COST_ENV_VAR, DEFAULT_ARGON2_M_COST),
+        env_or_default(env::ARGON2_T_COST_ENV_VAR, DEFAULT_ARGON2_T_COST),
+        env_or_default(env::ARGON2_P_COST_ENV_VAR, DEFAULT_ARGON2_P_COST),
+        None,
+    )
+    .expect("ARGON2_M_COST, ARGON2_T_COST and ARGON2_P_COST must form valid Argon2 parameters.")
+}
+
+fn set_password_history_size() -> usize {
+    dotenv().ok();
+    env_or_default(
+        env::PASSWORD_HISTORY_SIZE_ENV_VAR,
+        DEFAULT_PASSWORD_HISTORY_SIZE,
+    )
+}
+
+// A max age of zero days disables password expiry
+fn set_password_max_age() -> Option<chrono::Duration> {
+    dotenv().ok();
+    match env_or_default(
+        env::PASSWORD_MAX_AGE_DAYS_ENV_VAR,
+        DEFAULT_PASSWORD_MAX_AGE_DAYS,
+    ) {
+        0 => None,
+        days => Some(chrono::Duration::days(days)),
+    }
+}
+
+// The admin routes reject every request when no token is configured
+fn set_admin_api_token() -> Option<Secret<String>> {
+    dotenv().ok();
+    std_env::var(env::ADMIN_API_TOKEN_ENV_VAR)
+        .ok()
+        .filter(|token| !token.is_empty())
+        .map(Secret::new)
+}
+
+fn set_lockout_max_failures() -> u32 {
+    dotenv().ok();
+    env_or_default(
+        env::LOCKOUT_MAX_FAILURES_ENV_VAR,
+        DEFAULT_LOCKOUT_MAX_FAILURES,
+    )
+}
+
+fn set_lockout_base_duration() -> chrono::Duration {
+    dotenv().ok();
+    chrono::Duration::seconds(env_or_default(
+        env::LOCKOUT_BASE_SECONDS_ENV_VAR,
+        DEFAULT_LOCKOUT_BASE_SECONDS,
+    ))
+}
+
+fn set_lockout_max_duration() -> chrono::Duration {
+    dotenv().ok();
+    chrono::Duration::seconds(env_or_default(
+        env::LOCKOUT_MAX_SECONDS_ENV_VAR,
+        DEFAULT_LOCKOUT_MAX_SECONDS,
+    ))
+}
+
+fn set_public_url() -> String {
+    dotenv().ok();
+    std_env::var(env::PUBLIC_URL_ENV_VAR)
+        .ok()
+        .filter(|url| !url.is_empty())
+        .unwrap_or_else(|| DEFAULT_PUBLIC_URL.to_owned())
+}
+
+fn set_signup_mode() -> SignupMode {
+    dotenv().ok();
+    env_or_default(env::SIGNUP_MODE_ENV_VAR, SignupMode::Open)
+}
+
+fn set_optional_path(key: &str) -> Option<PathBuf> {
+    dotenv().ok();
+    std_env::var(key)
+        .ok()
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+}
+
+fn set_block_disposable_emails() -> bool {
+    dotenv().ok();
+    env_or_default(
+        env::BLOCK_DISPOSABLE_EMAILS_ENV_VAR,
+        DEFAULT_BLOCK_DISPOSABLE_EMAILS,
+    )
+}
+
+fn set_totp_issuer() -> String {
+    dotenv().ok();
+    std_env::var(env::TOTP_ISSUER_ENV_VAR)
+        .ok()
+        .filter(|issuer| !issuer.is_empty())
+        .unwrap_or_else(|| DEFAULT_TOTP_ISSUER.to_owned())
+}
+
+fn set_totp_skew_steps() -> u8 {
+    dotenv().ok();
+    env_or_default(env::TOTP_SKEW_STEPS_ENV_VAR, DEFAULT_TOTP_SKEW_STEPS)
+}
+
+fn set_hotp_look_ahead() -> u64 {
+    dotenv().ok();
+    env_or_default(env::HOTP_LOOK_AHEAD_ENV_VAR, DEFAULT_HOTP_LOOK_AHEAD)
+}
+
+fn set_webauthn_rp_id() -> String {
+    dotenv().ok();
+    std_env::var(env::WEBAUTHN_RP_ID_ENV_VAR)
+        .ok()
+        .filter(|id| !id.is_empty())
+        .unwrap_or_else(|| DEFAULT_WEBAUTHN_RP_ID.to_owned())
+}
+
+fn set_webauthn_rp_name() -> String {
+    dotenv().ok();
+    std_env::var(env::WEBAUTHN_RP_NAME_ENV_VAR)
+        .ok()
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| DEFAULT_WEBAUTHN_RP_NAME.to_owned())
+}
+
+// Browsers report the page's origin, which is normally the public URL
+fn set_webauthn_origin() -> String {
+    dotenv().ok();
+    std_env::var(env::WEBAUTHN_ORIGIN_ENV_VAR)
+        .ok()
+        .filter(|origin| !origin.is_empty())
+        .unwrap_or_else(|| PUBLIC_URL.trim_end_matches('/').to_owned())
+}
+
+fn set_two_fa_max_attempts() -> u32 {
+    dotenv().ok();
+    env_or_default(
+        env::TWO_FA_MAX_ATTEMPTS_ENV_VAR,
+        DEFAULT_TWO_FA_MAX_ATTEMPTS,
+    )
+}
+
+fn set_two_fa_resend_cooldown() -> chrono::Duration {
+    dotenv().ok();
+    chrono::Duration::seconds(env_or_default(
+        env::TWO_FA_RESEND_COOLDOWN_SECONDS_ENV_VAR,
+        DEFAULT_TWO_FA_RESEND_COOLDOWN_SECONDS,
+    ))
+}
+
+fn set_two_fa_max_resends() -> u32 {
+    dotenv().ok();
+    env_or_default(env::TWO_FA_MAX_RESENDS_ENV_VAR, DEFAULT_TWO_FA_MAX_RESENDS)
+}
+
+fn set_two_fa_max_pending_logins() -> u32 {
+    dotenv().ok();
+    env_or_default(
+        env::TWO_FA_MAX_PENDING_LOGINS_ENV_VAR,
+        DEFAULT_TWO_FA_MAX_PENDING_LOGINS,
+    )
+}
+
+fn set_two_fa_code_ttl() -> chrono::Duration {
+    dotenv().ok();
+    chrono::Duration::seconds(env_or_default(
+        env::TWO_FA_CODE_TTL_SECONDS_ENV_VAR,
+        DEFAULT_TWO_FA_CODE_TTL_SECONDS,
+    ))
+}
+
+fn set_two_fa_code_format() -> TwoFaCodeFormat {
+    dotenv().ok();
+    TwoFaCodeFormat::new(
+        env_or_default(env::TWO_FA_CODE_LENGTH_ENV_VAR, DEFAULT_TWO_FA_CODE_LENGTH),
+        env_or_default(
+            env::TWO_FA_CODE_ALPHABET_ENV_VAR,
+            TwoFaCodeAlphabet::Numeric,
+        ),
+    )
+    .expect("TWO_FA_CODE_LENGTH must be a valid 2FA code length.")
+}
+
+fn set_step_up_max_age() -> chrono::Duration {
+    dotenv().ok();
+    chrono::Duration::seconds(env_or_default(
+        env::STEP_UP_MAX_AGE_SECONDS_ENV_VAR,
+        DEFAULT_STEP_UP_MAX_AGE_SECONDS,
+    ))
+}
+
+// Unset and empty values both count as not configured
+fn set_optional(key: &str) -> Option<String> {
+    dotenv().ok();
+    std_env::var(key).ok().filter(|value| !value.is_empty())
+}
+
+// Optional settings fall back to a default when unset, but a value that is set and fails to
+// parse is a deployment mistake we want to surface at startup.
+fn env_or_default<T: FromStr>(key: &str, default: T) -> T {
+    match std_env::var(key) {
+        Ok(value) => value
+            .parse()
+            .unwrap_or_else(|_| panic!("{} must be a valid value.", key)),
+        Err(_) => default,
+    }
+}
+
 pub mod env {
     pub const JWT_SECRET_ENV_VAR: &str = "JWT_SECRET";
     pub const DATABASE_URL_ENV_VAR: &str = "DATABASE_URL";
     pub const REDIS_HOST_NAME_ENV_VAR: &str = "REDIS_HOST_NAME";
     pub const POSTMARK_AUTH_TOKEN_ENV_VAR: &str = "POSTMARK_AUTH_TOKEN";
+    pub const EMAIL_PROVIDER_ENV_VAR: &str = "EMAIL_PROVIDER";
+    pub const SMTP_HOST_ENV_VAR: &str = "SMTP_HOST";
+    pub const SMTP_PORT_ENV_VAR: &str = "SMTP_PORT";
+    pub const SMTP_TLS_ENV_VAR: &str = "SMTP_TLS";
+    pub const SMTP_USERNAME_ENV_VAR: &str = "SMTP_USERNAME";
+    pub const SMTP_PASSWORD_ENV_VAR: &str = "SMTP_PASSWORD";
+    pub const SMTP_POOL_SIZE_ENV_VAR: &str = "SMTP_POOL_SIZE";
+    pub const EMAIL_TEMPLATE_DIRS_ENV_VAR: &str = "EMAIL_TEMPLATE_DIRS";
+    pub const EMAIL_DEFAULT_LOCALE_ENV_VAR: &str = "EMAIL_DEFAULT_LOCALE";
+    pub const EMAIL_OUTBOX_MAX_ATTEMPTS_ENV_VAR: &str = "EMAIL_OUTBOX_MAX_ATTEMPTS";
+    pub const EMAIL_OUTBOX_RETRY_BASE_SECONDS_ENV_VAR: &str = "EMAIL_OUTBOX_RETRY_BASE_SECONDS";
+    pub const EMAIL_OUTBOX_RETRY_MAX_SECONDS_ENV_VAR: &str = "EMAIL_OUTBOX_RETRY_MAX_SECONDS";
+    pub const EMAIL_OUTBOX_POLL_INTERVAL_SECONDS_ENV_VAR: &str =
+        "EMAIL_OUTBOX_POLL_INTERVAL_SECONDS";
+    pub const ARGON2_M_COST_ENV_VAR: &str = "ARGON2_M_COST";
+    pub const ARGON2_T_COST_ENV_VAR: &str = "ARGON2_T_COST";
+    pub const ARGON2_P_COST_ENV_VAR: &str = "ARGON2_P_COST";
+    pub const PASSWORD_HISTORY_SIZE_ENV_VAR: &str = "PASSWORD_HISTORY_SIZE";
+    pub const PASSWORD_MAX_AGE_DAYS_ENV_VAR: &str = "PASSWORD_MAX_AGE_DAYS";
+    pub const ADMIN_API_TOKEN_ENV_VAR: &str = "ADMIN_API_TOKEN";
+    pub const LOCKOUT_MAX_FAILURES_ENV_VAR: &str = "LOCKOUT_MAX_FAILURES";
+    pub const LOCKOUT_BASE_SECONDS_ENV_VAR: &str = "LOCKOUT_BASE_SECONDS";
+    pub const LOCKOUT_MAX_SECONDS_ENV_VAR: &str = "LOCKOUT_MAX_SECONDS";
+    pub const PUBLIC_URL_ENV_VAR: &str = "PUBLIC_URL";
+    pub const SIGNUP_MODE_ENV_VAR: &str = "SIGNUP_MODE";
+    pub const EMAIL_DOMAIN_ALLOWLIST_PATH_ENV_VAR: &str = "EMAIL_DOMAIN_ALLOWLIST_PATH";
+    pub const EMAIL_DOMAIN_BLOCKLIST_PATH_ENV_VAR: &str = "EMAIL_DOMAIN_BLOCKLIST_PATH";
+    pub const BLOCK_DISPOSABLE_EMAILS_ENV_VAR: &str = "BLOCK_DISPOSABLE_EMAILS";
+    pub const TOTP_ISSUER_ENV_VAR: &str = "TOTP_ISSUER";
+    pub const TOTP_SKEW_STEPS_ENV_VAR: &str = "TOTP_SKEW_STEPS";
+    pub const HOTP_LOOK_AHEAD_ENV_VAR: &str = "HOTP_LOOK_AHEAD";
+    pub const WEBAUTHN_RP_ID_ENV_VAR: &str = "WEBAUTHN_RP_ID";
+    pub const WEBAUTHN_RP_NAME_ENV_VAR: &str = "WEBAUTHN_RP_NAME";
+    pub const WEBAUTHN_ORIGIN_ENV_VAR: &str = "WEBAUTHN_ORIGIN";
+    pub const TWO_FA_MAX_ATTEMPTS_ENV_VAR: &str = "TWO_FA_MAX_ATTEMPTS";
+    pub const TWO_FA_RESEND_COOLDOWN_SECONDS_ENV_VAR: &str = "TWO_FA_RESEND_COOLDOWN_SECONDS";
+    pub const TWO_FA_MAX_RESENDS_ENV_VAR: &str = "TWO_FA_MAX_RESENDS";
+    pub const TWO_FA_MAX_PENDING_LOGINS_ENV_VAR: &str = "TWO_FA_MAX_PENDING_LOGINS";
+    pub const TWO_FA_CODE_TTL_SECONDS_ENV_VAR: &str = "TWO_FA_CODE_TTL_SECONDS";
+    pub const TWO_FA_CODE_LENGTH_ENV_VAR: &str = "TWO_FA_CODE_LENGTH";
+    pub const TWO_FA_CODE_ALPHABET_ENV_VAR: &str = "TWO_FA_CODE_ALPHABET";
+    pub const STEP_UP_MAX_AGE_SECONDS_ENV_VAR: &str = "STEP_UP_MAX_AGE_SECONDS";
+    pub const TWILIO_ACCOUNT_SID_ENV_VAR: &str = "TWILIO_ACCOUNT_SID";
+    pub const TWILIO_AUTH_TOKEN_ENV_VAR: &str = "TWILIO_AUTH_TOKEN";
+    pub const TWILIO_FROM_NUMBER_ENV_VAR: &str = "TWILIO_FROM_NUMBER";
 }
 
 pub const JWT_COOKIE_NAME: &str = "jwt";
+pub const TRUSTED_DEVICE_COOKIE_NAME: &str = "trusted_device";
 pub const DEFAULT_REDIS_HOSTNAME: &str = "redis://127.0.0.1";
+// Base URL of this service as seen by users, used to build links sent by email
+pub const DEFAULT_PUBLIC_URL: &str = "http://localhost:3000";
+// Open connections to the SMTP server kept for reuse
+pub const DEFAULT_SMTP_POOL_SIZE: u32 = 10;
+// Attempts at delivering an email before it becomes a dead letter, and the wait after the
+// first and longest failures. The defaults keep trying for a little over an hour.
+pub const DEFAULT_EMAIL_OUTBOX_MAX_ATTEMPTS: u32 = 8;
+pub const DEFAULT_EMAIL_OUTBOX_RETRY_BASE_SECONDS: i64 = 30;
+pub const DEFAULT_EMAIL_OUTBOX_RETRY_MAX_SECONDS: i64 = 1800;
+// New emails wake the worker straight away; polling only picks up retries
+pub const DEFAULT_EMAIL_OUTBOX_POLL_INTERVAL_SECONDS: i64 = 5;
+// Emails the worker takes from the outbox at a time, and how long they are reserved for it.
+// The lease has to outlast the email client's timeout or an email could be sent twice.
+pub const EMAIL_OUTBOX_BATCH_SIZE: usize = 10;
+pub const EMAIL_OUTBOX_LEASE_SECONDS: i64 = 60;
+pub const DEFAULT_ARGON2_M_COST: u32 = 15000;
+pub const DEFAULT_ARGON2_T_COST: u32 = 2;
+pub const DEFAULT_ARGON2_P_COST: u32 = 1;
+// Number of most recent passwords, the current one included, that cannot be reused
+pub const DEFAULT_PASSWORD_HISTORY_SIZE: usize = 5;
+pub const DEFAULT_PASSWORD_MAX_AGE_DAYS: i64 = 0;
+// Failed logins before an account is locked, and how long the first and longest lockouts last
+pub const DEFAULT_LOCKOUT_MAX_FAILURES: u32 = 5;
+pub const DEFAULT_LOCKOUT_BASE_SECONDS: i64 = 60;
+pub const DEFAULT_LOCKOUT_MAX_SECONDS: i64 = 3600;
+// Failed logins further apart than this do not add up towards a lockout
+pub const LOGIN_FAILURE_WINDOW_SECONDS: i64 = 86_400;
+// How long invites stay valid unless the admin creating them asks otherwise
+pub const DEFAULT_INVITE_TTL_HOURS: i64 = 168;
+// Reject signups from the bundled list of disposable email providers
+pub const DEFAULT_BLOCK_DISPOSABLE_EMAILS: bool = true;
+// Name authenticator apps show next to the account
+pub const DEFAULT_TOTP_ISSUER: &str = "Auth Service";
+// Number of 30 second steps either side of the current one a TOTP code may be from
+pub const DEFAULT_TOTP_SKEW_STEPS: u8 = 1;
+// Button presses on a hardware token that may go unused before its codes stop being accepted
+pub const DEFAULT_HOTP_LOOK_AHEAD: u64 = 10;
+// Domain passkeys are registered for. Credentials only work on this domain and its subdomains.
+pub const DEFAULT_WEBAUTHN_RP_ID: &str = "localhost";
+pub const DEFAULT_WEBAUTHN_RP_NAME: &str = "Auth Service";
+// How long a browser has to answer a WebAuthn challenge
+pub const WEBAUTHN_CEREMONY_TTL_SECONDS: i64 = 300;
+// Wrong guesses allowed against one emailed or texted code before it is thrown away
+pub const DEFAULT_TWO_FA_MAX_ATTEMPTS: u32 = 5;
+// How long a user has to wait before asking for a code to be sent again, and how many times
+// one login attempt may have its code resent
+pub const DEFAULT_TWO_FA_RESEND_COOLDOWN_SECONDS: i64 = 30;
+pub const DEFAULT_TWO_FA_MAX_RESENDS: u32 = 3;
+// Logins a user can have waiting on a second factor at once, e.g. from different devices
+pub const DEFAULT_TWO_FA_MAX_PENDING_LOGINS: u32 = 5;
+// How long an emailed or texted code stays valid
+pub const DEFAULT_TWO_FA_CODE_TTL_SECONDS: i64 = 600;
+// Codes are six digits unless configured otherwise. Lengths outside the bounds are either too
+// easy to guess or too tedious to type.
+pub const DEFAULT_TWO_FA_CODE_LENGTH: usize = 6;
+pub const MIN_TWO_FA_CODE_LENGTH: usize = 4;
+pub const MAX_TWO_FA_CODE_LENGTH: usize = 12;
+// How long after logging in a user may still change their password or 2FA setup without
+// logging in again
+pub const DEFAULT_STEP_UP_MAX_AGE_SECONDS: i64 = 300;
 
 pub mod prod {
     pub const APP_ADDRESS: &str = "0.0.0.0:3000";
@@ -57,14 +496,28 @@ pub mod prod {
         pub const SENDER: &str = "bogdan@codeiron.io";
         pub const TIMEOUT: Duration = std::time::Duration::from_secs(10);
     }
+    pub mod sms_client {
+        use std::time::Duration;
+
+        pub const BASE_URL: &str = "https://api.twilio.com";
+        pub const TIMEOUT: Duration = std::time::Duration::from_secs(10);
+    }
 }
 
 pub mod test {
     pub const APP_ADDRESS: &str = "127.0.0.1:0";
+    pub const ADMIN_API_TOKEN: &str = "test-admin-token";
     pub mod email_client {
         use std::time::Duration;
 
         pub const SENDER: &str = "test@email.com";
         pub const TIMEOUT: Duration = std::time::Duration::from_millis(200);
     }
+    pub mod sms_client {
+        use std::time::Duration;
+
+        pub const ACCOUNT_SID: &str = "AC00000000000000000000000000000000";
+        pub const SENDER: &str = "+15005550006";
+        pub const TIMEOUT: Duration = std::time::Duration::from_millis(200);
+    }
 }
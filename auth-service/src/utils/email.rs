@@ -0,0 +1,34 @@
+use crate::app_state::app_state::AppState;
+use crate::domain::email_outbox::{IdempotencyKey, OutboxEmail};
+use crate::domain::email_template::TransactionalEmail;
+use crate::domain::error::AuthAPIError;
+use crate::domain::locale::Locale;
+use crate::domain::Email;
+use chrono::Utc;
+
+// Renders the email in the user's locale and leaves it in the outbox. The request doesn't wait
+// on the email provider; the outbox worker delivers the email and retries it if need be.
+pub(crate) async fn queue_email(
+    state: &AppState,
+    recipient: &Email,
+    locale: Option<&Locale>,
+    email: &TransactionalEmail,
+    idempotency_key: IdempotencyKey,
+) -> Result<(), AuthAPIError> {
+    let message = state.email_templates.render(email, locale);
+    let expires_at = email.expires_in().map(|expires_in| Utc::now() + expires_in);
+    state
+        .email_outbox
+        .write()
+        .await
+        .enqueue(OutboxEmail::new(
+            recipient.clone(),
+            message,
+            idempotency_key,
+            expires_at,
+        ))
+        .await
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+    state.email_outbox_wakeup.notify_one();
+    Ok(())
+}
@@ -1,3 +1,7 @@
+pub mod admin_auth;
 pub mod auth;
 pub mod constants;
+pub mod email;
+pub mod password_hashing;
 pub mod tracing;
+pub mod user_auth;
@@ -0,0 +1,70 @@
+use argon2::{password_hash::SaltString, Algorithm, Argon2, Params, PasswordHasher, Version};
+use color_eyre::eyre::{eyre, Result};
+use rand_core::OsRng;
+use std::time::{Duration, Instant};
+
+// Memory cost (in KiB) the calibration starts from, the OWASP minimum for Argon2id.
+const CALIBRATION_START_M_COST: u32 = 19 * 1024;
+const CALIBRATION_T_COST: u32 = 2;
+const CALIBRATION_SAMPLE_PASSWORD: &str = "calibration-password";
+
+// Picks Argon2id parameters that take roughly `target` to hash on the current machine.
+// Memory is raised first, up to `max_m_cost` KiB; once the cap is hit the iteration count
+// is raised instead. Returns the chosen parameters and the measured hashing time.
+pub fn calibrate_argon2_params(
+    target: Duration,
+    max_m_cost: u32,
+    p_cost: u32,
+) -> Result<(Params, Duration)> {
+    if max_m_cost < CALIBRATION_START_M_COST {
+        return Err(eyre!(
+            "Maximum memory cost must be at least {} KiB",
+            CALIBRATION_START_M_COST
+        ));
+    }
+
+    let baseline = Params::new(CALIBRATION_START_M_COST, CALIBRATION_T_COST, p_cost, None)?;
+    let baseline_elapsed = time_hash(&baseline)?;
+
+    // Argon2 cost grows linearly with both memory and iterations.
+    let scale = target.as_secs_f64() / baseline_elapsed.as_secs_f64();
+    let wanted_m_cost = (CALIBRATION_START_M_COST as f64 * scale) as u64;
+
+    let (m_cost, t_cost) = if wanted_m_cost <= max_m_cost as u64 {
+        (
+            wanted_m_cost.max(CALIBRATION_START_M_COST as u64) as u32,
+            CALIBRATION_T_COST,
+        )
+    } else {
+        let memory_scale = max_m_cost as f64 / CALIBRATION_START_M_COST as f64;
+        let t_cost = (CALIBRATION_T_COST as f64 * scale / memory_scale).ceil() as u32;
+        (max_m_cost, t_cost.max(CALIBRATION_T_COST))
+    };
+
+    let params = Params::new(m_cost, t_cost, p_cost, None)?;
+    let elapsed = time_hash(&params)?;
+
+    Ok((params, elapsed))
+}
+
+fn time_hash(params: &Params) -> Result<Duration> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hasher = Argon2::new(Algorithm::Argon2id, Version::V0x13, params.clone());
+
+    let start = Instant::now();
+    hasher
+        .hash_password(CALIBRATION_SAMPLE_PASSWORD.as_bytes(), &salt)
+        .map_err(|e| eyre!(e))?;
+
+    Ok(start.elapsed())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn calibration_rejects_memory_cap_below_minimum() {
+        assert!(calibrate_argon2_params(Duration::from_millis(1), 1024, 1).is_err());
+    }
+}
@@ -0,0 +1,169 @@
+use crate::app_state::app_state::AppState;
+use crate::domain::authentication::AuthLevel;
+use crate::domain::error::AuthAPIError;
+use crate::domain::user::User;
+use crate::domain::Email;
+use crate::utils::{
+    auth::{validate_token, Claims},
+    constants::JWT_COOKIE_NAME,
+};
+use axum::{async_trait, extract::FromRequestParts, http::request::Parts};
+use axum_extra::extract::CookieJar;
+use chrono::{DateTime, Duration, Utc};
+use secrecy::Secret;
+
+// Extractor for routes that act on the logged-in user's own account. Requires a valid auth
+// cookie belonging to an active account.
+pub struct AuthenticatedUser {
+    pub email: Email,
+    // How strongly and when the user logged in. Tokens without this information count as a
+    // single factor login of unknown age.
+    pub auth_level: AuthLevel,
+    pub auth_time: Option<DateTime<Utc>>,
+}
+
+impl AuthenticatedUser {
+    // Reads the user and their login context out of an already validated auth token
+    fn from_claims(claims: Claims) -> Result<Self, AuthAPIError> {
+        let email =
+            Email::parse(Secret::new(claims.sub)).map_err(|_| AuthAPIError::InvalidToken)?;
+        Ok(AuthenticatedUser {
+            email,
+            auth_level: claims.acr.unwrap_or(AuthLevel::Aal1),
+            auth_time: claims
+                .auth_time
+                .and_then(|auth_time| DateTime::from_timestamp(auth_time as i64, 0)),
+        })
+    }
+
+    // Guard for operations that need a login of at least `level` within the last `max_age`
+    pub fn require_recent_login(
+        &self,
+        level: AuthLevel,
+        max_age: Duration,
+    ) -> Result<(), AuthAPIError> {
+        let recent = self
+            .auth_time
+            .is_some_and(|auth_time| Utc::now() - auth_time <= max_age);
+        if self.auth_level >= level && recent {
+            return Ok(());
+        }
+        Err(AuthAPIError::ReauthenticationRequired { level, max_age })
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for AuthenticatedUser {
+    type Rejection = AuthAPIError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        authenticate(parts, state).await.map(|(user, _)| user)
+    }
+}
+
+// Extractor for sensitive operations such as changing the password or the 2FA setup. The login
+// has to be within the step-up max age, and for accounts with 2FA it has to have used two
+// factors, so a stolen auth cookie or a remembered device alone is not enough.
+pub struct StepUpUser {
+    pub email: Email,
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for StepUpUser {
+    type Rejection = AuthAPIError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let (authenticated, user) = authenticate(parts, state).await?;
+        authenticated
+            .require_recent_login(required_level(&user), state.settings.step_up.max_age)?;
+        Ok(StepUpUser {
+            email: authenticated.email,
+        })
+    }
+}
+
+// The strongest login the account can do
+fn required_level(user: &User) -> AuthLevel {
+    if user.require_2fa {
+        AuthLevel::Aal2
+    } else {
+        AuthLevel::Aal1
+    }
+}
+
+async fn authenticate(
+    parts: &Parts,
+    state: &AppState,
+) -> Result<(AuthenticatedUser, User), AuthAPIError> {
+    let jar = CookieJar::from_headers(&parts.headers);
+    let cookie = jar.get(JWT_COOKIE_NAME).ok_or(AuthAPIError::MissingToken)?;
+
+    let claims = validate_token(
+        Secret::new(cookie.value().to_owned()),
+        state.tokenstore.clone(),
+    )
+    .await
+    .map_err(|_| AuthAPIError::InvalidToken)?;
+    let authenticated = AuthenticatedUser::from_claims(claims)?;
+
+    let user = match state
+        .userstore
+        .read()
+        .await
+        .get_user(&authenticated.email)
+        .await
+    {
+        Ok(user) if user.status.is_active() => user,
+        Ok(_) => return Err(AuthAPIError::AccountInactive),
+        Err(_) => return Err(AuthAPIError::InvalidToken),
+    };
+    Ok((authenticated, user))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(auth_level: AuthLevel, logged_in_ago: Option<Duration>) -> AuthenticatedUser {
+        AuthenticatedUser {
+            email: Email::parse(Secret::new("test@example.com".to_owned())).unwrap(),
+            auth_level,
+            auth_time: logged_in_ago.map(|ago| Utc::now() - ago),
+        }
+    }
+
+    #[test]
+    fn recent_strong_enough_login_passes() {
+        let user = user(AuthLevel::Aal2, Some(Duration::seconds(10)));
+        assert!(user
+            .require_recent_login(AuthLevel::Aal1, Duration::minutes(5))
+            .is_ok());
+        assert!(user
+            .require_recent_login(AuthLevel::Aal2, Duration::minutes(5))
+            .is_ok());
+    }
+
+    #[test]
+    fn weak_old_or_undated_logins_need_reauthentication() {
+        let max_age = Duration::minutes(5);
+        for user in [
+            user(AuthLevel::Aal1, Some(Duration::seconds(10))),
+            user(AuthLevel::Aal2, Some(Duration::minutes(6))),
+            user(AuthLevel::Aal2, None),
+        ] {
+            assert!(matches!(
+                user.require_recent_login(AuthLevel::Aal2, max_age),
+                Err(AuthAPIError::ReauthenticationRequired {
+                    level: AuthLevel::Aal2,
+                    ..
+                })
+            ));
+        }
+    }
+}
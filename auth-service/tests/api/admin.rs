@@ -0,0 +1,178 @@
+use crate::helpers::{get_random_email, TestApp};
+use auth_service::{
+    domain::user::AccountStatus, routes::admin::AccountStatusHistoryResponse,
+    utils::constants::JWT_COOKIE_NAME, ErrorResponse,
+};
+
+#[tokio::test]
+async fn should_return_400_if_admin_token_missing() {
+    let mut app = TestApp::new().await;
+
+    let response = app
+        .http_client
+        .post(format!("{}/admin/must-change-password", &app.address))
+        .json(&serde_json::json!({
+            "email": get_random_email(),
+            "mustChangePassword": true
+        }))
+        .send()
+        .await
+        .expect("could not get must change password route");
+    assert_eq!(response.status().as_u16(), 400);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_return_401_if_admin_token_invalid() {
+    let mut app = TestApp::new().await;
+
+    let response = app
+        .http_client
+        .post(format!("{}/admin/must-change-password", &app.address))
+        .bearer_auth("not-the-admin-token")
+        .json(&serde_json::json!({
+            "email": get_random_email(),
+            "mustChangePassword": true
+        }))
+        .send()
+        .await
+        .expect("could not get must change password route");
+    assert_eq!(response.status().as_u16(), 401);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_return_404_if_user_not_found() {
+    let mut app = TestApp::new().await;
+
+    let response = app
+        .post_admin_must_change_password(&serde_json::json!({
+            "email": get_random_email(),
+            "mustChangePassword": true
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 404);
+    assert_eq!(
+        response
+            .json::<ErrorResponse>()
+            .await
+            .expect("Could not deserialize response body to ErrorResponse")
+            .error,
+        "User not found".to_owned()
+    );
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_reject_suspended_user_and_record_status_history() {
+    let mut app = TestApp::new().await;
+
+    let random_email = get_random_email();
+    let signup_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+        "requires2FA": false
+    });
+    let response = app.post_signup(&signup_body).await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    let response = app.post_login(&signup_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let token = response
+        .cookies()
+        .find(|cookie| cookie.name() == JWT_COOKIE_NAME)
+        .expect("No auth cookie found")
+        .value()
+        .to_owned();
+
+    let response = app
+        .post_admin_user_status(&serde_json::json!({
+            "email": random_email,
+            "status": "suspended",
+            "reason": "Suspicious activity"
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // The JWT issued before the suspension is no longer accepted
+    let response = app
+        .post_verify_token(&serde_json::json!({ "token": token }))
+        .await;
+    assert_eq!(response.status().as_u16(), 403);
+
+    let response = app.post_login(&signup_body).await;
+    assert_eq!(response.status().as_u16(), 403);
+    assert_eq!(
+        response
+            .json::<ErrorResponse>()
+            .await
+            .expect("Could not deserialize response body to ErrorResponse")
+            .error,
+        "Account is not active".to_owned()
+    );
+
+    let response = app
+        .post_admin_user_status(&serde_json::json!({
+            "email": random_email,
+            "status": "active",
+            "reason": "Verified with the user"
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = app.post_login(&signup_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = app
+        .post_admin_user_status_history(&serde_json::json!({ "email": random_email }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let body = response
+        .json::<AccountStatusHistoryResponse>()
+        .await
+        .expect("Could not deserialize response body to AccountStatusHistoryResponse");
+    assert_eq!(body.status, AccountStatus::Active);
+    let history: Vec<_> = body
+        .history
+        .iter()
+        .map(|change| (change.status, change.reason.as_str()))
+        .collect();
+    assert_eq!(
+        history,
+        vec![
+            (AccountStatus::Active, "Verified with the user"),
+            (AccountStatus::Suspended, "Suspicious activity"),
+        ]
+    );
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_return_422_if_status_unknown() {
+    let mut app = TestApp::new().await;
+
+    let random_email = get_random_email();
+    let signup_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+        "requires2FA": false
+    });
+    app.post_signup(&signup_body).await;
+
+    for status in ["deleted", "pending"] {
+        let response = app
+            .post_admin_user_status(&serde_json::json!({
+                "email": random_email,
+                "status": status,
+                "reason": "Closing the account"
+            }))
+            .await;
+        assert_eq!(response.status().as_u16(), 422, "Failed for {}", status);
+    }
+
+    app.clean_up().await;
+}
@@ -0,0 +1,118 @@
+use crate::helpers::{get_random_email, TestApp};
+use auth_service::ErrorResponse;
+
+async fn signup_and_login(app: &TestApp, email: &str, password: &str) {
+    let signup_body = serde_json::json!({
+        "email": email,
+        "password": password,
+        "requires2FA": false
+    });
+    let response = app.post_signup(&signup_body).await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    let login_body = serde_json::json!({
+        "email": email,
+        "password": password,
+        "requires2FA": false
+    });
+    let response = app.post_login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn should_return_400_if_jwt_cookie_missing() {
+    let mut app = TestApp::new().await;
+
+    let body = serde_json::json!({
+        "currentPassword": "password123",
+        "newPassword": "newpassword123"
+    });
+    let response = app.post_change_password(&body).await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_return_401_if_incorrect_current_password() {
+    let mut app = TestApp::new().await;
+    signup_and_login(&app, &get_random_email(), "password123").await;
+
+    let body = serde_json::json!({
+        "currentPassword": "wrongpassword",
+        "newPassword": "newpassword123"
+    });
+    let response = app.post_change_password(&body).await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_return_200_and_accept_new_password() {
+    let mut app = TestApp::new().await;
+    let random_email = get_random_email();
+    signup_and_login(&app, &random_email, "password123").await;
+
+    let body = serde_json::json!({
+        "currentPassword": "password123",
+        "newPassword": "newpassword123"
+    });
+    let response = app.post_change_password(&body).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let login_body = serde_json::json!({
+        "email": random_email,
+        "password": "newpassword123",
+        "requires2FA": false
+    });
+    let response = app.post_login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let login_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+        "requires2FA": false
+    });
+    let response = app.post_login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_return_400_if_password_was_used_recently() {
+    let mut app = TestApp::new().await;
+    signup_and_login(&app, &get_random_email(), "password123").await;
+
+    let body = serde_json::json!({
+        "currentPassword": "password123",
+        "newPassword": "password123"
+    });
+    let response = app.post_change_password(&body).await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    let body = serde_json::json!({
+        "currentPassword": "password123",
+        "newPassword": "newpassword123"
+    });
+    let response = app.post_change_password(&body).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let body = serde_json::json!({
+        "currentPassword": "newpassword123",
+        "newPassword": "password123"
+    });
+    let response = app.post_change_password(&body).await;
+    assert_eq!(response.status().as_u16(), 400);
+    assert_eq!(
+        response
+            .json::<ErrorResponse>()
+            .await
+            .expect("Could not deserialize response body to ErrorResponse")
+            .error,
+        "Password was used recently, please choose a different one".to_owned()
+    );
+
+    app.clean_up().await;
+}
@@ -0,0 +1,332 @@
+use crate::helpers::{get_random_email, TestApp};
+use auth_service::{
+    domain::{two_factor::TwoFactorMethod, user::PasswordChangeReason},
+    routes::{
+        email_code_login::LoginCodeResponse,
+        login::{PasswordChangeRequiredResponse, TwoFactorAuthResponse},
+    },
+    utils::constants::{DEFAULT_TWO_FA_MAX_ATTEMPTS, JWT_COOKIE_NAME},
+};
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+async fn signup(app: &TestApp, email: &str) {
+    let signup_body = serde_json::json!({
+        "email": email,
+        "password": "password123",
+        "requires2FA": false
+    });
+    let response = app.post_signup(&signup_body).await;
+    assert_eq!(response.status().as_u16(), 201);
+}
+
+async fn request_login_code(app: &TestApp, email: &str) -> (LoginCodeResponse, String) {
+    let response = app
+        .post_login_code(&serde_json::json!({ "email": email }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let body = response
+        .json::<LoginCodeResponse>()
+        .await
+        .expect("Could not deserialize response body to LoginCodeResponse");
+
+    // The code ends the first line of the email
+    let text = app.last_email_text().await;
+    let code = text
+        .lines()
+        .next()
+        .and_then(|line| line.rsplit(' ').next())
+        .expect("No code in email")
+        .to_owned();
+
+    (body, code)
+}
+
+#[tokio::test]
+async fn should_respond_identically_for_unknown_email() {
+    let mut app = TestApp::new().await;
+    let random_email = get_random_email();
+    signup(&app, &random_email).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let known = app
+        .post_login_code(&serde_json::json!({ "email": random_email }))
+        .await;
+    let unknown = app
+        .post_login_code(&serde_json::json!({ "email": get_random_email() }))
+        .await;
+    assert_eq!(known.status(), unknown.status());
+
+    let known = known.json::<LoginCodeResponse>().await.unwrap();
+    let unknown = unknown.json::<LoginCodeResponse>().await.unwrap();
+    assert_eq!(known.message, unknown.message);
+    assert_eq!(known.login_attempt_id.len(), unknown.login_attempt_id.len());
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_not_send_another_code_during_the_cooldown() {
+    let mut app = TestApp::new().await;
+    let random_email = get_random_email();
+    signup(&app, &random_email).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let (first, code) = request_login_code(&app, &random_email).await;
+
+    // The response doesn't give the cooldown away
+    let response = app
+        .post_login_code(&serde_json::json!({ "email": random_email }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let second = response.json::<LoginCodeResponse>().await.unwrap();
+    assert_eq!(second.message, first.message);
+
+    // The first attempt was not pushed out by the second request
+    let response = app
+        .post_verify_login_code(&serde_json::json!({
+            "email": random_email,
+            "loginAttemptId": first.login_attempt_id,
+            "code": code
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_log_in_once_with_emailed_code() {
+    let mut app = TestApp::new().await;
+    let random_email = get_random_email();
+    signup(&app, &random_email).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let (body, code) = request_login_code(&app, &random_email).await;
+    let verify_body = serde_json::json!({
+        "email": random_email,
+        "loginAttemptId": body.login_attempt_id,
+        "code": code
+    });
+
+    let response = app.post_verify_login_code(&verify_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response
+        .cookies()
+        .any(|cookie| cookie.name() == JWT_COOKIE_NAME));
+
+    let response = app.post_verify_login_code(&verify_body).await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_require_password_change_when_flagged_by_admin() {
+    let mut app = TestApp::new().await;
+    let random_email = get_random_email();
+    signup(&app, &random_email).await;
+
+    let response = app
+        .post_admin_must_change_password(&serde_json::json!({
+            "email": random_email,
+            "mustChangePassword": true
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let (body, code) = request_login_code(&app, &random_email).await;
+    let response = app
+        .post_verify_login_code(&serde_json::json!({
+            "email": random_email,
+            "loginAttemptId": body.login_attempt_id,
+            "code": code
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response
+        .cookies()
+        .all(|cookie| cookie.name() != JWT_COOKIE_NAME));
+    let body = response
+        .json::<PasswordChangeRequiredResponse>()
+        .await
+        .expect("Could not deserialize response body to PasswordChangeRequiredResponse");
+    assert_eq!(body.reason, PasswordChangeReason::Required);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_reject_wrong_code_or_unknown_attempt() {
+    let mut app = TestApp::new().await;
+    let random_email = get_random_email();
+    signup(&app, &random_email).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let (body, code) = request_login_code(&app, &random_email).await;
+    let wrong_code = if code == "123456" { "654321" } else { "123456" };
+
+    let response = app
+        .post_verify_login_code(&serde_json::json!({
+            "email": random_email,
+            "loginAttemptId": body.login_attempt_id,
+            "code": wrong_code
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    let response = app
+        .post_verify_login_code(&serde_json::json!({
+            "email": random_email,
+            "loginAttemptId": uuid::Uuid::new_v4().to_string(),
+            "code": code
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_return_429_after_too_many_wrong_codes() {
+    let mut app = TestApp::new().await;
+    let random_email = get_random_email();
+    signup(&app, &random_email).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let (body, code) = request_login_code(&app, &random_email).await;
+    let wrong_code = if code == "123456" { "654321" } else { "123456" };
+    let verify_body = |code: &str| {
+        serde_json::json!({
+            "email": random_email,
+            "loginAttemptId": body.login_attempt_id,
+            "code": code
+        })
+    };
+
+    for _ in 1..DEFAULT_TWO_FA_MAX_ATTEMPTS {
+        let response = app.post_verify_login_code(&verify_body(wrong_code)).await;
+        assert_eq!(response.status().as_u16(), 400);
+    }
+    let response = app.post_verify_login_code(&verify_body(wrong_code)).await;
+    assert_eq!(response.status().as_u16(), 429);
+
+    let response = app.post_verify_login_code(&verify_body(&code)).await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_not_accept_login_code_as_2fa_code() {
+    let mut app = TestApp::new().await;
+    let random_email = get_random_email();
+    signup(&app, &random_email).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let (body, code) = request_login_code(&app, &random_email).await;
+
+    let response = app
+        .post_verify_2fa(&serde_json::json!({
+            "email": random_email,
+            "loginAttemptId": body.login_attempt_id,
+            "2FACode": code
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_not_offer_email_as_the_second_factor_after_an_emailed_code() {
+    let mut app = TestApp::new().await;
+    let random_email = get_random_email();
+    let response = app
+        .post_signup(&serde_json::json!({
+            "email": random_email,
+            "password": "password123",
+            "requires2FA": true
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    // Only the login code is emailed, no 2FA code
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let (body, code) = request_login_code(&app, &random_email).await;
+    let response = app
+        .post_verify_login_code(&serde_json::json!({
+            "email": random_email,
+            "loginAttemptId": body.login_attempt_id,
+            "code": code
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let body = response
+        .json::<TwoFactorAuthResponse>()
+        .await
+        .expect("Could not deserialize response body to TwoFactorAuthResponse");
+    assert_eq!(body.methods, vec![TwoFactorMethod::RecoveryCode]);
+
+    let response = app
+        .post_resend_2fa(&serde_json::json!({
+            "email": random_email,
+            "loginAttemptId": body.login_attempt_id,
+            "method": "email"
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    app.clean_up().await;
+}
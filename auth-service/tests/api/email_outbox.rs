@@ -0,0 +1,293 @@
+use crate::helpers::{get_random_email, TestApp};
+use auth_service::app_state::settings::{EmailOutboxSettings, Settings};
+use auth_service::domain::email_outbox::EXPIRED_BEFORE_SENDING;
+use auth_service::routes::admin::DeadLettersResponse;
+use auth_service::routes::login::TwoFactorAuthResponse;
+use auth_service::utils::constants::test;
+use auth_service::ErrorResponse;
+use chrono::Duration;
+use secrecy::Secret;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+// Retries come round quickly so the tests don't have to wait long for them
+async fn app_with_outbox(max_attempts: u32) -> TestApp {
+    TestApp::with_settings(Settings {
+        admin_api_token: Some(Secret::new(test::ADMIN_API_TOKEN.to_owned())),
+        email_outbox: EmailOutboxSettings {
+            max_attempts,
+            base_delay: Duration::milliseconds(20),
+            max_delay: Duration::milliseconds(20),
+            poll_interval: Duration::milliseconds(20),
+        },
+        ..Settings::default()
+    })
+    .await
+}
+
+async fn dead_letters(app: &TestApp) -> DeadLettersResponse {
+    let response = app.get_admin_dead_letters().await;
+    assert_eq!(response.status().as_u16(), 200);
+    response
+        .json()
+        .await
+        .expect("Could not deserialize response body to DeadLettersResponse")
+}
+
+async fn wait_for_dead_letters(app: &TestApp, count: usize) -> DeadLettersResponse {
+    for _ in 0..100 {
+        let response = dead_letters(app).await;
+        if response.dead_letters.len() == count {
+            return response;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    panic!("Expected {} dead letters", count);
+}
+
+#[tokio::test]
+async fn login_succeeds_while_the_email_provider_is_down() {
+    let mut app = app_with_outbox(5).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(503))
+        .up_to_n_times(2)
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let body = serde_json::json!({
+        "email": get_random_email(),
+        "password": "password123",
+        "requires2FA": true
+    });
+    assert_eq!(app.post_signup(&body).await.status().as_u16(), 201);
+
+    let response = app.post_login(&body).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response
+            .json::<TwoFactorAuthResponse>()
+            .await
+            .expect("Could not deserialize response body to TwoFactorAuthResponse")
+            .message,
+        "2FA required"
+    );
+
+    // The code gets through once the provider is back. The worker records the send after the
+    // provider has answered, so wait for that rather than for the request.
+    let mut status = String::new();
+    for _ in 0..250 {
+        status = sqlx::query_scalar("SELECT status FROM email_outbox")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+        if status == "sent" {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    assert_eq!(status, "sent");
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn undeliverable_emails_become_dead_letters_that_can_be_replayed() {
+    let mut app = app_with_outbox(2).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(2)
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let email = get_random_email();
+    let body = serde_json::json!({
+        "email": email,
+        "password": "password123",
+        "requires2FA": false
+    });
+    assert_eq!(app.post_signup(&body).await.status().as_u16(), 201);
+    let response = app
+        .post_forgot_password(&serde_json::json!({ "email": email }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = wait_for_dead_letters(&app, 1).await;
+    let dead_letter = &response.dead_letters[0];
+    assert_eq!(dead_letter.recipient, email);
+    assert_eq!(dead_letter.subject, "Reset your password");
+    assert_eq!(dead_letter.attempts, 2);
+    assert!(dead_letter.last_error.as_deref().unwrap().contains("500"));
+    assert!(dead_letter.expires_at.is_some());
+
+    let response = app
+        .post_admin_replay_dead_letter(&serde_json::json!({ "id": dead_letter.id }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    app.wait_for_emails().await;
+    assert!(dead_letters(&app).await.dead_letters.is_empty());
+    assert!(app
+        .last_email_text()
+        .await
+        .starts_with("Your password reset token is: "));
+
+    // Only dead letters can be replayed
+    let response = app
+        .post_admin_replay_dead_letter(&serde_json::json!({ "id": dead_letter.id }))
+        .await;
+    assert_eq!(response.status().as_u16(), 404);
+    assert_eq!(
+        response
+            .json::<ErrorResponse>()
+            .await
+            .expect("Could not deserialize response body to ErrorResponse")
+            .error,
+        "Dead letter not found"
+    );
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn expired_dead_letters_are_emptied_and_cannot_be_replayed() {
+    let mut app = app_with_outbox(1).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let email = get_random_email();
+    let body = serde_json::json!({
+        "email": email,
+        "password": "password123",
+        "requires2FA": false
+    });
+    assert_eq!(app.post_signup(&body).await.status().as_u16(), 201);
+    app.post_forgot_password(&serde_json::json!({ "email": email }))
+        .await;
+    let id = wait_for_dead_letters(&app, 1).await.dead_letters[0]
+        .id
+        .clone();
+
+    // The reset token in the email has run out
+    sqlx::query("UPDATE email_outbox SET expires_at = NOW() WHERE id = $1")
+        .bind(&id)
+        .execute(&app.db_pool)
+        .await
+        .expect("Failed to expire email");
+
+    let response = app
+        .post_admin_replay_dead_letter(&serde_json::json!({ "id": id }))
+        .await;
+    assert_eq!(response.status().as_u16(), 410);
+
+    // The worker drops the body with the token in it
+    let mut text_body = String::new();
+    for _ in 0..100 {
+        text_body = sqlx::query_scalar("SELECT text_body FROM email_outbox WHERE id = $1")
+            .bind(&id)
+            .fetch_one(&app.db_pool)
+            .await
+            .expect("Failed to read email");
+        if text_body.is_empty() {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    assert!(text_body.is_empty());
+    assert_eq!(dead_letters(&app).await.dead_letters.len(), 1);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn emails_that_expire_while_queued_are_dead_lettered_unsent() {
+    let mut app = app_with_outbox(1000).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(503))
+        .mount(&app.email_server)
+        .await;
+
+    let email = get_random_email();
+    let body = serde_json::json!({
+        "email": email,
+        "password": "password123",
+        "requires2FA": false
+    });
+    assert_eq!(app.post_signup(&body).await.status().as_u16(), 201);
+    app.post_forgot_password(&serde_json::json!({ "email": email }))
+        .await;
+    for _ in 0..100 {
+        if !app
+            .email_server
+            .received_requests()
+            .await
+            .unwrap()
+            .is_empty()
+        {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+
+    // The reset token runs out while the provider is still down
+    sqlx::query("UPDATE email_outbox SET expires_at = NOW()")
+        .execute(&app.db_pool)
+        .await
+        .expect("Failed to expire email");
+
+    let dead_letter = wait_for_dead_letters(&app, 1).await.dead_letters.remove(0);
+    assert_eq!(
+        dead_letter.last_error.as_deref(),
+        Some(EXPIRED_BEFORE_SENDING)
+    );
+    let text_body: String = sqlx::query_scalar("SELECT text_body FROM email_outbox WHERE id = $1")
+        .bind(&dead_letter.id)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to read email");
+    assert!(text_body.is_empty());
+
+    // No more attempts are made to send it
+    let sends = app.email_server.received_requests().await.unwrap().len();
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    assert_eq!(
+        app.email_server.received_requests().await.unwrap().len(),
+        sends
+    );
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_return_400_for_malformed_dead_letter_id() {
+    let mut app = TestApp::new().await;
+
+    let response = app
+        .post_admin_replay_dead_letter(&serde_json::json!({ "id": "not-an-id" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    app.clean_up().await;
+}
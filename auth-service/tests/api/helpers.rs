@@ -1,13 +1,40 @@
 use auth_service::{
-    app_state::app_state::{AppState, CodeStore},
+    app_state::{
+        app_state::{AppState, CodeStore},
+        settings::Settings,
+    },
+    argon2_password_hasher::Argon2PasswordHasher,
     data_stores::{
-        postgres_user_store::PostgresUserStore, redis_two_fa_code_store::RedisTwoFACodeStore,
+        postgres_email_outbox_store::PostgresEmailOutboxStore,
+        postgres_hotp_store::PostgresHotpStore,
+        postgres_invite_store::PostgresInviteStore,
+        postgres_phone_store::PostgresPhoneStore,
+        postgres_recovery_code_store::PostgresRecoveryCodeStore,
+        postgres_totp_store::PostgresTotpStore,
+        postgres_trusted_device_store::PostgresTrustedDeviceStore,
+        postgres_user_store::PostgresUserStore,
+        postgres_webauthn_credential_store::PostgresWebAuthnCredentialStore,
+        redis_login_attempt_store::RedisLoginAttemptStore,
+        redis_two_fa_code_store::{RedisTwoFACodeStore, LOGIN_CODE_PREFIX, PHONE_CODE_PREFIX},
+        redis_webauthn_ceremony_store::RedisWebAuthnCeremonyStore,
+    },
+    domain::{
+        authentication::AuthMethod, email_domain_policy::EmailDomainPolicy,
+        email_template::EmailTemplates, phone_number::PhoneNumber, Email,
     },
-    domain::Email,
+    email_outbox_worker::EmailOutboxWorker,
     get_postgres_pool, get_redis_client,
     hashset_banned_token_store::HashsetBannedTokenStore,
+    legacy_password_hasher::LegacyPasswordHasher,
     postmark_email_client::PostmarkEmailClient,
-    utils::constants::{test, DATABASE_URL, REDIS_HOST_NAME},
+    twilio_sms_client::TwilioSmsClient,
+    utils::{
+        auth::validate_token,
+        constants::{
+            test, ARGON2_PARAMS, DATABASE_URL, JWT_COOKIE_NAME, PASSWORD_HISTORY_SIZE,
+            REDIS_HOST_NAME,
+        },
+    },
     Application,
 };
 use reqwest::{cookie::Jar, Client};
@@ -18,7 +45,7 @@ use sqlx::{
 };
 use std::str::FromStr;
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use tokio::sync::{Notify, RwLock};
 use uuid::Uuid;
 use wiremock::MockServer;
 
@@ -26,28 +53,110 @@ pub struct TestApp {
     pub address: String,
     pub cookie_jar: Arc<Jar>,
     pub http_client: Client,
+    pub user_store: Arc<RwLock<PostgresUserStore>>,
+    pub db_pool: PgPool,
     pub banned_token_store: Arc<RwLock<HashsetBannedTokenStore>>,
     pub two_fa_code_store: CodeStore,
     pub email_server: MockServer,
+    pub sms_server: MockServer,
     pub db_name: String,
     pub clean_up_called: bool,
 }
 
 impl TestApp {
     pub async fn new() -> Self {
+        Self::with_settings(Settings {
+            admin_api_token: Some(Secret::new(test::ADMIN_API_TOKEN.to_owned())),
+            ..Settings::default()
+        })
+        .await
+    }
+
+    pub async fn with_settings(settings: Settings) -> Self {
         let (pg_pool, db_name) = configure_postgresql().await;
         let redis_conn = configure_redis();
-        let user_store = Arc::new(RwLock::new(PostgresUserStore::new(pg_pool)));
+        let password_hasher = Arc::new(LegacyPasswordHasher::new(Argon2PasswordHasher::new(
+            ARGON2_PARAMS.clone(),
+        )));
+        let user_store = Arc::new(RwLock::new(PostgresUserStore::new(
+            pg_pool.clone(),
+            password_hasher,
+            *PASSWORD_HISTORY_SIZE,
+        )));
+        let invite_store = Arc::new(RwLock::new(PostgresInviteStore::new(pg_pool.clone())));
+        let totp_store = Arc::new(RwLock::new(PostgresTotpStore::new(pg_pool.clone())));
+        let hotp_store = Arc::new(RwLock::new(PostgresHotpStore::new(pg_pool.clone())));
+        let recovery_code_store =
+            Arc::new(RwLock::new(PostgresRecoveryCodeStore::new(pg_pool.clone())));
+        let webauthn_credential_store = Arc::new(RwLock::new(
+            PostgresWebAuthnCredentialStore::new(pg_pool.clone()),
+        ));
+        let phone_store = Arc::new(RwLock::new(PostgresPhoneStore::new(pg_pool.clone())));
+        let trusted_device_store = Arc::new(RwLock::new(PostgresTrustedDeviceStore::new(
+            pg_pool.clone(),
+        )));
+        let email_outbox = Arc::new(RwLock::new(PostgresEmailOutboxStore::new(pg_pool.clone())));
+        let email_outbox_wakeup = Arc::new(Notify::new());
         let token_store = Arc::new(RwLock::new(HashsetBannedTokenStore::new()));
-        let two_fa_code_store = Arc::new(RwLock::new(RedisTwoFACodeStore::new(redis_conn)));
+        let code_ttl = settings.two_fa.code_ttl;
+        let two_fa_code_store = Arc::new(RwLock::new(RedisTwoFACodeStore::new(
+            redis_conn.clone(),
+            code_ttl,
+        )));
+        let login_code_store = Arc::new(RwLock::new(RedisTwoFACodeStore::with_key_prefix(
+            redis_conn.clone(),
+            LOGIN_CODE_PREFIX,
+            code_ttl,
+        )));
+        let phone_code_store = Arc::new(RwLock::new(RedisTwoFACodeStore::with_key_prefix(
+            redis_conn.clone(),
+            PHONE_CODE_PREFIX,
+            code_ttl,
+        )));
+        let webauthn_ceremony_store = Arc::new(RwLock::new(RedisWebAuthnCeremonyStore::new(
+            redis_conn.clone(),
+        )));
+        let login_attempt_store = Arc::new(RwLock::new(RedisLoginAttemptStore::new(redis_conn)));
         let email_server = MockServer::start().await; // New!
         let base_url = email_server.uri(); // New!
         let email_client = Arc::new(configure_postmark_email_client(base_url)); // Updated!
+        let sms_server = MockServer::start().await;
+        let sms_client = Arc::new(configure_twilio_sms_client(sms_server.uri()));
+        let email_domain_policy = Arc::new(RwLock::new(
+            EmailDomainPolicy::load(&settings.email_domains)
+                .expect("Failed to load the email domain lists"),
+        ));
+        let email_templates = Arc::new(
+            EmailTemplates::load(&settings.email_templates)
+                .expect("Failed to load email templates"),
+        );
+        let email_outbox_worker = EmailOutboxWorker::new(
+            email_outbox.clone(),
+            email_client,
+            settings.email_outbox,
+            email_outbox_wakeup.clone(),
+        );
         let app_state = AppState::new(
-            user_store,
+            user_store.clone(),
             token_store.clone(),
             two_fa_code_store.clone(),
-            email_client.clone(),
+            login_code_store,
+            email_templates,
+            email_outbox,
+            email_outbox_wakeup,
+            login_attempt_store,
+            invite_store,
+            email_domain_policy,
+            totp_store,
+            hotp_store,
+            recovery_code_store,
+            webauthn_credential_store,
+            webauthn_ceremony_store,
+            sms_client,
+            phone_store,
+            phone_code_store,
+            trusted_device_store,
+            settings,
         );
         let app = Application::build(app_state, test::APP_ADDRESS)
             .await
@@ -57,6 +166,8 @@ impl TestApp {
 
         #[allow(clippy::let_underscore_future)]
         let _ = tokio::spawn(app.run());
+        #[allow(clippy::let_underscore_future)]
+        let _ = tokio::spawn(email_outbox_worker.run());
 
         let cookie_jar = Arc::new(Jar::default());
         let http_client = Client::builder()
@@ -68,9 +179,12 @@ impl TestApp {
             address,
             cookie_jar,
             http_client,
+            user_store,
+            db_pool: pg_pool,
             banned_token_store: token_store,
             two_fa_code_store,
             email_server,
+            sms_server,
             db_name,
             clean_up_called: false,
         }
@@ -109,6 +223,51 @@ impl TestApp {
             .expect("could not get login route")
     }
 
+    pub async fn post_login_code<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/login/email-code", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("could not get login code route")
+    }
+
+    pub async fn post_verify_login_code<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/login/email-code/verify", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("could not get verify login code route")
+    }
+
+    pub async fn post_magic_link<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/login/magic-link", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("could not get magic link route")
+    }
+
+    pub async fn get_magic_link_callback(&self, token: &str) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/login/magic-link/callback", &self.address))
+            .query(&[("token", token)])
+            .send()
+            .await
+            .expect("could not get magic link callback route")
+    }
+
     pub async fn post_logout(&self) -> reqwest::Response {
         self.http_client
             .post(&format!("{}/logout", &self.address))
@@ -129,6 +288,18 @@ impl TestApp {
             .expect("Failed to execute request.")
     }
 
+    pub async fn post_resend_2fa<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/resend-2fa", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("could not get resend 2fa route")
+    }
+
     pub async fn post_verify_token<Body>(&self, body: &Body) -> reqwest::Response
     where
         Body: serde::Serialize,
@@ -141,7 +312,354 @@ impl TestApp {
             .expect("could not get verify token route")
     }
 
+    pub async fn post_change_password<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/change-password", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("could not get change password route")
+    }
+
+    pub async fn post_forgot_password<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/forgot-password", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("could not get forgot password route")
+    }
+
+    pub async fn post_reset_password<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/reset-password", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("could not get reset password route")
+    }
+
+    pub async fn post_admin_must_change_password<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/admin/must-change-password", &self.address))
+            .bearer_auth(test::ADMIN_API_TOKEN)
+            .json(body)
+            .send()
+            .await
+            .expect("could not get must change password route")
+    }
+
+    pub async fn post_admin_user_status<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/admin/user-status", &self.address))
+            .bearer_auth(test::ADMIN_API_TOKEN)
+            .json(body)
+            .send()
+            .await
+            .expect("could not get user status route")
+    }
+
+    pub async fn post_admin_user_status_history<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/admin/user-status-history", &self.address))
+            .bearer_auth(test::ADMIN_API_TOKEN)
+            .json(body)
+            .send()
+            .await
+            .expect("could not get user status history route")
+    }
+
+    pub async fn post_totp_enroll(&self) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/2fa/totp/enroll", &self.address))
+            .send()
+            .await
+            .expect("could not get totp enroll route")
+    }
+
+    pub async fn post_totp_confirm<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/2fa/totp/confirm", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("could not get totp confirm route")
+    }
+
+    pub async fn post_hotp_enroll<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/2fa/hotp/enroll", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("could not get hotp enroll route")
+    }
+
+    pub async fn post_locale<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/locale", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("could not get locale route")
+    }
+
+    pub async fn post_recovery_codes(&self) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/2fa/recovery-codes", &self.address))
+            .send()
+            .await
+            .expect("could not get recovery codes route")
+    }
+
+    pub async fn post_webauthn_register_start(&self) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/webauthn/register/start", &self.address))
+            .send()
+            .await
+            .expect("could not get webauthn register start route")
+    }
+
+    pub async fn post_webauthn_register_finish<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/webauthn/register/finish", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("could not get webauthn register finish route")
+    }
+
+    pub async fn post_webauthn_login_start<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/webauthn/login/start", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("could not get webauthn login start route")
+    }
+
+    pub async fn post_webauthn_login_finish<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/webauthn/login/finish", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("could not get webauthn login finish route")
+    }
+
+    pub async fn post_sms_phone<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/2fa/sms/phone", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("could not get sms phone route")
+    }
+
+    pub async fn post_sms_phone_verify<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/2fa/sms/phone/verify", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("could not get sms phone verify route")
+    }
+
+    pub async fn get_2fa_methods(&self) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/2fa/methods", &self.address))
+            .send()
+            .await
+            .expect("could not get 2fa methods route")
+    }
+
+    pub async fn post_2fa_preferred_method<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/2fa/methods/preferred", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("could not get 2fa preferred method route")
+    }
+
+    pub async fn get_trusted_devices(&self) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/trusted-devices", &self.address))
+            .send()
+            .await
+            .expect("could not get trusted devices route")
+    }
+
+    pub async fn post_revoke_trusted_device<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/trusted-devices/revoke", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("could not get revoke trusted device route")
+    }
+
+    pub async fn post_admin_invite<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/admin/invites", &self.address))
+            .bearer_auth(test::ADMIN_API_TOKEN)
+            .json(body)
+            .send()
+            .await
+            .expect("could not get invites route")
+    }
+
+    pub async fn post_admin_reload_email_domains(&self) -> reqwest::Response {
+        self.http_client
+            .post(format!("{}/admin/email-domains/reload", &self.address))
+            .bearer_auth(test::ADMIN_API_TOKEN)
+            .send()
+            .await
+            .expect("could not get reload email domains route")
+    }
+
+    // Moves the user's last password change back in time
+    pub async fn age_password(&self, email: &str, age: chrono::Duration) {
+        sqlx::query("UPDATE users SET password_changed_at = NOW() - $1 WHERE email = $2")
+            .bind(age)
+            .bind(email)
+            .execute(&self.db_pool)
+            .await
+            .expect("Failed to age password");
+    }
+
+    pub async fn get_admin_dead_letters(&self) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/admin/email-outbox/dead-letters", &self.address))
+            .bearer_auth(test::ADMIN_API_TOKEN)
+            .send()
+            .await
+            .expect("could not get dead letters route")
+    }
+
+    pub async fn post_admin_replay_dead_letter<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!(
+                "{}/admin/email-outbox/dead-letters/replay",
+                &self.address
+            ))
+            .bearer_auth(test::ADMIN_API_TOKEN)
+            .json(body)
+            .send()
+            .await
+            .expect("could not get replay dead letter route")
+    }
+
+    // Returns how the user logged in, from the auth cookie set by `response`
+    pub async fn auth_methods(&self, response: &reqwest::Response) -> Vec<AuthMethod> {
+        let token = response
+            .cookies()
+            .find(|cookie| cookie.name() == JWT_COOKIE_NAME)
+            .expect("No auth cookie found")
+            .value()
+            .to_owned();
+        validate_token(Secret::new(token), self.banned_token_store.clone())
+            .await
+            .expect("Auth cookie is not a valid auth token")
+            .amr
+    }
+
+    // Returns the most recent request sent to the mock email server
+    pub async fn last_email(&self) -> serde_json::Value {
+        self.wait_for_emails().await;
+        let requests = self
+            .email_server
+            .received_requests()
+            .await
+            .expect("Request recording is disabled");
+        let request = requests.last().expect("No email was sent");
+        serde_json::from_slice(&request.body).expect("Email body is not JSON")
+    }
+
+    // Returns the text body of the most recent email sent through the mock email server
+    pub async fn last_email_text(&self) -> String {
+        self.last_email().await["TextBody"]
+            .as_str()
+            .expect("Email has no text body")
+            .to_owned()
+    }
+
+    // Emails go out from the outbox in the background. This waits until the worker has tried
+    // to send every email queued so far.
+    pub async fn wait_for_emails(&self) {
+        for _ in 0..250 {
+            let untried: i64 = sqlx::query_scalar(
+                "SELECT COUNT(*) FROM email_outbox WHERE status = 'pending' AND attempts = 0",
+            )
+            .fetch_one(&self.db_pool)
+            .await
+            .expect("Failed to count queued emails");
+            if untried == 0 {
+                return;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        panic!("Queued emails were not sent in time");
+    }
+
     pub async fn clean_up(&mut self) {
+        // The email server checks its expectations when dropped, which has to be after the
+        // emails went out
+        self.wait_for_emails().await;
         delete_database(&self.db_name).await;
         self.clean_up_called = true
     }
@@ -255,9 +773,10 @@ async fn delete_database(db_name: &str) {
         .await
         .expect("Failed to drop the database.");
 
-    // Drop the database
+    // Drop the database. The email outbox worker may still be polling it and reconnect after
+    // the backends above were terminated, so FORCE ends those connections too.
     connection
-        .execute(format!(r#"DROP DATABASE "{}";"#, db_name).as_str())
+        .execute(format!(r#"DROP DATABASE "{}" WITH (FORCE);"#, db_name).as_str())
         .await
         .expect("Failed to drop the database.");
 }
@@ -274,3 +793,20 @@ fn configure_postmark_email_client(base_url: String) -> PostmarkEmailClient {
 
     PostmarkEmailClient::new(base_url, sender, postmark_auth_token, http_client)
 }
+
+fn configure_twilio_sms_client(base_url: String) -> TwilioSmsClient {
+    let sender = PhoneNumber::parse(Secret::new(test::sms_client::SENDER.to_owned())).unwrap();
+
+    let http_client = Client::builder()
+        .timeout(test::sms_client::TIMEOUT)
+        .build()
+        .expect("Failed to build HTTP client");
+
+    TwilioSmsClient::new(
+        base_url,
+        test::sms_client::ACCOUNT_SID.to_owned(),
+        Secret::new("auth_token".to_owned()),
+        sender,
+        http_client,
+    )
+}
@@ -0,0 +1,183 @@
+use crate::helpers::{get_random_email, TestApp};
+use auth_service::app_state::settings::{HotpSettings, Settings};
+use auth_service::data_stores::postgres_hotp_store::PostgresHotpStore;
+use auth_service::domain::{
+    data_store::HotpStore,
+    hotp::{HotpSecret, HotpToken},
+    two_factor::TwoFactorMethod,
+};
+use auth_service::routes::hotp::EnrollHotpResponse;
+use auth_service::routes::login::TwoFactorAuthResponse;
+use secrecy::Secret;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+const SEED: &str = "3132333435363738393031323334353637383930";
+
+// Imports a token the way the seed file import does
+async fn import_token(app: &TestApp, serial: &str) -> HotpToken {
+    let secret = HotpSecret::parse(Secret::new(SEED.to_owned())).unwrap();
+    let token = HotpToken::new(serial, secret, 6, 0).unwrap();
+    PostgresHotpStore::new(app.db_pool.clone())
+        .add_token(token.clone())
+        .await
+        .expect("Failed to import token");
+    token
+}
+
+// Signs up a user without 2FA and logs in so the cookie jar holds an auth cookie
+async fn logged_in_user(app: &TestApp) -> serde_json::Value {
+    let login_body = serde_json::json!({
+        "email": get_random_email(),
+        "password": "password123",
+        "requires2FA": false
+    });
+    assert_eq!(app.post_signup(&login_body).await.status().as_u16(), 201);
+    assert_eq!(app.post_login(&login_body).await.status().as_u16(), 200);
+    login_body
+}
+
+async fn verify_with(app: &TestApp, login_body: &serde_json::Value, code: &str) -> u16 {
+    let login = app
+        .post_login(login_body)
+        .await
+        .json::<TwoFactorAuthResponse>()
+        .await
+        .expect("Login did not ask for 2FA");
+    assert_eq!(login.methods[0], TwoFactorMethod::Hotp);
+
+    app.post_verify_2fa(&serde_json::json!({
+        "email": login_body["email"],
+        "loginAttemptId": login.login_attempt_id,
+        "2FACode": code,
+        "method": "hotp"
+    }))
+    .await
+    .status()
+    .as_u16()
+}
+
+#[tokio::test]
+async fn should_enroll_a_hardware_token_and_accept_its_codes_at_login() {
+    let mut app = TestApp::new().await;
+    // Token users are never emailed codes
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let token = import_token(&app, "FOB-0001").await;
+    let login_body = logged_in_user(&app).await;
+
+    let response = app
+        .post_hotp_enroll(&serde_json::json!({
+            "serial": "FOB-0001",
+            "code": token.code_at(0).unwrap()
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let response = response
+        .json::<EnrollHotpResponse>()
+        .await
+        .expect("Could not deserialize response body to EnrollHotpResponse");
+    assert!(response
+        .recovery_codes
+        .is_some_and(|codes| !codes.is_empty()));
+    app.post_logout().await;
+
+    // Presses that never reached the server are skipped over
+    assert_eq!(
+        verify_with(&app, &login_body, &token.code_at(3).unwrap()).await,
+        200
+    );
+    app.post_logout().await;
+
+    // Neither the same code nor an earlier one works again
+    assert_eq!(
+        verify_with(&app, &login_body, &token.code_at(3).unwrap()).await,
+        400
+    );
+    assert_eq!(
+        verify_with(&app, &login_body, &token.code_at(2).unwrap()).await,
+        400
+    );
+    assert_eq!(
+        verify_with(&app, &login_body, &token.code_at(4).unwrap()).await,
+        200
+    );
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_only_accept_codes_within_the_look_ahead_window() {
+    let mut app = TestApp::with_settings(Settings {
+        hotp: HotpSettings { look_ahead: 2 },
+        ..Settings::default()
+    })
+    .await;
+    let token = import_token(&app, "FOB-0001").await;
+    import_token(&app, "FOB-0002").await;
+    let login_body = logged_in_user(&app).await;
+
+    let enroll_body = |serial: &str, counter: u64| {
+        serde_json::json!({
+            "serial": serial,
+            "code": token.code_at(counter).unwrap()
+        })
+    };
+
+    let response = app.post_hotp_enroll(&enroll_body("FOB-0001", 3)).await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    let response = app.post_hotp_enroll(&enroll_body("FOB-0001", 2)).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // The account now has 2FA, so changing its setup takes a login with the token
+    assert_eq!(
+        verify_with(&app, &login_body, &token.code_at(5).unwrap()).await,
+        200
+    );
+    let response = app.post_hotp_enroll(&enroll_body("FOB-0002", 0)).await;
+    assert_eq!(response.status().as_u16(), 409);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_not_enroll_unknown_or_taken_tokens() {
+    let mut app = TestApp::new().await;
+    let token = import_token(&app, "FOB-0001").await;
+
+    logged_in_user(&app).await;
+    let response = app
+        .post_hotp_enroll(&serde_json::json!({
+            "serial": "FOB-9999",
+            "code": token.code_at(0).unwrap()
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    let response = app
+        .post_hotp_enroll(&serde_json::json!({
+            "serial": "FOB-0001",
+            "code": token.code_at(0).unwrap()
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Someone else holding a valid code can't take the token over
+    logged_in_user(&app).await;
+    let response = app
+        .post_hotp_enroll(&serde_json::json!({
+            "serial": "FOB-0001",
+            "code": token.code_at(1).unwrap()
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    app.clean_up().await;
+}
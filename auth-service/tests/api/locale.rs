@@ -0,0 +1,125 @@
+use crate::helpers::{get_random_email, TestApp};
+use auth_service::app_state::settings::{EmailTemplateSettings, Settings};
+use auth_service::ErrorResponse;
+use std::path::PathBuf;
+use uuid::Uuid;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+// A template directory with a German password reset email
+fn german_templates() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("email-templates-{}", Uuid::new_v4()));
+    let de = dir.join("de");
+    std::fs::create_dir_all(&de).expect("Failed to create template directory");
+    for (file, contents) in [
+        ("password_reset.subject.txt", "Passwort zurücksetzen\n"),
+        ("password_reset.txt", "Ihr Token: {{ token }}\n"),
+        (
+            "password_reset.html",
+            "<p>Ihr Token: <b>{{ token }}</b></p>\n",
+        ),
+    ] {
+        std::fs::write(de.join(file), contents).expect("Failed to write template");
+    }
+    dir
+}
+
+#[tokio::test]
+async fn emails_are_sent_in_the_users_locale() {
+    let templates = german_templates();
+    let mut app = TestApp::with_settings(Settings {
+        email_templates: EmailTemplateSettings {
+            dirs: vec![templates.clone()],
+            ..EmailTemplateSettings::default()
+        },
+        ..Settings::default()
+    })
+    .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let email = get_random_email();
+    let signup_body = serde_json::json!({
+        "email": email,
+        "password": "password123",
+        "requires2FA": false,
+        "locale": "de_AT"
+    });
+    assert_eq!(app.post_signup(&signup_body).await.status().as_u16(), 201);
+
+    let forgot_body = serde_json::json!({ "email": email });
+    assert_eq!(
+        app.post_forgot_password(&forgot_body)
+            .await
+            .status()
+            .as_u16(),
+        200
+    );
+    let sent = app.last_email().await;
+    assert_eq!(sent["Subject"], "Passwort zurücksetzen");
+    assert!(sent["TextBody"]
+        .as_str()
+        .unwrap()
+        .starts_with("Ihr Token: "));
+    assert!(sent["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .starts_with("<p>Ihr Token: <b>"));
+
+    // Without a locale the user gets the built-in English emails
+    assert_eq!(app.post_login(&signup_body).await.status().as_u16(), 200);
+    let response = app
+        .post_locale(&serde_json::json!({ "locale": null }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    app.post_forgot_password(&forgot_body).await;
+    let sent = app.last_email().await;
+    assert_eq!(sent["Subject"], "Reset your password");
+    assert_ne!(sent["TextBody"], sent["HtmlBody"]);
+
+    std::fs::remove_dir_all(templates).ok();
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_return_400_for_invalid_locale() {
+    let mut app = TestApp::new().await;
+
+    let signup_body = serde_json::json!({
+        "email": get_random_email(),
+        "password": "password123",
+        "requires2FA": false,
+        "locale": "../../etc"
+    });
+    let response = app.post_signup(&signup_body).await;
+    assert_eq!(response.status().as_u16(), 400);
+    assert_eq!(
+        response
+            .json::<ErrorResponse>()
+            .await
+            .expect("Could not deserialize response body to ErrorResponse")
+            .error,
+        "Invalid locale"
+    );
+
+    let signup_body = serde_json::json!({
+        "email": signup_body["email"],
+        "password": "password123",
+        "requires2FA": false
+    });
+    assert_eq!(app.post_signup(&signup_body).await.status().as_u16(), 201);
+    assert_eq!(app.post_login(&signup_body).await.status().as_u16(), 200);
+
+    let response = app
+        .post_locale(&serde_json::json!({ "locale": "english" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    app.clean_up().await;
+}
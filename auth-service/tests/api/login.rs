@@ -1,7 +1,16 @@
 use crate::helpers::{get_random_email, TestApp};
 use auth_service::{
-    routes::login::TwoFactorAuthResponse, utils::constants::JWT_COOKIE_NAME, ErrorResponse,
+    app_state::settings::{LockoutPolicy, Settings},
+    domain::{
+        data_store::{LoginAttemptId, TwoFaCodeStore, UserStore},
+        user::PasswordChangeReason,
+        Email,
+    },
+    routes::login::{PasswordChangeRequiredResponse, TwoFactorAuthResponse},
+    utils::constants::JWT_COOKIE_NAME,
+    ErrorResponse,
 };
+use secrecy::{ExposeSecret, Secret};
 use wiremock::{
     matchers::{method, path},
     Mock, ResponseTemplate,
@@ -27,8 +36,11 @@ async fn should_return_401_if_incorrect_credentials() {
     // that a 401 HTTP status code is returned along with the appropriate error message.
     let mut app = TestApp::new().await;
 
+    // Failed logins are counted in Redis, which outlives the per-test database, so a fixed
+    // address would eventually get locked out across test runs
+    let random_email = get_random_email();
     let login = serde_json::json!({
-        "email": "existing_user@mail.com",
+        "email": random_email,
         "password": "correct_password",
         "requires2FA": true
     });
@@ -36,7 +48,7 @@ async fn should_return_401_if_incorrect_credentials() {
     assert_eq!(response.status().as_u16(), 201);
 
     let user = serde_json::json!({
-        "email": "existing_user@mail.com",
+        "email": random_email,
         "password": "dfjas:dlfkjasd:fljkad",
         "requires2FA": true
     });
@@ -150,3 +162,356 @@ async fn should_return_206_if_valid_credentials_and_2fa_enabled() {
     assert_eq!(true, true);
     app.clean_up().await;
 }
+
+#[tokio::test]
+async fn should_upgrade_legacy_bcrypt_hash_after_successful_login() {
+    let mut app = TestApp::new().await;
+
+    let random_email = get_random_email();
+    let email = Email::parse(Secret::new(random_email.clone())).unwrap();
+    let legacy_hash = bcrypt::hash("password123", 4).unwrap();
+
+    app.user_store
+        .read()
+        .await
+        .import_user(&email, Secret::new(legacy_hash.clone()), false)
+        .await
+        .expect("Failed to import legacy user");
+
+    let login_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+        "requires2FA": false
+    });
+
+    let response = app.post_login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // The upgrade happens in the background, so give it a moment to land.
+    let mut upgraded_hash = None;
+    for _ in 0..50 {
+        let user = app.user_store.read().await.get_user(&email).await.unwrap();
+        let stored_hash = user.password.as_ref().expose_secret().to_owned();
+        if stored_hash != legacy_hash {
+            upgraded_hash = Some(stored_hash);
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+
+    let upgraded_hash = upgraded_hash.expect("Legacy hash was not upgraded");
+    assert!(upgraded_hash.starts_with("$argon2id$"));
+
+    let response = app.post_login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_require_password_change_when_flagged_by_admin() {
+    let mut app = TestApp::new().await;
+
+    let random_email = get_random_email();
+    let signup_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+        "requires2FA": false
+    });
+    let response = app.post_signup(&signup_body).await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    let response = app
+        .post_admin_must_change_password(&serde_json::json!({
+            "email": random_email,
+            "mustChangePassword": true
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let login_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+        "requires2FA": false
+    });
+    let response = app.post_login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response
+        .cookies()
+        .all(|cookie| cookie.name() != JWT_COOKIE_NAME));
+
+    let body = response
+        .json::<PasswordChangeRequiredResponse>()
+        .await
+        .expect("Could not deserialize response body to PasswordChangeRequiredResponse");
+    assert_eq!(body.reason, PasswordChangeReason::Required);
+
+    // The token is not an auth token
+    let response = app
+        .post_verify_token(&serde_json::json!({ "token": body.password_change_token }))
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    let change_body = serde_json::json!({
+        "currentPassword": "password123",
+        "newPassword": "newpassword123",
+        "passwordChangeToken": body.password_change_token
+    });
+    let response = app.post_change_password(&change_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // The token is single use
+    let response = app.post_change_password(&change_body).await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    let login_body = serde_json::json!({
+        "email": random_email,
+        "password": "newpassword123",
+        "requires2FA": false
+    });
+    let response = app.post_login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response
+        .cookies()
+        .any(|cookie| cookie.name() == JWT_COOKIE_NAME));
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_require_password_change_when_password_expired() {
+    let mut app = TestApp::with_settings(Settings {
+        password_max_age: Some(chrono::Duration::days(90)),
+        ..Settings::default()
+    })
+    .await;
+
+    let random_email = get_random_email();
+    let signup_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+        "requires2FA": false
+    });
+    let response = app.post_signup(&signup_body).await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    let login_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+        "requires2FA": false
+    });
+    let response = app.post_login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response
+        .cookies()
+        .any(|cookie| cookie.name() == JWT_COOKIE_NAME));
+
+    app.age_password(&random_email, chrono::Duration::days(91))
+        .await;
+
+    let response = app.post_login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response
+        .cookies()
+        .all(|cookie| cookie.name() != JWT_COOKIE_NAME));
+    let body = response
+        .json::<PasswordChangeRequiredResponse>()
+        .await
+        .expect("Could not deserialize response body to PasswordChangeRequiredResponse");
+    assert_eq!(body.reason, PasswordChangeReason::Expired);
+
+    app.clean_up().await;
+}
+
+fn lockout_settings() -> Settings {
+    Settings {
+        lockout: LockoutPolicy {
+            max_failures: 3,
+            base_duration: chrono::Duration::seconds(60),
+            max_duration: chrono::Duration::seconds(600),
+        },
+        ..Settings::default()
+    }
+}
+
+#[tokio::test]
+async fn should_return_423_and_email_user_after_too_many_failed_logins() {
+    let mut app = TestApp::with_settings(lockout_settings()).await;
+
+    let random_email = get_random_email();
+    let signup_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+        "requires2FA": false
+    });
+    let response = app.post_signup(&signup_body).await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let wrong_login = serde_json::json!({
+        "email": random_email,
+        "password": "wrongpassword",
+        "requires2FA": false
+    });
+    for _ in 0..2 {
+        let response = app.post_login(&wrong_login).await;
+        assert_eq!(response.status().as_u16(), 401);
+    }
+
+    let response = app.post_login(&wrong_login).await;
+    assert_eq!(response.status().as_u16(), 423);
+    assert!(app
+        .last_email_text()
+        .await
+        .contains("3 failed login attempts"));
+
+    // The correct password is refused while the lockout lasts
+    let response = app.post_login(&signup_body).await;
+    assert_eq!(response.status().as_u16(), 423);
+    assert_eq!(
+        response
+            .json::<ErrorResponse>()
+            .await
+            .expect("Could not deserialize response body to ErrorResponse")
+            .error,
+        "Account temporarily locked after too many failed login attempts".to_owned()
+    );
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_count_wrong_2fa_codes_towards_the_lockout() {
+    let mut app = TestApp::with_settings(lockout_settings()).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let random_email = get_random_email();
+    let login_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+        "requires2FA": true
+    });
+    assert_eq!(app.post_signup(&login_body).await.status().as_u16(), 201);
+
+    let wrong_login = serde_json::json!({
+        "email": random_email,
+        "password": "wrongpassword",
+        "requires2FA": true
+    });
+    assert_eq!(app.post_login(&wrong_login).await.status().as_u16(), 401);
+
+    // The right password alone doesn't clear the failures, and each new attempt's wrong code
+    // adds to them
+    let email = Email::parse(Secret::new(random_email.clone())).unwrap();
+    let mut statuses = Vec::new();
+    for _ in 0..2 {
+        let response = app.post_login(&login_body).await;
+        assert_eq!(response.status().as_u16(), 200);
+        let login_attempt_id = response
+            .json::<TwoFactorAuthResponse>()
+            .await
+            .expect("Could not deserialize response body to TwoFactorAuthResponse")
+            .login_attempt_id;
+        let code = app
+            .two_fa_code_store
+            .read()
+            .await
+            .get_code(
+                &email,
+                &LoginAttemptId::parse(Secret::new(login_attempt_id.clone())).unwrap(),
+            )
+            .await
+            .unwrap();
+        let wrong_code = if code.as_ref().expose_secret() == "123456" {
+            "654321"
+        } else {
+            "123456"
+        };
+
+        let response = app
+            .post_verify_2fa(&serde_json::json!({
+                "email": random_email,
+                "loginAttemptId": login_attempt_id,
+                "2FACode": wrong_code
+            }))
+            .await;
+        statuses.push(response.status().as_u16());
+    }
+    assert_eq!(statuses, vec![400, 423]);
+
+    let response = app.post_login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 423);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_reset_failed_login_count_after_successful_login() {
+    let mut app = TestApp::with_settings(lockout_settings()).await;
+
+    let random_email = get_random_email();
+    let signup_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+        "requires2FA": false
+    });
+    let response = app.post_signup(&signup_body).await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    let wrong_login = serde_json::json!({
+        "email": random_email,
+        "password": "wrongpassword",
+        "requires2FA": false
+    });
+    for _ in 0..2 {
+        let response = app.post_login(&wrong_login).await;
+        assert_eq!(response.status().as_u16(), 401);
+    }
+
+    let response = app.post_login(&signup_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    for _ in 0..2 {
+        let response = app.post_login(&wrong_login).await;
+        assert_eq!(response.status().as_u16(), 401);
+    }
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_lock_out_unknown_accounts_without_sending_email() {
+    let mut app = TestApp::with_settings(lockout_settings()).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let wrong_login = serde_json::json!({
+        "email": get_random_email(),
+        "password": "wrongpassword",
+        "requires2FA": false
+    });
+    for _ in 0..2 {
+        let response = app.post_login(&wrong_login).await;
+        assert_eq!(response.status().as_u16(), 401);
+    }
+
+    let response = app.post_login(&wrong_login).await;
+    assert_eq!(response.status().as_u16(), 423);
+
+    app.clean_up().await;
+}
@@ -0,0 +1,281 @@
+use crate::helpers::{get_random_email, TestApp};
+use auth_service::{
+    domain::{
+        authentication::AuthMethod,
+        data_store::{LoginAttemptId, TwoFaCodeStore},
+        two_factor::TwoFactorMethod,
+        user::PasswordChangeReason,
+        Email,
+    },
+    routes::{
+        login::{PasswordChangeRequiredResponse, TwoFactorAuthResponse},
+        SignupResponse,
+    },
+    utils::constants::JWT_COOKIE_NAME,
+};
+use secrecy::{ExposeSecret, Secret};
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+async fn signup(app: &TestApp, email: &str, requires_2fa: bool) {
+    let signup_body = serde_json::json!({
+        "email": email,
+        "password": "password123",
+        "requires2FA": requires_2fa
+    });
+    let response = app.post_signup(&signup_body).await;
+    assert_eq!(response.status().as_u16(), 201);
+}
+
+async fn request_magic_link_token(app: &TestApp, email: &str) -> String {
+    let response = app
+        .post_magic_link(&serde_json::json!({ "email": email }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let text = app.last_email_text().await;
+    text.split("token=")
+        .nth(1)
+        .and_then(|rest| rest.split_whitespace().next())
+        .expect("No magic link in email")
+        .to_owned()
+}
+
+#[tokio::test]
+async fn should_not_send_email_for_unknown_user() {
+    let mut app = TestApp::new().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_magic_link(&serde_json::json!({ "email": get_random_email() }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_not_send_another_link_during_the_cooldown() {
+    let mut app = TestApp::new().await;
+    let random_email = get_random_email();
+    signup(&app, &random_email, false).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    request_magic_link_token(&app, &random_email).await;
+    let response = app
+        .post_magic_link(&serde_json::json!({ "email": random_email }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Login codes are held back as well
+    let response = app
+        .post_login_code(&serde_json::json!({ "email": random_email }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_log_in_once_with_magic_link() {
+    let mut app = TestApp::new().await;
+    let random_email = get_random_email();
+    signup(&app, &random_email, false).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let token = request_magic_link_token(&app, &random_email).await;
+
+    let response = app.get_magic_link_callback(&token).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let auth_cookie = response
+        .cookies()
+        .find(|cookie| cookie.name() == JWT_COOKIE_NAME)
+        .expect("No auth cookie found");
+    assert!(!auth_cookie.value().is_empty());
+
+    let response = app.get_magic_link_callback(&token).await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_require_password_change_when_flagged_by_admin() {
+    let mut app = TestApp::new().await;
+    let random_email = get_random_email();
+    signup(&app, &random_email, false).await;
+
+    let response = app
+        .post_admin_must_change_password(&serde_json::json!({
+            "email": random_email,
+            "mustChangePassword": true
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let token = request_magic_link_token(&app, &random_email).await;
+
+    let response = app.get_magic_link_callback(&token).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response
+        .cookies()
+        .all(|cookie| cookie.name() != JWT_COOKIE_NAME));
+    let body = response
+        .json::<PasswordChangeRequiredResponse>()
+        .await
+        .expect("Could not deserialize response body to PasswordChangeRequiredResponse");
+    assert_eq!(body.reason, PasswordChangeReason::Required);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_require_a_non_email_second_factor_after_a_magic_link() {
+    let mut app = TestApp::new().await;
+    let random_email = get_random_email();
+    let recovery_codes = app
+        .post_signup(&serde_json::json!({
+            "email": random_email,
+            "password": "password123",
+            "requires2FA": true
+        }))
+        .await
+        .json::<SignupResponse>()
+        .await
+        .expect("Could not deserialize response body to SignupResponse")
+        .recovery_codes
+        .expect("No recovery codes in signup response");
+
+    // Only the magic link is emailed, no 2FA code
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let token = request_magic_link_token(&app, &random_email).await;
+
+    let response = app.get_magic_link_callback(&token).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response
+        .cookies()
+        .all(|cookie| cookie.name() != JWT_COOKIE_NAME));
+    let json_body = response
+        .json::<TwoFactorAuthResponse>()
+        .await
+        .expect("Could not deserialize response body to TwoFactorAuthResponse");
+    assert_eq!(json_body.methods, vec![TwoFactorMethod::RecoveryCode]);
+
+    // The attempt's code can't be emailed, or used if it somehow was
+    let email = Email::parse(Secret::new(random_email.clone())).unwrap();
+    let login_attempt_id =
+        LoginAttemptId::parse(Secret::new(json_body.login_attempt_id.clone())).unwrap();
+    let code = app
+        .two_fa_code_store
+        .read()
+        .await
+        .get_code(&email, &login_attempt_id)
+        .await
+        .expect("Failed to get code");
+    let response = app
+        .post_resend_2fa(&serde_json::json!({
+            "email": random_email,
+            "loginAttemptId": json_body.login_attempt_id,
+            "method": "email"
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+    for method in [Some("email"), None] {
+        let response = app
+            .post_verify_2fa(&serde_json::json!({
+                "email": random_email,
+                "loginAttemptId": json_body.login_attempt_id,
+                "2FACode": code.as_ref().expose_secret(),
+                "method": method
+            }))
+            .await;
+        assert_eq!(response.status().as_u16(), 400);
+    }
+
+    let response = app
+        .post_verify_2fa(&serde_json::json!({
+            "email": random_email,
+            "loginAttemptId": json_body.login_attempt_id,
+            "2FACode": recovery_codes[0]
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        app.auth_methods(&response).await,
+        vec![AuthMethod::Email, AuthMethod::RecoveryCode]
+    );
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_refuse_a_magic_link_when_email_is_the_only_second_factor() {
+    let mut app = TestApp::new().await;
+    let random_email = get_random_email();
+    signup(&app, &random_email, true).await;
+    sqlx::query("DELETE FROM recovery_codes WHERE email = $1")
+        .bind(&random_email)
+        .execute(&app.db_pool)
+        .await
+        .expect("Failed to delete recovery codes");
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let token = request_magic_link_token(&app, &random_email).await;
+
+    let response = app.get_magic_link_callback(&token).await;
+    assert_eq!(response.status().as_u16(), 403);
+    assert!(response
+        .cookies()
+        .all(|cookie| cookie.name() != JWT_COOKIE_NAME));
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_return_401_if_token_invalid() {
+    let mut app = TestApp::new().await;
+
+    let response = app.get_magic_link_callback("invalid_token").await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    app.clean_up().await;
+}
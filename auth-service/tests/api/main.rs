@@ -1,7 +1,23 @@
+mod admin;
+mod change_password;
+mod email_code_login;
+mod email_outbox;
 mod helpers;
+mod hotp;
+mod locale;
 mod login;
 mod logout;
+mod magic_link;
+mod recovery_codes;
+mod resend_2fa;
+mod reset_password;
 mod root;
 mod signup;
+mod sms;
+mod step_up;
+mod totp;
+mod trusted_devices;
+mod two_factor_methods;
 mod verify_2fa;
 mod verify_token;
+mod webauthn;
@@ -0,0 +1,174 @@
+use crate::helpers::{get_random_email, TestApp};
+use auth_service::domain::authentication::AuthMethod;
+use auth_service::routes::login::TwoFactorAuthResponse;
+use auth_service::routes::recovery_codes::RecoveryCodesResponse;
+use auth_service::routes::SignupResponse;
+use wiremock::{
+    matchers::{body_string_contains, method, path},
+    Mock, ResponseTemplate,
+};
+
+// Signs up a user with 2FA and returns the recovery codes shown at signup
+async fn signup_with_2fa(app: &TestApp, email: &str) -> Vec<String> {
+    let response = app
+        .post_signup(&serde_json::json!({
+            "email": email,
+            "password": "password123",
+            "requires2FA": true
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    response
+        .json::<SignupResponse>()
+        .await
+        .expect("Could not deserialize response body to SignupResponse")
+        .recovery_codes
+        .expect("No recovery codes in signup response")
+}
+
+// Logs in with the password and completes 2FA with the given code
+async fn login_with_code(app: &TestApp, email: &str, code: &str) -> reqwest::Response {
+    let response = app
+        .post_login(&serde_json::json!({
+            "email": email,
+            "password": "password123",
+            "requires2FA": true
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let login_attempt_id = response
+        .json::<TwoFactorAuthResponse>()
+        .await
+        .expect("Could not deserialize response body to TwoFactorAuthResponse")
+        .login_attempt_id;
+
+    app.post_verify_2fa(&serde_json::json!({
+        "email": email,
+        "loginAttemptId": login_attempt_id,
+        "2FACode": code
+    }))
+    .await
+}
+
+fn mock_email_server_ok() -> Mock {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+}
+
+#[tokio::test]
+async fn should_return_recovery_codes_only_for_2fa_signups() {
+    let mut app = TestApp::new().await;
+
+    let codes = signup_with_2fa(&app, &get_random_email()).await;
+    assert_eq!(codes.len(), 10);
+
+    let response = app
+        .post_signup(&serde_json::json!({
+            "email": get_random_email(),
+            "password": "password123",
+            "requires2FA": false
+        }))
+        .await;
+    let body = response
+        .json::<SignupResponse>()
+        .await
+        .expect("Could not deserialize response body to SignupResponse");
+    assert!(body.recovery_codes.is_none());
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_accept_recovery_code_in_verify_2fa_once() {
+    let mut app = TestApp::new().await;
+    mock_email_server_ok().mount(&app.email_server).await;
+    let email = get_random_email();
+    let codes = signup_with_2fa(&app, &email).await;
+
+    // Codes are accepted however the user happens to type them
+    let typed = codes[0].replace('-', "").to_lowercase();
+    let response = login_with_code(&app, &email, &typed).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        app.auth_methods(&response).await,
+        vec![AuthMethod::Pwd, AuthMethod::RecoveryCode]
+    );
+
+    let response = login_with_code(&app, &email, &codes[0]).await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_replace_codes_when_regenerated() {
+    let mut app = TestApp::new().await;
+    mock_email_server_ok().mount(&app.email_server).await;
+    let email = get_random_email();
+    let old_codes = signup_with_2fa(&app, &email).await;
+    assert_eq!(
+        login_with_code(&app, &email, &old_codes[0])
+            .await
+            .status()
+            .as_u16(),
+        200
+    );
+
+    let response = app.post_recovery_codes().await;
+    assert_eq!(response.status().as_u16(), 200);
+    let new_codes = response
+        .json::<RecoveryCodesResponse>()
+        .await
+        .expect("Could not deserialize response body to RecoveryCodesResponse")
+        .recovery_codes;
+    assert_eq!(new_codes.len(), 10);
+
+    let response = login_with_code(&app, &email, &old_codes[1]).await;
+    assert_eq!(response.status().as_u16(), 400);
+    let response = login_with_code(&app, &email, &new_codes[0]).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_return_400_when_regenerating_without_2fa() {
+    let mut app = TestApp::new().await;
+    let body = serde_json::json!({
+        "email": get_random_email(),
+        "password": "password123",
+        "requires2FA": false
+    });
+    assert_eq!(app.post_signup(&body).await.status().as_u16(), 201);
+    assert_eq!(app.post_login(&body).await.status().as_u16(), 200);
+
+    let response = app.post_recovery_codes().await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_warn_by_email_when_recovery_codes_run_low() {
+    let mut app = TestApp::new().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .and(body_string_contains("running low on recovery codes"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    mock_email_server_ok().mount(&app.email_server).await;
+    let email = get_random_email();
+    let codes = signup_with_2fa(&app, &email).await;
+
+    // Using the seventh code leaves three, which is when the warning goes out
+    for code in &codes[..7] {
+        let response = login_with_code(&app, &email, code).await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+
+    app.clean_up().await;
+}
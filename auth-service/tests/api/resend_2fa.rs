@@ -0,0 +1,191 @@
+use crate::helpers::{get_random_email, TestApp};
+use auth_service::app_state::settings::{Settings, TwoFactorSettings};
+use auth_service::domain::{
+    data_store::{LoginAttemptId, TwoFaCodeStore},
+    Email,
+};
+use auth_service::routes::login::TwoFactorAuthResponse;
+use auth_service::utils::constants::test;
+use chrono::Duration;
+use secrecy::{ExposeSecret, Secret};
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+async fn app_with_resends(resend_cooldown: Duration, max_resends: u32) -> TestApp {
+    let app = TestApp::with_settings(Settings {
+        admin_api_token: Some(Secret::new(test::ADMIN_API_TOKEN.to_owned())),
+        two_fa: TwoFactorSettings {
+            resend_cooldown,
+            max_resends,
+            ..TwoFactorSettings::default()
+        },
+        ..Settings::default()
+    })
+    .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app
+}
+
+// Signs up a 2FA user and starts a login, returning the email and login attempt id
+async fn start_2fa_login(app: &TestApp) -> (String, String) {
+    let email = get_random_email();
+    let login_body = serde_json::json!({
+        "email": email,
+        "password": "password123",
+        "requires2FA": true
+    });
+    assert_eq!(app.post_signup(&login_body).await.status().as_u16(), 201);
+
+    let login_attempt_id = app
+        .post_login(&login_body)
+        .await
+        .json::<TwoFactorAuthResponse>()
+        .await
+        .expect("Could not deserialize response body to TwoFactorAuthResponse")
+        .login_attempt_id;
+    (email, login_attempt_id)
+}
+
+async fn stored_2fa_code(app: &TestApp, email: &str, login_attempt_id: &str) -> String {
+    let email = Email::parse(Secret::new(email.to_owned())).unwrap();
+    let login_attempt_id = LoginAttemptId::parse(Secret::new(login_attempt_id.to_owned())).unwrap();
+    let code = app
+        .two_fa_code_store
+        .read()
+        .await
+        .get_code(&email, &login_attempt_id)
+        .await
+        .unwrap();
+    code.as_ref().expose_secret().to_owned()
+}
+
+#[tokio::test]
+async fn should_send_new_code_that_replaces_the_old_one() {
+    let mut app = app_with_resends(Duration::zero(), 3).await;
+    let (email, login_attempt_id) = start_2fa_login(&app).await;
+    let old_code = stored_2fa_code(&app, &email, &login_attempt_id).await;
+
+    let resend_body = serde_json::json!({ "email": email, "loginAttemptId": login_attempt_id });
+    let response = app.post_resend_2fa(&resend_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+    app.wait_for_emails().await;
+    assert_eq!(app.email_server.received_requests().await.unwrap().len(), 2);
+
+    let new_code = stored_2fa_code(&app, &email, &login_attempt_id).await;
+    let verify_body = |code: &str| {
+        serde_json::json!({
+            "email": email,
+            "loginAttemptId": login_attempt_id,
+            "2FACode": code
+        })
+    };
+    if old_code != new_code {
+        let response = app.post_verify_2fa(&verify_body(&old_code)).await;
+        assert_eq!(response.status().as_u16(), 400);
+    }
+    let response = app.post_verify_2fa(&verify_body(&new_code)).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_return_429_during_cooldown() {
+    let mut app = app_with_resends(Duration::seconds(60), 3).await;
+    let (email, login_attempt_id) = start_2fa_login(&app).await;
+
+    let response = app
+        .post_resend_2fa(&serde_json::json!({ "email": email, "loginAttemptId": login_attempt_id }))
+        .await;
+    assert_eq!(response.status().as_u16(), 429);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn resent_code_keeps_the_login_attempt_listed() {
+    let mut app = TestApp::with_settings(Settings {
+        two_fa: TwoFactorSettings {
+            resend_cooldown: Duration::zero(),
+            code_ttl: Duration::seconds(2),
+            ..TwoFactorSettings::default()
+        },
+        ..Settings::default()
+    })
+    .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let (email, login_attempt_id) = start_2fa_login(&app).await;
+
+    tokio::time::sleep(std::time::Duration::from_millis(1200)).await;
+    let resend_body = serde_json::json!({ "email": email, "loginAttemptId": login_attempt_id });
+    assert_eq!(
+        app.post_resend_2fa(&resend_body).await.status().as_u16(),
+        200
+    );
+
+    // Past the first code's expiry, but not the resent one's
+    tokio::time::sleep(std::time::Duration::from_millis(1200)).await;
+    let attempts = app
+        .two_fa_code_store
+        .read()
+        .await
+        .get_login_attempts(&Email::parse(Secret::new(email)).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(
+        attempts,
+        vec![LoginAttemptId::parse(Secret::new(login_attempt_id)).unwrap()]
+    );
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_return_429_after_max_resends() {
+    let mut app = app_with_resends(Duration::zero(), 2).await;
+    let (email, login_attempt_id) = start_2fa_login(&app).await;
+    let resend_body = serde_json::json!({ "email": email, "loginAttemptId": login_attempt_id });
+
+    for _ in 0..2 {
+        let response = app.post_resend_2fa(&resend_body).await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+    let response = app.post_resend_2fa(&resend_body).await;
+    assert_eq!(response.status().as_u16(), 429);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_return_401_for_unknown_login_attempt() {
+    let mut app = app_with_resends(Duration::zero(), 3).await;
+    let (email, _) = start_2fa_login(&app).await;
+
+    let response = app
+        .post_resend_2fa(&serde_json::json!({
+            "email": email,
+            "loginAttemptId": uuid::Uuid::new_v4().to_string()
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    let response = app
+        .post_resend_2fa(&serde_json::json!({
+            "email": email,
+            "loginAttemptId": "not-a-uuid"
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    app.clean_up().await;
+}
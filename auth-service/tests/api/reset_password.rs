@@ -0,0 +1,208 @@
+use crate::helpers::{get_random_email, TestApp};
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+async fn request_reset_token(app: &TestApp, email: &str) -> String {
+    let response = app
+        .post_forgot_password(&serde_json::json!({ "email": email }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // The token ends the first line of the email
+    let text = app.last_email_text().await;
+    text.lines()
+        .next()
+        .and_then(|line| line.rsplit(' ').next())
+        .expect("No token in email")
+        .to_owned()
+}
+
+async fn signup(app: &TestApp, email: &str) {
+    let signup_body = serde_json::json!({
+        "email": email,
+        "password": "password123",
+        "requires2FA": false
+    });
+    let response = app.post_signup(&signup_body).await;
+    assert_eq!(response.status().as_u16(), 201);
+}
+
+#[tokio::test]
+async fn forgot_password_should_not_send_email_for_unknown_user() {
+    let mut app = TestApp::new().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_forgot_password(&serde_json::json!({ "email": get_random_email() }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_return_200_and_accept_new_password() {
+    let mut app = TestApp::new().await;
+    let random_email = get_random_email();
+    signup(&app, &random_email).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let token = request_reset_token(&app, &random_email).await;
+
+    let response = app
+        .post_reset_password(&serde_json::json!({
+            "token": token,
+            "newPassword": "newpassword123"
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let login_body = serde_json::json!({
+        "email": random_email,
+        "password": "newpassword123",
+        "requires2FA": false
+    });
+    let response = app.post_login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_not_reset_password_of_inactive_account() {
+    let mut app = TestApp::new().await;
+    let random_email = get_random_email();
+    signup(&app, &random_email).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let token = request_reset_token(&app, &random_email).await;
+
+    let response = app
+        .post_admin_user_status(&serde_json::json!({
+            "email": random_email,
+            "status": "suspended",
+            "reason": "Suspicious activity"
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // A token sent before the suspension no longer works, and no new one is sent
+    let response = app
+        .post_reset_password(&serde_json::json!({
+            "token": token,
+            "newPassword": "newpassword123"
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 403);
+
+    let response = app
+        .post_forgot_password(&serde_json::json!({ "email": random_email }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_return_401_if_token_used_twice() {
+    let mut app = TestApp::new().await;
+    let random_email = get_random_email();
+    signup(&app, &random_email).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let token = request_reset_token(&app, &random_email).await;
+
+    let body = serde_json::json!({
+        "token": token,
+        "newPassword": "newpassword123"
+    });
+    let response = app.post_reset_password(&body).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let body = serde_json::json!({
+        "token": token,
+        "newPassword": "anotherpassword123"
+    });
+    let response = app.post_reset_password(&body).await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_return_401_if_auth_token_is_used() {
+    let mut app = TestApp::new().await;
+    let random_email = get_random_email();
+    signup(&app, &random_email).await;
+
+    let login_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+        "requires2FA": false
+    });
+    let response = app.post_login(&login_body).await;
+    let auth_cookie = response
+        .cookies()
+        .find(|cookie| cookie.name() == "jwt")
+        .expect("No auth cookie found");
+
+    let body = serde_json::json!({
+        "token": auth_cookie.value(),
+        "newPassword": "newpassword123"
+    });
+    let response = app.post_reset_password(&body).await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_return_400_if_password_was_used_recently() {
+    let mut app = TestApp::new().await;
+    let random_email = get_random_email();
+    signup(&app, &random_email).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let token = request_reset_token(&app, &random_email).await;
+
+    let body = serde_json::json!({
+        "token": token,
+        "newPassword": "password123"
+    });
+    let response = app.post_reset_password(&body).await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    app.clean_up().await;
+}
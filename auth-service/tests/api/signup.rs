@@ -1,5 +1,13 @@
 use crate::helpers::{get_random_email, TestApp};
-use auth_service::{routes::SignupResponse, ErrorResponse};
+use auth_service::{
+    app_state::settings::{EmailDomainSettings, Settings, SignupMode},
+    routes::admin::CreateInviteResponse,
+    utils::constants::test,
+    ErrorResponse,
+};
+use secrecy::Secret;
+use std::path::PathBuf;
+use uuid::Uuid;
 
 #[tokio::test]
 async fn should_return_422_if_malformed() {
@@ -89,3 +97,209 @@ async fn should_return_409_if_email_already_exists() {
     );
     app.clean_up().await;
 }
+
+fn invite_only_settings() -> Settings {
+    Settings {
+        admin_api_token: Some(Secret::new(test::ADMIN_API_TOKEN.to_owned())),
+        signup_mode: SignupMode::InviteOnly,
+        ..Settings::default()
+    }
+}
+
+async fn create_invite(app: &TestApp, body: serde_json::Value) -> String {
+    let response = app.post_admin_invite(&body).await;
+    assert_eq!(response.status().as_u16(), 201);
+    response
+        .json::<CreateInviteResponse>()
+        .await
+        .expect("Could not deserialize response body to CreateInviteResponse")
+        .invite_code
+}
+
+fn signup_body(email: &str, invite_code: Option<&str>) -> serde_json::Value {
+    serde_json::json!({
+        "email": email,
+        "password": "password123",
+        "requires2FA": false,
+        "inviteCode": invite_code
+    })
+}
+
+#[tokio::test]
+async fn should_return_403_without_invite_code_in_invite_only_mode() {
+    let mut app = TestApp::with_settings(invite_only_settings()).await;
+
+    let response = app
+        .post_signup(&signup_body(&get_random_email(), None))
+        .await;
+    assert_eq!(response.status().as_u16(), 403);
+
+    let response = app
+        .post_signup(&signup_body(&get_random_email(), Some("doesnotexist")))
+        .await;
+    assert_eq!(response.status().as_u16(), 403);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_consume_single_use_invite() {
+    let mut app = TestApp::with_settings(invite_only_settings()).await;
+    let code = create_invite(&app, serde_json::json!({})).await;
+
+    let response = app
+        .post_signup(&signup_body(&get_random_email(), Some(&code)))
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    let response = app
+        .post_signup(&signup_body(&get_random_email(), Some(&code)))
+        .await;
+    assert_eq!(response.status().as_u16(), 403);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_only_accept_bound_invite_for_its_email() {
+    let mut app = TestApp::with_settings(invite_only_settings()).await;
+    let invited_email = get_random_email();
+    let code = create_invite(&app, serde_json::json!({ "email": invited_email })).await;
+
+    let response = app
+        .post_signup(&signup_body(&get_random_email(), Some(&code)))
+        .await;
+    assert_eq!(response.status().as_u16(), 403);
+
+    let response = app
+        .post_signup(&signup_body(&invited_email, Some(&code)))
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_return_403_for_expired_invite() {
+    let mut app = TestApp::with_settings(invite_only_settings()).await;
+    let code = create_invite(&app, serde_json::json!({ "maxUses": 5 })).await;
+
+    sqlx::query("UPDATE invites SET expires_at = NOW() - INTERVAL '1 minute' WHERE code = $1")
+        .bind(&code)
+        .execute(&app.db_pool)
+        .await
+        .expect("Failed to expire invite");
+
+    let response = app
+        .post_signup(&signup_body(&get_random_email(), Some(&code)))
+        .await;
+    assert_eq!(response.status().as_u16(), 403);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn failed_signup_should_not_use_up_invite() {
+    let mut app = TestApp::with_settings(invite_only_settings()).await;
+    let code = create_invite(&app, serde_json::json!({ "maxUses": 2 })).await;
+    let email = get_random_email();
+
+    let response = app.post_signup(&signup_body(&email, Some(&code))).await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    let response = app.post_signup(&signup_body(&email, Some(&code))).await;
+    assert_eq!(response.status().as_u16(), 409);
+
+    let response = app
+        .post_signup(&signup_body(&get_random_email(), Some(&code)))
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    app.clean_up().await;
+}
+
+fn domain_list_file(contents: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("email-domains-{}.txt", Uuid::new_v4()));
+    std::fs::write(&path, contents).expect("Failed to write domain list");
+    path
+}
+
+async fn signup_error(app: &TestApp, email: &str) -> (u16, String) {
+    let response = app.post_signup(&signup_body(email, None)).await;
+    let status = response.status().as_u16();
+    let error = response
+        .json::<ErrorResponse>()
+        .await
+        .expect("Could not deserialize response body to ErrorResponse")
+        .error;
+    (status, error)
+}
+
+#[tokio::test]
+async fn should_return_400_for_disposable_email() {
+    let mut app = TestApp::new().await;
+
+    let (status, error) = signup_error(&app, "someone@mailinator.com").await;
+    assert_eq!(status, 400);
+    assert_eq!(error, "Disposable email addresses are not allowed");
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_only_allow_allowlisted_domains() {
+    let allowlist = domain_list_file("ourcompany.com\n");
+    let mut app = TestApp::with_settings(Settings {
+        email_domains: EmailDomainSettings {
+            allowlist_path: Some(allowlist.clone()),
+            ..EmailDomainSettings::default()
+        },
+        ..Settings::default()
+    })
+    .await;
+
+    let (status, error) = signup_error(&app, &get_random_email()).await;
+    assert_eq!(status, 400);
+    assert_eq!(error, "Email domain is not allowed");
+
+    let response = app
+        .post_signup(&signup_body(
+            &format!("{}@ourcompany.com", Uuid::new_v4()),
+            None,
+        ))
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    std::fs::remove_file(allowlist).ok();
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_apply_reloaded_blocklist_without_restart() {
+    let blocklist = domain_list_file("# nothing blocked yet\n");
+    let mut app = TestApp::with_settings(Settings {
+        admin_api_token: Some(Secret::new(test::ADMIN_API_TOKEN.to_owned())),
+        email_domains: EmailDomainSettings {
+            blocklist_path: Some(blocklist.clone()),
+            ..EmailDomainSettings::default()
+        },
+        ..Settings::default()
+    })
+    .await;
+
+    let response = app
+        .post_signup(&signup_body(&get_random_email(), None))
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    std::fs::write(&blocklist, "exanple.com\n").expect("Failed to update domain list");
+    let response = app.post_admin_reload_email_domains().await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let (status, error) = signup_error(&app, &get_random_email()).await;
+    assert_eq!(status, 400);
+    assert_eq!(error, "Email domain is blocked");
+
+    std::fs::remove_file(blocklist).ok();
+    app.clean_up().await;
+}
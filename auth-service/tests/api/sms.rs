@@ -0,0 +1,419 @@
+use crate::helpers::{get_random_email, TestApp};
+use auth_service::app_state::settings::{EmailTemplateSettings, Settings, TwoFactorSettings};
+use auth_service::domain::{
+    data_store::{LoginAttemptId, TwoFaCodeStore},
+    Email,
+};
+use auth_service::routes::login::TwoFactorAuthResponse;
+use auth_service::utils::constants::{test, JWT_COOKIE_NAME};
+use chrono::Duration;
+use secrecy::{ExposeSecret, Secret};
+use uuid::Uuid;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+async fn mock_sms_provider(app: &TestApp) {
+    Mock::given(path(format!(
+        "/2010-04-01/Accounts/{}/Messages.json",
+        test::sms_client::ACCOUNT_SID
+    )))
+    .and(method("POST"))
+    .respond_with(ResponseTemplate::new(201))
+    .mount(&app.sms_server)
+    .await;
+}
+
+// The six digit code in the most recent SMS the provider was asked to send
+async fn last_sms_code(app: &TestApp) -> String {
+    let requests = app.sms_server.received_requests().await.unwrap();
+    let body = String::from_utf8_lossy(&requests.last().expect("No SMS was sent").body).to_string();
+    let message = body
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("Body="))
+        .expect("SMS request has no body field");
+    message[message.len() - 6..].to_owned()
+}
+
+// The number the most recent SMS was addressed to, as form encoded in the request
+async fn last_sms_recipient(app: &TestApp) -> String {
+    let requests = app.sms_server.received_requests().await.unwrap();
+    let body = String::from_utf8_lossy(&requests.last().expect("No SMS was sent").body).to_string();
+    body.split('&')
+        .find_map(|pair| pair.strip_prefix("To="))
+        .expect("SMS request has no recipient field")
+        .to_owned()
+}
+
+async fn stored_2fa_code(app: &TestApp, email: &str, login_attempt_id: &str) -> String {
+    let email = Email::parse(Secret::new(email.to_owned())).unwrap();
+    let login_attempt_id = LoginAttemptId::parse(Secret::new(login_attempt_id.to_owned())).unwrap();
+    let code = app
+        .two_fa_code_store
+        .read()
+        .await
+        .get_code(&email, &login_attempt_id)
+        .await
+        .unwrap();
+    code.as_ref().expose_secret().to_owned()
+}
+
+// Signs up a user without 2FA and logs in so the cookie jar holds an auth cookie
+async fn logged_in_user(app: &TestApp) -> String {
+    let email = get_random_email();
+    let body = serde_json::json!({
+        "email": email,
+        "password": "password123",
+        "requires2FA": false
+    });
+    assert_eq!(app.post_signup(&body).await.status().as_u16(), 201);
+    assert_eq!(app.post_login(&body).await.status().as_u16(), 200);
+    email
+}
+
+async fn register_and_verify_phone(app: &TestApp) {
+    let response = app
+        .post_sms_phone(&serde_json::json!({ "phoneNumber": "+44 7700 900123" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let code = last_sms_code(app).await;
+    let response = app
+        .post_sms_phone_verify(&serde_json::json!({ "code": code }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn should_return_400_if_registering_phone_without_auth_cookie() {
+    let mut app = TestApp::new().await;
+
+    let response = app
+        .post_sms_phone(&serde_json::json!({ "phoneNumber": "+447700900123" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_return_400_for_invalid_phone_number() {
+    let mut app = TestApp::new().await;
+    logged_in_user(&app).await;
+
+    for number in ["07700900123", "+44 7700 9001a3", ""] {
+        let response = app
+            .post_sms_phone(&serde_json::json!({ "phoneNumber": number }))
+            .await;
+        assert_eq!(response.status().as_u16(), 400, "Failed for {:?}", number);
+    }
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_not_allow_preferring_sms_before_phone_is_verified() {
+    let mut app = TestApp::new().await;
+    mock_sms_provider(&app).await;
+    logged_in_user(&app).await;
+
+    let response = app
+        .post_2fa_preferred_method(&serde_json::json!({ "method": "sms" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    let response = app
+        .post_sms_phone(&serde_json::json!({ "phoneNumber": "+447700900123" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let code = last_sms_code(&app).await;
+    let wrong_code = if code == "123456" { "654321" } else { "123456" };
+    let response = app
+        .post_sms_phone_verify(&serde_json::json!({ "code": wrong_code }))
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    let response = app
+        .post_2fa_preferred_method(&serde_json::json!({ "method": "sms" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_return_429_if_registering_phones_too_quickly() {
+    let mut app = TestApp::new().await;
+    mock_sms_provider(&app).await;
+    logged_in_user(&app).await;
+
+    let response = app
+        .post_sms_phone(&serde_json::json!({ "phoneNumber": "+447700900123" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let code = last_sms_code(&app).await;
+
+    let response = app
+        .post_sms_phone(&serde_json::json!({ "phoneNumber": "+447700900456" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 429);
+    assert_eq!(app.sms_server.received_requests().await.unwrap().len(), 1);
+
+    // The number registered first is still the one waiting to be verified
+    let response = app
+        .post_sms_phone_verify(&serde_json::json!({ "code": code }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_send_2fa_code_by_sms_once_chosen() {
+    let mut app = TestApp::new().await;
+    mock_sms_provider(&app).await;
+    // Only the first login's code goes out by email
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let email = get_random_email();
+    let login_body = serde_json::json!({
+        "email": email,
+        "password": "password123",
+        "requires2FA": true
+    });
+    assert_eq!(app.post_signup(&login_body).await.status().as_u16(), 201);
+
+    let login_attempt_id = |response: reqwest::Response| async move {
+        response
+            .json::<TwoFactorAuthResponse>()
+            .await
+            .expect("Could not deserialize response body to TwoFactorAuthResponse")
+            .login_attempt_id
+    };
+
+    let attempt = login_attempt_id(app.post_login(&login_body).await).await;
+    let response = app
+        .post_verify_2fa(&serde_json::json!({
+            "email": email,
+            "loginAttemptId": attempt,
+            "2FACode": stored_2fa_code(&app, &email, &attempt).await
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    register_and_verify_phone(&app).await;
+    let response = app
+        .post_2fa_preferred_method(&serde_json::json!({ "method": "sms" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    app.post_logout().await;
+
+    let attempt = login_attempt_id(app.post_login(&login_body).await).await;
+    let response = app
+        .post_verify_2fa(&serde_json::json!({
+            "email": email,
+            "loginAttemptId": attempt,
+            "2FACode": last_sms_code(&app).await
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response
+        .cookies()
+        .any(|cookie| cookie.name() == JWT_COOKIE_NAME));
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_keep_texting_the_verified_number_until_a_new_one_is_verified() {
+    let mut app = TestApp::with_settings(Settings {
+        two_fa: TwoFactorSettings {
+            resend_cooldown: Duration::zero(),
+            ..TwoFactorSettings::default()
+        },
+        ..Settings::default()
+    })
+    .await;
+    mock_sms_provider(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let email = get_random_email();
+    let login_body = serde_json::json!({
+        "email": email,
+        "password": "password123",
+        "requires2FA": true
+    });
+    assert_eq!(app.post_signup(&login_body).await.status().as_u16(), 201);
+
+    let login_attempt_id = |response: reqwest::Response| async move {
+        response
+            .json::<TwoFactorAuthResponse>()
+            .await
+            .expect("Could not deserialize response body to TwoFactorAuthResponse")
+            .login_attempt_id
+    };
+
+    let attempt = login_attempt_id(app.post_login(&login_body).await).await;
+    let response = app
+        .post_verify_2fa(&serde_json::json!({
+            "email": email,
+            "loginAttemptId": attempt,
+            "2FACode": stored_2fa_code(&app, &email, &attempt).await
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    register_and_verify_phone(&app).await;
+    let response = app
+        .post_2fa_preferred_method(&serde_json::json!({ "method": "sms" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = app
+        .post_sms_phone(&serde_json::json!({ "phoneNumber": "+447700900456" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let new_number_code = last_sms_code(&app).await;
+
+    let attempt = login_attempt_id(app.post_login(&login_body).await).await;
+    assert_eq!(last_sms_recipient(&app).await, "%2B447700900123");
+    let response = app
+        .post_verify_2fa(&serde_json::json!({
+            "email": email,
+            "loginAttemptId": attempt,
+            "2FACode": last_sms_code(&app).await
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = app
+        .post_sms_phone_verify(&serde_json::json!({ "code": new_number_code }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    assert_eq!(app.post_login(&login_body).await.status().as_u16(), 200);
+    assert_eq!(last_sms_recipient(&app).await, "%2B447700900456");
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_text_2fa_codes_in_the_users_locale() {
+    let templates = std::env::temp_dir().join(format!("email-templates-{}", Uuid::new_v4()));
+    std::fs::create_dir_all(templates.join("de")).expect("Failed to create template directory");
+    std::fs::write(
+        templates.join("de/two_factor_code.sms.txt"),
+        "Ihr Anmeldecode lautet {{ code }}\n",
+    )
+    .expect("Failed to write template");
+    let mut app = TestApp::with_settings(Settings {
+        email_templates: EmailTemplateSettings {
+            dirs: vec![templates.clone()],
+            ..EmailTemplateSettings::default()
+        },
+        ..Settings::default()
+    })
+    .await;
+    mock_sms_provider(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let email = get_random_email();
+    let login_body = serde_json::json!({
+        "email": email,
+        "password": "password123",
+        "requires2FA": true,
+        "locale": "de"
+    });
+    assert_eq!(app.post_signup(&login_body).await.status().as_u16(), 201);
+    let attempt = app
+        .post_login(&login_body)
+        .await
+        .json::<TwoFactorAuthResponse>()
+        .await
+        .expect("Could not deserialize response body to TwoFactorAuthResponse")
+        .login_attempt_id;
+    let response = app
+        .post_verify_2fa(&serde_json::json!({
+            "email": email,
+            "loginAttemptId": attempt,
+            "2FACode": stored_2fa_code(&app, &email, &attempt).await
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    register_and_verify_phone(&app).await;
+    let response = app
+        .post_2fa_preferred_method(&serde_json::json!({ "method": "sms" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    app.post_logout().await;
+
+    assert_eq!(app.post_login(&login_body).await.status().as_u16(), 200);
+    let requests = app.sms_server.received_requests().await.unwrap();
+    let body = String::from_utf8_lossy(&requests.last().expect("No SMS was sent").body).to_string();
+    // The form encoding turns spaces into plus signs
+    assert!(body.contains("Body=Ihr+Anmeldecode+lautet+"));
+
+    std::fs::remove_dir_all(templates).ok();
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_text_phone_verification_codes_in_the_users_locale() {
+    let templates = std::env::temp_dir().join(format!("email-templates-{}", Uuid::new_v4()));
+    std::fs::create_dir_all(templates.join("de")).expect("Failed to create template directory");
+    std::fs::write(
+        templates.join("de/phone_verification_code.sms.txt"),
+        "Ihr Bestätigungscode lautet {{ code }}\n",
+    )
+    .expect("Failed to write template");
+    let mut app = TestApp::with_settings(Settings {
+        email_templates: EmailTemplateSettings {
+            dirs: vec![templates.clone()],
+            ..EmailTemplateSettings::default()
+        },
+        ..Settings::default()
+    })
+    .await;
+    mock_sms_provider(&app).await;
+
+    let body = serde_json::json!({
+        "email": get_random_email(),
+        "password": "password123",
+        "requires2FA": false,
+        "locale": "de"
+    });
+    assert_eq!(app.post_signup(&body).await.status().as_u16(), 201);
+    assert_eq!(app.post_login(&body).await.status().as_u16(), 200);
+
+    let response = app
+        .post_sms_phone(&serde_json::json!({ "phoneNumber": "+447700900123" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let requests = app.sms_server.received_requests().await.unwrap();
+    let body = String::from_utf8_lossy(&requests.last().expect("No SMS was sent").body).to_string();
+    assert!(body.contains("Body=Ihr+Best%C3%A4tigungscode+lautet+"));
+
+    let response = app
+        .post_sms_phone_verify(&serde_json::json!({ "code": last_sms_code(&app).await }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    std::fs::remove_dir_all(templates).ok();
+    app.clean_up().await;
+}
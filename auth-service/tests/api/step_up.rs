@@ -0,0 +1,254 @@
+use crate::helpers::{get_random_email, TestApp};
+use auth_service::app_state::settings::{Settings, StepUpSettings};
+use auth_service::domain::{
+    data_store::{LoginAttemptId, TwoFaCodeStore},
+    Email,
+};
+use auth_service::routes::login::TwoFactorAuthResponse;
+use auth_service::routes::SignupResponse;
+use auth_service::ReauthenticationRequiredResponse;
+use secrecy::{ExposeSecret, Secret};
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+fn change_password_body() -> serde_json::Value {
+    serde_json::json!({
+        "currentPassword": "password123",
+        "newPassword": "newpassword123"
+    })
+}
+
+// Signs up a user with 2FA, who gets their codes by email
+async fn signup_2fa_user(app: &TestApp) -> serde_json::Value {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let login_body = serde_json::json!({
+        "email": get_random_email(),
+        "password": "password123",
+        "requires2FA": true
+    });
+    assert_eq!(app.post_signup(&login_body).await.status().as_u16(), 201);
+    login_body
+}
+
+// Logs in with the password and completes 2FA with the stored code
+async fn login_with_2fa(app: &TestApp, login_body: &serde_json::Value, remember_device: bool) {
+    let login_attempt_id = app
+        .post_login(login_body)
+        .await
+        .json::<TwoFactorAuthResponse>()
+        .await
+        .expect("Login did not ask for 2FA")
+        .login_attempt_id;
+
+    let email = login_body["email"].as_str().unwrap();
+    let code = app
+        .two_fa_code_store
+        .read()
+        .await
+        .get_code(
+            &Email::parse(Secret::new(email.to_owned())).unwrap(),
+            &LoginAttemptId::parse(Secret::new(login_attempt_id.clone())).unwrap(),
+        )
+        .await
+        .expect("Failed to get code");
+
+    let response = app
+        .post_verify_2fa(&serde_json::json!({
+            "email": email,
+            "loginAttemptId": login_attempt_id,
+            "2FACode": code.as_ref().expose_secret(),
+            "rememberDevice": remember_device
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+// Checks the response asks the client to log in again at `acr`
+async fn assert_reauthentication_required(response: reqwest::Response, acr: &str, max_age: i64) {
+    assert_eq!(response.status().as_u16(), 401);
+    let challenge = response
+        .headers()
+        .get("www-authenticate")
+        .expect("No WWW-Authenticate header")
+        .to_str()
+        .unwrap()
+        .to_owned();
+    assert!(challenge.contains(r#"error="insufficient_user_authentication""#));
+    assert!(challenge.contains(&format!(r#"acr_values="{acr}""#)));
+
+    let body = response
+        .json::<serde_json::Value>()
+        .await
+        .expect("Could not deserialize response body");
+    assert_eq!(body["acr"], acr);
+    assert_eq!(body["maxAge"], max_age);
+    assert!(
+        serde_json::from_value::<ReauthenticationRequiredResponse>(body).is_ok(),
+        "Response body is not a ReauthenticationRequiredResponse"
+    );
+}
+
+#[tokio::test]
+async fn should_require_a_fresh_login_after_max_age() {
+    let mut app = TestApp::with_settings(Settings {
+        step_up: StepUpSettings {
+            max_age: chrono::Duration::seconds(1),
+        },
+        ..Settings::default()
+    })
+    .await;
+
+    let login_body = serde_json::json!({
+        "email": get_random_email(),
+        "password": "password123",
+        "requires2FA": false
+    });
+    assert_eq!(app.post_signup(&login_body).await.status().as_u16(), 201);
+    assert_eq!(app.post_login(&login_body).await.status().as_u16(), 200);
+
+    tokio::time::sleep(std::time::Duration::from_millis(2100)).await;
+
+    let response = app.post_change_password(&change_password_body()).await;
+    assert_reauthentication_required(response, "aal1", 1).await;
+
+    // Logging in again is enough for an account without 2FA
+    assert_eq!(app.post_login(&login_body).await.status().as_u16(), 200);
+    let response = app.post_change_password(&change_password_body()).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_require_the_second_factor_for_2fa_accounts() {
+    let mut app = TestApp::new().await;
+    let login_body = signup_2fa_user(&app).await;
+
+    login_with_2fa(&app, &login_body, true).await;
+
+    // The remembered device skips 2FA at login, but that login only counts as the password
+    let response = app.post_login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = app.post_change_password(&change_password_body()).await;
+    assert_reauthentication_required(response, "aal2", 300).await;
+
+    let response = app.post_totp_enroll().await;
+    assert_reauthentication_required(response, "aal2", 300).await;
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_ask_for_2fa_on_a_remembered_device_when_stepping_up() {
+    let mut app = TestApp::new().await;
+    let login_body = signup_2fa_user(&app).await;
+
+    login_with_2fa(&app, &login_body, true).await;
+
+    let mut step_up_body = login_body.clone();
+    step_up_body["stepUp"] = serde_json::json!(true);
+    login_with_2fa(&app, &step_up_body, false).await;
+
+    let response = app.post_change_password(&change_password_body()).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_allow_sensitive_operations_right_after_a_2fa_login() {
+    let mut app = TestApp::new().await;
+    let login_body = signup_2fa_user(&app).await;
+
+    login_with_2fa(&app, &login_body, false).await;
+
+    let response = app.post_change_password(&change_password_body()).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_not_step_up_with_a_magic_link_and_an_inbox_second_factor() {
+    let mut app = TestApp::new().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let email = get_random_email();
+    let recovery_codes = app
+        .post_signup(&serde_json::json!({
+            "email": email,
+            "password": "password123",
+            "requires2FA": true
+        }))
+        .await
+        .json::<SignupResponse>()
+        .await
+        .expect("Could not deserialize response body to SignupResponse")
+        .recovery_codes
+        .expect("No recovery codes in signup response");
+
+    let response = app
+        .post_magic_link(&serde_json::json!({ "email": email }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let text = app.last_email_text().await;
+    let token = text
+        .split("token=")
+        .nth(1)
+        .and_then(|rest| rest.split_whitespace().next())
+        .expect("No magic link in email");
+    let login_attempt_id = app
+        .get_magic_link_callback(token)
+        .await
+        .json::<TwoFactorAuthResponse>()
+        .await
+        .expect("Magic link did not ask for 2FA")
+        .login_attempt_id;
+
+    // The inbox that got the link can't also supply the second factor
+    let code = app
+        .two_fa_code_store
+        .read()
+        .await
+        .get_code(
+            &Email::parse(Secret::new(email.clone())).unwrap(),
+            &LoginAttemptId::parse(Secret::new(login_attempt_id.clone())).unwrap(),
+        )
+        .await
+        .expect("Failed to get code");
+    let response = app
+        .post_verify_2fa(&serde_json::json!({
+            "email": email,
+            "loginAttemptId": login_attempt_id,
+            "2FACode": code.as_ref().expose_secret(),
+            "method": "email"
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    // A recovery code finishes the login, but only a password makes it count as two factors
+    let response = app
+        .post_verify_2fa(&serde_json::json!({
+            "email": email,
+            "loginAttemptId": login_attempt_id,
+            "2FACode": recovery_codes[0]
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = app.post_change_password(&change_password_body()).await;
+    assert_reauthentication_required(response, "aal2", 300).await;
+
+    app.clean_up().await;
+}
@@ -0,0 +1,232 @@
+use crate::helpers::{get_random_email, TestApp};
+use auth_service::routes::login::TwoFactorAuthResponse;
+use auth_service::routes::totp::{ConfirmTotpResponse, TotpEnrollmentResponse};
+use auth_service::utils::constants::JWT_COOKIE_NAME;
+use totp_rs::{Algorithm, Secret, TOTP};
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+// Code an authenticator app would show `steps_ahead` time steps from now
+fn totp_code(secret: &str, steps_ahead: u64) -> String {
+    let totp = TOTP::new(
+        Algorithm::SHA1,
+        6,
+        0,
+        30,
+        Secret::Encoded(secret.to_owned()).to_bytes().unwrap(),
+        None,
+        "test".to_owned(),
+    )
+    .unwrap();
+    let now = chrono::Utc::now().timestamp() as u64;
+    totp.generate(now + steps_ahead * 30)
+}
+
+// Signs up a user without 2FA and logs in so the cookie jar holds an auth cookie
+async fn logged_in_user(app: &TestApp) -> String {
+    let email = get_random_email();
+    let body = serde_json::json!({
+        "email": email,
+        "password": "password123",
+        "requires2FA": false
+    });
+    assert_eq!(app.post_signup(&body).await.status().as_u16(), 201);
+    assert_eq!(app.post_login(&body).await.status().as_u16(), 200);
+    email
+}
+
+// Enrolls and confirms an authenticator app, returning its secret
+async fn enroll_totp(app: &TestApp) -> String {
+    let response = app.post_totp_enroll().await;
+    assert_eq!(response.status().as_u16(), 200);
+    let enrollment = response
+        .json::<TotpEnrollmentResponse>()
+        .await
+        .expect("Could not deserialize response body to TotpEnrollmentResponse");
+
+    let response = app
+        .post_totp_confirm(&serde_json::json!({ "code": totp_code(&enrollment.secret, 0) }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    enrollment.secret
+}
+
+#[tokio::test]
+async fn should_return_400_if_enrolling_without_auth_cookie() {
+    let mut app = TestApp::new().await;
+
+    let response = app.post_totp_enroll().await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_return_otpauth_uri_and_qr_codes_on_enroll() {
+    let mut app = TestApp::new().await;
+    let email = logged_in_user(&app).await;
+
+    let response = app.post_totp_enroll().await;
+    assert_eq!(response.status().as_u16(), 200);
+    let enrollment = response
+        .json::<TotpEnrollmentResponse>()
+        .await
+        .expect("Could not deserialize response body to TotpEnrollmentResponse");
+
+    let uri = url_decoded(&enrollment.otpauth_uri);
+    assert!(uri.starts_with("otpauth://totp/"));
+    assert!(uri.contains(&email));
+    assert!(uri.contains(&format!("secret={}", enrollment.secret)));
+    assert!(enrollment.qr_code_svg.contains("<svg"));
+    assert!(!enrollment.qr_code_png.is_empty());
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_confirm_enrollment_only_with_valid_code() {
+    let mut app = TestApp::new().await;
+    let email = logged_in_user(&app).await;
+
+    let response = app
+        .post_totp_confirm(&serde_json::json!({ "code": "123456" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    let enrollment = app
+        .post_totp_enroll()
+        .await
+        .json::<TotpEnrollmentResponse>()
+        .await
+        .expect("Could not deserialize response body to TotpEnrollmentResponse");
+
+    // A code from well outside the skew window
+    let wrong_code = totp_code(&enrollment.secret, 5);
+    let response = app
+        .post_totp_confirm(&serde_json::json!({ "code": wrong_code }))
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    let response = app
+        .post_totp_confirm(&serde_json::json!({ "code": totp_code(&enrollment.secret, 0) }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let recovery_codes = response
+        .json::<ConfirmTotpResponse>()
+        .await
+        .expect("Could not deserialize response body to ConfirmTotpResponse")
+        .recovery_codes;
+    assert_eq!(recovery_codes.map(|codes| codes.len()), Some(10));
+
+    // With 2FA on, changing it needs a login that used the second factor
+    let response = app.post_totp_enroll().await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    let response = app
+        .post_login(&serde_json::json!({
+            "email": email,
+            "password": "password123",
+            "requires2FA": true,
+            "totpCode": totp_code(&enrollment.secret, 1)
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = app.post_totp_enroll().await;
+    assert_eq!(response.status().as_u16(), 409);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_accept_totp_code_in_verify_2fa_once() {
+    let mut app = TestApp::new().await;
+    let email = logged_in_user(&app).await;
+    let secret = enroll_totp(&app).await;
+
+    // No code is emailed to users with an authenticator app
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let login_body = serde_json::json!({
+        "email": email,
+        "password": "password123",
+        "requires2FA": true
+    });
+    let response = app.post_login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let login_attempt_id = response
+        .json::<TwoFactorAuthResponse>()
+        .await
+        .expect("Could not deserialize response body to TwoFactorAuthResponse")
+        .login_attempt_id;
+
+    // The confirmation used the current step, so the next one is still within the window
+    let code = totp_code(&secret, 1);
+    let response = app
+        .post_verify_2fa(&serde_json::json!({
+            "email": email,
+            "loginAttemptId": login_attempt_id,
+            "2FACode": code
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response
+        .cookies()
+        .any(|cookie| cookie.name() == JWT_COOKIE_NAME));
+
+    let response = app.post_login(&login_body).await;
+    let login_attempt_id = response
+        .json::<TwoFactorAuthResponse>()
+        .await
+        .expect("Could not deserialize response body to TwoFactorAuthResponse")
+        .login_attempt_id;
+
+    let response = app
+        .post_verify_2fa(&serde_json::json!({
+            "email": email,
+            "loginAttemptId": login_attempt_id,
+            "2FACode": code
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_log_in_with_totp_code_sent_with_password() {
+    let mut app = TestApp::new().await;
+    let email = logged_in_user(&app).await;
+    let secret = enroll_totp(&app).await;
+
+    let code = totp_code(&secret, 1);
+    let login_body = serde_json::json!({
+        "email": email,
+        "password": "password123",
+        "requires2FA": true,
+        "totpCode": code
+    });
+    let response = app.post_login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response
+        .cookies()
+        .any(|cookie| cookie.name() == JWT_COOKIE_NAME));
+
+    // Replaying the code is refused
+    let response = app.post_login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    app.clean_up().await;
+}
+
+fn url_decoded(uri: &str) -> String {
+    uri.replace("%40", "@").replace("%20", " ")
+}
@@ -0,0 +1,165 @@
+use crate::helpers::{get_random_email, TestApp};
+use auth_service::domain::{
+    data_store::{LoginAttemptId, TwoFaCodeStore},
+    Email,
+};
+use auth_service::routes::login::TwoFactorAuthResponse;
+use auth_service::routes::trusted_devices::TrustedDevicesResponse;
+use auth_service::utils::constants::{JWT_COOKIE_NAME, TRUSTED_DEVICE_COOKIE_NAME};
+use secrecy::{ExposeSecret, Secret};
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+async fn signup_2fa_user(app: &TestApp) -> serde_json::Value {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let login_body = serde_json::json!({
+        "email": get_random_email(),
+        "password": "password123",
+        "requires2FA": true
+    });
+    assert_eq!(app.post_signup(&login_body).await.status().as_u16(), 201);
+    login_body
+}
+
+// Logs in with the password and completes 2FA with the stored code
+async fn login_with_2fa(
+    app: &TestApp,
+    login_body: &serde_json::Value,
+    remember_device: bool,
+) -> reqwest::Response {
+    let login_attempt_id = app
+        .post_login(login_body)
+        .await
+        .json::<TwoFactorAuthResponse>()
+        .await
+        .expect("Login did not ask for 2FA")
+        .login_attempt_id;
+
+    let email = login_body["email"].as_str().unwrap();
+    let code = app
+        .two_fa_code_store
+        .read()
+        .await
+        .get_code(
+            &Email::parse(Secret::new(email.to_owned())).unwrap(),
+            &LoginAttemptId::parse(Secret::new(login_attempt_id.clone())).unwrap(),
+        )
+        .await
+        .expect("Failed to get code");
+
+    let response = app
+        .post_verify_2fa(&serde_json::json!({
+            "email": email,
+            "loginAttemptId": login_attempt_id,
+            "2FACode": code.as_ref().expose_secret(),
+            "rememberDevice": remember_device
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    response
+}
+
+fn sets_auth_cookie(response: &reqwest::Response) -> bool {
+    response
+        .cookies()
+        .any(|cookie| cookie.name() == JWT_COOKIE_NAME && !cookie.value().is_empty())
+}
+
+#[tokio::test]
+async fn should_skip_2fa_on_a_remembered_device() {
+    let mut app = TestApp::new().await;
+    let login_body = signup_2fa_user(&app).await;
+
+    let response = login_with_2fa(&app, &login_body, true).await;
+    assert!(response
+        .cookies()
+        .any(|cookie| cookie.name() == TRUSTED_DEVICE_COOKIE_NAME));
+
+    let response = app.post_login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(sets_auth_cookie(&response));
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_still_require_2fa_without_remember_device() {
+    let mut app = TestApp::new().await;
+    let login_body = signup_2fa_user(&app).await;
+
+    let response = login_with_2fa(&app, &login_body, false).await;
+    assert!(response
+        .cookies()
+        .all(|cookie| cookie.name() != TRUSTED_DEVICE_COOKIE_NAME));
+
+    let response = app.post_login(&login_body).await;
+    assert!(!sets_auth_cookie(&response));
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_not_skip_2fa_for_another_user() {
+    let mut app = TestApp::new().await;
+    let first_user = signup_2fa_user(&app).await;
+    login_with_2fa(&app, &first_user, true).await;
+
+    let second_user = signup_2fa_user(&app).await;
+    let response = app.post_login(&second_user).await;
+    assert!(!sets_auth_cookie(&response));
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_list_and_revoke_trusted_devices() {
+    let mut app = TestApp::new().await;
+    let login_body = signup_2fa_user(&app).await;
+    login_with_2fa(&app, &login_body, true).await;
+
+    let response = app.get_trusted_devices().await;
+    assert_eq!(response.status().as_u16(), 200);
+    let devices = response
+        .json::<TrustedDevicesResponse>()
+        .await
+        .expect("Could not deserialize response body to TrustedDevicesResponse")
+        .devices;
+    assert_eq!(devices.len(), 1);
+
+    let revoke_body = serde_json::json!({ "id": devices[0].id });
+    let response = app.post_revoke_trusted_device(&revoke_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // The cookie is still in the browser but no longer trusted
+    let response = app.post_login(&login_body).await;
+    assert!(!sets_auth_cookie(&response));
+
+    let response = app.post_revoke_trusted_device(&revoke_body).await;
+    assert_eq!(response.status().as_u16(), 404);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_return_400_if_logged_out_or_id_is_malformed() {
+    let mut app = TestApp::new().await;
+
+    let response = app.get_trusted_devices().await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    let login_body = signup_2fa_user(&app).await;
+    login_with_2fa(&app, &login_body, false).await;
+    let response = app
+        .post_revoke_trusted_device(&serde_json::json!({ "id": "not-a-uuid" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    app.clean_up().await;
+}
@@ -0,0 +1,233 @@
+use crate::helpers::{get_random_email, TestApp};
+use auth_service::domain::{
+    data_store::{LoginAttemptId, TwoFaCodeStore},
+    two_factor::TwoFactorMethod,
+    Email,
+};
+use auth_service::routes::login::TwoFactorAuthResponse;
+use auth_service::routes::two_factor_methods::TwoFactorMethodsResponse;
+use auth_service::utils::constants::test;
+use secrecy::{ExposeSecret, Secret};
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+async fn mock_sms_provider(app: &TestApp) {
+    Mock::given(path(format!(
+        "/2010-04-01/Accounts/{}/Messages.json",
+        test::sms_client::ACCOUNT_SID
+    )))
+    .and(method("POST"))
+    .respond_with(ResponseTemplate::new(201))
+    .mount(&app.sms_server)
+    .await;
+}
+
+// The six digit code in the most recent SMS the provider was asked to send
+async fn last_sms_code(app: &TestApp) -> String {
+    let requests = app.sms_server.received_requests().await.unwrap();
+    let body = String::from_utf8_lossy(&requests.last().expect("No SMS was sent").body).to_string();
+    let message = body
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("Body="))
+        .expect("SMS request has no body field");
+    message[message.len() - 6..].to_owned()
+}
+
+async fn stored_2fa_code(app: &TestApp, email: &str, login_attempt_id: &str) -> String {
+    let email = Email::parse(Secret::new(email.to_owned())).unwrap();
+    let login_attempt_id = LoginAttemptId::parse(Secret::new(login_attempt_id.to_owned())).unwrap();
+    let code = app
+        .two_fa_code_store
+        .read()
+        .await
+        .get_code(&email, &login_attempt_id)
+        .await
+        .unwrap();
+    code.as_ref().expose_secret().to_owned()
+}
+
+async fn signup_2fa_user(app: &TestApp) -> serde_json::Value {
+    let login_body = serde_json::json!({
+        "email": get_random_email(),
+        "password": "password123",
+        "requires2FA": true
+    });
+    assert_eq!(app.post_signup(&login_body).await.status().as_u16(), 201);
+    login_body
+}
+
+async fn start_login(app: &TestApp, login_body: &serde_json::Value) -> TwoFactorAuthResponse {
+    app.post_login(login_body)
+        .await
+        .json::<TwoFactorAuthResponse>()
+        .await
+        .expect("Login did not ask for 2FA")
+}
+
+async fn get_methods(app: &TestApp) -> TwoFactorMethodsResponse {
+    let response = app.get_2fa_methods().await;
+    assert_eq!(response.status().as_u16(), 200);
+    response
+        .json::<TwoFactorMethodsResponse>()
+        .await
+        .expect("Could not deserialize response body to TwoFactorMethodsResponse")
+}
+
+#[tokio::test]
+async fn should_ask_for_the_preferred_method_first() {
+    let mut app = TestApp::new().await;
+    mock_sms_provider(&app).await;
+    // Only the first login's code goes out by email
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let login_body = signup_2fa_user(&app).await;
+    let email = login_body["email"].as_str().unwrap();
+
+    let login = start_login(&app, &login_body).await;
+    // Recovery codes are handed out at signup and stay at the end
+    assert_eq!(
+        login.methods,
+        vec![TwoFactorMethod::Email, TwoFactorMethod::RecoveryCode]
+    );
+    let response = app
+        .post_verify_2fa(&serde_json::json!({
+            "email": email,
+            "loginAttemptId": login.login_attempt_id,
+            "2FACode": stored_2fa_code(&app, email, &login.login_attempt_id).await,
+            "method": "email"
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = app
+        .post_sms_phone(&serde_json::json!({ "phoneNumber": "+44 7700 900123" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let response = app
+        .post_sms_phone_verify(&serde_json::json!({ "code": last_sms_code(&app).await }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let methods = get_methods(&app).await;
+    assert_eq!(
+        methods.methods,
+        vec![
+            TwoFactorMethod::Email,
+            TwoFactorMethod::Sms,
+            TwoFactorMethod::RecoveryCode
+        ]
+    );
+    assert_eq!(methods.preferred_method, Some(TwoFactorMethod::Email));
+
+    let response = app
+        .post_2fa_preferred_method(&serde_json::json!({ "method": "sms" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let methods = get_methods(&app).await;
+    assert_eq!(
+        methods.methods,
+        vec![
+            TwoFactorMethod::Sms,
+            TwoFactorMethod::Email,
+            TwoFactorMethod::RecoveryCode
+        ]
+    );
+    assert_eq!(methods.preferred_method, Some(TwoFactorMethod::Sms));
+    app.post_logout().await;
+
+    let login = start_login(&app, &login_body).await;
+    assert_eq!(
+        login.methods,
+        vec![
+            TwoFactorMethod::Sms,
+            TwoFactorMethod::Email,
+            TwoFactorMethod::RecoveryCode
+        ]
+    );
+    let response = app
+        .post_verify_2fa(&serde_json::json!({
+            "email": email,
+            "loginAttemptId": login.login_attempt_id,
+            "2FACode": last_sms_code(&app).await,
+            "method": "sms"
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_reject_methods_the_user_has_not_set_up() {
+    let mut app = TestApp::new().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let login_body = signup_2fa_user(&app).await;
+    let email = login_body["email"].as_str().unwrap();
+    let login = start_login(&app, &login_body).await;
+
+    let response = app
+        .post_resend_2fa(&serde_json::json!({
+            "email": email,
+            "loginAttemptId": login.login_attempt_id,
+            "method": "sms"
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    let response = app
+        .post_verify_2fa(&serde_json::json!({
+            "email": email,
+            "loginAttemptId": login.login_attempt_id,
+            "2FACode": "123456",
+            "method": "totp"
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    // The emailed code is only accepted as what it is
+    let code = stored_2fa_code(&app, email, &login.login_attempt_id).await;
+    let response = app
+        .post_verify_2fa(&serde_json::json!({
+            "email": email,
+            "loginAttemptId": login.login_attempt_id,
+            "2FACode": code,
+            "method": "email"
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    for method in ["totp", "recovery_code"] {
+        let response = app
+            .post_2fa_preferred_method(&serde_json::json!({ "method": method }))
+            .await;
+        assert_eq!(response.status().as_u16(), 400);
+    }
+    assert_eq!(
+        get_methods(&app).await.preferred_method,
+        Some(TwoFactorMethod::Email)
+    );
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_return_400_when_listing_methods_without_auth_cookie() {
+    let mut app = TestApp::new().await;
+
+    let response = app.get_2fa_methods().await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    app.clean_up().await;
+}
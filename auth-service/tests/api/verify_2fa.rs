@@ -1,8 +1,11 @@
 use crate::helpers::{get_random_email, TestApp};
-use auth_service::domain::data_store::TwoFaCodeStore;
+use auth_service::app_state::settings::{Settings, TwoFactorSettings};
+use auth_service::domain::authentication::AuthMethod;
+use auth_service::domain::data_store::{LoginAttemptId, TwoFaCodeStore};
+use auth_service::domain::user::PasswordChangeReason;
 use auth_service::domain::Email;
-use auth_service::routes::login::TwoFactorAuthResponse;
-use auth_service::utils::constants::JWT_COOKIE_NAME;
+use auth_service::routes::login::{PasswordChangeRequiredResponse, TwoFactorAuthResponse};
+use auth_service::utils::constants::{test, JWT_COOKIE_NAME};
 use secrecy::{ExposeSecret, Secret};
 use wiremock::{
     matchers::{method, path},
@@ -43,8 +46,10 @@ async fn should_return_200_if_correct_code() {
 
     let code_store = app.two_fa_code_store.read().await;
     let email_obj = Email::parse(Secret::new(random_email.clone())).unwrap();
-    let (_, code) = code_store
-        .get_code(&email_obj)
+    let login_attempt_id =
+        LoginAttemptId::parse(Secret::new(json_body.login_attempt_id.clone())).unwrap();
+    let code = code_store
+        .get_code(&email_obj, &login_attempt_id)
         .await
         .expect("Failed to get code");
 
@@ -66,6 +71,10 @@ async fn should_return_200_if_correct_code() {
         .expect("No auth cookie found");
 
     assert!(!auth_cookie.value().is_empty());
+    assert_eq!(
+        app.auth_methods(&response).await,
+        vec![AuthMethod::Pwd, AuthMethod::Email]
+    );
     app.clean_up().await;
 }
 
@@ -155,8 +164,10 @@ async fn should_return_401_if_same_code_twice() {
 
     let code_store = app.two_fa_code_store.read().await;
     let email_obj = Email::parse(Secret::new(random_email.clone())).unwrap();
-    let (_, code) = code_store
-        .get_code(&email_obj)
+    let login_attempt_id =
+        LoginAttemptId::parse(Secret::new(json_body.login_attempt_id.clone())).unwrap();
+    let code = code_store
+        .get_code(&email_obj, &login_attempt_id)
         .await
         .expect("Failed to get code");
 
@@ -182,3 +193,299 @@ async fn should_return_401_if_same_code_twice() {
     assert_eq!(response.status().as_u16(), 401);
     app.clean_up().await;
 }
+
+#[tokio::test]
+async fn should_return_429_and_invalidate_code_after_too_many_wrong_codes() {
+    let mut app = TestApp::with_settings(Settings {
+        admin_api_token: Some(Secret::new(test::ADMIN_API_TOKEN.to_owned())),
+        two_fa: TwoFactorSettings {
+            max_attempts: 3,
+            ..TwoFactorSettings::default()
+        },
+        ..Settings::default()
+    })
+    .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let random_email = get_random_email();
+    let login_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+        "requires2FA": true
+    });
+    assert_eq!(app.post_signup(&login_body).await.status().as_u16(), 201);
+    let login_attempt_id = app
+        .post_login(&login_body)
+        .await
+        .json::<TwoFactorAuthResponse>()
+        .await
+        .expect("Could not deserialize response body to TwoFactorAuthResponse")
+        .login_attempt_id;
+
+    let email = Email::parse(Secret::new(random_email.clone())).unwrap();
+    let code = app
+        .two_fa_code_store
+        .read()
+        .await
+        .get_code(
+            &email,
+            &LoginAttemptId::parse(Secret::new(login_attempt_id.clone())).unwrap(),
+        )
+        .await
+        .expect("Failed to get code");
+    let code = code.as_ref().expose_secret().to_owned();
+    let wrong_code = if code == "123456" { "654321" } else { "123456" };
+
+    let verify_request = |code: &str| {
+        serde_json::json!({
+            "email": random_email,
+            "loginAttemptId": login_attempt_id,
+            "2FACode": code
+        })
+    };
+
+    for _ in 0..2 {
+        let response = app.post_verify_2fa(&verify_request(wrong_code)).await;
+        assert_eq!(response.status().as_u16(), 400);
+    }
+    let response = app.post_verify_2fa(&verify_request(wrong_code)).await;
+    assert_eq!(response.status().as_u16(), 429);
+
+    // The right code no longer works once the attempt has been thrown away
+    let response = app.post_verify_2fa(&verify_request(&code)).await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_require_password_change_after_2fa_when_flagged() {
+    let mut app = TestApp::new().await;
+
+    let random_email = get_random_email();
+    let signup_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+        "requires2FA": true
+    });
+    let response = app.post_signup(&signup_body).await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    let response = app
+        .post_admin_must_change_password(&serde_json::json!({
+            "email": random_email,
+            "mustChangePassword": true
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // The second factor is still required before a password change token is handed out
+    let login_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+        "requires2FA": true
+    });
+    let response = app.post_login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let json_body = response
+        .json::<TwoFactorAuthResponse>()
+        .await
+        .expect("Could not deserialize response body to TwoFactorAuthResponse");
+
+    let email = Email::parse(Secret::new(random_email.clone())).unwrap();
+    let code = app
+        .two_fa_code_store
+        .read()
+        .await
+        .get_code(
+            &email,
+            &LoginAttemptId::parse(Secret::new(json_body.login_attempt_id.clone())).unwrap(),
+        )
+        .await
+        .expect("Failed to get code");
+
+    let response = app
+        .post_verify_2fa(&serde_json::json!({
+            "email": random_email,
+            "loginAttemptId": json_body.login_attempt_id,
+            "2FACode": code.as_ref().expose_secret()
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response
+        .cookies()
+        .all(|cookie| cookie.name() != JWT_COOKIE_NAME));
+
+    let body = response
+        .json::<PasswordChangeRequiredResponse>()
+        .await
+        .expect("Could not deserialize response body to PasswordChangeRequiredResponse");
+    assert_eq!(body.reason, PasswordChangeReason::Required);
+
+    app.clean_up().await;
+}
+
+async fn start_2fa_login(app: &TestApp, login_body: &serde_json::Value) -> (String, String) {
+    let login_attempt_id = app
+        .post_login(login_body)
+        .await
+        .json::<TwoFactorAuthResponse>()
+        .await
+        .expect("Could not deserialize response body to TwoFactorAuthResponse")
+        .login_attempt_id;
+
+    let email = Email::parse(Secret::new(
+        login_body["email"].as_str().unwrap().to_owned(),
+    ))
+    .unwrap();
+    let code = app
+        .two_fa_code_store
+        .read()
+        .await
+        .get_code(
+            &email,
+            &LoginAttemptId::parse(Secret::new(login_attempt_id.clone())).unwrap(),
+        )
+        .await
+        .expect("Failed to get code");
+    (login_attempt_id, code.as_ref().expose_secret().to_owned())
+}
+
+#[tokio::test]
+async fn should_complete_parallel_logins_independently() {
+    let mut app = TestApp::new().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let random_email = get_random_email();
+    let login_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+        "requires2FA": true
+    });
+    assert_eq!(app.post_signup(&login_body).await.status().as_u16(), 201);
+
+    let (first_attempt, first_code) = start_2fa_login(&app, &login_body).await;
+    let (second_attempt, second_code) = start_2fa_login(&app, &login_body).await;
+
+    let verify_request = |attempt: &str, code: &str| {
+        serde_json::json!({
+            "email": random_email,
+            "loginAttemptId": attempt,
+            "2FACode": code
+        })
+    };
+
+    // Finishing the second login leaves the first one open
+    let response = app
+        .post_verify_2fa(&verify_request(&second_attempt, &second_code))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let response = app
+        .post_verify_2fa(&verify_request(&first_attempt, &first_code))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_drop_the_oldest_login_when_too_many_are_pending() {
+    let mut app = TestApp::with_settings(Settings {
+        admin_api_token: Some(Secret::new(test::ADMIN_API_TOKEN.to_owned())),
+        two_fa: TwoFactorSettings {
+            max_pending_logins: 2,
+            ..TwoFactorSettings::default()
+        },
+        ..Settings::default()
+    })
+    .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let random_email = get_random_email();
+    let login_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+        "requires2FA": true
+    });
+    assert_eq!(app.post_signup(&login_body).await.status().as_u16(), 201);
+
+    let mut attempts = Vec::new();
+    for _ in 0..3 {
+        attempts.push(start_2fa_login(&app, &login_body).await);
+    }
+
+    let verify_request = |(attempt, code): &(String, String)| {
+        serde_json::json!({
+            "email": random_email,
+            "loginAttemptId": attempt,
+            "2FACode": code
+        })
+    };
+
+    let response = app.post_verify_2fa(&verify_request(&attempts[0])).await;
+    assert_eq!(response.status().as_u16(), 401);
+    for attempt in &attempts[1..] {
+        let response = app.post_verify_2fa(&verify_request(attempt)).await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_reject_codes_past_the_configured_lifetime() {
+    let mut app = TestApp::with_settings(Settings {
+        two_fa: TwoFactorSettings {
+            code_ttl: chrono::Duration::seconds(1),
+            ..TwoFactorSettings::default()
+        },
+        ..Settings::default()
+    })
+    .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let random_email = get_random_email();
+    let login_body = serde_json::json!({
+        "email": random_email,
+        "password": "password123",
+        "requires2FA": true
+    });
+    assert_eq!(app.post_signup(&login_body).await.status().as_u16(), 201);
+
+    let (login_attempt_id, code) = start_2fa_login(&app, &login_body).await;
+    tokio::time::sleep(std::time::Duration::from_millis(1500)).await;
+
+    let response = app
+        .post_verify_2fa(&serde_json::json!({
+            "email": random_email,
+            "loginAttemptId": login_attempt_id,
+            "2FACode": code
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    app.clean_up().await;
+}
@@ -0,0 +1,316 @@
+use crate::helpers::{get_random_email, TestApp};
+use auth_service::app_state::settings::WebAuthnSettings;
+use auth_service::routes::login::TwoFactorAuthResponse;
+use auth_service::routes::webauthn::{
+    FinishRegistrationResponse, LoginOptionsResponse, RegistrationOptionsResponse,
+};
+use auth_service::routes::SignupResponse;
+use auth_service::utils::constants::JWT_COOKIE_NAME;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ciborium::value::Value;
+use p256::ecdsa::{signature::Signer, Signature, SigningKey};
+use rand_core::OsRng;
+use sha2::{Digest, Sha256};
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+const USER_PRESENT: u8 = 0x01;
+const USER_VERIFIED: u8 = 0x04;
+const ATTESTED_CREDENTIAL_DATA: u8 = 0x40;
+
+// Software stand-in for a passkey or security key holding a single P-256 credential. It
+// answers challenges the way a browser would hand them to the server, base64url-encoded.
+#[derive(Clone)]
+struct SoftwareAuthenticator {
+    key: SigningKey,
+    credential_id: Vec<u8>,
+    sign_count: u32,
+}
+
+impl SoftwareAuthenticator {
+    fn new() -> Self {
+        Self {
+            key: SigningKey::random(&mut OsRng),
+            credential_id: rand::random::<[u8; 16]>().to_vec(),
+            sign_count: 0,
+        }
+    }
+
+    fn credential_id(&self) -> String {
+        URL_SAFE_NO_PAD.encode(&self.credential_id)
+    }
+
+    fn client_data(ceremony_type: &str, challenge: &str) -> Vec<u8> {
+        serde_json::json!({
+            "type": ceremony_type,
+            "challenge": challenge,
+            "origin": WebAuthnSettings::default().origin,
+            "crossOrigin": false
+        })
+        .to_string()
+        .into_bytes()
+    }
+
+    fn authenticator_data(&self, flags: u8) -> Vec<u8> {
+        let mut data = Sha256::digest(WebAuthnSettings::default().rp_id.as_bytes()).to_vec();
+        data.push(flags);
+        data.extend_from_slice(&self.sign_count.to_be_bytes());
+        data
+    }
+
+    // Body for /webauthn/register/finish
+    fn register(&self, challenge: &str) -> serde_json::Value {
+        let point = self.key.verifying_key().to_encoded_point(false);
+        let public_key = Value::Map(vec![
+            (Value::from(1), Value::from(2)),
+            (Value::from(3), Value::from(-7)),
+            (Value::from(-1), Value::from(1)),
+            (Value::from(-2), Value::Bytes(point.x().unwrap().to_vec())),
+            (Value::from(-3), Value::Bytes(point.y().unwrap().to_vec())),
+        ]);
+
+        let mut auth_data =
+            self.authenticator_data(USER_PRESENT | USER_VERIFIED | ATTESTED_CREDENTIAL_DATA);
+        auth_data.extend_from_slice(&[0; 16]);
+        auth_data.extend_from_slice(&(self.credential_id.len() as u16).to_be_bytes());
+        auth_data.extend_from_slice(&self.credential_id);
+        ciborium::into_writer(&public_key, &mut auth_data).unwrap();
+
+        let attestation = Value::Map(vec![
+            (Value::from("fmt"), Value::from("none")),
+            (Value::from("attStmt"), Value::Map(vec![])),
+            (Value::from("authData"), Value::Bytes(auth_data)),
+        ]);
+        let mut attestation_object = Vec::new();
+        ciborium::into_writer(&attestation, &mut attestation_object).unwrap();
+
+        serde_json::json!({
+            "clientDataJSON": URL_SAFE_NO_PAD.encode(Self::client_data("webauthn.create", challenge)),
+            "attestationObject": URL_SAFE_NO_PAD.encode(attestation_object)
+        })
+    }
+
+    // Body for /webauthn/login/finish
+    fn assert(&mut self, challenge: &str, flags: u8) -> serde_json::Value {
+        self.sign_count += 1;
+        let client_data = Self::client_data("webauthn.get", challenge);
+        let auth_data = self.authenticator_data(flags);
+
+        let mut signed = auth_data.clone();
+        signed.extend_from_slice(&Sha256::digest(&client_data));
+        let signature: Signature = self.key.sign(&signed);
+
+        serde_json::json!({
+            "credentialId": self.credential_id(),
+            "clientDataJSON": URL_SAFE_NO_PAD.encode(client_data),
+            "authenticatorData": URL_SAFE_NO_PAD.encode(auth_data),
+            "signature": URL_SAFE_NO_PAD.encode(signature.to_der().as_bytes())
+        })
+    }
+}
+
+// Signs up a user without 2FA and logs in so the cookie jar holds an auth cookie
+async fn logged_in_user(app: &TestApp) -> String {
+    let email = get_random_email();
+    let body = serde_json::json!({
+        "email": email,
+        "password": "password123",
+        "requires2FA": false
+    });
+    assert_eq!(app.post_signup(&body).await.status().as_u16(), 201);
+    assert_eq!(app.post_login(&body).await.status().as_u16(), 200);
+    email
+}
+
+async fn register(app: &TestApp, authenticator: &SoftwareAuthenticator) {
+    let response = app.post_webauthn_register_start().await;
+    assert_eq!(response.status().as_u16(), 200);
+    let options = response
+        .json::<RegistrationOptionsResponse>()
+        .await
+        .expect("Could not deserialize response body to RegistrationOptionsResponse")
+        .public_key;
+
+    let response = app
+        .post_webauthn_register_finish(&authenticator.register(&options.challenge))
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+    let registered = response
+        .json::<FinishRegistrationResponse>()
+        .await
+        .expect("Could not deserialize response body to FinishRegistrationResponse");
+    assert_eq!(registered.credential_id, authenticator.credential_id());
+}
+
+async fn login_challenge(app: &TestApp, body: &serde_json::Value) -> LoginOptionsResponse {
+    let response = app.post_webauthn_login_start(body).await;
+    assert_eq!(response.status().as_u16(), 200);
+    response
+        .json::<LoginOptionsResponse>()
+        .await
+        .expect("Could not deserialize response body to LoginOptionsResponse")
+}
+
+#[tokio::test]
+async fn should_return_400_if_registering_without_auth_cookie() {
+    let mut app = TestApp::new().await;
+
+    let response = app.post_webauthn_register_start().await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_log_in_with_passkey_alone() {
+    let mut app = TestApp::new().await;
+    let email = logged_in_user(&app).await;
+    let mut authenticator = SoftwareAuthenticator::new();
+    register(&app, &authenticator).await;
+    app.post_logout().await;
+
+    let options = login_challenge(&app, &serde_json::json!({ "email": email })).await;
+    assert_eq!(options.public_key.user_verification, "required");
+    assert_eq!(
+        options.public_key.allow_credentials[0].id,
+        authenticator.credential_id()
+    );
+
+    let response = app
+        .post_webauthn_login_finish(
+            &authenticator.assert(&options.public_key.challenge, USER_PRESENT | USER_VERIFIED),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response
+        .cookies()
+        .any(|cookie| cookie.name() == JWT_COOKIE_NAME));
+
+    // A challenge can only be answered once
+    let response = app
+        .post_webauthn_login_finish(
+            &authenticator.assert(&options.public_key.challenge, USER_PRESENT | USER_VERIFIED),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_require_user_verification_without_password() {
+    let mut app = TestApp::new().await;
+    logged_in_user(&app).await;
+    let mut authenticator = SoftwareAuthenticator::new();
+    register(&app, &authenticator).await;
+
+    // Discoverable login, where the passkey tells the server who the user is
+    let options = login_challenge(&app, &serde_json::json!({})).await;
+    assert!(options.public_key.allow_credentials.is_empty());
+
+    let response = app
+        .post_webauthn_login_finish(
+            &authenticator.assert(&options.public_key.challenge, USER_PRESENT),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_reject_cloned_authenticator_with_stale_counter() {
+    let mut app = TestApp::new().await;
+    let email = logged_in_user(&app).await;
+    let mut authenticator = SoftwareAuthenticator::new();
+    register(&app, &authenticator).await;
+    let mut clone = authenticator.clone();
+
+    let options = login_challenge(&app, &serde_json::json!({ "email": email })).await;
+    let response = app
+        .post_webauthn_login_finish(
+            &authenticator.assert(&options.public_key.challenge, USER_PRESENT | USER_VERIFIED),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let options = login_challenge(&app, &serde_json::json!({ "email": email })).await;
+    let response = app
+        .post_webauthn_login_finish(
+            &clone.assert(&options.public_key.challenge, USER_PRESENT | USER_VERIFIED),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_accept_passkey_as_second_factor() {
+    let mut app = TestApp::new().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let email = get_random_email();
+    let login_body = serde_json::json!({
+        "email": email,
+        "password": "password123",
+        "requires2FA": true
+    });
+    let recovery_codes = app
+        .post_signup(&login_body)
+        .await
+        .json::<SignupResponse>()
+        .await
+        .expect("Could not deserialize response body to SignupResponse")
+        .recovery_codes
+        .expect("No recovery codes in signup response");
+
+    let login_attempt_id = |response: reqwest::Response| async move {
+        response
+            .json::<TwoFactorAuthResponse>()
+            .await
+            .expect("Could not deserialize response body to TwoFactorAuthResponse")
+            .login_attempt_id
+    };
+
+    // Log in once with a recovery code to register a passkey
+    let attempt = login_attempt_id(app.post_login(&login_body).await).await;
+    let response = app
+        .post_verify_2fa(&serde_json::json!({
+            "email": email,
+            "loginAttemptId": attempt,
+            "2FACode": recovery_codes[0]
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let mut authenticator = SoftwareAuthenticator::new();
+    register(&app, &authenticator).await;
+    app.post_logout().await;
+
+    let attempt = login_attempt_id(app.post_login(&login_body).await).await;
+    let options = login_challenge(
+        &app,
+        &serde_json::json!({ "email": email, "loginAttemptId": attempt }),
+    )
+    .await;
+    assert_eq!(options.public_key.user_verification, "preferred");
+
+    // The password already counts as one factor, so user presence is enough
+    let response = app
+        .post_webauthn_login_finish(
+            &authenticator.assert(&options.public_key.challenge, USER_PRESENT),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response
+        .cookies()
+        .any(|cookie| cookie.name() == JWT_COOKIE_NAME));
+
+    app.clean_up().await;
+}